@@ -47,7 +47,7 @@ impl State {
             },
         );
 
-        metalshaper::apple_ir::parse_apple_ir(&std::fs::read("test.air")?);
+        metalshaper::apple_ir::parse_apple_ir(&std::fs::read("test.air")?)?;
 
         Ok(Self {
             window,
@@ -76,6 +76,8 @@ impl State {
                     alpha: 1.0,
                 },
                 texture: drawable.clone(),
+                level: 0,
+                slice: 0,
             }],
             ..Default::default()
         };
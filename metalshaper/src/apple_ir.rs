@@ -0,0 +1,852 @@
+use std::{collections::HashMap, io::Cursor};
+
+use anyhow::{Result, anyhow};
+use bitstream_io::{BitRead, BitReader, LittleEndian};
+
+use crate::debug_info::AIRMetadataRecord;
+use crate::version::{AIRIdentification, AIRModuleVersion};
+
+type AIRBitReader<'a> = BitReader<Cursor<&'a [u8]>, LittleEndian>;
+
+/// Magic of the wrapper header `metal -c` puts in front of the bitcode.
+pub const AIR_WRAPPER_MAGIC: u32 = 0x0B17_C0DE;
+/// `BC 0xC0DE`, the magic of LLVM bitcode.
+pub const BITCODE_MAGIC: u32 = 0xDEC0_4342;
+
+/// Abbreviation ids every block understands.
+const END_BLOCK: u32 = 0;
+const ENTER_SUBBLOCK: u32 = 1;
+const DEFINE_ABBREV: u32 = 2;
+const UNABBREV_RECORD: u32 = 3;
+
+/// `BLOCKINFO_CODE_SETBID`, which selects the block later abbreviations in
+/// a `BLOCKINFO` block apply to.
+const BLOCKINFO_CODE_SETBID: u32 = 1;
+
+/// Record codes of `MODULE_BLOCK`.
+pub(crate) mod module_code {
+    pub const VERSION: u32 = 1;
+    pub const TRIPLE: u32 = 2;
+    pub const GLOBALVAR: u32 = 7;
+    pub const FUNCTION: u32 = 8;
+    pub const ALIAS_OLD: u32 = 9;
+    pub const ALIAS: u32 = 14;
+    pub const IFUNC: u32 = 15;
+
+    /// Whether a record defines a global value id. Those are numbered in
+    /// record order, before any constant.
+    pub fn defines_global_value(code: u32) -> bool {
+        matches!(code, GLOBALVAR | FUNCTION | ALIAS_OLD | ALIAS | IFUNC)
+    }
+}
+
+/// `STRTAB_BLOB`, the string table global value names point into.
+const STRTAB_BLOB: u32 = 1;
+
+/// `METADATA_STRINGS`, whose strings are packed into a blob.
+const METADATA_STRINGS: u32 = 35;
+
+#[derive(Debug)]
+pub struct AIRSignature {
+    pub magic: u32,
+    pub version: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub cpu_type: u32,
+    pub magic2: u32,
+}
+
+impl AIRSignature {
+    pub fn new_from(
+        magic: u32,
+        version: u32,
+        offset: u32,
+        size: u32,
+        cpu_type: u32,
+        magic2: u32,
+    ) -> Self {
+        Self {
+            magic,
+            version,
+            offset,
+            size,
+            cpu_type,
+            magic2,
+        }
+    }
+
+    /// Reads the wrapper header and returns it with the bitcode it wraps.
+    pub fn parse(content: &[u8]) -> Result<(Self, &[u8])> {
+        let word = |offset: usize| -> Result<u32> {
+            content
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .ok_or_else(|| anyhow!("AIR file is truncated at byte {}.", offset))
+        };
+
+        let magic = word(0)?;
+
+        if magic != AIR_WRAPPER_MAGIC {
+            return Err(anyhow!("Not an AIR file, magic is {:#010x}.", magic));
+        }
+
+        let offset = word(8)?;
+        let size = word(12)?;
+
+        let signature = Self::new_from(
+            magic,
+            word(4)?,
+            offset,
+            size,
+            word(16)?,
+            word(offset as usize)?,
+        );
+
+        let bitcode = content
+            .get(offset as usize..offset as usize + size as usize)
+            .ok_or_else(|| anyhow!("AIR file is shorter than its header says."))?;
+
+        Ok((signature, bitcode))
+    }
+}
+
+/// A decoded AIR module: the raw block structure, plus what it was
+/// produced by.
+#[derive(Debug)]
+pub struct AIRModule {
+    pub signature: Option<AIRSignature>,
+    pub items: Vec<AIRItem>,
+    pub version: AIRModuleVersion,
+}
+
+impl AIRModule {
+    pub fn blocks(&self, ty: BlockType) -> impl Iterator<Item = &AIRBlock> {
+        self.items.iter().filter_map(move |item| match item {
+            AIRItem::Block(block) if block.ty == ty => Some(block),
+            _ => None,
+        })
+    }
+
+    pub fn module_block(&self) -> Result<&AIRBlock> {
+        self.blocks(BlockType::Module)
+            .next()
+            .ok_or_else(|| anyhow!("AIR file has no module block."))
+    }
+
+    /// The module's global variables and functions, by value id.
+    pub fn global_values(&self) -> Result<Vec<AIRGlobalValue>> {
+        let module = self.module_block()?;
+
+        let version = module
+            .records()
+            .find(|record| record.code == module_code::VERSION)
+            .and_then(|record| record.operands.first().copied())
+            .unwrap_or(0);
+
+        // Older modules keep names in the value symbol table instead.
+        if version < 2 {
+            return Err(anyhow!(
+                "Module version {} has no string table, which is not supported.",
+                version
+            ));
+        }
+
+        let strtab = self
+            .blocks(BlockType::Strtab)
+            .flat_map(AIRBlock::records)
+            .find(|record| record.code == STRTAB_BLOB)
+            .and_then(|record| record.blob.as_deref())
+            .unwrap_or_default();
+
+        module
+            .records()
+            .filter(|record| module_code::defines_global_value(record.code))
+            .enumerate()
+            .map(|(id, record)| {
+                let (offset, size) = match record.operands.as_slice() {
+                    [offset, size, ..] => (*offset as usize, *size as usize),
+                    _ => return Err(anyhow!("Global value {} has no name.", id)),
+                };
+
+                let name = strtab
+                    .get(offset..offset.saturating_add(size))
+                    .ok_or_else(|| anyhow!("Name of global value {} is out of bounds.", id))?;
+
+                // Both keep `isproto` or the initializer id as operand 4,
+                // aliases always have a definition.
+                let (kind, is_declaration) = match record.code {
+                    module_code::FUNCTION => (
+                        AIRGlobalValueKind::Function,
+                        record.operands.get(4) != Some(&0),
+                    ),
+                    module_code::GLOBALVAR => (
+                        AIRGlobalValueKind::Variable,
+                        record.operands.get(4).is_none_or(|init| *init == 0),
+                    ),
+                    _ => (AIRGlobalValueKind::Alias, false),
+                };
+
+                Ok(AIRGlobalValue {
+                    id: id as u64,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    kind,
+                    is_declaration,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRGlobalValueKind {
+    Variable,
+    Function,
+    Alias,
+}
+
+#[derive(Debug, Clone)]
+pub struct AIRGlobalValue {
+    pub id: u64,
+    pub name: String,
+    pub kind: AIRGlobalValueKind,
+    /// Whether the module only declares it, like the `air.*` intrinsics.
+    pub is_declaration: bool,
+}
+
+/// Parses an `.air` file, or bare bitcode as found in a `.metallib`.
+///
+/// The identification block is checked before the module is parsed, and
+/// the module's Metal version before anything interprets it, so files
+/// from an unsupported compiler fail with an error naming the version.
+pub fn parse_apple_ir(content: &[u8]) -> Result<AIRModule> {
+    let (signature, bitstream) = split_apple_ir(content)?;
+
+    let mut bitstream = AIRBitstream::new(bitstream);
+    let mut items = vec![];
+
+    while let Some(ty) = bitstream.next_block_type()? {
+        let identified = items.iter().any(
+            |item| matches!(item, AIRItem::Block(block) if block.ty == BlockType::Identification),
+        );
+
+        if ty == BlockType::Module && !identified {
+            return Err(anyhow!(
+                "AIR module isn't preceded by an identification block."
+            ));
+        }
+
+        let block = bitstream.parse_block(ty)?;
+
+        if ty == BlockType::Identification {
+            AIRIdentification::from_block(&block)?.check_supported()?;
+        }
+
+        items.push(AIRItem::Block(block));
+    }
+
+    let version = AIRModuleVersion::from_items(&items)?;
+    version.check_supported()?;
+
+    Ok(AIRModule {
+        signature,
+        items,
+        version,
+    })
+}
+
+/// Splits an `.air` file into its wrapper header, if it has one, and its
+/// bitstream after the bitcode magic.
+pub fn split_apple_ir(content: &[u8]) -> Result<(Option<AIRSignature>, &[u8])> {
+    let (signature, bitcode) = match content.get(0..4) {
+        Some(magic) if magic == BITCODE_MAGIC.to_le_bytes() => (None, content),
+        _ => {
+            let (signature, bitcode) = AIRSignature::parse(content)?;
+            (Some(signature), bitcode)
+        }
+    };
+
+    match bitcode.split_first_chunk::<4>() {
+        Some((magic, bitstream)) if *magic == BITCODE_MAGIC.to_le_bytes() => {
+            Ok((signature, bitstream))
+        }
+        _ => Err(anyhow!("AIR file doesn't contain LLVM bitcode.")),
+    }
+}
+
+/// Parses the top level of a bitstream, without its magic.
+pub fn parse_bitstream(bitcode: &[u8]) -> Result<Vec<AIRItem>> {
+    let mut bitstream = AIRBitstream::new(bitcode);
+    let mut items = vec![];
+
+    while let Some(ty) = bitstream.next_block_type()? {
+        items.push(AIRItem::Block(bitstream.parse_block(ty)?));
+    }
+
+    Ok(items)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockType {
+    BlockInfo,
+    Module,
+    ParamAttr,
+    ParamAttrGroup,
+    Constants,
+    Function,
+    Identification,
+    ValueSymtab,
+    Metadata,
+    MetadataAttachment,
+    Type,
+    UseList,
+    ModuleStrtab,
+    GlobalValSummary,
+    OperandBundleTags,
+    MetadataKind,
+    Strtab,
+    FullLTOGlobalValSummary,
+    Symtab,
+    SyncScopeNames,
+    Unknown(u32),
+}
+
+impl BlockType {
+    pub fn from_u32(v: u32) -> Self {
+        match v {
+            0 => Self::BlockInfo,
+            8 => Self::Module,
+            9 => Self::ParamAttr,
+            10 => Self::ParamAttrGroup,
+            11 => Self::Constants,
+            12 => Self::Function,
+            13 => Self::Identification,
+            14 => Self::ValueSymtab,
+            15 => Self::Metadata,
+            16 => Self::MetadataAttachment,
+            17 => Self::Type,
+            18 => Self::UseList,
+            19 => Self::ModuleStrtab,
+            20 => Self::GlobalValSummary,
+            21 => Self::OperandBundleTags,
+            22 => Self::MetadataKind,
+            23 => Self::Strtab,
+            24 => Self::FullLTOGlobalValSummary,
+            25 => Self::Symtab,
+            26 => Self::SyncScopeNames,
+            _ => Self::Unknown(v),
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            Self::BlockInfo => 0,
+            Self::Module => 8,
+            Self::ParamAttr => 9,
+            Self::ParamAttrGroup => 10,
+            Self::Constants => 11,
+            Self::Function => 12,
+            Self::Identification => 13,
+            Self::ValueSymtab => 14,
+            Self::Metadata => 15,
+            Self::MetadataAttachment => 16,
+            Self::Type => 17,
+            Self::UseList => 18,
+            Self::ModuleStrtab => 19,
+            Self::GlobalValSummary => 20,
+            Self::OperandBundleTags => 21,
+            Self::MetadataKind => 22,
+            Self::Strtab => 23,
+            Self::FullLTOGlobalValSummary => 24,
+            Self::Symtab => 25,
+            Self::SyncScopeNames => 26,
+            Self::Unknown(v) => *v,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AIRBlock {
+    pub ty: BlockType,
+    pub abbreviation_width: u32,
+    /// The length of the block's body in 32-bit words.
+    pub block_length: u32,
+    pub items: Vec<AIRItem>,
+}
+
+impl AIRBlock {
+    pub fn blocks(&self, ty: BlockType) -> impl Iterator<Item = &AIRBlock> {
+        self.items.iter().filter_map(move |item| match item {
+            AIRItem::Block(block) if block.ty == ty => Some(block),
+            _ => None,
+        })
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &AIRRecord> {
+        self.items.iter().filter_map(|item| match item {
+            AIRItem::Record(record) => Some(record),
+            _ => None,
+        })
+    }
+
+    /// The records of a `METADATA_BLOCK`, in the form debug info is read
+    /// from.
+    pub fn metadata_records(&self) -> Result<Vec<AIRMetadataRecord>> {
+        self.records()
+            .map(|record| match record.code {
+                METADATA_STRINGS => Ok(AIRMetadataRecord::Strings(record.metadata_strings()?)),
+                code => Ok(AIRMetadataRecord::Record {
+                    code,
+                    operands: record.operands.clone(),
+                }),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AIRItem {
+    Block(AIRBlock),
+    Abbreviation(AIRAbbreviation),
+    Record(AIRRecord),
+}
+
+#[derive(Debug, Clone)]
+pub struct AIRAbbreviation {
+    pub operands: Vec<AIROperand>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AIROperand {
+    Literal(u64),
+    Fixed(u64),
+    Variable(u64),
+    Array(Box<AIROperand>),
+    Char6,
+    Blob,
+}
+
+#[derive(Debug, Clone)]
+pub struct AIRRecord {
+    pub code: u32,
+    pub operands: Vec<u64>,
+    pub blob: Option<Vec<u8>>,
+    /// The abbreviation id the record was read with, `None` if it was
+    /// unabbreviated.
+    pub abbreviation: Option<u32>,
+}
+
+impl AIRRecord {
+    /// Reads the operands as the characters of a string, which is how
+    /// LLVM encodes most names.
+    pub fn string(&self) -> String {
+        self.operands.iter().map(|c| *c as u8 as char).collect()
+    }
+
+    /// Splits a `METADATA_STRINGS` record. Its operands are the string
+    /// count and the offset of the characters in the blob, which starts
+    /// with the lengths as a bitstream of VBR6s.
+    pub fn metadata_strings(&self) -> Result<Vec<String>> {
+        let (Some(count), Some(offset), Some(blob)) =
+            (self.operands.first(), self.operands.get(1), &self.blob)
+        else {
+            return Err(anyhow!("Malformed METADATA_STRINGS record."));
+        };
+
+        let lengths = blob
+            .get(..*offset as usize)
+            .ok_or_else(|| anyhow!("METADATA_STRINGS offset is out of bounds."))?;
+        let mut reader: AIRBitReader = BitReader::new(Cursor::new(lengths));
+
+        let mut characters = &blob[*offset as usize..];
+        let mut strings = vec![];
+
+        for _ in 0..*count {
+            let length = read_vbr(&mut reader, 6)? as usize;
+
+            let (string, rest) = characters
+                .split_at_checked(length)
+                .ok_or_else(|| anyhow!("METADATA_STRINGS is truncated."))?;
+
+            strings.push(String::from_utf8_lossy(string).into_owned());
+            characters = rest;
+        }
+
+        Ok(strings)
+    }
+}
+
+/// A reader for the top level of a bitstream, which is a sequence of
+/// blocks with an abbreviation width of 2.
+pub struct AIRBitstream<'a> {
+    reader: AIRBitReader<'a>,
+    end: u64,
+    /// Abbreviations `BLOCKINFO` defined for each block id.
+    block_info: HashMap<u32, Vec<AIRAbbreviation>>,
+}
+
+impl<'a> AIRBitstream<'a> {
+    pub fn new(bitcode: &'a [u8]) -> Self {
+        Self {
+            reader: BitReader::new(Cursor::new(bitcode)),
+            end: bitcode.len() as u64 * 8,
+            block_info: HashMap::new(),
+        }
+    }
+
+    /// Reads the id of the next top-level block, `None` at the end of the
+    /// stream. The block itself is read with `parse_block`.
+    pub fn next_block_type(&mut self) -> Result<Option<BlockType>> {
+        if self.position()? + 32 > self.end {
+            return Ok(None);
+        }
+
+        match self.reader.read_var::<u32>(2)? {
+            ENTER_SUBBLOCK => Ok(Some(BlockType::from_u32(
+                read_vbr(&mut self.reader, 8)? as u32
+            ))),
+            id => Err(anyhow!(
+                "Unexpected abbreviation id {} at the top level of the bitstream.",
+                id
+            )),
+        }
+    }
+
+    fn position(&mut self) -> Result<u64> {
+        Ok(self.reader.position_in_bits()?)
+    }
+
+    fn align_32(&mut self) -> Result<()> {
+        let missing = (32 - self.position()? % 32) % 32;
+
+        self.reader.skip(missing as u32)?;
+
+        Ok(())
+    }
+
+    /// Parses a block, right after its `ENTER_SUBBLOCK` and block id.
+    pub fn parse_block(&mut self, ty: BlockType) -> Result<AIRBlock> {
+        let abbreviation_width = read_vbr(&mut self.reader, 4)? as u32;
+
+        self.align_32()?;
+
+        let block_length = self.reader.read_var::<u32>(32)?;
+        let block_end = self.position()? + block_length as u64 * 32;
+
+        if block_end > self.end {
+            return Err(anyhow!(
+                "{:?} block is {} words long, past the end of the bitstream.",
+                ty,
+                block_length
+            ));
+        }
+
+        let items = self.parse_block_items(ty, abbreviation_width)?;
+
+        if self.position()? != block_end {
+            return Err(anyhow!(
+                "{:?} block ended at bit {}, expected {}.",
+                ty,
+                self.position()?,
+                block_end
+            ));
+        }
+
+        Ok(AIRBlock {
+            ty,
+            abbreviation_width,
+            block_length,
+            items,
+        })
+    }
+
+    fn parse_block_items(&mut self, ty: BlockType, width: u32) -> Result<Vec<AIRItem>> {
+        let mut abbreviations = self
+            .block_info
+            .get(&ty.to_u32())
+            .cloned()
+            .unwrap_or_default();
+
+        let mut items = vec![];
+        let mut current_block_id = None;
+
+        loop {
+            if width == 0 || width > 32 {
+                return Err(anyhow!("{:?} block has abbreviation width {}.", ty, width));
+            }
+
+            let id = self.reader.read_var::<u32>(width)?;
+
+            match id {
+                END_BLOCK => {
+                    self.align_32()?;
+                    return Ok(items);
+                }
+                ENTER_SUBBLOCK => {
+                    let ty = BlockType::from_u32(read_vbr(&mut self.reader, 8)? as u32);
+                    items.push(AIRItem::Block(self.parse_block(ty)?));
+                }
+                DEFINE_ABBREV => {
+                    let abbreviation = self.parse_define_abbreviation()?;
+
+                    // Inside `BLOCKINFO`, abbreviations are meant for the
+                    // block selected by the last `SETBID`.
+                    match (ty, current_block_id) {
+                        (BlockType::BlockInfo, Some(block_id)) => self
+                            .block_info
+                            .entry(block_id)
+                            .or_default()
+                            .push(abbreviation.clone()),
+                        (BlockType::BlockInfo, None) => {
+                            return Err(anyhow!("BLOCKINFO abbreviation before SETBID."));
+                        }
+                        _ => abbreviations.push(abbreviation.clone()),
+                    }
+
+                    items.push(AIRItem::Abbreviation(abbreviation));
+                }
+                UNABBREV_RECORD => {
+                    let code = read_vbr(&mut self.reader, 6)? as u32;
+                    let count = read_vbr(&mut self.reader, 6)?;
+
+                    let operands = (0..count)
+                        .map(|_| read_vbr(&mut self.reader, 6))
+                        .collect::<Result<Vec<_>>>()?;
+
+                    items.push(AIRItem::Record(AIRRecord {
+                        code,
+                        operands,
+                        blob: None,
+                        abbreviation: None,
+                    }));
+                }
+                id => {
+                    let abbreviation =
+                        abbreviations.get(id as usize - 4).cloned().ok_or_else(|| {
+                            anyhow!("{:?} block uses undefined abbreviation {}.", ty, id)
+                        })?;
+
+                    items.push(AIRItem::Record(
+                        self.parse_abbreviated_record(&abbreviation, id)?,
+                    ));
+                }
+            }
+
+            if let (BlockType::BlockInfo, Some(AIRItem::Record(record))) = (ty, items.last())
+                && record.code == BLOCKINFO_CODE_SETBID
+            {
+                current_block_id = record.operands.first().map(|v| *v as u32);
+            }
+        }
+    }
+
+    fn parse_define_abbreviation(&mut self) -> Result<AIRAbbreviation> {
+        let mut operands_left = read_vbr(&mut self.reader, 5)?;
+        let mut operands = vec![];
+
+        while operands_left > 0 {
+            operands.push(self.parse_operand(&mut operands_left)?);
+        }
+
+        Ok(AIRAbbreviation { operands })
+    }
+
+    fn parse_operand(&mut self, operands_left: &mut u64) -> Result<AIROperand> {
+        *operands_left -= 1;
+
+        let is_literal = self.reader.read_var::<u8>(1)? == 1;
+
+        if is_literal {
+            return Ok(AIROperand::Literal(read_vbr(&mut self.reader, 8)?));
+        }
+
+        Ok(match self.reader.read_var::<u8>(3)? {
+            1 => AIROperand::Fixed(read_vbr(&mut self.reader, 5)?),
+            2 => AIROperand::Variable(read_vbr(&mut self.reader, 5)?),
+            3 => {
+                if *operands_left == 0 {
+                    return Err(anyhow!("Array abbreviation without an element type."));
+                }
+
+                AIROperand::Array(Box::new(self.parse_operand(operands_left)?))
+            }
+            4 => AIROperand::Char6,
+            5 => AIROperand::Blob,
+            encoding => {
+                return Err(anyhow!(
+                    "Abbreviation encoding {} not implemented.",
+                    encoding
+                ));
+            }
+        })
+    }
+
+    fn parse_abbreviated_record(
+        &mut self,
+        abbreviation: &AIRAbbreviation,
+        id: u32,
+    ) -> Result<AIRRecord> {
+        let mut values = vec![];
+        let mut blob = None;
+
+        for operand in &abbreviation.operands {
+            match operand {
+                AIROperand::Array(element) => {
+                    let length = read_vbr(&mut self.reader, 6)?;
+
+                    for _ in 0..length {
+                        values.push(read_scalar_operand(&mut self.reader, element)?);
+                    }
+                }
+                AIROperand::Blob => {
+                    let length = read_vbr(&mut self.reader, 6)? as usize;
+                    self.align_32()?;
+
+                    let mut bytes = vec![0; length];
+                    self.reader.read_bytes(&mut bytes)?;
+                    self.align_32()?;
+
+                    blob = Some(bytes);
+                }
+                scalar => values.push(read_scalar_operand(&mut self.reader, scalar)?),
+            }
+        }
+
+        if values.is_empty() {
+            return Err(anyhow!("Abbreviation {} has no record code.", id));
+        }
+
+        let code = values.remove(0) as u32;
+
+        Ok(AIRRecord {
+            code,
+            operands: values,
+            blob,
+            abbreviation: Some(id),
+        })
+    }
+}
+
+fn read_vbr(reader: &mut AIRBitReader, width: u64) -> Result<u64> {
+    if !(1..=32).contains(&width) {
+        // This is `MaxChunkSize` in LLVM
+        return Err(anyhow!("VBR Overflowed!"));
+    }
+    let test_bit = 1u64 << (width - 1);
+    let mask = test_bit - 1;
+    let mut res = 0;
+    let mut offset = 0;
+    loop {
+        let next = reader.read_var::<u64>(width as u32)?;
+        res |= (next & mask) << offset;
+        offset += width - 1;
+        // 64 may not be divisible by width
+        if offset > 63 + width {
+            return Err(anyhow!("VBR Overflowed!"));
+        }
+        if next & test_bit == 0 {
+            break;
+        }
+    }
+    Ok(res)
+}
+
+fn read_char6(reader: &mut AIRBitReader) -> Result<u64> {
+    let value = reader.read_var::<u8>(6)?;
+
+    Ok(u64::from(match value {
+        0..=25 => value + b'a',
+        26..=51 => value + (b'A' - 26),
+        52..=61 => value - (52 - b'0'),
+        62 => b'.',
+        63 => b'_',
+        _ => return Err(anyhow!("Not a valid 6-bit character!")),
+    }))
+}
+
+fn read_scalar_operand(reader: &mut AIRBitReader, op: &AIROperand) -> Result<u64> {
+    Ok(match op {
+        AIROperand::Literal(value) => *value,
+        AIROperand::Fixed(0) | AIROperand::Variable(0) => 0,
+        AIROperand::Fixed(width) => reader.read_var::<u64>(*width as u32)?,
+        AIROperand::Variable(width) => read_vbr(reader, *width)?,
+        AIROperand::Char6 => read_char6(reader)?,
+        _ => {
+            return Err(anyhow!(
+                "`{:?}` is not a Scalar operand, use `read_payload_operand` instead.",
+                op
+            ));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_air() -> Vec<u8> {
+        std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../test.air")).unwrap()
+    }
+
+    #[test]
+    fn parses_test_air() {
+        let module = parse_apple_ir(&test_air()).unwrap();
+
+        assert_eq!(module.signature.as_ref().unwrap().magic, AIR_WRAPPER_MAGIC);
+        assert!(matches!(
+            module.items.first(),
+            Some(AIRItem::Block(AIRBlock {
+                ty: BlockType::Identification,
+                ..
+            }))
+        ));
+
+        let main = module
+            .global_values()
+            .unwrap()
+            .into_iter()
+            .find(|value| value.name == "main0")
+            .unwrap();
+        assert_eq!(main.kind, AIRGlobalValueKind::Function);
+        assert!(!main.is_declaration);
+
+        let mut strings = vec![];
+        for block in module.module_block().unwrap().blocks(BlockType::Metadata) {
+            for record in block.metadata_records().unwrap() {
+                if let AIRMetadataRecord::Strings(s) = record {
+                    strings.extend(s);
+                }
+            }
+        }
+        assert!(strings.iter().any(|s| s == "air.vertex_id"));
+    }
+
+    #[test]
+    fn splits_wrapper_headers() {
+        let air = test_air();
+        let (signature, bitstream) = split_apple_ir(&air).unwrap();
+        let signature = signature.unwrap();
+        assert_eq!(signature.magic2, BITCODE_MAGIC);
+
+        // Bare bitcode, as found in metallibs.
+        let start = signature.offset as usize;
+        let bare = &air[start..start + signature.size as usize];
+        let (none, bare_bitstream) = split_apple_ir(bare).unwrap();
+        assert!(none.is_none());
+        assert_eq!(bare_bitstream, bitstream);
+
+        let error = |content: &[u8]| split_apple_ir(content).unwrap_err().to_string();
+        assert!(error(b"\x7fELF....").starts_with("Not an AIR file"));
+        assert!(error(&air[..12]).contains("truncated"));
+        assert!(error(&air[..start + 16]).contains("shorter than its header"));
+    }
+
+    #[test]
+    fn numbers_block_types() {
+        for id in 0..32 {
+            assert_eq!(BlockType::from_u32(id).to_u32(), id);
+        }
+
+        assert_eq!(BlockType::from_u32(13), BlockType::Identification);
+        assert_eq!(BlockType::from_u32(7), BlockType::Unknown(7));
+    }
+}
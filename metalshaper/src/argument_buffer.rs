@@ -0,0 +1,561 @@
+use anyhow::{Result, anyhow};
+
+use crate::AIRAddressSpace;
+use crate::reflection::{
+    AIRArgumentReflection, AIRFunctionReflection, SPIRVArgumentBlock, SPIRVArgumentBufferEntry,
+    SPIRVArgumentBufferLayout, SPIRVArgumentLocation, SPIRVDescriptorType,
+};
+use crate::spirv::SPIRVTargetFeatures;
+use crate::types::AIRType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRTextureAccess {
+    Sample,
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRArgumentKind {
+    /// A `device` or `constant` pointer.
+    Buffer(AIRAddressSpace),
+    Texture(AIRTextureAccess),
+    Sampler,
+    /// Plain data stored inline, like a `float4` or a nested struct.
+    Data {
+        size: u32,
+        alignment: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRArgumentBufferMember {
+    /// The member's `[[id(n)]]`.
+    pub id: u32,
+    pub name: String,
+    pub kind: AIRArgumentKind,
+    /// 1 for a single resource, 0 for an unbounded array.
+    pub array_length: u32,
+}
+
+/// A struct of resources passed through a single `device`/`constant`
+/// pointer parameter at `[[buffer(index)]]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRArgumentBuffer {
+    pub name: String,
+    pub index: u32,
+    pub members: Vec<AIRArgumentBufferMember>,
+}
+
+impl AIRArgumentBuffer {
+    /// Builds an argument buffer from the members of a pointer parameter's
+    /// struct, as described by its `air.struct_type_info` metadata. Structs
+    /// made only of plain data are ordinary buffers and yield `None`.
+    pub fn detect(name: &str, index: u32, members: Vec<AIRArgumentBufferMember>) -> Option<Self> {
+        let has_resources = members
+            .iter()
+            .any(|m| !matches!(m.kind, AIRArgumentKind::Data { .. }));
+
+        if !has_resources {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_string(),
+            index,
+            members,
+        })
+    }
+
+    /// The argument buffer behind an `air.indirect_buffer` argument, `None`
+    /// for any other argument.
+    ///
+    /// Resources carry their `[[id(n)]]` in their `air.indirect_argument`.
+    /// Plain data has none and, as in Metal, takes the id after the
+    /// previous member's.
+    pub fn from_reflection(argument: &AIRArgumentReflection) -> Result<Option<Self>> {
+        let (Some(struct_type), Some(index)) = (&argument.struct_type, argument.location_index)
+        else {
+            return Ok(None);
+        };
+
+        if argument.kind != "air.indirect_buffer" {
+            return Ok(None);
+        }
+
+        let mut members = vec![];
+        let mut next_id = 0;
+
+        for member in &struct_type.members {
+            let array_length = member.array_length.max(1);
+
+            let (id, kind) = match &member.argument {
+                Some(resource) => {
+                    let id = resource.location_index.ok_or_else(|| {
+                        anyhow!("`{}` in `{}` has no id.", member.name, struct_type.name)
+                    })?;
+
+                    let has = |qualifier: &str| resource.qualifiers.iter().any(|q| q == qualifier);
+
+                    let kind = match resource.kind.as_str() {
+                        "air.buffer" => AIRArgumentKind::Buffer(
+                            resource.address_space.unwrap_or(AIRAddressSpace::Device),
+                        ),
+                        "air.texture" if has("air.read_write") => {
+                            AIRArgumentKind::Texture(AIRTextureAccess::ReadWrite)
+                        }
+                        "air.texture" if has("air.write") => {
+                            AIRArgumentKind::Texture(AIRTextureAccess::Write)
+                        }
+                        "air.texture" if has("air.read") => {
+                            AIRArgumentKind::Texture(AIRTextureAccess::Read)
+                        }
+                        "air.texture" => AIRArgumentKind::Texture(AIRTextureAccess::Sample),
+                        "air.sampler" => AIRArgumentKind::Sampler,
+                        kind => {
+                            return Err(anyhow!("{} in argument buffers not implemented.", kind));
+                        }
+                    };
+
+                    (id, kind)
+                }
+                None => {
+                    // Nested structs are aligned like their widest member,
+                    // which is at most a 16-byte vector.
+                    let alignment = AIRType::from_metal_name(&member.type_name)
+                        .map(|ty| ty.alignment())
+                        .unwrap_or(16);
+
+                    (
+                        next_id,
+                        AIRArgumentKind::Data {
+                            size: member.size / array_length,
+                            alignment,
+                        },
+                    )
+                }
+            };
+
+            next_id = id + array_length;
+
+            members.push(AIRArgumentBufferMember {
+                id,
+                name: member.name.clone(),
+                kind,
+                array_length,
+            });
+        }
+
+        Ok(Self::detect(
+            argument.name.as_deref().unwrap_or(&struct_type.name),
+            index,
+            members,
+        ))
+    }
+
+    /// Assigns every member a place in descriptor set `set`.
+    ///
+    /// Textures and samplers always become descriptors. Buffers become
+    /// device addresses in the argument block if the device supports them,
+    /// and descriptors otherwise. Plain data is copied into the block.
+    pub fn layout(
+        &self,
+        features: &SPIRVTargetFeatures,
+        set: u32,
+    ) -> Result<SPIRVArgumentBufferLayout> {
+        let mut members = self.members.iter().collect::<Vec<_>>();
+        members.sort_by_key(|m| m.id);
+
+        let uses_block = members.iter().any(|m| match m.kind {
+            AIRArgumentKind::Data { .. } => true,
+            AIRArgumentKind::Buffer(_) => features.buffer_device_address,
+            _ => false,
+        });
+
+        let mut block_size = 0_u32;
+        let mut binding = if uses_block { 1 } else { 0 };
+        let mut unbounded: Option<&str> = None;
+        let mut entries = vec![];
+
+        for member in members {
+            // Only the highest binding of a set may have a variable count.
+            if let Some(name) = unbounded {
+                return Err(anyhow!(
+                    "`{}` in `{}` is unbounded and must be the last member.",
+                    name,
+                    self.name
+                ));
+            }
+
+            let count = match member.array_length {
+                0 => {
+                    if !features.runtime_descriptor_arrays {
+                        return Err(anyhow!(
+                            "`{}` in `{}` needs runtime descriptor arrays, which this device lacks.",
+                            member.name,
+                            self.name
+                        ));
+                    }
+
+                    unbounded = Some(&member.name);
+                    None
+                }
+                n => Some(n),
+            };
+
+            let location = match (member.kind, count) {
+                (AIRArgumentKind::Buffer(_), Some(n)) if features.buffer_device_address => {
+                    let offset = block_size.next_multiple_of(8);
+                    block_size = offset + 8 * n;
+
+                    SPIRVArgumentLocation::Address { offset }
+                }
+                (AIRArgumentKind::Data { size, alignment }, Some(n)) => {
+                    let offset = block_size.next_multiple_of(alignment.max(1));
+                    block_size = offset + size * n;
+
+                    SPIRVArgumentLocation::Data {
+                        offset,
+                        size: size * n,
+                    }
+                }
+                (AIRArgumentKind::Data { .. }, None) => {
+                    return Err(anyhow!(
+                        "Unbounded data array `{}` in `{}` not implemented.",
+                        member.name,
+                        self.name
+                    ));
+                }
+                (kind, count) => {
+                    let descriptor_type = match kind {
+                        // Metal buffers have no static size, so even
+                        // `constant` ones are runtime arrays in storage
+                        // buffers.
+                        AIRArgumentKind::Buffer(_) => SPIRVDescriptorType::StorageBuffer,
+                        AIRArgumentKind::Texture(
+                            AIRTextureAccess::Sample | AIRTextureAccess::Read,
+                        ) => SPIRVDescriptorType::SampledImage,
+                        AIRArgumentKind::Texture(_) => SPIRVDescriptorType::StorageImage,
+                        _ => SPIRVDescriptorType::Sampler,
+                    };
+
+                    binding += 1;
+
+                    SPIRVArgumentLocation::Descriptor {
+                        binding: binding - 1,
+                        descriptor_type,
+                        count,
+                    }
+                }
+            };
+
+            entries.push(SPIRVArgumentBufferEntry {
+                id: member.id,
+                name: member.name.clone(),
+                location,
+            });
+        }
+
+        Ok(SPIRVArgumentBufferLayout {
+            index: self.index,
+            set,
+            block: uses_block.then(|| SPIRVArgumentBlock {
+                binding: 0,
+                size: block_size.next_multiple_of(16),
+            }),
+            entries,
+        })
+    }
+}
+
+/// The layouts of the argument buffers of an entry point, in the order of
+/// its arguments. Set 0 holds the other buffers, so the argument buffers
+/// take sets 1 and up.
+///
+/// The translator has no `PhysicalStorageBuffer` pointers yet, so buffers
+/// are descriptors even on devices with buffer device addresses.
+pub fn argument_buffer_layouts(
+    reflection: &AIRFunctionReflection,
+    features: &SPIRVTargetFeatures,
+) -> Result<Vec<(AIRArgumentBuffer, SPIRVArgumentBufferLayout)>> {
+    let features = SPIRVTargetFeatures {
+        buffer_device_address: false,
+        ..features.clone()
+    };
+    let mut layouts = vec![];
+
+    for argument in &reflection.arguments {
+        let Some(argument_buffer) = AIRArgumentBuffer::from_reflection(argument)? else {
+            continue;
+        };
+
+        let layout = argument_buffer.layout(&features, layouts.len() as u32 + 1)?;
+        layouts.push((argument_buffer, layout));
+    }
+
+    Ok(layouts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflection::{AIRStructMemberReflection, AIRStructReflection};
+
+    fn member(id: u32, name: &str, kind: AIRArgumentKind) -> AIRArgumentBufferMember {
+        AIRArgumentBufferMember {
+            id,
+            name: name.to_string(),
+            kind,
+            array_length: 1,
+        }
+    }
+
+    fn resource(kind: &str, id: u32, qualifiers: &[&str]) -> AIRArgumentReflection {
+        AIRArgumentReflection {
+            index: Some(0),
+            kind: kind.to_string(),
+            name: None,
+            type_name: None,
+            location_index: Some(id),
+            address_space: None,
+            type_size: None,
+            type_alignment: None,
+            struct_type: None,
+            qualifiers: qualifiers.iter().map(|q| q.to_string()).collect(),
+        }
+    }
+
+    fn struct_member(
+        name: &str,
+        type_name: &str,
+        size: u32,
+        argument: Option<AIRArgumentReflection>,
+    ) -> AIRStructMemberReflection {
+        AIRStructMemberReflection {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            offset: 0,
+            size,
+            array_length: 0,
+            struct_type: None,
+            argument,
+        }
+    }
+
+    fn material() -> AIRArgumentBuffer {
+        AIRArgumentBuffer::detect(
+            "material",
+            2,
+            vec![
+                member(
+                    3,
+                    "tint",
+                    AIRArgumentKind::Data {
+                        size: 16,
+                        alignment: 16,
+                    },
+                ),
+                member(
+                    0,
+                    "albedo",
+                    AIRArgumentKind::Texture(AIRTextureAccess::Sample),
+                ),
+                member(1, "sampler", AIRArgumentKind::Sampler),
+                member(
+                    2,
+                    "lights",
+                    AIRArgumentKind::Buffer(AIRAddressSpace::Constant),
+                ),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn lays_out_descriptors_and_block() {
+        let layout = material()
+            .layout(&SPIRVTargetFeatures::default(), 1)
+            .unwrap();
+
+        assert_eq!(
+            layout.block,
+            Some(SPIRVArgumentBlock {
+                binding: 0,
+                size: 16
+            })
+        );
+        assert_eq!(
+            layout.entry(0).unwrap().location,
+            SPIRVArgumentLocation::Descriptor {
+                binding: 1,
+                descriptor_type: SPIRVDescriptorType::SampledImage,
+                count: Some(1),
+            }
+        );
+        assert_eq!(
+            layout.entry(1).unwrap().location,
+            SPIRVArgumentLocation::Descriptor {
+                binding: 2,
+                descriptor_type: SPIRVDescriptorType::Sampler,
+                count: Some(1),
+            }
+        );
+        assert_eq!(
+            layout.entry(2).unwrap().location,
+            SPIRVArgumentLocation::Descriptor {
+                binding: 3,
+                descriptor_type: SPIRVDescriptorType::StorageBuffer,
+                count: Some(1),
+            }
+        );
+        assert_eq!(
+            layout.entry(3).unwrap().location,
+            SPIRVArgumentLocation::Data {
+                offset: 0,
+                size: 16
+            }
+        );
+    }
+
+    #[test]
+    fn stores_buffer_addresses_in_the_block() {
+        let features = SPIRVTargetFeatures {
+            buffer_device_address: true,
+            ..Default::default()
+        };
+        let layout = material().layout(&features, 1).unwrap();
+
+        assert_eq!(
+            layout.entry(2).unwrap().location,
+            SPIRVArgumentLocation::Address { offset: 0 }
+        );
+        assert_eq!(
+            layout.entry(3).unwrap().location,
+            SPIRVArgumentLocation::Data {
+                offset: 16,
+                size: 16
+            }
+        );
+        assert_eq!(layout.block.unwrap().size, 32);
+    }
+
+    #[test]
+    fn only_the_last_member_is_unbounded() {
+        let features = SPIRVTargetFeatures {
+            runtime_descriptor_arrays: true,
+            ..Default::default()
+        };
+        let textures = |id| AIRArgumentBufferMember {
+            array_length: 0,
+            ..member(
+                id,
+                "textures",
+                AIRArgumentKind::Texture(AIRTextureAccess::Read),
+            )
+        };
+
+        let last = AIRArgumentBuffer::detect(
+            "last",
+            0,
+            vec![member(0, "sampler", AIRArgumentKind::Sampler), textures(1)],
+        )
+        .unwrap();
+        assert_eq!(
+            last.layout(&features, 1)
+                .unwrap()
+                .entry(1)
+                .unwrap()
+                .location,
+            SPIRVArgumentLocation::Descriptor {
+                binding: 1,
+                descriptor_type: SPIRVDescriptorType::SampledImage,
+                count: None,
+            }
+        );
+        assert!(last.layout(&SPIRVTargetFeatures::default(), 1).is_err());
+
+        let first = AIRArgumentBuffer::detect(
+            "first",
+            0,
+            vec![textures(0), member(1, "sampler", AIRArgumentKind::Sampler)],
+        )
+        .unwrap();
+        assert!(first.layout(&features, 1).is_err());
+    }
+
+    #[test]
+    fn reads_members_from_reflection() {
+        let mut argument = resource("air.indirect_buffer", 4, &[]);
+        argument.name = Some("material".to_string());
+        argument.struct_type = Some(AIRStructReflection {
+            name: "Material".to_string(),
+            size: Some(48),
+            members: vec![
+                struct_member(
+                    "albedo",
+                    "texture2d<float>",
+                    8,
+                    Some(resource("air.texture", 5, &["air.sample"])),
+                ),
+                struct_member(
+                    "output",
+                    "texture2d<float, access::write>",
+                    8,
+                    Some(resource("air.texture", 6, &["air.write"])),
+                ),
+                struct_member("tint", "float4", 16, None),
+                struct_member("weights", "float", 8, None),
+            ],
+        });
+        argument.struct_type.as_mut().unwrap().members[3].array_length = 2;
+
+        let argument_buffer = AIRArgumentBuffer::from_reflection(&argument)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(argument_buffer.name, "material");
+        assert_eq!(argument_buffer.index, 4);
+        assert_eq!(
+            argument_buffer.members,
+            vec![
+                member(
+                    5,
+                    "albedo",
+                    AIRArgumentKind::Texture(AIRTextureAccess::Sample)
+                ),
+                member(
+                    6,
+                    "output",
+                    AIRArgumentKind::Texture(AIRTextureAccess::Write)
+                ),
+                // Plain data takes the ids after the previous member.
+                member(
+                    7,
+                    "tint",
+                    AIRArgumentKind::Data {
+                        size: 16,
+                        alignment: 16
+                    }
+                ),
+                AIRArgumentBufferMember {
+                    array_length: 2,
+                    ..member(
+                        8,
+                        "weights",
+                        AIRArgumentKind::Data {
+                            size: 4,
+                            alignment: 4
+                        }
+                    )
+                },
+            ]
+        );
+
+        // Structs of plain data and other arguments are ordinary buffers.
+        argument.struct_type.as_mut().unwrap().members.drain(..2);
+        assert_eq!(AIRArgumentBuffer::from_reflection(&argument).unwrap(), None);
+        argument.kind = "air.buffer".to_string();
+        assert_eq!(AIRArgumentBuffer::from_reflection(&argument).unwrap(), None);
+    }
+}
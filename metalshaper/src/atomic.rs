@@ -0,0 +1,513 @@
+use anyhow::{Result, anyhow};
+
+use crate::AIRAddressSpace;
+use crate::spirv::{
+    SPIRVCapability, SPIRVModule, SPIRVOp, SPIRVScope, SPIRVStorageClass, SPIRVTargetFeatures,
+    memory_semantics,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRAtomicKind {
+    Load,
+    Store,
+    Exchange,
+    CompareExchange,
+    Add,
+    Sub,
+    Min,
+    Max,
+    And,
+    Or,
+    Xor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRAtomicType {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+}
+
+impl AIRAtomicType {
+    pub fn width(&self) -> u32 {
+        match self {
+            Self::Int { width, .. } | Self::Float { width } => *width,
+        }
+    }
+}
+
+/// Where the atomic operates: plain memory or a texel of a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRAtomicTarget {
+    Memory(AIRAddressSpace),
+    Texture,
+}
+
+impl AIRAtomicTarget {
+    pub fn scope(&self) -> SPIRVScope {
+        match self {
+            Self::Memory(address_space) => address_space.scope(),
+            Self::Texture => SPIRVScope::Device,
+        }
+    }
+
+    pub fn memory_semantics(&self) -> u32 {
+        match self {
+            Self::Memory(address_space) => address_space.memory_semantics(),
+            Self::Texture => memory_semantics::IMAGE_MEMORY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AIRAtomic {
+    pub kind: AIRAtomicKind,
+    pub target: AIRAtomicTarget,
+    pub ty: AIRAtomicType,
+}
+
+impl AIRAtomic {
+    /// Decodes an atomic intrinsic name such as `air.atomic.global.add.u.i32`,
+    /// `air.atomic.local.cmpxchg.weak.i32` or `air.atomic.texture_2d.max.s.i64`.
+    pub fn from_intrinsic(name: &str) -> Result<Self> {
+        let parts = name.split('.').collect::<Vec<_>>();
+
+        if parts.len() < 5 || parts[0] != "air" || parts[1] != "atomic" {
+            return Err(anyhow!("`{}` is not an AIR atomic intrinsic.", name));
+        }
+
+        let target = match parts[2] {
+            "global" => AIRAtomicTarget::Memory(AIRAddressSpace::Device),
+            "local" => AIRAtomicTarget::Memory(AIRAddressSpace::Threadgroup),
+            t if t.starts_with("texture") => AIRAtomicTarget::Texture,
+            t => return Err(anyhow!("Atomic target `{}` not implemented.", t)),
+        };
+
+        let kind = match parts[3] {
+            "load" => AIRAtomicKind::Load,
+            "store" => AIRAtomicKind::Store,
+            "xchg" => AIRAtomicKind::Exchange,
+            "cmpxchg" => AIRAtomicKind::CompareExchange,
+            "add" => AIRAtomicKind::Add,
+            "sub" => AIRAtomicKind::Sub,
+            "min" => AIRAtomicKind::Min,
+            "max" => AIRAtomicKind::Max,
+            "and" => AIRAtomicKind::And,
+            "or" => AIRAtomicKind::Or,
+            "xor" => AIRAtomicKind::Xor,
+            k => return Err(anyhow!("Atomic operation `{}` not implemented.", k)),
+        };
+
+        // Only the sign marker (`s`/`u`) matters between the operation and the
+        // type, `weak`/`strong` on cmpxchg has no SPIR-V equivalent.
+        let signed = parts[4..parts.len() - 1].contains(&"s");
+
+        let ty = match parts.last() {
+            Some(&"i32") => AIRAtomicType::Int { width: 32, signed },
+            Some(&"i64") => AIRAtomicType::Int { width: 64, signed },
+            Some(&"f32") => AIRAtomicType::Float { width: 32 },
+            Some(&"f64") => AIRAtomicType::Float { width: 64 },
+            t => {
+                return Err(anyhow!(
+                    "Atomic type {:?} of `{}` not implemented.",
+                    t,
+                    name
+                ));
+            }
+        };
+
+        Ok(Self { kind, target, ty })
+    }
+
+    /// Builds the equivalent of an LLVM `atomicrmw` instruction, `operation`
+    /// being the `bitc::RMWOperations` code from the bitcode record.
+    pub fn from_atomic_rmw(
+        operation: u64,
+        address_space: AIRAddressSpace,
+        ty: AIRAtomicType,
+    ) -> Result<Self> {
+        let with_sign = |signed: bool| match ty {
+            AIRAtomicType::Int { width, .. } => AIRAtomicType::Int { width, signed },
+            float => float,
+        };
+
+        let (kind, ty) = match operation {
+            0 => (AIRAtomicKind::Exchange, ty),
+            1 | 11 => (AIRAtomicKind::Add, ty),
+            2 | 12 => (AIRAtomicKind::Sub, ty),
+            3 => (AIRAtomicKind::And, ty),
+            5 => (AIRAtomicKind::Or, ty),
+            6 => (AIRAtomicKind::Xor, ty),
+            7 => (AIRAtomicKind::Max, with_sign(true)),
+            8 => (AIRAtomicKind::Min, with_sign(true)),
+            9 => (AIRAtomicKind::Max, with_sign(false)),
+            10 => (AIRAtomicKind::Min, with_sign(false)),
+            _ => {
+                return Err(anyhow!(
+                    "atomicrmw operation {} not implemented.",
+                    operation
+                ));
+            }
+        };
+
+        Ok(Self {
+            kind,
+            target: AIRAtomicTarget::Memory(address_space),
+            ty,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRMemoryOrder {
+    Relaxed,
+    Acquire,
+    Release,
+    AcquireRelease,
+    SequentiallyConsistent,
+}
+
+impl AIRMemoryOrder {
+    /// Decodes the `memory_order` argument of the `air.atomic.*` intrinsics,
+    /// which follows the C++ `std::memory_order` numbering.
+    pub fn from_metal(v: u64) -> Result<Self> {
+        Ok(match v {
+            0 => Self::Relaxed,
+            // `memory_order_consume` is promoted to acquire, like every C++ compiler does.
+            1 | 2 => Self::Acquire,
+            3 => Self::Release,
+            4 => Self::AcquireRelease,
+            5 => Self::SequentiallyConsistent,
+            _ => return Err(anyhow!("Memory order {} not implemented.", v)),
+        })
+    }
+
+    /// Decodes an LLVM `AtomicOrdering` as stored in `atomicrmw`/`cmpxchg` records.
+    pub fn from_llvm(v: u64) -> Result<Self> {
+        Ok(match v {
+            1 | 2 => Self::Relaxed,
+            3 => Self::Acquire,
+            4 => Self::Release,
+            5 => Self::AcquireRelease,
+            6 => Self::SequentiallyConsistent,
+            _ => return Err(anyhow!("Atomic ordering {} not implemented.", v)),
+        })
+    }
+
+    /// SPIR-V forbids release semantics on loads and acquire semantics on
+    /// stores, so the order is narrowed for them.
+    pub fn for_kind(&self, kind: AIRAtomicKind) -> Self {
+        match (kind, self) {
+            (AIRAtomicKind::Load, Self::Release) => Self::Relaxed,
+            (AIRAtomicKind::Load, Self::AcquireRelease) => Self::Acquire,
+            (AIRAtomicKind::Store, Self::Acquire) => Self::Relaxed,
+            (AIRAtomicKind::Store, Self::AcquireRelease) => Self::Release,
+            (_, order) => *order,
+        }
+    }
+
+    pub fn semantics(&self, target: AIRAtomicTarget) -> u32 {
+        let ordering = match self {
+            // Relaxed atomics must not name any storage class.
+            Self::Relaxed => return memory_semantics::NONE,
+            Self::Acquire => memory_semantics::ACQUIRE,
+            Self::Release => memory_semantics::RELEASE,
+            Self::AcquireRelease => memory_semantics::ACQUIRE_RELEASE,
+            Self::SequentiallyConsistent => memory_semantics::SEQUENTIALLY_CONSISTENT,
+        };
+
+        ordering | target.memory_semantics()
+    }
+}
+
+/// The address an atomic operates on.
+#[derive(Debug, Clone, Copy)]
+pub enum AIRAtomicAddress {
+    Pointer(u32),
+    Texel {
+        image: u32,
+        coordinate: u32,
+        sample: Option<u32>,
+    },
+}
+
+/// Result ids of the SSA values an atomic consumes.
+#[derive(Debug, Clone, Copy)]
+pub struct AIRAtomicOperands {
+    pub address: AIRAtomicAddress,
+    pub value: Option<u32>,
+    /// The expected value of a compare-exchange.
+    pub comparator: Option<u32>,
+    /// The order a compare-exchange uses when the comparison fails.
+    pub failure_order: Option<AIRMemoryOrder>,
+}
+
+/// Emits the SPIR-V for `atomic` into `module` and returns the id of the
+/// value it produces, `None` for stores.
+///
+/// Compare-exchange returns the original value only, the caller derives the
+/// success flag with an `OpIEqual` against the comparator.
+pub fn lower_atomic(
+    module: &mut SPIRVModule,
+    features: &SPIRVTargetFeatures,
+    atomic: &AIRAtomic,
+    order: AIRMemoryOrder,
+    operands: &AIRAtomicOperands,
+) -> Result<Option<u32>> {
+    require_atomic_capabilities(module, features, atomic)?;
+
+    // Like every integer the translator declares, the type is unsigned and
+    // the opcode carries the signedness.
+    let result_type = match atomic.ty {
+        AIRAtomicType::Int { width, .. } => module.type_int(width, false),
+        AIRAtomicType::Float { width } => module.type_float(width),
+    };
+
+    let pointer = match operands.address {
+        AIRAtomicAddress::Pointer(pointer) => pointer,
+        AIRAtomicAddress::Texel {
+            image,
+            coordinate,
+            sample,
+        } => {
+            let pointer_type = module.type_pointer(SPIRVStorageClass::Image, result_type);
+            let sample = match sample {
+                Some(s) => s,
+                None => module.constant_u32(0),
+            };
+
+            module.emit_value(
+                SPIRVOp::ImageTexelPointer,
+                pointer_type,
+                &[image, coordinate, sample],
+            )
+        }
+    };
+
+    let scope = module.constant_u32(atomic.target.scope() as u32);
+    let semantics = module.constant_u32(order.for_kind(atomic.kind).semantics(atomic.target));
+
+    let value = || {
+        operands
+            .value
+            .ok_or_else(|| anyhow!("{:?} requires a value operand.", atomic.kind))
+    };
+
+    let op = match (atomic.kind, atomic.ty) {
+        (AIRAtomicKind::Load, _) => {
+            return Ok(Some(module.emit_value(
+                SPIRVOp::AtomicLoad,
+                result_type,
+                &[pointer, scope, semantics],
+            )));
+        }
+        (AIRAtomicKind::Store, _) => {
+            module.emit(
+                SPIRVOp::AtomicStore,
+                vec![pointer, scope, semantics, value()?],
+            );
+            return Ok(None);
+        }
+        (AIRAtomicKind::CompareExchange, _) => {
+            let comparator = operands
+                .comparator
+                .ok_or_else(|| anyhow!("CompareExchange requires a comparator operand."))?;
+
+            // The unequal semantics may not be stronger than the equal ones
+            // and may not release.
+            let failure_order = operands
+                .failure_order
+                .unwrap_or(order)
+                .for_kind(AIRAtomicKind::Load);
+            let unequal = module.constant_u32(failure_order.semantics(atomic.target));
+
+            return Ok(Some(module.emit_value(
+                SPIRVOp::AtomicCompareExchange,
+                result_type,
+                &[pointer, scope, semantics, unequal, value()?, comparator],
+            )));
+        }
+        (AIRAtomicKind::Exchange, _) => SPIRVOp::AtomicExchange,
+        (AIRAtomicKind::Add, AIRAtomicType::Int { .. }) => SPIRVOp::AtomicIAdd,
+        (AIRAtomicKind::Add, AIRAtomicType::Float { .. }) => SPIRVOp::AtomicFAddEXT,
+        (AIRAtomicKind::Sub, AIRAtomicType::Int { .. }) => SPIRVOp::AtomicISub,
+        (AIRAtomicKind::Min, AIRAtomicType::Int { signed: true, .. }) => SPIRVOp::AtomicSMin,
+        (AIRAtomicKind::Min, AIRAtomicType::Int { signed: false, .. }) => SPIRVOp::AtomicUMin,
+        (AIRAtomicKind::Max, AIRAtomicType::Int { signed: true, .. }) => SPIRVOp::AtomicSMax,
+        (AIRAtomicKind::Max, AIRAtomicType::Int { signed: false, .. }) => SPIRVOp::AtomicUMax,
+        (AIRAtomicKind::And, AIRAtomicType::Int { .. }) => SPIRVOp::AtomicAnd,
+        (AIRAtomicKind::Or, AIRAtomicType::Int { .. }) => SPIRVOp::AtomicOr,
+        (AIRAtomicKind::Xor, AIRAtomicType::Int { .. }) => SPIRVOp::AtomicXor,
+        (kind, ty) => {
+            return Err(anyhow!("Atomic {:?} on {:?} not implemented.", kind, ty));
+        }
+    };
+
+    Ok(Some(module.emit_value(
+        op,
+        result_type,
+        &[pointer, scope, semantics, value()?],
+    )))
+}
+
+fn require_atomic_capabilities(
+    module: &mut SPIRVModule,
+    features: &SPIRVTargetFeatures,
+    atomic: &AIRAtomic,
+) -> Result<()> {
+    let unsupported = || {
+        Err(anyhow!(
+            "{:?} atomics on {:?} are not supported by this device.",
+            atomic.ty,
+            atomic.target
+        ))
+    };
+
+    match (atomic.ty, atomic.target) {
+        (AIRAtomicType::Int { width: 32, .. }, _) => {}
+        (AIRAtomicType::Int { width: 64, .. }, AIRAtomicTarget::Memory(address_space)) => {
+            let supported = features.int64
+                && match address_space {
+                    AIRAddressSpace::Threadgroup => features.shared_int64_atomics,
+                    _ => features.buffer_int64_atomics,
+                };
+
+            if !supported {
+                return unsupported();
+            }
+
+            module.capability(SPIRVCapability::Int64Atomics);
+        }
+        (AIRAtomicType::Int { width: 64, .. }, AIRAtomicTarget::Texture) => {
+            if !features.int64 || !features.image_int64_atomics {
+                return unsupported();
+            }
+
+            module.capability(SPIRVCapability::Int64Atomics);
+            module.capability(SPIRVCapability::Int64ImageEXT);
+            module.extension("SPV_EXT_shader_image_int64");
+        }
+        (AIRAtomicType::Float { width }, target) => {
+            let threadgroup = target == AIRAtomicTarget::Memory(AIRAddressSpace::Threadgroup);
+            let texture = target == AIRAtomicTarget::Texture;
+
+            let supported = match (atomic.kind, width) {
+                (AIRAtomicKind::Add, 32) if texture => features.image_float32_atomic_add,
+                (AIRAtomicKind::Add, 32) if threadgroup => features.shared_float32_atomic_add,
+                (AIRAtomicKind::Add, 32) => features.buffer_float32_atomic_add,
+                (AIRAtomicKind::Add, 64) if threadgroup => features.shared_float64_atomic_add,
+                (AIRAtomicKind::Add, 64) if !texture => features.buffer_float64_atomic_add,
+                // Loads, stores and exchanges, everything else is rejected
+                // when lowering.
+                (_, 32) if texture => features.image_float32_atomics,
+                (_, 32) if threadgroup => features.shared_float32_atomics,
+                (_, 32) => features.buffer_float32_atomics,
+                _ => false,
+            };
+
+            if !supported {
+                return unsupported();
+            }
+
+            if atomic.kind == AIRAtomicKind::Add {
+                module.capability(match width {
+                    32 => SPIRVCapability::AtomicFloat32AddEXT,
+                    _ => SPIRVCapability::AtomicFloat64AddEXT,
+                });
+                module.extension("SPV_EXT_shader_atomic_float_add");
+            }
+        }
+        _ => return unsupported(),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_intrinsic_names() {
+        assert_eq!(
+            AIRAtomic::from_intrinsic("air.atomic.global.add.u.i32").unwrap(),
+            AIRAtomic {
+                kind: AIRAtomicKind::Add,
+                target: AIRAtomicTarget::Memory(AIRAddressSpace::Device),
+                ty: AIRAtomicType::Int {
+                    width: 32,
+                    signed: false
+                },
+            }
+        );
+        assert_eq!(
+            AIRAtomic::from_intrinsic("air.atomic.local.cmpxchg.weak.i32").unwrap(),
+            AIRAtomic {
+                kind: AIRAtomicKind::CompareExchange,
+                target: AIRAtomicTarget::Memory(AIRAddressSpace::Threadgroup),
+                ty: AIRAtomicType::Int {
+                    width: 32,
+                    signed: false
+                },
+            }
+        );
+        assert_eq!(
+            AIRAtomic::from_intrinsic("air.atomic.texture_2d.max.s.i64").unwrap(),
+            AIRAtomic {
+                kind: AIRAtomicKind::Max,
+                target: AIRAtomicTarget::Texture,
+                ty: AIRAtomicType::Int {
+                    width: 64,
+                    signed: true
+                },
+            }
+        );
+        assert_eq!(
+            AIRAtomic::from_intrinsic("air.atomic.global.xchg.f32")
+                .unwrap()
+                .ty,
+            AIRAtomicType::Float { width: 32 }
+        );
+
+        assert!(AIRAtomic::from_intrinsic("air.atomic.global.add").is_err());
+        assert!(AIRAtomic::from_intrinsic("air.atomic.global.add.u.i16").is_err());
+        assert!(AIRAtomic::from_intrinsic("air.atomic.constant.add.u.i32").is_err());
+        assert!(AIRAtomic::from_intrinsic("air.wg.barrier.a.b").is_err());
+    }
+
+    #[test]
+    fn orders_memory() {
+        let device = AIRAtomicTarget::Memory(AIRAddressSpace::Device);
+        let threadgroup = AIRAtomicTarget::Memory(AIRAddressSpace::Threadgroup);
+
+        assert_eq!(
+            AIRMemoryOrder::Relaxed.semantics(device),
+            memory_semantics::NONE
+        );
+        assert_eq!(
+            AIRMemoryOrder::Acquire.semantics(device),
+            memory_semantics::ACQUIRE | memory_semantics::UNIFORM_MEMORY
+        );
+        assert_eq!(
+            AIRMemoryOrder::SequentiallyConsistent.semantics(threadgroup),
+            memory_semantics::SEQUENTIALLY_CONSISTENT | memory_semantics::WORKGROUP_MEMORY
+        );
+        assert_eq!(
+            AIRMemoryOrder::Release.semantics(AIRAtomicTarget::Texture),
+            memory_semantics::RELEASE | memory_semantics::IMAGE_MEMORY
+        );
+
+        assert_eq!(
+            AIRMemoryOrder::AcquireRelease.for_kind(AIRAtomicKind::Load),
+            AIRMemoryOrder::Acquire
+        );
+        assert_eq!(
+            AIRMemoryOrder::AcquireRelease.for_kind(AIRAtomicKind::Store),
+            AIRMemoryOrder::Release
+        );
+        assert_eq!(
+            AIRMemoryOrder::from_metal(1).unwrap(),
+            AIRMemoryOrder::Acquire
+        );
+        assert!(AIRMemoryOrder::from_llvm(0).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::spirv::{SPIRVBuiltIn, SPIRVExecutionMode, SPIRVModule, SPIRVOp};
+
+/// `VkSpecializationInfo` id of the framebuffer height, which is needed to
+/// flip `[[position]]` in fragment shaders.
+pub const SPIRV_FRAMEBUFFER_HEIGHT_SPEC_ID: u32 = 0xFFFF_0000;
+
+/// How the translator makes up for Metal's clip space Y axis pointing up,
+/// while Vulkan's points down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SPIRVYFlipMode {
+    /// Leave positions as they are. The image comes out upside down, which
+    /// is fine for targets that are sampled with flipped coordinates.
+    None,
+    /// Negate `position.y` when a vertex shader writes it.
+    Position,
+    /// Keep positions and have the runtime set a negative viewport height.
+    /// Needs Vulkan 1.1 or `VK_KHR_maintenance1`.
+    NegativeViewport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SPIRVOrigin {
+    UpperLeft,
+    LowerLeft,
+}
+
+/// Coordinate fixups applied while translating AIR.
+///
+/// Metal's fragment `[[position]]` and `[[point_coord]]` both start at the
+/// upper left of the render target, like Vulkan's `FragCoord` and
+/// `PointCoord`. They only have to be flipped when Y isn't, as the image is
+/// then stored upside down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SPIRVCoordinateFixups {
+    pub y_flip: SPIRVYFlipMode,
+    pub frag_coord_origin: SPIRVOrigin,
+    pub point_coord_origin: SPIRVOrigin,
+}
+
+impl Default for SPIRVCoordinateFixups {
+    fn default() -> Self {
+        Self::new(SPIRVYFlipMode::Position)
+    }
+}
+
+impl SPIRVCoordinateFixups {
+    /// The fixups that reproduce native Metal output with `y_flip`.
+    pub fn new(y_flip: SPIRVYFlipMode) -> Self {
+        let origin = match y_flip {
+            SPIRVYFlipMode::None => SPIRVOrigin::LowerLeft,
+            _ => SPIRVOrigin::UpperLeft,
+        };
+
+        Self {
+            y_flip,
+            frag_coord_origin: origin,
+            point_coord_origin: origin,
+        }
+    }
+
+    /// Whether a front-facing winding has to be swapped. Flipping Y either
+    /// way keeps the triangles as Metal would rasterize them, not flipping
+    /// mirrors them.
+    pub fn inverts_winding(&self) -> bool {
+        self.y_flip == SPIRVYFlipMode::None
+    }
+
+    /// Whether the runtime has to flip its viewports.
+    pub fn negative_viewport(&self) -> bool {
+        self.y_flip == SPIRVYFlipMode::NegativeViewport
+    }
+}
+
+/// Fixes up a `float4` clip space position before a vertex shader stores it.
+pub fn fixup_vertex_position(
+    module: &mut SPIRVModule,
+    fixups: &SPIRVCoordinateFixups,
+    position: u32,
+) -> u32 {
+    if fixups.y_flip != SPIRVYFlipMode::Position {
+        return position;
+    }
+
+    let float = module.type_float(32);
+    let float4 = module.type_vector(float, 4);
+
+    let y = module.emit_value(SPIRVOp::CompositeExtract, float, &[position, 1]);
+    let flipped = module.emit_value(SPIRVOp::FNegate, float, &[y]);
+
+    module.emit_value(SPIRVOp::CompositeInsert, float4, &[flipped, position, 1])
+}
+
+/// Loads the fragment's `[[position]]`.
+pub fn load_frag_coord(module: &mut SPIRVModule, fixups: &SPIRVCoordinateFixups) -> u32 {
+    let float = module.type_float(32);
+    let float4 = module.type_vector(float, 4);
+
+    let frag_coord = module.load_builtin_input(SPIRVBuiltIn::FragCoord, float4);
+
+    if fixups.frag_coord_origin == SPIRVOrigin::UpperLeft {
+        return frag_coord;
+    }
+
+    let height = module.spec_constant(
+        float,
+        &[1.0_f32.to_bits()],
+        SPIRV_FRAMEBUFFER_HEIGHT_SPEC_ID,
+    );
+
+    let y = module.emit_value(SPIRVOp::CompositeExtract, float, &[frag_coord, 1]);
+    let flipped = module.emit_value(SPIRVOp::FSub, float, &[height, y]);
+
+    module.emit_value(SPIRVOp::CompositeInsert, float4, &[flipped, frag_coord, 1])
+}
+
+/// Loads the fragment's `[[point_coord]]`.
+pub fn load_point_coord(module: &mut SPIRVModule, fixups: &SPIRVCoordinateFixups) -> u32 {
+    let float = module.type_float(32);
+    let float2 = module.type_vector(float, 2);
+
+    let point_coord = module.load_builtin_input(SPIRVBuiltIn::PointCoord, float2);
+
+    if fixups.point_coord_origin == SPIRVOrigin::UpperLeft {
+        return point_coord;
+    }
+
+    let one = module.constant_f32(1.0);
+
+    let y = module.emit_value(SPIRVOp::CompositeExtract, float, &[point_coord, 1]);
+    let flipped = module.emit_value(SPIRVOp::FSub, float, &[one, y]);
+
+    module.emit_value(SPIRVOp::CompositeInsert, float2, &[flipped, point_coord, 1])
+}
+
+/// Declares the fragment origin of a fragment entry point. Vulkan only
+/// allows `OriginUpperLeft`.
+pub fn declare_fragment_origin(module: &mut SPIRVModule, entry_point: u32) {
+    module.execution_mode(entry_point, SPIRVExecutionMode::OriginUpperLeft, &[]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(module: &SPIRVModule) -> Vec<SPIRVOp> {
+        module.functions().iter().map(|i| i.op).collect()
+    }
+
+    #[test]
+    fn flips_vertex_positions() {
+        for y_flip in [
+            SPIRVYFlipMode::None,
+            SPIRVYFlipMode::Position,
+            SPIRVYFlipMode::NegativeViewport,
+        ] {
+            let mut module = SPIRVModule::new();
+            let position = module.id();
+            let fixed =
+                fixup_vertex_position(&mut module, &SPIRVCoordinateFixups::new(y_flip), position);
+
+            match y_flip {
+                SPIRVYFlipMode::Position => {
+                    assert_ne!(fixed, position);
+                    assert_eq!(
+                        ops(&module),
+                        [
+                            SPIRVOp::CompositeExtract,
+                            SPIRVOp::FNegate,
+                            SPIRVOp::CompositeInsert
+                        ]
+                    );
+                }
+                _ => {
+                    assert_eq!(fixed, position);
+                    assert!(ops(&module).is_empty());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flips_fragment_coordinates_without_y_flip() {
+        for y_flip in [
+            SPIRVYFlipMode::None,
+            SPIRVYFlipMode::Position,
+            SPIRVYFlipMode::NegativeViewport,
+        ] {
+            let fixups = SPIRVCoordinateFixups::new(y_flip);
+            assert_eq!(fixups.inverts_winding(), y_flip == SPIRVYFlipMode::None);
+
+            let mut module = SPIRVModule::new();
+            load_frag_coord(&mut module, &fixups);
+            load_point_coord(&mut module, &fixups);
+
+            let subtractions = ops(&module)
+                .into_iter()
+                .filter(|op| *op == SPIRVOp::FSub)
+                .count();
+
+            match y_flip {
+                SPIRVYFlipMode::None => assert_eq!(subtractions, 2),
+                _ => assert_eq!(subtractions, 0),
+            }
+        }
+    }
+}
@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+
+use crate::spirv::{SPIRVModule, SPIRVOp, SPIRVTargetFeatures, string_operands};
+
+/// Record codes of LLVM's `METADATA_BLOCK` that matter for debug info.
+mod metadata_code {
+    pub const STRING_OLD: u32 = 1;
+    pub const NODE: u32 = 3;
+    pub const NAME: u32 = 4;
+    pub const KIND: u32 = 6;
+    pub const LOCATION: u32 = 7;
+    pub const NAMED_NODE: u32 = 10;
+    pub const ATTACHMENT: u32 = 11;
+    pub const FILE: u32 = 16;
+    pub const SUBPROGRAM: u32 = 21;
+    pub const LEXICAL_BLOCK: u32 = 22;
+    pub const LEXICAL_BLOCK_FILE: u32 = 23;
+    pub const GLOBAL_DECL_ATTACHMENT: u32 = 36;
+    pub const INDEX_OFFSET: u32 = 38;
+    pub const INDEX: u32 = 39;
+}
+
+/// Instructions of `NonSemantic.Shader.DebugInfo.100`.
+mod shader_debug_info {
+    pub const SET: &str = "NonSemantic.Shader.DebugInfo.100";
+    pub const VERSION: u32 = 100;
+    pub const DWARF_VERSION: u32 = 4;
+
+    pub const DEBUG_COMPILATION_UNIT: u32 = 1;
+    pub const DEBUG_SOURCE: u32 = 35;
+    pub const DEBUG_LINE: u32 = 103;
+    pub const DEBUG_NO_LINE: u32 = 104;
+}
+
+/// SPIR-V has no source language for MSL.
+const SOURCE_LANGUAGE_UNKNOWN: u32 = 0;
+
+/// The longest string a single instruction can hold, longer sources are
+/// not embedded.
+const MAX_EMBEDDED_SOURCE: usize = (u16::MAX as usize - 4) * 4;
+
+/// A record of a `METADATA_BLOCK`, with `METADATA_STRINGS` already split
+/// out of its blob.
+#[derive(Debug, Clone)]
+pub enum AIRMetadataRecord {
+    Record { code: u32, operands: Vec<u64> },
+    Strings(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AIRDebugFile {
+    pub filename: String,
+    pub directory: String,
+}
+
+impl AIRDebugFile {
+    fn from_path(path: &str) -> Self {
+        let path = Path::new(path);
+
+        Self {
+            filename: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            directory: path
+                .parent()
+                .map(|parent| parent.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn path(&self) -> String {
+        if self.directory.is_empty() || Path::new(&self.filename).is_absolute() {
+            return self.filename.clone();
+        }
+
+        Path::new(&self.directory)
+            .join(&self.filename)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRSourceLocation {
+    pub file: AIRDebugFile,
+    pub line: u32,
+    pub column: u32,
+    /// The enclosing MSL function, if known.
+    pub function: Option<String>,
+}
+
+impl fmt::Display for AIRSourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.path(), self.line, self.column)?;
+
+        if let Some(function) = &self.function {
+            write!(f, " (in `{}`)", function)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum AIRMetadata {
+    String(String),
+    Node(Vec<Option<u64>>),
+    File(AIRDebugFile),
+    Subprogram {
+        name: Option<u64>,
+        file: Option<u64>,
+    },
+    Block {
+        parent: Option<u64>,
+        file: Option<u64>,
+    },
+    Location {
+        line: u32,
+        column: u32,
+        scope: u64,
+    },
+    Other,
+}
+
+/// Debug info of a module, built from its metadata records.
+///
+/// Metadata ids are assigned in record order, references to other nodes
+/// are stored as `id + 1` with 0 meaning none.
+///
+/// Without `-g`, modules still name their source in `air.source_file_name`,
+/// and `-frecord-sources` records its text in `llvm_utils.sources`.
+#[derive(Debug, Clone, Default)]
+pub struct AIRDebugInfo {
+    metadata: Vec<AIRMetadata>,
+    source_file: Option<AIRDebugFile>,
+    /// Path to the recorded text of the file.
+    sources: HashMap<String, String>,
+}
+
+impl AIRDebugInfo {
+    /// Reads the records of the module's metadata block, optionally
+    /// followed by those of a function's.
+    pub fn from_records(records: &[AIRMetadataRecord]) -> Result<Self> {
+        let mut metadata = vec![];
+        let mut named_nodes = vec![];
+        let mut name = String::new();
+
+        for record in records {
+            let (code, operands) = match record {
+                AIRMetadataRecord::Strings(strings) => {
+                    metadata.extend(strings.iter().cloned().map(AIRMetadata::String));
+                    continue;
+                }
+                AIRMetadataRecord::Record { code, operands } => (*code, operands),
+            };
+
+            let operand = |index: usize| {
+                operands.get(index).copied().ok_or_else(|| {
+                    anyhow!("Metadata record {} is missing operand {}.", code, index)
+                })
+            };
+            let reference = |index: usize| operand(index).map(|v| v.checked_sub(1));
+
+            let node = match code {
+                metadata_code::NAME => {
+                    name = operands.iter().map(|c| *c as u8 as char).collect();
+                    continue;
+                }
+                // Unlike other nodes, the operands are stored without the offset.
+                metadata_code::NAMED_NODE => {
+                    named_nodes.push((std::mem::take(&mut name), operands.clone()));
+                    continue;
+                }
+                // These don't define a metadata id.
+                metadata_code::KIND
+                | metadata_code::ATTACHMENT
+                | metadata_code::GLOBAL_DECL_ATTACHMENT
+                | metadata_code::INDEX_OFFSET
+                | metadata_code::INDEX => continue,
+                metadata_code::STRING_OLD => {
+                    AIRMetadata::String(operands.iter().map(|c| *c as u8 as char).collect())
+                }
+                metadata_code::NODE => {
+                    AIRMetadata::Node(operands.iter().map(|v| v.checked_sub(1)).collect())
+                }
+                metadata_code::FILE => AIRMetadata::File(AIRDebugFile {
+                    filename: Self::string_of(&metadata, reference(1)?)?,
+                    directory: Self::string_of(&metadata, reference(2)?)?,
+                }),
+                metadata_code::SUBPROGRAM => AIRMetadata::Subprogram {
+                    name: reference(2)?,
+                    file: reference(4)?,
+                },
+                metadata_code::LEXICAL_BLOCK | metadata_code::LEXICAL_BLOCK_FILE => {
+                    AIRMetadata::Block {
+                        parent: reference(1)?,
+                        file: reference(2)?,
+                    }
+                }
+                // Unlike other nodes, the scope is stored without the offset.
+                metadata_code::LOCATION => AIRMetadata::Location {
+                    line: operand(1)? as u32,
+                    column: operand(2)? as u32,
+                    scope: operand(3)?,
+                },
+                _ => AIRMetadata::Other,
+            };
+
+            metadata.push(node);
+        }
+
+        let mut debug_info = Self {
+            metadata,
+            ..Default::default()
+        };
+
+        for (name, nodes) in named_nodes {
+            for node in nodes {
+                match name.as_str() {
+                    "air.source_file_name" => {
+                        let path = debug_info.node_string(node, 0)?;
+                        debug_info.source_file = Some(AIRDebugFile::from_path(&path));
+                    }
+                    "llvm_utils.sources" => {
+                        let Some(AIRMetadata::Node(files)) = debug_info.metadata.get(node as usize)
+                        else {
+                            continue;
+                        };
+
+                        for file in files.clone().into_iter().flatten() {
+                            let path = debug_info.node_string(file, 0)?;
+                            let source = debug_info.node_string(file, 1)?;
+                            debug_info.sources.insert(path, source);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(debug_info)
+    }
+
+    /// The string operand `index` of the node `id`.
+    fn node_string(&self, id: u64, index: usize) -> Result<String> {
+        match self.metadata.get(id as usize) {
+            Some(AIRMetadata::Node(operands)) => {
+                Self::string_of(&self.metadata, operands.get(index).copied().flatten())
+            }
+            _ => Err(anyhow!("Metadata {} is not a node.", id)),
+        }
+    }
+
+    fn string_of(metadata: &[AIRMetadata], id: Option<u64>) -> Result<String> {
+        match id.map(|id| metadata.get(id as usize)) {
+            None => Ok(String::new()),
+            Some(Some(AIRMetadata::String(s))) => Ok(s.clone()),
+            Some(_) => Err(anyhow!("Metadata {:?} is not a string.", id)),
+        }
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &AIRDebugFile> {
+        let files = self.metadata.iter().filter_map(|m| match m {
+            AIRMetadata::File(file) => Some(file),
+            _ => None,
+        });
+
+        self.source_file.iter().chain(files)
+    }
+
+    /// The text of the file at `path`, if it was recorded in the module.
+    pub fn source(&self, path: &str) -> Option<&str> {
+        self.sources.get(path).map(String::as_str)
+    }
+
+    /// Resolves a function block `DEBUG_LOC` record, whose scope is stored
+    /// as `id + 1`.
+    pub fn resolve(&self, line: u32, column: u32, scope: u64) -> Option<AIRSourceLocation> {
+        let mut scope = scope.checked_sub(1);
+        let mut file = None;
+        let mut function = None;
+
+        while let Some(id) = scope {
+            match self.metadata.get(id as usize)? {
+                AIRMetadata::Subprogram { name, file: f } => {
+                    file = file.or(*f);
+                    function = name.and_then(|n| Self::string_of(&self.metadata, Some(n)).ok());
+                    scope = None;
+                }
+                AIRMetadata::Block { parent, file: f } => {
+                    file = file.or(*f);
+                    scope = *parent;
+                }
+                _ => return None,
+            }
+        }
+
+        let file = match self.metadata.get(file? as usize)? {
+            AIRMetadata::File(file) => file.clone(),
+            _ => return None,
+        };
+
+        Some(AIRSourceLocation {
+            file,
+            line,
+            column,
+            function,
+        })
+    }
+
+    /// Resolves a `DILocation` node, as attached to instructions with
+    /// `!dbg`.
+    pub fn location(&self, id: u64) -> Option<AIRSourceLocation> {
+        match self.metadata.get(id as usize)? {
+            AIRMetadata::Location {
+                line,
+                column,
+                scope,
+            } => self.resolve(*line, *column, scope + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Prefixes a translation error with the MSL source location it came from.
+pub fn cite_source<T>(result: Result<T>, location: Option<&AIRSourceLocation>) -> Result<T> {
+    match location {
+        Some(location) => result.map_err(|e| e.context(format!("{}", location))),
+        None => result,
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SPIRVDebugInfoLevel {
+    #[default]
+    None,
+    /// `OpSource` and `OpLine`, which every SPIR-V consumer understands.
+    Lines,
+    /// `Lines` plus `NonSemantic.Shader.DebugInfo.100`, which lets tools
+    /// like RenderDoc step through the MSL source.
+    Full,
+}
+
+/// Emits source locations into a translated module. The translator calls
+/// `line` before every instruction and `start_block` after every label, as
+/// `OpLine` doesn't carry over to the next block.
+#[derive(Debug)]
+pub struct SPIRVDebugInfoEmitter {
+    level: SPIRVDebugInfoLevel,
+    set: Option<u32>,
+    /// File path to its `OpString` and `DebugSource`.
+    files: HashMap<String, (u32, Option<u32>)>,
+    current: Option<(String, u32, u32)>,
+}
+
+impl SPIRVDebugInfoEmitter {
+    pub fn new(level: SPIRVDebugInfoLevel, features: &SPIRVTargetFeatures) -> Result<Self> {
+        if level == SPIRVDebugInfoLevel::Full && !features.non_semantic_info {
+            return Err(anyhow!(
+                "Full debug info needs VK_KHR_shader_non_semantic_info, which this device lacks."
+            ));
+        }
+
+        Ok(Self {
+            level,
+            set: None,
+            files: HashMap::new(),
+            current: None,
+        })
+    }
+
+    /// Declares every source file of `debug_info`. With `embed_source`, the
+    /// recorded text of the files is embedded, or else the files are read
+    /// from disk if they still exist.
+    pub fn declare_sources(
+        &mut self,
+        module: &mut SPIRVModule,
+        debug_info: &AIRDebugInfo,
+        embed_source: bool,
+    ) {
+        if self.level == SPIRVDebugInfoLevel::None {
+            return;
+        }
+
+        for file in debug_info.files() {
+            let path = file.path();
+
+            if self.files.contains_key(&path) {
+                continue;
+            }
+
+            let source = embed_source
+                .then(|| {
+                    debug_info
+                        .source(&path)
+                        .map(str::to_string)
+                        .or_else(|| std::fs::read_to_string(&path).ok())
+                })
+                .flatten()
+                .filter(|s| s.len() < MAX_EMBEDDED_SOURCE);
+
+            let name = module.string(&path);
+
+            let mut operands = vec![SOURCE_LANGUAGE_UNKNOWN, 0, name];
+            if let Some(source) = &source {
+                operands.extend(string_operands(source));
+            }
+
+            module.debug(SPIRVOp::Source, operands);
+
+            let debug_source = (self.level == SPIRVDebugInfoLevel::Full)
+                .then(|| self.declare_debug_source(module, name, source.as_deref()));
+
+            self.files.insert(path, (name, debug_source));
+        }
+    }
+
+    fn declare_debug_source(
+        &mut self,
+        module: &mut SPIRVModule,
+        name: u32,
+        source: Option<&str>,
+    ) -> u32 {
+        let first = self.set.is_none();
+        let set = self.debug_info_set(module);
+
+        let mut operands = vec![name];
+        if let Some(source) = source {
+            operands.push(module.string(source));
+        }
+
+        let debug_source = module.global_ext_inst(set, shader_debug_info::DEBUG_SOURCE, &operands);
+
+        // The first file is the one the library was compiled from.
+        if first {
+            let version = module.constant_u32(shader_debug_info::VERSION);
+            let dwarf_version = module.constant_u32(shader_debug_info::DWARF_VERSION);
+            let language = module.constant_u32(SOURCE_LANGUAGE_UNKNOWN);
+
+            module.global_ext_inst(
+                set,
+                shader_debug_info::DEBUG_COMPILATION_UNIT,
+                &[version, dwarf_version, debug_source, language],
+            );
+        }
+
+        debug_source
+    }
+
+    fn debug_info_set(&mut self, module: &mut SPIRVModule) -> u32 {
+        if let Some(set) = self.set {
+            return set;
+        }
+
+        module.extension("SPV_KHR_non_semantic_info");
+        let set = module.ext_inst_import(shader_debug_info::SET);
+        self.set = Some(set);
+
+        set
+    }
+
+    /// Marks the following instructions as coming from `location`.
+    pub fn line(&mut self, module: &mut SPIRVModule, location: Option<&AIRSourceLocation>) {
+        if self.level == SPIRVDebugInfoLevel::None {
+            return;
+        }
+
+        let Some(location) = location else {
+            if self.current.take().is_some() {
+                module.emit(SPIRVOp::NoLine, vec![]);
+
+                if let Some(set) = self.set {
+                    let void = module.type_void();
+                    module.ext_inst(void, set, shader_debug_info::DEBUG_NO_LINE, &[]);
+                }
+            }
+
+            return;
+        };
+
+        let path = location.file.path();
+        let key = (path.clone(), location.line, location.column);
+
+        if self.current.as_ref() == Some(&key) {
+            return;
+        }
+
+        let (name, debug_source) = match self.files.get(&path) {
+            Some(file) => *file,
+            // Files missing from the metadata still get an `OpLine`.
+            None => {
+                let name = module.string(&path);
+                self.files.insert(path, (name, None));
+                (name, None)
+            }
+        };
+
+        module.emit(SPIRVOp::Line, vec![name, location.line, location.column]);
+
+        if let (Some(set), Some(debug_source)) = (self.set, debug_source) {
+            let void = module.type_void();
+            let line = module.constant_u32(location.line);
+            let column = module.constant_u32(location.column);
+
+            module.ext_inst(
+                void,
+                set,
+                shader_debug_info::DEBUG_LINE,
+                &[debug_source, line, line, column, column],
+            );
+        }
+
+        self.current = Some(key);
+    }
+
+    pub fn start_block(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apple_ir::{BlockType, parse_apple_ir};
+
+    fn record(code: u32, operands: &[u64]) -> AIRMetadataRecord {
+        AIRMetadataRecord::Record {
+            code,
+            operands: operands.to_vec(),
+        }
+    }
+
+    /// `main0` in `/src/test.metal`, with a location in a lexical block.
+    fn debug_info() -> AIRDebugInfo {
+        AIRDebugInfo::from_records(&[
+            // 0 to 2
+            AIRMetadataRecord::Strings(vec![
+                "main0".to_string(),
+                "test.metal".to_string(),
+                "/src".to_string(),
+            ]),
+            // 3
+            record(metadata_code::FILE, &[0, 2, 3]),
+            // 4
+            record(metadata_code::SUBPROGRAM, &[0, 0, 1, 0, 4]),
+            // 5
+            record(metadata_code::LEXICAL_BLOCK, &[0, 5, 4]),
+            // 6
+            record(metadata_code::LOCATION, &[0, 7, 3, 5]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn resolves_scopes() {
+        let debug_info = debug_info();
+        let location = debug_info.resolve(7, 3, 6).unwrap();
+
+        assert_eq!(location.file.path(), "/src/test.metal");
+        assert_eq!(location.function.as_deref(), Some("main0"));
+        assert_eq!(location.to_string(), "/src/test.metal:7:3 (in `main0`)");
+        assert_eq!(debug_info.location(6), Some(location.clone()));
+
+        // Strings and files aren't scopes.
+        assert!(debug_info.resolve(7, 3, 1).is_none());
+        assert!(debug_info.resolve(7, 3, 4).is_none());
+        assert!(debug_info.resolve(7, 3, 0).is_none());
+
+        let error = cite_source::<()>(Err(anyhow!("bad")), Some(&location)).unwrap_err();
+        assert_eq!(error.to_string(), location.to_string());
+    }
+
+    #[test]
+    fn reads_recorded_sources() {
+        let air = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../test.air")).unwrap();
+        let module = parse_apple_ir(&air).unwrap();
+
+        let mut records = vec![];
+        for block in module.module_block().unwrap().blocks(BlockType::Metadata) {
+            records.extend(block.metadata_records().unwrap());
+        }
+
+        // `test.air` was built without line tables, only with its source.
+        let debug_info = AIRDebugInfo::from_records(&records).unwrap();
+        let file = debug_info.files().next().unwrap();
+
+        assert_eq!(file.filename, "test.metal");
+        assert!(
+            debug_info
+                .source(&file.path())
+                .unwrap()
+                .contains("vertex main0_out main0(")
+        );
+    }
+
+    #[test]
+    fn emits_lines_once_per_block() {
+        let location = debug_info().location(6).unwrap();
+        let features = SPIRVTargetFeatures::default();
+
+        assert!(SPIRVDebugInfoEmitter::new(SPIRVDebugInfoLevel::Full, &features).is_err());
+
+        let mut module = SPIRVModule::new();
+        let mut emitter =
+            SPIRVDebugInfoEmitter::new(SPIRVDebugInfoLevel::Lines, &features).unwrap();
+        emitter.declare_sources(&mut module, &debug_info(), false);
+
+        emitter.line(&mut module, Some(&location));
+        emitter.line(&mut module, Some(&location));
+        emitter.start_block();
+        emitter.line(&mut module, Some(&location));
+        emitter.line(&mut module, None);
+
+        let ops: Vec<_> = module.functions().iter().map(|i| i.op).collect();
+        assert_eq!(ops, [SPIRVOp::Line, SPIRVOp::Line, SPIRVOp::NoLine]);
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{Result, anyhow};
+
+use crate::spirv::{
+    SPIRVBuiltIn, SPIRVCapability, SPIRVDecoration, SPIRVExecutionMode, SPIRVModule, SPIRVOp,
+    SPIRVStorageClass, SPIRVTargetFeatures,
+};
+
+/// The qualifier of a `[[depth(...)]]` output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRDepthQualifier {
+    Any,
+    Greater,
+    Less,
+}
+
+impl AIRDepthQualifier {
+    pub fn from_metadata(name: &str) -> Result<Self> {
+        Ok(match name {
+            "air.depth_any" | "air.any" => Self::Any,
+            "air.depth_greater" | "air.greater" => Self::Greater,
+            "air.depth_less" | "air.less" => Self::Less,
+            _ => return Err(anyhow!("Depth qualifier {} not implemented.", name)),
+        })
+    }
+}
+
+/// Built-in fragment outputs besides `[[color(n)]]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRFragmentOutput {
+    Depth(AIRDepthQualifier),
+    Stencil,
+    SampleMask,
+}
+
+/// Built-in fragment inputs handled here, `[[position]]` and
+/// `[[point_coord]]` are left to the coordinate fixups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRFragmentInput {
+    SampleId,
+    FrontFacing,
+    SampleMask,
+}
+
+impl AIRFragmentInput {
+    pub fn from_metadata(name: &str) -> Option<Self> {
+        Some(match name {
+            "air.sample_id" => Self::SampleId,
+            "air.front_facing" => Self::FrontFacing,
+            "air.sample_mask_in" | "air.sample_mask" => Self::SampleMask,
+            _ => return None,
+        })
+    }
+}
+
+/// Declares the `Output` variable for `output` and the execution modes it
+/// implies on `entry_point`. The translator stores the shader's value to the
+/// returned variable with `store_fragment_output`.
+pub fn declare_fragment_output(
+    module: &mut SPIRVModule,
+    features: &SPIRVTargetFeatures,
+    entry_point: u32,
+    output: AIRFragmentOutput,
+) -> Result<u32> {
+    let (builtin, ty) = match output {
+        AIRFragmentOutput::Depth(qualifier) => {
+            module.execution_mode(entry_point, SPIRVExecutionMode::DepthReplacing, &[]);
+
+            let mode = match qualifier {
+                AIRDepthQualifier::Any => None,
+                AIRDepthQualifier::Greater => Some(SPIRVExecutionMode::DepthGreater),
+                AIRDepthQualifier::Less => Some(SPIRVExecutionMode::DepthLess),
+            };
+
+            if let Some(mode) = mode {
+                module.execution_mode(entry_point, mode, &[]);
+            }
+
+            (SPIRVBuiltIn::FragDepth, module.type_float(32))
+        }
+        AIRFragmentOutput::Stencil => {
+            if !features.stencil_export {
+                return Err(anyhow!(
+                    "[[stencil]] needs VK_EXT_shader_stencil_export, which this device lacks."
+                ));
+            }
+
+            module.capability(SPIRVCapability::StencilExportEXT);
+            module.extension("SPV_EXT_shader_stencil_export");
+            module.execution_mode(entry_point, SPIRVExecutionMode::StencilRefReplacingEXT, &[]);
+
+            (SPIRVBuiltIn::FragStencilRefEXT, module.type_int(32, false))
+        }
+        AIRFragmentOutput::SampleMask => (SPIRVBuiltIn::SampleMask, sample_mask_type(module)),
+    };
+
+    let variable = module.variable(SPIRVStorageClass::Output, ty);
+    module.decorate(variable, SPIRVDecoration::BuiltIn, &[builtin as u32]);
+
+    Ok(variable)
+}
+
+/// Stores `value` to a variable from `declare_fragment_output`. Metal's
+/// `[[sample_mask]]` is a single `uint`, Vulkan's an array of them.
+pub fn store_fragment_output(
+    module: &mut SPIRVModule,
+    output: AIRFragmentOutput,
+    variable: u32,
+    value: u32,
+) {
+    let value = match output {
+        AIRFragmentOutput::SampleMask => {
+            let ty = sample_mask_type(module);
+            module.emit_value(SPIRVOp::CompositeConstruct, ty, &[value])
+        }
+        _ => value,
+    };
+
+    module.store(variable, value);
+}
+
+pub fn load_fragment_input(module: &mut SPIRVModule, input: AIRFragmentInput) -> u32 {
+    match input {
+        AIRFragmentInput::SampleId => {
+            module.capability(SPIRVCapability::SampleRateShading);
+
+            let uint = module.type_int(32, false);
+            module.load_builtin_input(SPIRVBuiltIn::SampleId, uint)
+        }
+        AIRFragmentInput::FrontFacing => {
+            let bool = module.type_bool();
+            module.load_builtin_input(SPIRVBuiltIn::FrontFacing, bool)
+        }
+        AIRFragmentInput::SampleMask => {
+            let uint = module.type_int(32, false);
+            let ty = sample_mask_type(module);
+
+            let mask = module.load_builtin_input(SPIRVBuiltIn::SampleMask, ty);
+            module.emit_value(SPIRVOp::CompositeExtract, uint, &[mask, 0])
+        }
+    }
+}
+
+/// Lowers `air.discard_fragment`. Returns `true` if the emitted instruction
+/// terminates the current block, in which case the translator has to start
+/// a new one before emitting anything else.
+pub fn lower_discard(module: &mut SPIRVModule, features: &SPIRVTargetFeatures) -> bool {
+    // Metal keeps discarded fragments running as helpers, so derivatives in
+    // the rest of the quad stay defined. `OpKill` ends them instead.
+    if features.demote_to_helper_invocation {
+        module.capability(SPIRVCapability::DemoteToHelperInvocationEXT);
+        module.extension("SPV_EXT_demote_to_helper_invocation");
+        module.emit(SPIRVOp::DemoteToHelperInvocationEXT, vec![]);
+
+        return false;
+    }
+
+    module.emit(SPIRVOp::Kill, vec![]);
+
+    true
+}
+
+/// Lowers `[[early_fragment_tests]]`.
+pub fn declare_early_fragment_tests(module: &mut SPIRVModule, entry_point: u32) {
+    module.execution_mode(entry_point, SPIRVExecutionMode::EarlyFragmentTests, &[]);
+}
+
+fn sample_mask_type(module: &mut SPIRVModule) -> u32 {
+    let uint = module.type_int(32, false);
+    module.type_array(uint, 1)
+}
@@ -0,0 +1,1689 @@
+use anyhow::{Result, anyhow};
+
+use crate::AIRAddressSpace;
+use crate::apple_ir::{AIRBlock, AIRModule, AIRRecord, BlockType, module_code};
+use crate::debug_info::AIRMetadataRecord;
+
+/// Record codes of `TYPE_BLOCK_ID_NEW`.
+pub(crate) mod type_code {
+    pub const VOID: u32 = 2;
+    pub const FLOAT: u32 = 3;
+    pub const DOUBLE: u32 = 4;
+    pub const LABEL: u32 = 5;
+    pub const OPAQUE: u32 = 6;
+    pub const INTEGER: u32 = 7;
+    pub const POINTER: u32 = 8;
+    pub const HALF: u32 = 10;
+    pub const ARRAY: u32 = 11;
+    pub const VECTOR: u32 = 12;
+    pub const METADATA: u32 = 16;
+    pub const STRUCT_ANON: u32 = 18;
+    pub const STRUCT_NAME: u32 = 19;
+    pub const STRUCT_NAMED: u32 = 20;
+    pub const FUNCTION: u32 = 21;
+    pub const BFLOAT: u32 = 23;
+    pub const OPAQUE_POINTER: u32 = 25;
+}
+
+/// Record codes of `CONSTANTS_BLOCK`.
+mod constants_code {
+    pub const SETTYPE: u32 = 1;
+    pub const NULL: u32 = 2;
+    pub const UNDEF: u32 = 3;
+    pub const INTEGER: u32 = 4;
+    pub const FLOAT: u32 = 6;
+    pub const AGGREGATE: u32 = 7;
+    pub const STRING: u32 = 8;
+    pub const CSTRING: u32 = 9;
+    pub const CE_CAST: u32 = 11;
+    pub const CE_GEP_OLD: u32 = 12;
+    pub const CE_INBOUNDS_GEP: u32 = 20;
+    pub const DATA: u32 = 22;
+    pub const CE_GEP_WITH_INRANGE_INDEX: u32 = 24;
+    pub const POISON: u32 = 26;
+    pub const CE_GEP: u32 = 32;
+}
+
+/// Record codes of `FUNCTION_BLOCK`.
+mod function_code {
+    pub const DECLAREBLOCKS: u32 = 1;
+    pub const INST_BINOP: u32 = 2;
+    pub const INST_CAST: u32 = 3;
+    pub const INST_EXTRACTELT: u32 = 6;
+    pub const INST_INSERTELT: u32 = 7;
+    pub const INST_SHUFFLEVEC: u32 = 8;
+    pub const INST_RET: u32 = 10;
+    pub const INST_BR: u32 = 11;
+    pub const INST_SWITCH: u32 = 12;
+    pub const INST_UNREACHABLE: u32 = 15;
+    pub const INST_PHI: u32 = 16;
+    pub const INST_ALLOCA: u32 = 19;
+    pub const INST_LOAD: u32 = 20;
+    pub const INST_EXTRACTVAL: u32 = 26;
+    pub const INST_INSERTVAL: u32 = 27;
+    pub const INST_CMP2: u32 = 28;
+    pub const INST_VSELECT: u32 = 29;
+    pub const DEBUG_LOC_AGAIN: u32 = 33;
+    pub const INST_CALL: u32 = 34;
+    pub const DEBUG_LOC: u32 = 35;
+    pub const INST_FENCE: u32 = 36;
+    pub const INST_ATOMICRMW_OLD: u32 = 38;
+    pub const INST_LOADATOMIC: u32 = 41;
+    pub const INST_GEP: u32 = 43;
+    pub const INST_STORE: u32 = 44;
+    pub const INST_STOREATOMIC: u32 = 45;
+    pub const INST_CMPXCHG: u32 = 46;
+    pub const OPERAND_BUNDLE: u32 = 55;
+    pub const INST_UNOP: u32 = 56;
+    pub const INST_FREEZE: u32 = 58;
+    pub const INST_ATOMICRMW: u32 = 59;
+    pub const BLOCKADDR_USERS: u32 = 60;
+}
+
+/// `CALL_EXPLICIT_TYPE` and `CALL_FMF`, bits of a call's calling convention
+/// operand.
+const CALL_EXPLICIT_TYPE: u64 = 1 << 15;
+const CALL_FMF: u64 = 1 << 17;
+
+/// An LLVM type, by its index in the module's type table. Unlike `AIRType`
+/// these are signless and know nothing about Metal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIRValueType {
+    Void,
+    Label,
+    Metadata,
+    Int(u32),
+    Half,
+    Float,
+    Double,
+    BFloat,
+    Vector {
+        element: usize,
+        count: u32,
+    },
+    Array {
+        element: usize,
+        length: u32,
+    },
+    Struct {
+        name: Option<String>,
+        members: Vec<usize>,
+        packed: bool,
+    },
+    /// `pointee` is `None` in modules with opaque pointers.
+    Pointer {
+        address_space: AIRAddressSpace,
+        pointee: Option<usize>,
+    },
+    Function {
+        result: usize,
+        parameters: Vec<usize>,
+    },
+    /// Bodiless structs like `struct._texture_2d_t`, and types nothing is
+    /// ever computed in.
+    Opaque(Option<String>),
+}
+
+/// The module's type table, which instructions may add derived types to.
+#[derive(Debug, Clone, Default)]
+pub struct AIRTypeTable {
+    types: Vec<AIRValueType>,
+}
+
+impl AIRTypeTable {
+    pub fn from_module(module: &AIRModule) -> Result<Self> {
+        let mut types = vec![];
+        let mut name = None;
+
+        for record in module
+            .module_block()?
+            .blocks(BlockType::Type)
+            .flat_map(AIRBlock::records)
+        {
+            let operand = |index: usize| {
+                record.operands.get(index).copied().ok_or_else(|| {
+                    anyhow!("Type record {} is missing operand {}.", record.code, index)
+                })
+            };
+            let members = |from: usize| {
+                record
+                    .operands
+                    .get(from..)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|ty| *ty as usize)
+                    .collect::<Vec<_>>()
+            };
+
+            types.push(match record.code {
+                type_code::VOID => AIRValueType::Void,
+                type_code::LABEL => AIRValueType::Label,
+                type_code::METADATA => AIRValueType::Metadata,
+                type_code::HALF => AIRValueType::Half,
+                type_code::BFLOAT => AIRValueType::BFloat,
+                type_code::FLOAT => AIRValueType::Float,
+                type_code::DOUBLE => AIRValueType::Double,
+                type_code::INTEGER => AIRValueType::Int(operand(0)? as u32),
+                type_code::POINTER | type_code::OPAQUE_POINTER => {
+                    let (address_space, pointee) = module.version.pointer_type(record)?;
+
+                    AIRValueType::Pointer {
+                        address_space,
+                        pointee: pointee.map(|ty| ty as usize),
+                    }
+                }
+                type_code::ARRAY => AIRValueType::Array {
+                    element: operand(1)? as usize,
+                    length: operand(0)? as u32,
+                },
+                type_code::VECTOR => AIRValueType::Vector {
+                    element: operand(1)? as usize,
+                    count: operand(0)? as u32,
+                },
+                type_code::STRUCT_NAME => {
+                    name = Some(record.string());
+                    continue;
+                }
+                type_code::STRUCT_ANON | type_code::STRUCT_NAMED => AIRValueType::Struct {
+                    name: match record.code {
+                        type_code::STRUCT_NAMED => name.take(),
+                        _ => None,
+                    },
+                    members: members(1),
+                    packed: operand(0)? != 0,
+                },
+                type_code::OPAQUE => AIRValueType::Opaque(name.take()),
+                type_code::FUNCTION => AIRValueType::Function {
+                    result: operand(1)? as usize,
+                    parameters: members(2),
+                },
+                // `NUMENTRY` only sizes the table.
+                1 => continue,
+                _ => AIRValueType::Opaque(None),
+            });
+        }
+
+        Ok(Self { types })
+    }
+
+    pub fn get(&self, ty: usize) -> Result<&AIRValueType> {
+        self.types
+            .get(ty)
+            .ok_or_else(|| anyhow!("Type {} is out of bounds.", ty))
+    }
+
+    /// The id of `ty`, adding it to the table if no type is equal to it.
+    pub fn intern(&mut self, ty: AIRValueType) -> usize {
+        // Named structs are distinct even if their members are equal, but
+        // none are ever created here.
+        match self.types.iter().position(|t| *t == ty) {
+            Some(id) => id,
+            None => {
+                self.types.push(ty);
+                self.types.len() - 1
+            }
+        }
+    }
+
+    pub fn is_pointer(&self, ty: usize) -> bool {
+        matches!(self.get(ty), Ok(AIRValueType::Pointer { .. }))
+    }
+
+    /// The scalar type of a scalar or vector.
+    pub fn scalar(&self, ty: usize) -> Result<usize> {
+        match self.get(ty)? {
+            AIRValueType::Vector { element, .. } => Ok(*element),
+            _ => Ok(ty),
+        }
+    }
+
+    /// The component count of a vector, 1 for anything else.
+    pub fn component_count(&self, ty: usize) -> u32 {
+        match self.get(ty) {
+            Ok(AIRValueType::Vector { count, .. }) => *count,
+            _ => 1,
+        }
+    }
+
+    pub fn is_float(&self, ty: usize) -> bool {
+        matches!(
+            self.scalar(ty).and_then(|scalar| self.get(scalar)),
+            Ok(AIRValueType::Half | AIRValueType::Float | AIRValueType::Double)
+        )
+    }
+
+    /// The type of member `index` of a struct, array or vector.
+    pub fn member(&self, ty: usize, index: u64) -> Result<usize> {
+        match self.get(ty)? {
+            AIRValueType::Struct { members, .. } => members
+                .get(index as usize)
+                .copied()
+                .ok_or_else(|| anyhow!("Struct type {} has no member {}.", ty, index)),
+            AIRValueType::Array { element, .. } | AIRValueType::Vector { element, .. } => {
+                Ok(*element)
+            }
+            other => Err(anyhow!("{:?} has no members.", other)),
+        }
+    }
+
+    /// A vector of `count` `element`s, or `element` itself for a single one.
+    pub fn vector_of(&mut self, element: usize, count: u32) -> usize {
+        match count {
+            1 => element,
+            _ => self.intern(AIRValueType::Vector { element, count }),
+        }
+    }
+
+    /// Size and alignment in bytes, following the AIR data layout: scalars
+    /// and pointers are naturally aligned, vectors to their size rounded up
+    /// to a power of two.
+    pub fn size_and_alignment(&self, ty: usize) -> Result<(u32, u32)> {
+        Ok(match self.get(ty)? {
+            AIRValueType::Int(1) => (1, 1),
+            AIRValueType::Int(width) => (width / 8, width / 8),
+            AIRValueType::Half | AIRValueType::BFloat => (2, 2),
+            AIRValueType::Float => (4, 4),
+            AIRValueType::Double | AIRValueType::Pointer { .. } => (8, 8),
+            AIRValueType::Vector { element, count } => {
+                let (size, _) = self.size_and_alignment(*element)?;
+                let alignment = (size * count).next_power_of_two();
+
+                (size * count, alignment)
+            }
+            AIRValueType::Array { element, length } => {
+                let (_, alignment) = self.size_and_alignment(*element)?;
+
+                (self.stride(*element)? * length, alignment)
+            }
+            AIRValueType::Struct {
+                members, packed, ..
+            } => {
+                let mut size = 0u32;
+                let mut alignment = 1;
+
+                for member in members {
+                    let (member_size, member_alignment) = self.size_and_alignment(*member)?;
+                    let member_alignment = if *packed { 1 } else { member_alignment };
+
+                    size = size.next_multiple_of(member_alignment) + member_size;
+                    alignment = alignment.max(member_alignment);
+                }
+
+                (size.next_multiple_of(alignment), alignment)
+            }
+            other => return Err(anyhow!("{:?} has no size.", other)),
+        })
+    }
+
+    /// The distance between two consecutive `ty`s in memory.
+    pub fn stride(&self, ty: usize) -> Result<u32> {
+        let (size, alignment) = self.size_and_alignment(ty)?;
+
+        Ok(size.next_multiple_of(alignment))
+    }
+
+    /// The byte offsets of a struct's members.
+    pub fn member_offsets(&self, ty: usize) -> Result<Vec<u32>> {
+        let AIRValueType::Struct {
+            members, packed, ..
+        } = self.get(ty)?
+        else {
+            return Err(anyhow!("Type {} is not a struct.", ty));
+        };
+
+        let mut offsets = vec![];
+        let mut size = 0u32;
+
+        for member in members {
+            let (member_size, alignment) = self.size_and_alignment(*member)?;
+            let offset = match packed {
+                true => size,
+                false => size.next_multiple_of(alignment),
+            };
+
+            offsets.push(offset);
+            size = offset + member_size;
+        }
+
+        Ok(offsets)
+    }
+}
+
+/// A constant of a `CONSTANTS_BLOCK`. Operands are value ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIRConstant {
+    /// The value sign-extended to 64 bits.
+    Integer(i64),
+    /// The bits of a `half`, `float` or `double`.
+    Float(u64),
+    Null,
+    Undef,
+    Poison,
+    Aggregate(Vec<u64>),
+    /// The elements of an array or vector of scalars, as integers or the
+    /// bits of floats.
+    Data(Vec<u64>),
+    Cast {
+        opcode: AIRCastOp,
+        operand: u64,
+    },
+    GetElementPtr {
+        source: usize,
+        base: u64,
+        indices: Vec<u64>,
+    },
+    /// A constant expression the translator doesn't lower, by record code.
+    Unsupported(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIRValueKind {
+    GlobalVariable {
+        name: String,
+        address_space: AIRAddressSpace,
+        value_type: usize,
+        initializer: Option<u64>,
+    },
+    Function {
+        name: String,
+        /// The function type, not the pointer to it.
+        function_type: usize,
+    },
+    Alias,
+    Constant(AIRConstant),
+    Argument(u32),
+    /// The result of an instruction of the function.
+    Instruction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRValue {
+    pub ty: usize,
+    pub kind: AIRValueKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRBinaryOp {
+    Add,
+    Sub,
+    Mul,
+    UDiv,
+    SDiv,
+    URem,
+    SRem,
+    Shl,
+    LShr,
+    AShr,
+    And,
+    Or,
+    Xor,
+}
+
+impl AIRBinaryOp {
+    fn from_u64(v: u64) -> Result<Self> {
+        Ok(match v {
+            0 => Self::Add,
+            1 => Self::Sub,
+            2 => Self::Mul,
+            3 => Self::UDiv,
+            // `fdiv` and `frem` share the codes of the signed ones.
+            4 => Self::SDiv,
+            5 => Self::URem,
+            6 => Self::SRem,
+            7 => Self::Shl,
+            8 => Self::LShr,
+            9 => Self::AShr,
+            10 => Self::And,
+            11 => Self::Or,
+            12 => Self::Xor,
+            _ => return Err(anyhow!("Binary operator {} not implemented.", v)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRCastOp {
+    Trunc,
+    ZExt,
+    SExt,
+    FPToUI,
+    FPToSI,
+    UIToFP,
+    SIToFP,
+    FPTrunc,
+    FPExt,
+    PtrToInt,
+    IntToPtr,
+    BitCast,
+    AddrSpaceCast,
+}
+
+impl AIRCastOp {
+    fn from_u64(v: u64) -> Result<Self> {
+        Ok(match v {
+            0 => Self::Trunc,
+            1 => Self::ZExt,
+            2 => Self::SExt,
+            3 => Self::FPToUI,
+            4 => Self::FPToSI,
+            5 => Self::UIToFP,
+            6 => Self::SIToFP,
+            7 => Self::FPTrunc,
+            8 => Self::FPExt,
+            9 => Self::PtrToInt,
+            10 => Self::IntToPtr,
+            11 => Self::BitCast,
+            12 => Self::AddrSpaceCast,
+            _ => return Err(anyhow!("Cast {} not implemented.", v)),
+        })
+    }
+}
+
+/// An `icmp` or `fcmp` predicate, numbered like `CmpInst::Predicate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AIRPredicate(pub u64);
+
+impl AIRPredicate {
+    pub const FCMP_FALSE: u64 = 0;
+    pub const FCMP_OEQ: u64 = 1;
+    pub const FCMP_OGT: u64 = 2;
+    pub const FCMP_OGE: u64 = 3;
+    pub const FCMP_OLT: u64 = 4;
+    pub const FCMP_OLE: u64 = 5;
+    pub const FCMP_ONE: u64 = 6;
+    pub const FCMP_ORD: u64 = 7;
+    pub const FCMP_UNO: u64 = 8;
+    pub const FCMP_UEQ: u64 = 9;
+    pub const FCMP_UGT: u64 = 10;
+    pub const FCMP_UGE: u64 = 11;
+    pub const FCMP_ULT: u64 = 12;
+    pub const FCMP_ULE: u64 = 13;
+    pub const FCMP_UNE: u64 = 14;
+    pub const FCMP_TRUE: u64 = 15;
+    pub const ICMP_EQ: u64 = 32;
+    pub const ICMP_NE: u64 = 33;
+    pub const ICMP_UGT: u64 = 34;
+    pub const ICMP_UGE: u64 = 35;
+    pub const ICMP_ULT: u64 = 36;
+    pub const ICMP_ULE: u64 = 37;
+    pub const ICMP_SGT: u64 = 38;
+    pub const ICMP_SGE: u64 = 39;
+    pub const ICMP_SLT: u64 = 40;
+    pub const ICMP_SLE: u64 = 41;
+}
+
+/// A `DEBUG_LOC` record, the scope being a metadata id + 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AIRDebugLocation {
+    pub line: u32,
+    pub column: u32,
+    pub scope: u64,
+}
+
+/// An instruction with its operands resolved to value ids and its
+/// successors to block indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIRInstructionKind {
+    Binary {
+        op: AIRBinaryOp,
+        lhs: u64,
+        rhs: u64,
+    },
+    FNeg(u64),
+    Cast {
+        op: AIRCastOp,
+        operand: u64,
+    },
+    GetElementPtr {
+        source: usize,
+        base: u64,
+        indices: Vec<u64>,
+    },
+    Select {
+        condition: u64,
+        true_value: u64,
+        false_value: u64,
+    },
+    ExtractElement {
+        vector: u64,
+        index: u64,
+    },
+    InsertElement {
+        vector: u64,
+        element: u64,
+        index: u64,
+    },
+    ShuffleVector {
+        first: u64,
+        second: u64,
+        mask: u64,
+    },
+    Compare {
+        predicate: AIRPredicate,
+        lhs: u64,
+        rhs: u64,
+    },
+    Phi {
+        incoming: Vec<(u64, usize)>,
+    },
+    Alloca {
+        allocated: usize,
+    },
+    Load {
+        pointer: u64,
+        /// The ordering of atomic loads.
+        ordering: Option<u64>,
+    },
+    Store {
+        pointer: u64,
+        value: u64,
+        ordering: Option<u64>,
+    },
+    ExtractValue {
+        aggregate: u64,
+        indices: Vec<u32>,
+    },
+    InsertValue {
+        aggregate: u64,
+        value: u64,
+        indices: Vec<u32>,
+    },
+    Call {
+        callee: u64,
+        arguments: Vec<u64>,
+    },
+    AtomicRMW {
+        operation: u64,
+        pointer: u64,
+        value: u64,
+        ordering: u64,
+    },
+    CompareExchange {
+        pointer: u64,
+        comparator: u64,
+        value: u64,
+        success: u64,
+        failure: u64,
+    },
+    Fence {
+        ordering: u64,
+    },
+    Freeze(u64),
+    Return(Option<u64>),
+    Branch(usize),
+    ConditionalBranch {
+        condition: u64,
+        true_target: usize,
+        false_target: usize,
+    },
+    Switch {
+        condition: u64,
+        default: usize,
+        cases: Vec<(u64, usize)>,
+    },
+    Unreachable,
+}
+
+impl AIRInstructionKind {
+    pub fn is_terminator(&self) -> bool {
+        matches!(
+            self,
+            Self::Return(_)
+                | Self::Branch(_)
+                | Self::ConditionalBranch { .. }
+                | Self::Switch { .. }
+                | Self::Unreachable
+        )
+    }
+
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Self::Branch(target) => vec![*target],
+            Self::ConditionalBranch {
+                true_target,
+                false_target,
+                ..
+            } => vec![*true_target, *false_target],
+            Self::Switch { default, cases, .. } => std::iter::once(*default)
+                .chain(cases.iter().map(|(_, target)| *target))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Points every edge to `from` at `to` instead.
+    pub fn replace_successor(&mut self, from: usize, to: usize) {
+        let replace = |target: &mut usize| {
+            if *target == from {
+                *target = to;
+            }
+        };
+
+        match self {
+            Self::Branch(target) => replace(target),
+            Self::ConditionalBranch {
+                true_target,
+                false_target,
+                ..
+            } => {
+                replace(true_target);
+                replace(false_target);
+            }
+            Self::Switch { default, cases, .. } => {
+                replace(default);
+                cases.iter_mut().for_each(|(_, target)| replace(target));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRInstruction {
+    /// The value id of the result, `None` for instructions without one.
+    pub result: Option<u64>,
+    pub kind: AIRInstructionKind,
+    pub location: Option<AIRDebugLocation>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AIRBasicBlock {
+    /// Ends with the terminator.
+    pub instructions: Vec<AIRInstruction>,
+}
+
+impl AIRBasicBlock {
+    pub fn terminator(&self) -> Result<&AIRInstruction> {
+        self.instructions
+            .last()
+            .filter(|instruction| instruction.kind.is_terminator())
+            .ok_or_else(|| anyhow!("Basic block has no terminator."))
+    }
+
+    pub fn terminator_mut(&mut self) -> Result<&mut AIRInstruction> {
+        self.instructions
+            .last_mut()
+            .filter(|instruction| instruction.kind.is_terminator())
+            .ok_or_else(|| anyhow!("Basic block has no terminator."))
+    }
+
+    pub fn successors(&self) -> Vec<usize> {
+        self.terminator()
+            .map(|terminator| terminator.kind.successors())
+            .unwrap_or_default()
+    }
+}
+
+/// A decoded function body.
+///
+/// Value ids are shared with the module: globals come first, then the
+/// module's constants, the function's arguments, its constants and the
+/// results of its instructions. `values` holds all of them.
+#[derive(Debug, Clone)]
+pub struct AIRFunction {
+    pub name: String,
+    pub function_type: usize,
+    pub values: Vec<AIRValue>,
+    pub blocks: Vec<AIRBasicBlock>,
+    /// The records of the function's `METADATA_BLOCK`, which debug
+    /// locations may refer to.
+    pub metadata: Vec<AIRMetadataRecord>,
+}
+
+impl AIRFunction {
+    /// Decodes the body of the function named `name`.
+    pub fn from_module(module: &AIRModule, types: &mut AIRTypeTable, name: &str) -> Result<Self> {
+        let module_block = module.module_block()?;
+        let mut values = module_values(module, types)?;
+
+        // Bodies are written in the order of the functions that have one.
+        let body_index = values
+            .iter()
+            .filter(|value| matches!(value.kind, AIRValueKind::Function { .. }))
+            .zip(module_block.records().filter(|record| {
+                record.code == module_code::FUNCTION && record.operands.get(4) == Some(&0)
+            }))
+            .zip(0..)
+            .find_map(|((value, _), index)| match &value.kind {
+                AIRValueKind::Function { name: n, .. } if n == name => Some(index),
+                _ => None,
+            });
+
+        let function_type = values
+            .iter()
+            .find_map(|value| match &value.kind {
+                AIRValueKind::Function {
+                    name: n,
+                    function_type,
+                } if n == name => Some(*function_type),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("AIR module has no function named {}.", name))?;
+
+        let body = body_index
+            .and_then(|index| module_block.blocks(BlockType::Function).nth(index))
+            .ok_or_else(|| anyhow!("Function {} is only declared.", name))?;
+
+        let AIRValueType::Function { parameters, .. } = types.get(function_type)?.clone() else {
+            return Err(anyhow!("{} doesn't have a function type.", name));
+        };
+
+        for (index, ty) in parameters.into_iter().enumerate() {
+            values.push(AIRValue {
+                ty,
+                kind: AIRValueKind::Argument(index as u32),
+            });
+        }
+
+        let mut decoder = AIRFunctionDecoder {
+            types,
+            values,
+            blocks: vec![],
+            current: AIRBasicBlock::default(),
+            last_location: None,
+        };
+
+        let mut metadata = vec![];
+
+        for item in &body.items {
+            match item {
+                crate::apple_ir::AIRItem::Block(block) if block.ty == BlockType::Constants => {
+                    let constants = decode_constants(block, decoder.types)?;
+                    decoder.values.extend(constants);
+                }
+                crate::apple_ir::AIRItem::Block(block) if block.ty == BlockType::Metadata => {
+                    metadata.extend(block.metadata_records()?);
+                }
+                crate::apple_ir::AIRItem::Record(record) => decoder
+                    .decode(record)
+                    .map_err(|e| e.context(format!("Decoding function {}", name)))?,
+                _ => {}
+            }
+        }
+
+        if !decoder.current.instructions.is_empty() {
+            return Err(anyhow!("Function {} ends in the middle of a block.", name));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            function_type,
+            values: decoder.values,
+            blocks: decoder.blocks,
+            metadata,
+        })
+    }
+
+    pub fn value(&self, id: u64) -> Result<&AIRValue> {
+        self.values
+            .get(id as usize)
+            .ok_or_else(|| anyhow!("Value {} is out of bounds.", id))
+    }
+
+    /// The integer value of a constant, `None` for anything else.
+    pub fn constant_integer(&self, id: u64) -> Option<i64> {
+        match self.value(id).ok()?.kind {
+            AIRValueKind::Constant(AIRConstant::Integer(v)) => Some(v),
+            AIRValueKind::Constant(AIRConstant::Null) => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Adds a value created by a transformation of the function.
+    pub fn add_value(&mut self, ty: usize) -> u64 {
+        self.values.push(AIRValue {
+            ty,
+            kind: AIRValueKind::Instruction,
+        });
+
+        self.values.len() as u64 - 1
+    }
+
+    pub fn add_constant(&mut self, ty: usize, constant: AIRConstant) -> u64 {
+        self.values.push(AIRValue {
+            ty,
+            kind: AIRValueKind::Constant(constant),
+        });
+
+        self.values.len() as u64 - 1
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut predecessors = vec![vec![]; self.blocks.len()];
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            for successor in block.successors() {
+                if !predecessors[successor].contains(&index) {
+                    predecessors[successor].push(index);
+                }
+            }
+        }
+
+        predecessors
+    }
+}
+
+/// The globals and constants of the module, by value id.
+pub fn module_values(module: &AIRModule, types: &mut AIRTypeTable) -> Result<Vec<AIRValue>> {
+    let module_block = module.module_block()?;
+    let globals = module.global_values()?;
+
+    let mut values = vec![];
+
+    for (record, global) in module_block
+        .records()
+        .filter(|record| module_code::defines_global_value(record.code))
+        .zip(globals)
+    {
+        let name = global.name;
+        let operand = |index: usize| {
+            record
+                .operands
+                .get(index)
+                .copied()
+                .ok_or_else(|| anyhow!("Global {} is missing operand {}.", name, index))
+        };
+
+        values.push(match record.code {
+            module_code::GLOBALVAR => {
+                let flags = operand(3)?;
+
+                // With `explicitType` the record holds the value type and
+                // the address space, otherwise the pointer type.
+                let (value_type, address_space) = match flags & 2 {
+                    0 => match types.get(operand(2)? as usize)? {
+                        AIRValueType::Pointer {
+                            address_space,
+                            pointee: Some(pointee),
+                        } => (*pointee, *address_space),
+                        _ => return Err(anyhow!("Global {} has no value type.", name)),
+                    },
+                    _ => (
+                        operand(2)? as usize,
+                        AIRAddressSpace::from_u32((flags >> 2) as u32)?,
+                    ),
+                };
+
+                let ty = types.intern(AIRValueType::Pointer {
+                    address_space,
+                    pointee: pointee_of(module, value_type),
+                });
+
+                AIRValue {
+                    ty,
+                    kind: AIRValueKind::GlobalVariable {
+                        name: name.clone(),
+                        address_space,
+                        value_type,
+                        initializer: operand(4)?.checked_sub(1),
+                    },
+                }
+            }
+            module_code::FUNCTION => {
+                let function_type = operand(2)? as usize;
+                let ty = types.intern(AIRValueType::Pointer {
+                    address_space: AIRAddressSpace::Thread,
+                    pointee: pointee_of(module, function_type),
+                });
+
+                AIRValue {
+                    ty,
+                    kind: AIRValueKind::Function {
+                        name: name.clone(),
+                        function_type,
+                    },
+                }
+            }
+            _ => AIRValue {
+                ty: operand(2)? as usize,
+                kind: AIRValueKind::Alias,
+            },
+        });
+    }
+
+    for block in module_block.blocks(BlockType::Constants) {
+        values.extend(decode_constants(block, types)?);
+    }
+
+    Ok(values)
+}
+
+/// `Some(pointee)` with typed pointers, `None` with opaque ones.
+fn pointee_of(module: &AIRModule, pointee: usize) -> Option<usize> {
+    match module.version.pointers {
+        crate::version::AIRPointerEncoding::Typed => Some(pointee),
+        crate::version::AIRPointerEncoding::Opaque => None,
+    }
+}
+
+fn decode_constants(block: &AIRBlock, types: &mut AIRTypeTable) -> Result<Vec<AIRValue>> {
+    let mut values = vec![];
+    let mut ty = None;
+
+    for record in block.records() {
+        if record.code == constants_code::SETTYPE {
+            ty = record.operands.first().map(|ty| *ty as usize);
+            continue;
+        }
+
+        let ty = ty.ok_or_else(|| anyhow!("Constant before the first SETTYPE."))?;
+        let operands = &record.operands;
+
+        let constant = match record.code {
+            constants_code::NULL => AIRConstant::Null,
+            constants_code::UNDEF => AIRConstant::Undef,
+            constants_code::POISON => AIRConstant::Poison,
+            constants_code::INTEGER => {
+                AIRConstant::Integer(decode_signed(operands.first().copied().unwrap_or(0)))
+            }
+            constants_code::FLOAT => AIRConstant::Float(operands.first().copied().unwrap_or(0)),
+            constants_code::AGGREGATE => AIRConstant::Aggregate(operands.clone()),
+            constants_code::DATA | constants_code::STRING => AIRConstant::Data(operands.clone()),
+            constants_code::CSTRING => {
+                let mut data = operands.clone();
+                data.push(0);
+                AIRConstant::Data(data)
+            }
+            constants_code::CE_CAST => match operands.as_slice() {
+                [opcode, _, operand, ..] => AIRConstant::Cast {
+                    opcode: AIRCastOp::from_u64(*opcode)?,
+                    operand: *operand,
+                },
+                _ => return Err(anyhow!("Malformed constant cast.")),
+            },
+            code @ (constants_code::CE_GEP_OLD
+            | constants_code::CE_INBOUNDS_GEP
+            | constants_code::CE_GEP_WITH_INRANGE_INDEX
+            | constants_code::CE_GEP) => {
+                // Newer records start with the source type and flags, older
+                // ones only have the source type if their length is odd.
+                let (source, pairs) = match (code, operands.len() % 2) {
+                    (constants_code::CE_GEP_WITH_INRANGE_INDEX | constants_code::CE_GEP, _) => {
+                        (operands.first().copied(), operands.get(2..))
+                    }
+                    (_, 1) => (operands.first().copied(), operands.get(1..)),
+                    _ => (None, Some(operands.as_slice())),
+                };
+
+                let operands = pairs
+                    .unwrap_or_default()
+                    .chunks(2)
+                    .map(|pair| pair.get(1).copied())
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| anyhow!("Malformed constant getelementptr."))?;
+
+                let (base, indices) = operands
+                    .split_first()
+                    .ok_or_else(|| anyhow!("Constant getelementptr has no base."))?;
+
+                AIRConstant::GetElementPtr {
+                    source: source
+                        .ok_or_else(|| anyhow!("Constant getelementptr has no source type."))?
+                        as usize,
+                    base: *base,
+                    indices: indices.to_vec(),
+                }
+            }
+            code => AIRConstant::Unsupported(code),
+        };
+
+        // Make sure derived types like `i1` vectors can be interned later
+        // without shifting ids.
+        let _ = types.get(ty)?;
+
+        values.push(AIRValue {
+            ty,
+            kind: AIRValueKind::Constant(constant),
+        });
+    }
+
+    Ok(values)
+}
+
+/// Signed VBRs keep the sign in the lowest bit.
+fn decode_signed(v: u64) -> i64 {
+    match v & 1 {
+        0 => (v >> 1) as i64,
+        _ if v == 1 => i64::MIN,
+        _ => -((v >> 1) as i64),
+    }
+}
+
+struct AIRFunctionDecoder<'a> {
+    types: &'a mut AIRTypeTable,
+    values: Vec<AIRValue>,
+    blocks: Vec<AIRBasicBlock>,
+    current: AIRBasicBlock,
+    last_location: Option<AIRDebugLocation>,
+}
+
+/// Reads the operands of an instruction record, whose value operands are
+/// relative to the id of the instruction's result.
+struct AIROperandReader<'a> {
+    operands: &'a [u64],
+    position: usize,
+    next_value: u64,
+}
+
+impl AIROperandReader<'_> {
+    fn literal(&mut self) -> Result<u64> {
+        let value =
+            self.operands.get(self.position).copied().ok_or_else(|| {
+                anyhow!("Instruction record is missing operand {}.", self.position)
+            })?;
+        self.position += 1;
+
+        Ok(value)
+    }
+
+    /// A value whose type is implied by the instruction.
+    fn value(&mut self) -> Result<u64> {
+        let relative = self.literal()?;
+
+        Ok((self.next_value as u32).wrapping_sub(relative as u32) as u64)
+    }
+
+    /// A value followed by its type if it's a forward reference.
+    fn value_and_type(&mut self) -> Result<(u64, Option<usize>)> {
+        let value = self.value()?;
+
+        if value >= self.next_value {
+            return Ok((value, Some(self.literal()? as usize)));
+        }
+
+        Ok((value, None))
+    }
+
+    /// A phi operand, a signed VBR relative id.
+    fn signed_value(&mut self) -> Result<u64> {
+        let relative = decode_signed(self.literal()?);
+
+        Ok((self.next_value as i64 - relative) as u64)
+    }
+
+    fn rest(&self) -> &[u64] {
+        self.operands.get(self.position..).unwrap_or_default()
+    }
+}
+
+impl AIRFunctionDecoder<'_> {
+    fn value_type(&self, value: u64, forward: Option<usize>) -> Result<usize> {
+        match forward {
+            Some(ty) => Ok(ty),
+            None => self
+                .values
+                .get(value as usize)
+                .map(|value| value.ty)
+                .ok_or_else(|| anyhow!("Value {} is used before it's defined.", value)),
+        }
+    }
+
+    fn decode(&mut self, record: &AIRRecord) -> Result<()> {
+        let mut reader = AIROperandReader {
+            operands: &record.operands,
+            position: 0,
+            next_value: self.values.len() as u64,
+        };
+
+        use AIRInstructionKind as Kind;
+
+        let (kind, ty) = match record.code {
+            function_code::DECLAREBLOCKS => {
+                self.blocks.reserve(reader.literal()? as usize);
+                return Ok(());
+            }
+            function_code::DEBUG_LOC => {
+                let location = AIRDebugLocation {
+                    line: reader.literal()? as u32,
+                    column: reader.literal()? as u32,
+                    scope: reader.literal()?,
+                };
+
+                self.attach_location(location);
+                return Ok(());
+            }
+            function_code::DEBUG_LOC_AGAIN => {
+                if let Some(location) = self.last_location {
+                    self.attach_location(location);
+                }
+
+                return Ok(());
+            }
+            function_code::OPERAND_BUNDLE | function_code::BLOCKADDR_USERS => return Ok(()),
+            // Debug records of newer compilers.
+            61..=65 => return Ok(()),
+            function_code::INST_BINOP => {
+                let (lhs, forward) = reader.value_and_type()?;
+                let ty = self.value_type(lhs, forward)?;
+                let rhs = reader.value()?;
+                let op = AIRBinaryOp::from_u64(reader.literal()?)?;
+
+                (Kind::Binary { op, lhs, rhs }, Some(ty))
+            }
+            function_code::INST_UNOP => {
+                let (operand, forward) = reader.value_and_type()?;
+                let ty = self.value_type(operand, forward)?;
+
+                match reader.literal()? {
+                    0 => (Kind::FNeg(operand), Some(ty)),
+                    op => return Err(anyhow!("Unary operator {} not implemented.", op)),
+                }
+            }
+            function_code::INST_CAST => {
+                let (operand, _) = reader.value_and_type()?;
+                let ty = reader.literal()? as usize;
+                let op = AIRCastOp::from_u64(reader.literal()?)?;
+
+                (Kind::Cast { op, operand }, Some(ty))
+            }
+            function_code::INST_GEP => {
+                reader.literal()?;
+                let source = reader.literal()? as usize;
+
+                let (base, forward) = reader.value_and_type()?;
+                let base_type = self.value_type(base, forward)?;
+
+                let mut indices = vec![];
+                while !reader.rest().is_empty() {
+                    indices.push(reader.value_and_type()?.0);
+                }
+
+                let ty = self.gep_type(source, base_type, &indices)?;
+
+                (
+                    Kind::GetElementPtr {
+                        source,
+                        base,
+                        indices,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_VSELECT => {
+                let (true_value, forward) = reader.value_and_type()?;
+                let ty = self.value_type(true_value, forward)?;
+                let false_value = reader.value()?;
+                let (condition, _) = reader.value_and_type()?;
+
+                (
+                    Kind::Select {
+                        condition,
+                        true_value,
+                        false_value,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_EXTRACTELT => {
+                let (vector, forward) = reader.value_and_type()?;
+                let ty = self.types.member(self.value_type(vector, forward)?, 0)?;
+                let (index, _) = reader.value_and_type()?;
+
+                (Kind::ExtractElement { vector, index }, Some(ty))
+            }
+            function_code::INST_INSERTELT => {
+                let (vector, forward) = reader.value_and_type()?;
+                let ty = self.value_type(vector, forward)?;
+                let element = reader.value()?;
+                let (index, _) = reader.value_and_type()?;
+
+                (
+                    Kind::InsertElement {
+                        vector,
+                        element,
+                        index,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_SHUFFLEVEC => {
+                let (first, forward) = reader.value_and_type()?;
+                let element = self.types.member(self.value_type(first, forward)?, 0)?;
+                let second = reader.value()?;
+                let mask = reader.value()?;
+
+                let count = self.types.component_count(self.value_type(mask, None)?);
+                let ty = self.types.vector_of(element, count);
+
+                (
+                    Kind::ShuffleVector {
+                        first,
+                        second,
+                        mask,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_CMP2 => {
+                let (lhs, forward) = reader.value_and_type()?;
+                let operand_type = self.value_type(lhs, forward)?;
+                let rhs = reader.value()?;
+                let predicate = AIRPredicate(reader.literal()?);
+
+                let bool = self.types.intern(AIRValueType::Int(1));
+                let count = self.types.component_count(operand_type);
+                let ty = self.types.vector_of(bool, count);
+
+                (
+                    Kind::Compare {
+                        predicate,
+                        lhs,
+                        rhs,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_RET => match reader.rest() {
+                [] => (Kind::Return(None), None),
+                _ => (Kind::Return(Some(reader.value_and_type()?.0)), None),
+            },
+            function_code::INST_BR => match reader.rest() {
+                [target] => (Kind::Branch(*target as usize), None),
+                _ => {
+                    let true_target = reader.literal()? as usize;
+                    let false_target = reader.literal()? as usize;
+                    let condition = reader.value()?;
+
+                    (
+                        Kind::ConditionalBranch {
+                            condition,
+                            true_target,
+                            false_target,
+                        },
+                        None,
+                    )
+                }
+            },
+            function_code::INST_SWITCH => {
+                reader.literal()?;
+                let condition = reader.value()?;
+                let default = reader.literal()? as usize;
+
+                let cases = reader
+                    .rest()
+                    .chunks(2)
+                    .map(|case| match case {
+                        [value, target] => Ok((*value, *target as usize)),
+                        _ => Err(anyhow!("Malformed switch case.")),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                (
+                    Kind::Switch {
+                        condition,
+                        default,
+                        cases,
+                    },
+                    None,
+                )
+            }
+            function_code::INST_UNREACHABLE => (Kind::Unreachable, None),
+            function_code::INST_PHI => {
+                let ty = reader.literal()? as usize;
+                let mut incoming = vec![];
+
+                // A trailing odd operand holds fast-math flags.
+                while reader.rest().len() >= 2 {
+                    let value = reader.signed_value()?;
+                    let block = reader.literal()? as usize;
+
+                    incoming.push((value, block));
+                }
+
+                (Kind::Phi { incoming }, Some(ty))
+            }
+            function_code::INST_ALLOCA => {
+                let allocated = reader.literal()? as usize;
+                let ty = self.types.intern(AIRValueType::Pointer {
+                    address_space: AIRAddressSpace::Thread,
+                    pointee: self.typed_pointee(allocated),
+                });
+
+                (Kind::Alloca { allocated }, Some(ty))
+            }
+            function_code::INST_LOAD | function_code::INST_LOADATOMIC => {
+                let (pointer, _) = reader.value_and_type()?;
+                let ty = reader.literal()? as usize;
+
+                let ordering = match record.code {
+                    function_code::INST_LOADATOMIC => {
+                        reader.literal()?;
+                        reader.literal()?;
+                        Some(reader.literal()?)
+                    }
+                    _ => None,
+                };
+
+                (Kind::Load { pointer, ordering }, Some(ty))
+            }
+            function_code::INST_STORE | function_code::INST_STOREATOMIC => {
+                let (pointer, _) = reader.value_and_type()?;
+                let (value, _) = reader.value_and_type()?;
+
+                let ordering = match record.code {
+                    function_code::INST_STOREATOMIC => {
+                        reader.literal()?;
+                        reader.literal()?;
+                        Some(reader.literal()?)
+                    }
+                    _ => None,
+                };
+
+                (
+                    Kind::Store {
+                        pointer,
+                        value,
+                        ordering,
+                    },
+                    None,
+                )
+            }
+            function_code::INST_EXTRACTVAL => {
+                let (aggregate, forward) = reader.value_and_type()?;
+                let indices = reader.rest().iter().map(|i| *i as u32).collect::<Vec<_>>();
+
+                let mut ty = self.value_type(aggregate, forward)?;
+                for index in &indices {
+                    ty = self.types.member(ty, *index as u64)?;
+                }
+
+                (Kind::ExtractValue { aggregate, indices }, Some(ty))
+            }
+            function_code::INST_INSERTVAL => {
+                let (aggregate, forward) = reader.value_and_type()?;
+                let ty = self.value_type(aggregate, forward)?;
+                let (value, _) = reader.value_and_type()?;
+                let indices = reader.rest().iter().map(|i| *i as u32).collect();
+
+                (
+                    Kind::InsertValue {
+                        aggregate,
+                        value,
+                        indices,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_CALL => {
+                reader.literal()?;
+                let calling_convention = reader.literal()?;
+
+                if calling_convention & CALL_FMF != 0 {
+                    reader.literal()?;
+                }
+
+                let explicit_type = (calling_convention & CALL_EXPLICIT_TYPE != 0)
+                    .then(|| reader.literal())
+                    .transpose()?;
+
+                let (callee, forward) = reader.value_and_type()?;
+
+                let function_type = match explicit_type {
+                    Some(ty) => ty as usize,
+                    None => match self.types.get(self.value_type(callee, forward)?)? {
+                        AIRValueType::Pointer {
+                            pointee: Some(pointee),
+                            ..
+                        } => *pointee,
+                        _ => return Err(anyhow!("Call without a function type.")),
+                    },
+                };
+
+                let AIRValueType::Function { result, parameters } =
+                    self.types.get(function_type)?.clone()
+                else {
+                    return Err(anyhow!("Call of a value that isn't a function."));
+                };
+
+                let arguments = parameters
+                    .iter()
+                    .map(|_| reader.value())
+                    .collect::<Result<Vec<_>>>()?;
+
+                let ty = match self.types.get(result)? {
+                    AIRValueType::Void => None,
+                    _ => Some(result),
+                };
+
+                (Kind::Call { callee, arguments }, ty)
+            }
+            function_code::INST_ATOMICRMW | function_code::INST_ATOMICRMW_OLD => {
+                let (pointer, _) = reader.value_and_type()?;
+
+                let (value, ty) = match record.code {
+                    function_code::INST_ATOMICRMW => {
+                        let (value, forward) = reader.value_and_type()?;
+                        (value, self.value_type(value, forward)?)
+                    }
+                    _ => {
+                        let value = reader.value()?;
+                        let pointer_type = self.value_type(pointer, None)?;
+                        (value, self.types.member_of_pointer(pointer_type)?)
+                    }
+                };
+
+                let operation = reader.literal()?;
+                reader.literal()?;
+                let ordering = reader.literal()?;
+
+                (
+                    Kind::AtomicRMW {
+                        operation,
+                        pointer,
+                        value,
+                        ordering,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_CMPXCHG => {
+                let (pointer, _) = reader.value_and_type()?;
+                let (comparator, forward) = reader.value_and_type()?;
+                let value_type = self.value_type(comparator, forward)?;
+                let value = reader.value()?;
+
+                reader.literal()?;
+                let success = reader.literal()?;
+                reader.literal()?;
+                let failure = reader.literal()?;
+
+                let bool = self.types.intern(AIRValueType::Int(1));
+                let ty = self.types.intern(AIRValueType::Struct {
+                    name: None,
+                    members: vec![value_type, bool],
+                    packed: false,
+                });
+
+                (
+                    Kind::CompareExchange {
+                        pointer,
+                        comparator,
+                        value,
+                        success,
+                        failure,
+                    },
+                    Some(ty),
+                )
+            }
+            function_code::INST_FENCE => (
+                Kind::Fence {
+                    ordering: reader.literal()?,
+                },
+                None,
+            ),
+            function_code::INST_FREEZE => {
+                let (operand, forward) = reader.value_and_type()?;
+                let ty = self.value_type(operand, forward)?;
+
+                (Kind::Freeze(operand), Some(ty))
+            }
+            code => {
+                return Err(anyhow!("Instruction record {} not implemented.", code));
+            }
+        };
+
+        let result = ty.map(|ty| {
+            self.values.push(AIRValue {
+                ty,
+                kind: AIRValueKind::Instruction,
+            });
+
+            self.values.len() as u64 - 1
+        });
+
+        let terminator = kind.is_terminator();
+
+        self.current.instructions.push(AIRInstruction {
+            result,
+            kind,
+            location: None,
+        });
+
+        if terminator {
+            self.blocks.push(std::mem::take(&mut self.current));
+        }
+
+        Ok(())
+    }
+
+    fn attach_location(&mut self, location: AIRDebugLocation) {
+        self.last_location = Some(location);
+
+        let instruction = match self.current.instructions.last_mut() {
+            Some(instruction) => Some(instruction),
+            // The terminator that just ended the block.
+            None => self
+                .blocks
+                .last_mut()
+                .and_then(|block| block.instructions.last_mut()),
+        };
+
+        if let Some(instruction) = instruction {
+            instruction.location = Some(location);
+        }
+    }
+
+    fn typed_pointee(&self, ty: usize) -> Option<usize> {
+        // Opaque modules have no pointer records with a pointee.
+        let opaque = self
+            .types
+            .types
+            .iter()
+            .any(|t| matches!(t, AIRValueType::Pointer { pointee: None, .. }));
+
+        (!opaque).then_some(ty)
+    }
+
+    /// The pointer type a `getelementptr` produces.
+    fn gep_type(&mut self, source: usize, base_type: usize, indices: &[u64]) -> Result<usize> {
+        let AIRValueType::Pointer { address_space, .. } = *self.types.get(base_type)? else {
+            return Err(anyhow!("getelementptr on a value that isn't a pointer."));
+        };
+
+        // The first index steps over whole `source`s.
+        let mut ty = source;
+
+        for index in indices.iter().skip(1) {
+            let member = match self.values.get(*index as usize).map(|v| &v.kind) {
+                Some(AIRValueKind::Constant(AIRConstant::Integer(v))) => *v as u64,
+                _ => 0,
+            };
+
+            ty = self.types.member(ty, member)?;
+        }
+
+        let pointee = self.typed_pointee(ty);
+
+        Ok(self.types.intern(AIRValueType::Pointer {
+            address_space,
+            pointee,
+        }))
+    }
+}
+
+impl AIRTypeTable {
+    /// The pointee of a typed pointer.
+    pub fn member_of_pointer(&self, ty: usize) -> Result<usize> {
+        match self.get(ty)? {
+            AIRValueType::Pointer {
+                pointee: Some(pointee),
+                ..
+            } => Ok(*pointee),
+            other => Err(anyhow!("{:?} is not a typed pointer.", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apple_ir::parse_apple_ir;
+
+    fn test_module() -> AIRModule {
+        let air = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../test.air")).unwrap();
+        parse_apple_ir(&air).unwrap()
+    }
+
+    #[test]
+    fn decodes_signed_vbr() {
+        assert_eq!(decode_signed(4), 2);
+        assert_eq!(decode_signed(5), -2);
+        assert_eq!(decode_signed(1), i64::MIN);
+    }
+
+    #[test]
+    fn lays_out_vectors_like_air() {
+        let mut types = AIRTypeTable::default();
+        let float = types.intern(AIRValueType::Float);
+        let float3 = types.vector_of(float, 3);
+        let s = types.intern(AIRValueType::Struct {
+            name: None,
+            members: vec![float, float3],
+            packed: false,
+        });
+
+        assert_eq!(types.size_and_alignment(float3).unwrap(), (12, 16));
+        assert_eq!(types.stride(float3).unwrap(), 16);
+        assert_eq!(types.member_offsets(s).unwrap(), vec![0, 16]);
+        assert_eq!(types.size_and_alignment(s).unwrap(), (32, 16));
+    }
+
+    #[test]
+    fn decodes_vertex_function() {
+        let module = test_module();
+        let mut types = AIRTypeTable::from_module(&module).unwrap();
+        let function = AIRFunction::from_module(&module, &mut types, "main0").unwrap();
+
+        assert_eq!(function.blocks.len(), 1);
+
+        let kinds = function.blocks[0]
+            .instructions
+            .iter()
+            .map(|instruction| &instruction.kind)
+            .collect::<Vec<_>>();
+
+        assert!(matches!(
+            kinds[0],
+            AIRInstructionKind::Cast {
+                op: AIRCastOp::SExt,
+                operand: 25
+            }
+        ));
+        assert!(matches!(
+            kinds[1],
+            AIRInstructionKind::GetElementPtr { base: 0, .. }
+        ));
+        assert!(matches!(
+            kinds.last(),
+            Some(AIRInstructionKind::Return(Some(43)))
+        ));
+        assert!(matches!(
+            function.value(0).unwrap().kind,
+            AIRValueKind::GlobalVariable {
+                address_space: AIRAddressSpace::Constant,
+                initializer: Some(18),
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::spirv::{SPIRVModule, SPIRVOp};
+
+/// `GLSL.std.450` instruction numbers.
+mod glsl {
+    pub const ROUND: u32 = 1;
+    pub const ROUND_EVEN: u32 = 2;
+    pub const TRUNC: u32 = 3;
+    pub const FABS: u32 = 4;
+    pub const SABS: u32 = 5;
+    pub const FSIGN: u32 = 6;
+    pub const FLOOR: u32 = 8;
+    pub const CEIL: u32 = 9;
+    pub const FRACT: u32 = 10;
+    pub const SIN: u32 = 13;
+    pub const COS: u32 = 14;
+    pub const TAN: u32 = 15;
+    pub const ASIN: u32 = 16;
+    pub const ACOS: u32 = 17;
+    pub const ATAN: u32 = 18;
+    pub const SINH: u32 = 19;
+    pub const COSH: u32 = 20;
+    pub const TANH: u32 = 21;
+    pub const ASINH: u32 = 22;
+    pub const ACOSH: u32 = 23;
+    pub const ATANH: u32 = 24;
+    pub const ATAN2: u32 = 25;
+    pub const POW: u32 = 26;
+    pub const EXP: u32 = 27;
+    pub const LOG: u32 = 28;
+    pub const EXP2: u32 = 29;
+    pub const LOG2: u32 = 30;
+    pub const SQRT: u32 = 31;
+    pub const INVERSE_SQRT: u32 = 32;
+    pub const FMIN: u32 = 37;
+    pub const UMIN: u32 = 38;
+    pub const SMIN: u32 = 39;
+    pub const FMAX: u32 = 40;
+    pub const UMAX: u32 = 41;
+    pub const SMAX: u32 = 42;
+    pub const FCLAMP: u32 = 43;
+    pub const UCLAMP: u32 = 44;
+    pub const SCLAMP: u32 = 45;
+    pub const FMIX: u32 = 46;
+    pub const STEP: u32 = 48;
+    pub const SMOOTH_STEP: u32 = 49;
+    pub const FMA: u32 = 50;
+    pub const LDEXP: u32 = 53;
+    pub const LENGTH: u32 = 66;
+    pub const DISTANCE: u32 = 67;
+    pub const CROSS: u32 = 68;
+    pub const NORMALIZE: u32 = 69;
+    pub const FACE_FORWARD: u32 = 70;
+    pub const REFLECT: u32 = 71;
+    pub const REFRACT: u32 = 72;
+}
+
+/// How a math function is lowered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRMathOp {
+    /// A `GLSL.std.450` extended instruction.
+    GLSL(u32),
+    Core(SPIRVOp),
+    /// `clamp(x, 0, 1)`, which needs the bounds as constants.
+    Saturate,
+}
+
+/// A math function of the Metal standard library, or the LLVM intrinsic
+/// clang folds it to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AIRMathFunction {
+    pub op: AIRMathOp,
+    /// Whether integer operands are signed, and have to be sign-extended
+    /// when narrow integers are emulated.
+    pub signed: bool,
+    /// How many of the call's arguments are operands. `llvm.abs` has a
+    /// trailing `is_int_min_poison` flag.
+    pub operands: usize,
+}
+
+impl AIRMathFunction {
+    /// Parses names like `air.fast_sqrt.f32`, `air.max.s.v2i32` or
+    /// `llvm.fma.v4f32`.
+    pub fn from_intrinsic(name: &str) -> Option<Self> {
+        let mut parts = name.split('.');
+
+        let (base, signed) = match parts.next()? {
+            "air" => {
+                let base = parts.next()?;
+                let base = base
+                    .strip_prefix("fast_")
+                    .or_else(|| base.strip_prefix("precise_"))
+                    .unwrap_or(base);
+
+                (base, parts.next() == Some("s"))
+            }
+            "llvm" => {
+                let base = parts.next()?;
+                (base, matches!(base, "abs" | "smin" | "smax"))
+            }
+            _ => return None,
+        };
+
+        let (op, operands) = match base {
+            "fabs" | "abs" if signed => (AIRMathOp::GLSL(glsl::SABS), 1),
+            "fabs" | "abs" => (AIRMathOp::GLSL(glsl::FABS), 1),
+            "round" => (AIRMathOp::GLSL(glsl::ROUND), 1),
+            "rint" | "roundeven" => (AIRMathOp::GLSL(glsl::ROUND_EVEN), 1),
+            "trunc" => (AIRMathOp::GLSL(glsl::TRUNC), 1),
+            "sign" => (AIRMathOp::GLSL(glsl::FSIGN), 1),
+            "floor" => (AIRMathOp::GLSL(glsl::FLOOR), 1),
+            "ceil" => (AIRMathOp::GLSL(glsl::CEIL), 1),
+            "fract" => (AIRMathOp::GLSL(glsl::FRACT), 1),
+            "sin" => (AIRMathOp::GLSL(glsl::SIN), 1),
+            "cos" => (AIRMathOp::GLSL(glsl::COS), 1),
+            "tan" => (AIRMathOp::GLSL(glsl::TAN), 1),
+            "asin" => (AIRMathOp::GLSL(glsl::ASIN), 1),
+            "acos" => (AIRMathOp::GLSL(glsl::ACOS), 1),
+            "atan" => (AIRMathOp::GLSL(glsl::ATAN), 1),
+            "sinh" => (AIRMathOp::GLSL(glsl::SINH), 1),
+            "cosh" => (AIRMathOp::GLSL(glsl::COSH), 1),
+            "tanh" => (AIRMathOp::GLSL(glsl::TANH), 1),
+            "asinh" => (AIRMathOp::GLSL(glsl::ASINH), 1),
+            "acosh" => (AIRMathOp::GLSL(glsl::ACOSH), 1),
+            "atanh" => (AIRMathOp::GLSL(glsl::ATANH), 1),
+            "atan2" => (AIRMathOp::GLSL(glsl::ATAN2), 2),
+            "pow" | "powr" => (AIRMathOp::GLSL(glsl::POW), 2),
+            "exp" => (AIRMathOp::GLSL(glsl::EXP), 1),
+            "log" => (AIRMathOp::GLSL(glsl::LOG), 1),
+            "exp2" => (AIRMathOp::GLSL(glsl::EXP2), 1),
+            "log2" => (AIRMathOp::GLSL(glsl::LOG2), 1),
+            "sqrt" => (AIRMathOp::GLSL(glsl::SQRT), 1),
+            "rsqrt" => (AIRMathOp::GLSL(glsl::INVERSE_SQRT), 1),
+            "fmin" | "minnum" => (AIRMathOp::GLSL(glsl::FMIN), 2),
+            "fmax" | "maxnum" => (AIRMathOp::GLSL(glsl::FMAX), 2),
+            "min" | "smin" if signed => (AIRMathOp::GLSL(glsl::SMIN), 2),
+            "min" | "umin" => (AIRMathOp::GLSL(glsl::UMIN), 2),
+            "max" | "smax" if signed => (AIRMathOp::GLSL(glsl::SMAX), 2),
+            "max" | "umax" => (AIRMathOp::GLSL(glsl::UMAX), 2),
+            "fclamp" => (AIRMathOp::GLSL(glsl::FCLAMP), 3),
+            "clamp" if signed => (AIRMathOp::GLSL(glsl::SCLAMP), 3),
+            "clamp" if name.contains(".u.") => (AIRMathOp::GLSL(glsl::UCLAMP), 3),
+            "clamp" => (AIRMathOp::GLSL(glsl::FCLAMP), 3),
+            "saturate" => (AIRMathOp::Saturate, 1),
+            "mix" => (AIRMathOp::GLSL(glsl::FMIX), 3),
+            "step" => (AIRMathOp::GLSL(glsl::STEP), 2),
+            "smoothstep" => (AIRMathOp::GLSL(glsl::SMOOTH_STEP), 3),
+            "fma" | "fmuladd" => (AIRMathOp::GLSL(glsl::FMA), 3),
+            "ldexp" => (AIRMathOp::GLSL(glsl::LDEXP), 2),
+            "length" => (AIRMathOp::GLSL(glsl::LENGTH), 1),
+            "distance" => (AIRMathOp::GLSL(glsl::DISTANCE), 2),
+            "cross" => (AIRMathOp::GLSL(glsl::CROSS), 2),
+            "normalize" => (AIRMathOp::GLSL(glsl::NORMALIZE), 1),
+            "faceforward" => (AIRMathOp::GLSL(glsl::FACE_FORWARD), 3),
+            "reflect" => (AIRMathOp::GLSL(glsl::REFLECT), 2),
+            "refract" => (AIRMathOp::GLSL(glsl::REFRACT), 3),
+            "dot" => (AIRMathOp::Core(SPIRVOp::Dot), 2),
+            "popcount" | "ctpop" => (AIRMathOp::Core(SPIRVOp::BitCount), 1),
+            _ => return None,
+        };
+
+        Some(Self {
+            op,
+            signed,
+            operands,
+        })
+    }
+}
+
+/// Emits `function` on `operands`. `saturate` takes the `0` and `1` of its
+/// operand's type as two more operands.
+pub fn lower_math_function(
+    module: &mut SPIRVModule,
+    function: &AIRMathFunction,
+    result_type: u32,
+    operands: &[u32],
+) -> u32 {
+    let instruction = match function.op {
+        AIRMathOp::GLSL(instruction) => instruction,
+        AIRMathOp::Saturate => glsl::FCLAMP,
+        AIRMathOp::Core(op) => return module.emit_value(op, result_type, operands),
+    };
+
+    let set = module.ext_inst_import("GLSL.std.450");
+    module.ext_inst(result_type, set, instruction, operands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_air_and_llvm_names() {
+        let sqrt = AIRMathFunction::from_intrinsic("air.fast_sqrt.v4f32").unwrap();
+        assert_eq!(sqrt.op, AIRMathOp::GLSL(glsl::SQRT));
+
+        let max = AIRMathFunction::from_intrinsic("air.max.s.v2i32").unwrap();
+        assert_eq!(max.op, AIRMathOp::GLSL(glsl::SMAX));
+        assert!(max.signed);
+
+        let clamp = AIRMathFunction::from_intrinsic("air.clamp.u.i32").unwrap();
+        assert_eq!(clamp.op, AIRMathOp::GLSL(glsl::UCLAMP));
+
+        let abs = AIRMathFunction::from_intrinsic("llvm.abs.i32").unwrap();
+        assert_eq!((abs.op, abs.operands), (AIRMathOp::GLSL(glsl::SABS), 1));
+
+        let fma = AIRMathFunction::from_intrinsic("llvm.fmuladd.f32").unwrap();
+        assert_eq!((fma.op, fma.signed), (AIRMathOp::GLSL(glsl::FMA), false));
+
+        assert_eq!(AIRMathFunction::from_intrinsic("air.wg.barrier"), None);
+    }
+}
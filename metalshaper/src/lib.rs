@@ -0,0 +1,77 @@
+pub mod apple_ir;
+pub mod argument_buffer;
+pub mod atomic;
+pub mod coordinates;
+pub mod debug_info;
+pub mod fragment;
+pub mod function;
+pub mod intrinsics;
+pub mod metadata;
+pub mod metallib;
+pub mod reflection;
+pub mod simdgroup;
+pub mod spirv;
+pub mod structurize;
+pub mod translate;
+pub mod types;
+pub mod version;
+
+use anyhow::{Result, anyhow};
+
+use crate::spirv::{SPIRVScope, SPIRVStorageClass, memory_semantics};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AIRShaderStage {
+    Vertex,
+    Fragment,
+    Kernel,
+}
+
+/// LLVM `addrspace` numbers as used by AIR.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AIRAddressSpace {
+    Thread = 0,
+    Device = 1,
+    Constant = 2,
+    Threadgroup = 3,
+}
+
+impl AIRAddressSpace {
+    pub fn from_u32(v: u32) -> Result<Self> {
+        Ok(match v {
+            0 => Self::Thread,
+            1 => Self::Device,
+            2 => Self::Constant,
+            3 => Self::Threadgroup,
+            _ => return Err(anyhow!("AIR address space {} not implemented.", v)),
+        })
+    }
+
+    pub fn storage_class(&self) -> SPIRVStorageClass {
+        match self {
+            Self::Thread => SPIRVStorageClass::Function,
+            Self::Device => SPIRVStorageClass::StorageBuffer,
+            Self::Constant => SPIRVStorageClass::Uniform,
+            Self::Threadgroup => SPIRVStorageClass::Workgroup,
+        }
+    }
+
+    /// The widest scope another invocation can observe this memory from.
+    pub fn scope(&self) -> SPIRVScope {
+        match self {
+            Self::Thread => SPIRVScope::Invocation,
+            Self::Device | Self::Constant => SPIRVScope::Device,
+            Self::Threadgroup => SPIRVScope::Workgroup,
+        }
+    }
+
+    /// The storage class bits of a memory semantics operand.
+    pub fn memory_semantics(&self) -> u32 {
+        match self {
+            Self::Thread => memory_semantics::NONE,
+            Self::Device | Self::Constant => memory_semantics::UNIFORM_MEMORY,
+            Self::Threadgroup => memory_semantics::WORKGROUP_MEMORY,
+        }
+    }
+}
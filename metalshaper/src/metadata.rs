@@ -0,0 +1,211 @@
+use anyhow::{Result, anyhow};
+
+use crate::apple_ir::{AIRBlock, BlockType, module_code};
+
+mod constants_code {
+    pub const SETTYPE: u32 = 1;
+    pub const NULL: u32 = 2;
+    pub const INTEGER: u32 = 4;
+}
+
+mod metadata_code {
+    pub const STRING_OLD: u32 = 1;
+    pub const VALUE: u32 = 2;
+    pub const NODE: u32 = 3;
+    pub const NAME: u32 = 4;
+    pub const DISTINCT_NODE: u32 = 5;
+    pub const KIND: u32 = 6;
+    pub const NAMED_NODE: u32 = 10;
+    pub const ATTACHMENT: u32 = 11;
+    pub const STRINGS: u32 = 35;
+    pub const GLOBAL_DECL_ATTACHMENT: u32 = 36;
+    pub const INDEX_OFFSET: u32 = 38;
+    pub const INDEX: u32 = 39;
+}
+
+/// Distinct nodes can refer to themselves, tuples nested deeper than this
+/// are left unresolved.
+const MAX_NODE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone)]
+enum AIRNamedMetadataNode {
+    String(String),
+    Value(u64),
+    Node(Vec<u64>),
+    Other,
+}
+
+/// An operand of a metadata tuple, resolved from its id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AIRMetadataOperand {
+    String(String),
+    Integer(i64),
+    /// A global variable or function, by value id.
+    Global(u64),
+    Node(Vec<AIRMetadataOperand>),
+    Null,
+    Other,
+}
+
+impl AIRMetadataOperand {
+    pub fn integer(&self) -> Result<u32> {
+        match self {
+            Self::Integer(v) => {
+                u32::try_from(*v).map_err(|_| anyhow!("Metadata {} is out of range.", v))
+            }
+            other => Err(anyhow!("Metadata {:?} is not an integer.", other)),
+        }
+    }
+
+    pub fn string(&self) -> Result<&str> {
+        match self {
+            Self::String(s) => Ok(s),
+            other => Err(anyhow!("Metadata {:?} is not a string.", other)),
+        }
+    }
+
+    pub fn node(&self) -> Result<&[AIRMetadataOperand]> {
+        match self {
+            Self::Node(operands) => Ok(operands),
+            other => Err(anyhow!("Metadata {:?} is not a tuple.", other)),
+        }
+    }
+}
+
+/// The module level metadata needed to look up `!name = !{...}` tuples
+/// like `!air.version` or `!air.kernel`.
+///
+/// Metadata ids are assigned in record order, tuples refer to their
+/// operands as `id + 1` while named nodes use plain ids.
+pub struct AIRNamedMetadata {
+    nodes: Vec<AIRNamedMetadataNode>,
+    named: Vec<(String, Vec<u64>)>,
+    globals: u64,
+    integers: Vec<Option<i64>>,
+}
+
+impl AIRNamedMetadata {
+    pub fn new(module: &AIRBlock) -> Result<Self> {
+        // Global values are numbered before the module's constants.
+        let globals = module
+            .records()
+            .filter(|record| module_code::defines_global_value(record.code))
+            .count();
+
+        let mut integers = vec![None; globals];
+
+        for record in module
+            .blocks(BlockType::Constants)
+            .flat_map(AIRBlock::records)
+        {
+            match record.code {
+                constants_code::SETTYPE => {}
+                // Zero is written as `null`.
+                constants_code::NULL => integers.push(Some(0)),
+                // Signed VBRs keep the sign in the lowest bit.
+                constants_code::INTEGER => {
+                    integers.push(record.operands.first().map(|v| match v & 1 {
+                        0 => (v >> 1) as i64,
+                        _ => -((v >> 1) as i64),
+                    }))
+                }
+                _ => integers.push(None),
+            }
+        }
+
+        let mut nodes = vec![];
+        let mut named = vec![];
+        let mut name = None;
+
+        for record in module
+            .blocks(BlockType::Metadata)
+            .flat_map(AIRBlock::records)
+        {
+            let node = match record.code {
+                metadata_code::STRINGS => {
+                    nodes.extend(
+                        record
+                            .metadata_strings()?
+                            .into_iter()
+                            .map(AIRNamedMetadataNode::String),
+                    );
+                    continue;
+                }
+                metadata_code::NAME => {
+                    name = Some(record.string());
+                    continue;
+                }
+                metadata_code::NAMED_NODE => {
+                    named.push((name.take().unwrap_or_default(), record.operands.clone()));
+                    continue;
+                }
+                metadata_code::KIND
+                | metadata_code::ATTACHMENT
+                | metadata_code::GLOBAL_DECL_ATTACHMENT
+                | metadata_code::INDEX_OFFSET
+                | metadata_code::INDEX => continue,
+                metadata_code::STRING_OLD => AIRNamedMetadataNode::String(record.string()),
+                metadata_code::VALUE => {
+                    AIRNamedMetadataNode::Value(record.operands.get(1).copied().unwrap_or(u64::MAX))
+                }
+                metadata_code::NODE | metadata_code::DISTINCT_NODE => {
+                    AIRNamedMetadataNode::Node(record.operands.clone())
+                }
+                _ => AIRNamedMetadataNode::Other,
+            };
+
+            nodes.push(node);
+        }
+
+        Ok(Self {
+            nodes,
+            named,
+            globals: globals as u64,
+            integers,
+        })
+    }
+
+    /// The operands of `!name`, `None` if the module doesn't have it.
+    pub fn named(&self, name: &str) -> Option<Vec<AIRMetadataOperand>> {
+        let (_, ids) = self.named.iter().find(|(n, _)| n == name)?;
+
+        Some(ids.iter().map(|id| self.resolve(*id, 0)).collect())
+    }
+
+    /// The operands of the first tuple of `!name`.
+    pub fn tuple(&self, name: &str) -> Result<Option<Vec<AIRMetadataOperand>>> {
+        let Some(operands) = self.named(name) else {
+            return Ok(None);
+        };
+
+        match operands.into_iter().next() {
+            Some(AIRMetadataOperand::Node(operands)) => Ok(Some(operands)),
+            _ => Err(anyhow!("!{} is not a tuple.", name)),
+        }
+    }
+
+    fn resolve(&self, id: u64, depth: usize) -> AIRMetadataOperand {
+        match self.nodes.get(id as usize) {
+            Some(AIRNamedMetadataNode::String(s)) => AIRMetadataOperand::String(s.clone()),
+            Some(AIRNamedMetadataNode::Value(value)) if *value < self.globals => {
+                AIRMetadataOperand::Global(*value)
+            }
+            Some(AIRNamedMetadataNode::Value(value)) => match self.integers.get(*value as usize) {
+                Some(Some(v)) => AIRMetadataOperand::Integer(*v),
+                _ => AIRMetadataOperand::Other,
+            },
+            Some(AIRNamedMetadataNode::Node(operands)) if depth < MAX_NODE_DEPTH => {
+                AIRMetadataOperand::Node(
+                    operands
+                        .iter()
+                        .map(|id| match id.checked_sub(1) {
+                            Some(id) => self.resolve(id, depth + 1),
+                            None => AIRMetadataOperand::Null,
+                        })
+                        .collect(),
+                )
+            }
+            _ => AIRMetadataOperand::Other,
+        }
+    }
+}
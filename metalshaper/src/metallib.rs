@@ -0,0 +1,328 @@
+use std::ops::Range;
+
+use anyhow::{Result, anyhow};
+
+use crate::AIRShaderStage;
+use crate::apple_ir::{AIRModule, parse_apple_ir};
+use crate::version::{AIRVersion, MetalLanguageVersion};
+
+/// `MTLB`, the magic of a `.metallib`.
+pub const METALLIB_MAGIC: [u8; 4] = *b"MTLB";
+
+const HEADER_SIZE: usize = 0x58;
+
+/// Tags of a function entry. Each is followed by its size as a `u16` and
+/// its data, except for `ENDT`.
+mod tag {
+    pub const NAME: [u8; 4] = *b"NAME";
+    pub const TYPE: [u8; 4] = *b"TYPE";
+    pub const VERSION: [u8; 4] = *b"VERS";
+    pub const BITCODE_SIZE: [u8; 4] = *b"MDSZ";
+    pub const OFFSETS: [u8; 4] = *b"OFFT";
+    pub const END: [u8; 4] = *b"ENDT";
+}
+
+/// The function type in a `TYPE` tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRLibraryFunctionType {
+    Vertex,
+    Fragment,
+    Kernel,
+    Unqualified,
+    Visible,
+    Extern,
+    Intersection,
+    Unknown(u8),
+}
+
+impl AIRLibraryFunctionType {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::Vertex,
+            1 => Self::Fragment,
+            2 => Self::Kernel,
+            3 => Self::Unqualified,
+            4 => Self::Visible,
+            5 => Self::Extern,
+            6 => Self::Intersection,
+            _ => Self::Unknown(v),
+        }
+    }
+
+    pub fn stage(&self) -> Option<AIRShaderStage> {
+        match self {
+            Self::Vertex => Some(AIRShaderStage::Vertex),
+            Self::Fragment => Some(AIRShaderStage::Fragment),
+            Self::Kernel => Some(AIRShaderStage::Kernel),
+            _ => None,
+        }
+    }
+}
+
+/// A function of a `.metallib`, each of which has its own AIR module.
+#[derive(Debug, Clone)]
+pub struct AIRLibraryFunction {
+    pub name: String,
+    pub ty: AIRLibraryFunctionType,
+    pub air_version: Option<AIRVersion>,
+    pub language_version: Option<MetalLanguageVersion>,
+    /// Where the function's module is in the file.
+    pub bitcode: Range<usize>,
+}
+
+/// The header of a `.metallib` and its function list.
+#[derive(Debug, Clone)]
+pub struct AIRLibrary {
+    pub platform: u16,
+    pub version: (u16, u16),
+    pub library_type: u8,
+    pub os: u8,
+    pub os_version: (u16, u16),
+    pub functions: Vec<AIRLibraryFunction>,
+}
+
+impl AIRLibrary {
+    pub fn is_metallib(content: &[u8]) -> bool {
+        content.starts_with(&METALLIB_MAGIC)
+    }
+
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        if !Self::is_metallib(content) {
+            return Err(anyhow!(
+                "Not a metallib, magic is {:02x?}.",
+                content.get(..4)
+            ));
+        }
+
+        let mut header = AIRLibraryReader::new(content, 4..HEADER_SIZE)?;
+
+        let platform = header.u16()?;
+        let version = (header.u16()?, header.u16()?);
+        let library_type = header.u8()?;
+        let os = header.u8()?;
+        let os_version = (header.u16()?, header.u16()?);
+
+        let file_size = header.u64()?;
+
+        if file_size as usize > content.len() {
+            return Err(anyhow!(
+                "Metallib is {} bytes long, but its header says {}.",
+                content.len(),
+                file_size
+            ));
+        }
+
+        let function_list = header.range()?;
+        // Public, then private metadata. Neither is needed to find modules.
+        header.range()?;
+        header.range()?;
+        let bitcode = header.range()?;
+
+        if bitcode.end > content.len() {
+            return Err(anyhow!("Metallib bitcode section is out of bounds."));
+        }
+
+        let mut list = AIRLibraryReader::new(content, function_list)?;
+        let count = list.u32()?;
+
+        let functions = (0..count)
+            .map(|_| Self::parse_function(&mut list, content, &bitcode))
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            platform,
+            version,
+            library_type,
+            os,
+            os_version,
+            functions,
+        })
+    }
+
+    fn parse_function(
+        list: &mut AIRLibraryReader,
+        content: &[u8],
+        bitcode: &Range<usize>,
+    ) -> Result<AIRLibraryFunction> {
+        // The size of the entry's tags, which end with `ENDT` anyway.
+        list.u32()?;
+
+        let mut name = None;
+        let mut ty = AIRLibraryFunctionType::Unqualified;
+        let mut air_version = None;
+        let mut language_version = None;
+        let mut size = None;
+        let mut offset = None;
+
+        loop {
+            let tag = list.tag()?;
+
+            if tag == tag::END {
+                break;
+            }
+
+            let length = list.u16()? as usize;
+            let mut data = AIRLibraryReader::new(content, list.position..list.position + length)?;
+            list.position += length;
+
+            match tag {
+                tag::NAME => {
+                    let bytes = data.rest();
+                    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                    name = Some(String::from_utf8_lossy(&bytes[..end]).into_owned());
+                }
+                tag::TYPE => ty = AIRLibraryFunctionType::from_u8(data.u8()?),
+                tag::VERSION => {
+                    air_version = Some(AIRVersion {
+                        major: data.u16()? as u32,
+                        minor: data.u16()? as u32,
+                        patch: 0,
+                    });
+                    language_version = Some(MetalLanguageVersion::new(
+                        data.u16()? as u32,
+                        data.u16()? as u32,
+                    ));
+                }
+                tag::BITCODE_SIZE => size = Some(data.u64()? as usize),
+                // Offsets into the public metadata, private metadata and
+                // bitcode sections.
+                tag::OFFSETS => {
+                    data.u64()?;
+                    data.u64()?;
+                    offset = Some(data.u64()? as usize);
+                }
+                _ => {}
+            }
+        }
+
+        let name = name.ok_or_else(|| anyhow!("Metallib function without a name."))?;
+
+        let (Some(offset), Some(size)) = (offset, size) else {
+            return Err(anyhow!("Metallib function {} has no bitcode.", name));
+        };
+
+        let start = bitcode.start.saturating_add(offset);
+        let end = start.saturating_add(size);
+
+        if end > bitcode.end {
+            return Err(anyhow!(
+                "Bitcode of metallib function {} is out of bounds.",
+                name
+            ));
+        }
+
+        Ok(AIRLibraryFunction {
+            name,
+            ty,
+            air_version,
+            language_version,
+            bitcode: start..end,
+        })
+    }
+
+    pub fn function(&self, name: &str) -> Option<&AIRLibraryFunction> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    /// Parses the AIR module of `function`, `content` being the whole file.
+    pub fn module(&self, content: &[u8], function: &AIRLibraryFunction) -> Result<AIRModule> {
+        let bitcode = content
+            .get(function.bitcode.clone())
+            .ok_or_else(|| anyhow!("Metallib function {} is out of bounds.", function.name))?;
+
+        parse_apple_ir(bitcode)
+            .map_err(|e| e.context(format!("In metallib function {}", function.name)))
+    }
+}
+
+/// Little-endian reads within one section of a metallib.
+struct AIRLibraryReader<'a> {
+    content: &'a [u8],
+    position: usize,
+    end: usize,
+}
+
+impl<'a> AIRLibraryReader<'a> {
+    fn new(content: &'a [u8], range: Range<usize>) -> Result<Self> {
+        if range.start > range.end || range.end > content.len() {
+            return Err(anyhow!(
+                "Metallib section {:?} is out of bounds of the {} byte file.",
+                range,
+                content.len()
+            ));
+        }
+
+        Ok(Self {
+            content,
+            position: range.start,
+            end: range.end,
+        })
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.position + N > self.end {
+            return Err(anyhow!("Metallib is truncated at byte {}.", self.position));
+        }
+
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.content[self.position..self.position + N]);
+        self.position += N;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn tag(&mut self) -> Result<[u8; 4]> {
+        self.bytes()
+    }
+
+    /// An offset and size pair.
+    fn range(&mut self) -> Result<Range<usize>> {
+        let offset = self.u64()? as usize;
+        let size = self.u64()? as usize;
+
+        let end = offset
+            .checked_add(size)
+            .ok_or_else(|| anyhow!("Metallib section at {} overflows.", offset))?;
+
+        Ok(offset..end)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.content[self.position..self.end];
+        self.position = self.end;
+
+        rest
+    }
+}
+
+/// Parses the modules of an `.air` file, or those of every function of a
+/// `.metallib`.
+pub fn parse_modules(content: &[u8]) -> Result<Vec<AIRModule>> {
+    if !AIRLibrary::is_metallib(content) {
+        return Ok(vec![parse_apple_ir(content)?]);
+    }
+
+    let library = AIRLibrary::parse(content)?;
+
+    library
+        .functions
+        .iter()
+        .map(|function| library.module(content, function))
+        .collect()
+}
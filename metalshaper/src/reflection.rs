@@ -0,0 +1,499 @@
+use anyhow::{Result, anyhow};
+
+use crate::apple_ir::AIRModule;
+use crate::metadata::{AIRMetadataOperand, AIRNamedMetadata};
+use crate::{AIRAddressSpace, AIRShaderStage};
+
+/// The Vulkan descriptor types translated shaders bind resources through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVDescriptorType {
+    Sampler,
+    SampledImage,
+    StorageImage,
+    UniformBuffer,
+    StorageBuffer,
+}
+
+/// Where one member of a Metal argument buffer ended up after translation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SPIRVArgumentLocation {
+    /// A binding in the argument buffer's descriptor set. A `count` of `None`
+    /// is a variable-sized array, sized when allocating the set.
+    Descriptor {
+        binding: u32,
+        descriptor_type: SPIRVDescriptorType,
+        count: Option<u32>,
+    },
+    /// A 64-bit buffer device address stored in the argument block.
+    Address { offset: u32 },
+    /// Plain data copied into the argument block.
+    Data { offset: u32, size: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SPIRVArgumentBufferEntry {
+    /// The member's `[[id(n)]]`.
+    pub id: u32,
+    pub name: String,
+    pub location: SPIRVArgumentLocation,
+}
+
+/// The storage buffer holding the addresses and plain data of an argument
+/// buffer. Always binding 0 of the set when present.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SPIRVArgumentBlock {
+    pub binding: u32,
+    pub size: u32,
+}
+
+/// How the runtime has to populate an argument buffer passed at
+/// `[[buffer(index)]]`: which descriptor set, which bindings and what goes
+/// into the argument block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SPIRVArgumentBufferLayout {
+    pub index: u32,
+    pub set: u32,
+    pub block: Option<SPIRVArgumentBlock>,
+    pub entries: Vec<SPIRVArgumentBufferEntry>,
+}
+
+impl SPIRVArgumentBufferLayout {
+    pub fn entry(&self, id: u32) -> Option<&SPIRVArgumentBufferEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+}
+
+/// An argument or output of an entry point, as described by its
+/// `!air.vertex`, `!air.fragment` or `!air.kernel` metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRArgumentReflection {
+    /// The position in the function's parameters, `None` for outputs.
+    pub index: Option<u32>,
+    /// `air.buffer`, `air.texture`, `air.position` and so on.
+    pub kind: String,
+    pub name: Option<String>,
+    pub type_name: Option<String>,
+    /// The `n` of `[[buffer(n)]]`, `[[attribute(n)]]`, `[[color(n)]]` and
+    /// the like.
+    pub location_index: Option<u32>,
+    /// The address space of buffer arguments.
+    pub address_space: Option<AIRAddressSpace>,
+    /// Size and alignment of the pointee of buffer arguments.
+    pub type_size: Option<u32>,
+    pub type_alignment: Option<u32>,
+    /// The layout of the pointee of buffer arguments that point to a struct.
+    pub struct_type: Option<AIRStructReflection>,
+    /// The keys that stand alone, like `user(locn0)`, `air.flat` or the
+    /// `air.depth_any` of a depth output.
+    pub qualifiers: Vec<String>,
+}
+
+impl AIRArgumentReflection {
+    fn from_metadata(operands: &[AIRMetadataOperand]) -> Result<Self> {
+        let (index, rest) = match operands {
+            [AIRMetadataOperand::Integer(index), rest @ ..] => (Some(*index as u32), rest),
+            rest => (None, rest),
+        };
+
+        let kind = rest
+            .first()
+            .ok_or_else(|| anyhow!("Argument metadata {:?} has no kind.", operands))?
+            .string()?
+            .to_string();
+
+        // The rest are keys, most of them followed by a value.
+        let value_of = |key: &str| {
+            rest.iter()
+                .position(|operand| matches!(operand, AIRMetadataOperand::String(s) if s == key))
+                .and_then(|position| rest.get(position + 1))
+        };
+
+        let mut qualifiers = vec![];
+        let mut keys = rest.iter().skip(1);
+
+        while let Some(key) = keys.next() {
+            let AIRMetadataOperand::String(key) = key else {
+                continue;
+            };
+
+            match key.as_str() {
+                "air.arg_name" | "air.arg_type_name" => {
+                    keys.next();
+                }
+                "air.location_index"
+                | "air.struct_type_info"
+                | "air.address_space"
+                | "air.buffer_size"
+                | "air.depth_qualifier" => {}
+                key if key.starts_with("air.arg_") => {}
+                key => qualifiers.push(key.to_string()),
+            }
+        }
+
+        let location_index = match kind.as_str() {
+            "air.render_target" => rest.get(1),
+            _ => value_of("air.location_index"),
+        };
+
+        Ok(Self {
+            index,
+            kind,
+            name: value_of("air.arg_name").and_then(|v| v.string().ok().map(str::to_string)),
+            type_name: value_of("air.arg_type_name")
+                .and_then(|v| v.string().ok().map(str::to_string)),
+            location_index: location_index.and_then(|v| v.integer().ok()),
+            address_space: value_of("air.address_space")
+                .map(|v| AIRAddressSpace::from_u32(v.integer()?))
+                .transpose()?,
+            type_size: value_of("air.arg_type_size").and_then(|v| v.integer().ok()),
+            type_alignment: value_of("air.arg_type_align_size").and_then(|v| v.integer().ok()),
+            struct_type: match (
+                value_of("air.struct_type_info"),
+                value_of("air.arg_type_name"),
+            ) {
+                (Some(info), Some(name)) => Some(AIRStructReflection::from_metadata(
+                    name.string()?,
+                    value_of("air.arg_type_size")
+                        .map(|v| v.integer())
+                        .transpose()?,
+                    info.node()?,
+                )?),
+                _ => None,
+            },
+            qualifiers,
+        })
+    }
+
+    fn write_json(&self, json: &mut String) {
+        json.push('{');
+        json_field(json, "index", &json_option(self.index));
+        json.push(',');
+        json_field(json, "kind", &json_string(&self.kind));
+        json.push(',');
+        json_field(
+            json,
+            "name",
+            &json_option(self.name.as_deref().map(json_string)),
+        );
+        json.push(',');
+        json_field(
+            json,
+            "type",
+            &json_option(self.type_name.as_deref().map(json_string)),
+        );
+        json.push(',');
+        json_field(json, "location_index", &json_option(self.location_index));
+        json.push(',');
+        json_field(json, "type_size", &json_option(self.type_size));
+        json.push(',');
+        json_field(json, "type_alignment", &json_option(self.type_alignment));
+        json.push(',');
+        json_field(json, "struct", "");
+
+        match &self.struct_type {
+            Some(struct_type) => struct_type.write_json(json),
+            None => json.push_str("null"),
+        }
+
+        json.push('}');
+    }
+}
+
+/// A member of a struct behind a buffer argument, from the argument's
+/// `air.struct_type_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRStructMemberReflection {
+    pub name: String,
+    /// The Metal type, or the element type of arrays.
+    pub type_name: String,
+    pub offset: u32,
+    /// The size of the whole member, arrays included.
+    pub size: u32,
+    /// 0 if the member isn't an array.
+    pub array_length: u32,
+    pub struct_type: Option<AIRStructReflection>,
+    /// The `air.indirect_argument` of a resource in an argument buffer,
+    /// whose location index is the member's `[[id(n)]]`.
+    pub argument: Option<AIRArgumentReflection>,
+}
+
+/// The layout of a struct as the Metal compiler laid it out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRStructReflection {
+    pub name: String,
+    /// Only known for the top-level struct of an argument, nested ones end
+    /// where the next member begins.
+    pub size: Option<u32>,
+    pub members: Vec<AIRStructMemberReflection>,
+}
+
+impl AIRStructReflection {
+    /// Reads `air.struct_type_info`, which lists each member as its offset,
+    /// size, array length, type name and name, optionally followed by
+    /// `air.*` keys like a nested `air.struct_type_info`.
+    fn from_metadata(
+        name: &str,
+        size: Option<u32>,
+        operands: &[AIRMetadataOperand],
+    ) -> Result<Self> {
+        let mut members = vec![];
+        let mut rest = operands;
+
+        while let [
+            offset,
+            member_size,
+            array_length,
+            type_name,
+            member_name,
+            tail @ ..,
+        ] = rest
+        {
+            let type_name = type_name.string()?;
+            let mut struct_type = None;
+            let mut argument = None;
+
+            rest = tail;
+
+            // Keys are followed by a tuple or nothing, an integer starts
+            // the next member.
+            while let [AIRMetadataOperand::String(key), tail @ ..] = rest {
+                rest = tail;
+
+                if let [AIRMetadataOperand::Node(operands), tail @ ..] = rest {
+                    match key.as_str() {
+                        "air.struct_type_info" => {
+                            struct_type = Some(Self::from_metadata(type_name, None, operands)?);
+                        }
+                        "air.indirect_argument" => {
+                            argument = Some(AIRArgumentReflection::from_metadata(operands)?);
+                        }
+                        _ => {}
+                    }
+
+                    rest = tail;
+                }
+            }
+
+            members.push(AIRStructMemberReflection {
+                name: member_name.string()?.to_string(),
+                type_name: type_name.to_string(),
+                offset: offset.integer()?,
+                size: member_size.integer()?,
+                array_length: array_length.integer()?,
+                struct_type,
+                argument,
+            });
+        }
+
+        if !rest.is_empty() {
+            return Err(anyhow!(
+                "Malformed struct type info of {}: {:?}.",
+                name,
+                rest
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            size,
+            members,
+        })
+    }
+
+    fn write_json(&self, json: &mut String) {
+        json.push('{');
+        json_field(json, "name", &json_string(&self.name));
+        json.push(',');
+        json_field(json, "size", &json_option(self.size));
+        json.push(',');
+        json_field(json, "members", "[");
+
+        for (i, member) in self.members.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            json.push('{');
+            json_field(json, "name", &json_string(&member.name));
+            json.push(',');
+            json_field(json, "type", &json_string(&member.type_name));
+            json.push(',');
+            json_field(json, "offset", &member.offset.to_string());
+            json.push(',');
+            json_field(json, "size", &member.size.to_string());
+            json.push(',');
+            json_field(json, "array_length", &member.array_length.to_string());
+            json.push(',');
+            json_field(json, "struct", "");
+
+            match &member.struct_type {
+                Some(struct_type) => struct_type.write_json(json),
+                None => json.push_str("null"),
+            }
+
+            json.push('}');
+        }
+
+        json.push_str("]}");
+    }
+}
+
+/// The interface of an entry point of an AIR module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRFunctionReflection {
+    pub name: String,
+    pub stage: AIRShaderStage,
+    pub outputs: Vec<AIRArgumentReflection>,
+    pub arguments: Vec<AIRArgumentReflection>,
+    /// The keys after the arguments, like `air.early_fragment_tests`.
+    pub attributes: Vec<String>,
+}
+
+impl AIRFunctionReflection {
+    /// Reads the entry points of `module` from its metadata.
+    pub fn from_module(module: &AIRModule) -> Result<Vec<Self>> {
+        let metadata = AIRNamedMetadata::new(module.module_block()?)?;
+        let globals = module.global_values()?;
+
+        let mut functions = vec![];
+
+        for (stage, name) in [
+            (AIRShaderStage::Vertex, "air.vertex"),
+            (AIRShaderStage::Fragment, "air.fragment"),
+            (AIRShaderStage::Kernel, "air.kernel"),
+        ] {
+            for entry_point in metadata.named(name).unwrap_or_default() {
+                let operands = entry_point.node()?;
+
+                let Some(AIRMetadataOperand::Global(id)) = operands.first() else {
+                    return Err(anyhow!("!{} entry doesn't start with a function.", name));
+                };
+
+                let function = globals
+                    .get(*id as usize)
+                    .ok_or_else(|| anyhow!("!{} refers to unknown global {}.", name, id))?;
+
+                // Kernels have no outputs, newer compilers still write an
+                // empty tuple for them.
+                let (outputs, arguments) = match operands {
+                    [_, arguments] => (None, Some(arguments)),
+                    [_, outputs, arguments, ..] => (Some(outputs), Some(arguments)),
+                    _ => (None, None),
+                };
+
+                let read = |node: Option<&AIRMetadataOperand>| -> Result<Vec<_>> {
+                    match node {
+                        Some(node) => node
+                            .node()?
+                            .iter()
+                            .map(|argument| AIRArgumentReflection::from_metadata(argument.node()?))
+                            .collect(),
+                        None => Ok(vec![]),
+                    }
+                };
+
+                let attributes = operands
+                    .get(3..)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|operand| operand.string().ok().map(str::to_string))
+                    .collect();
+
+                functions.push(Self {
+                    name: function.name.clone(),
+                    stage,
+                    outputs: read(outputs)?,
+                    arguments: read(arguments)?,
+                    attributes,
+                });
+            }
+        }
+
+        Ok(functions)
+    }
+
+    pub fn write_json(&self, json: &mut String) {
+        let stage = match self.stage {
+            AIRShaderStage::Vertex => "vertex",
+            AIRShaderStage::Fragment => "fragment",
+            AIRShaderStage::Kernel => "kernel",
+        };
+
+        json.push('{');
+        json_field(json, "name", &json_string(&self.name));
+        json.push(',');
+        json_field(json, "stage", &json_string(stage));
+
+        for (key, arguments) in [("outputs", &self.outputs), ("arguments", &self.arguments)] {
+            json.push(',');
+            json_field(json, key, "[");
+
+            for (i, argument) in arguments.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+
+                argument.write_json(json);
+            }
+
+            json.push(']');
+        }
+
+        json.push(',');
+        json_field(json, "attributes", "[");
+
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+
+            json.push_str(&json_string(attribute));
+        }
+
+        json.push_str("]}");
+    }
+}
+
+/// Reflection of `functions` as a JSON object, for tools that can't link
+/// against metalshaper.
+pub fn reflection_json(functions: &[AIRFunctionReflection]) -> String {
+    let mut json = String::from("{\"functions\":[");
+
+    for (i, function) in functions.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+
+        function.write_json(&mut json);
+    }
+
+    json.push_str("]}");
+    json
+}
+
+fn json_field(json: &mut String, key: &str, value: &str) {
+    json.push_str(&json_string(key));
+    json.push(':');
+    json.push_str(value);
+}
+
+fn json_option<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
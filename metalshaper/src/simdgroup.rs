@@ -0,0 +1,677 @@
+use anyhow::{Result, anyhow};
+
+use crate::AIRShaderStage;
+use crate::spirv::{
+    SPIRVBuiltIn, SPIRVCapability, SPIRVGroupOperation, SPIRVModule, SPIRVOp, SPIRVScope,
+    SPIRVSubgroupFeatures, SPIRVTargetFeatures,
+};
+use crate::types::{AIRScalarType, AIRType, SPIRVScalarRepresentation};
+
+/// Whether a function works across the whole SIMD-group (`simd_*`) or only
+/// within groups of four lanes (`quad_*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRSIMDScope {
+    SIMDGroup,
+    QuadGroup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRSIMDOperation {
+    Sum,
+    Product,
+    Min,
+    Max,
+    And,
+    Or,
+    Xor,
+    PrefixInclusiveSum,
+    PrefixExclusiveSum,
+    PrefixInclusiveProduct,
+    PrefixExclusiveProduct,
+    All,
+    Any,
+    Ballot,
+    ActiveThreadsMask,
+    IsFirst,
+    Broadcast,
+    BroadcastFirst,
+    Shuffle,
+    ShuffleXor,
+    ShuffleUp,
+    ShuffleDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AIRSIMDFunction {
+    pub scope: AIRSIMDScope,
+    pub operation: AIRSIMDOperation,
+    /// The value type, or the result type for `ballot` and `active_threads_mask`.
+    pub ty: AIRType,
+}
+
+impl AIRSIMDFunction {
+    /// Decodes intrinsic names such as `air.simd_sum.f32`,
+    /// `air.simd_max.u.i32` or `air.quad_shuffle_xor.v4f16`.
+    pub fn from_intrinsic(name: &str) -> Result<Self> {
+        let parts = name.split('.').collect::<Vec<_>>();
+
+        if parts.len() < 2 || parts[0] != "air" {
+            return Err(anyhow!("`{}` is not an AIR SIMD-group intrinsic.", name));
+        }
+
+        let (scope, operation) = if let Some(o) = parts[1].strip_prefix("simd_") {
+            (AIRSIMDScope::SIMDGroup, o)
+        } else if let Some(o) = parts[1].strip_prefix("quad_") {
+            (AIRSIMDScope::QuadGroup, o)
+        } else {
+            return Err(anyhow!("`{}` is not an AIR SIMD-group intrinsic.", name));
+        };
+
+        let operation = match operation {
+            "sum" => AIRSIMDOperation::Sum,
+            "product" => AIRSIMDOperation::Product,
+            "min" => AIRSIMDOperation::Min,
+            "max" => AIRSIMDOperation::Max,
+            "and" => AIRSIMDOperation::And,
+            "or" => AIRSIMDOperation::Or,
+            "xor" => AIRSIMDOperation::Xor,
+            "prefix_inclusive_sum" => AIRSIMDOperation::PrefixInclusiveSum,
+            "prefix_exclusive_sum" => AIRSIMDOperation::PrefixExclusiveSum,
+            "prefix_inclusive_product" => AIRSIMDOperation::PrefixInclusiveProduct,
+            "prefix_exclusive_product" => AIRSIMDOperation::PrefixExclusiveProduct,
+            "all" => AIRSIMDOperation::All,
+            "any" => AIRSIMDOperation::Any,
+            "ballot" => AIRSIMDOperation::Ballot,
+            "active_threads_mask" => AIRSIMDOperation::ActiveThreadsMask,
+            "is_first" => AIRSIMDOperation::IsFirst,
+            "broadcast" => AIRSIMDOperation::Broadcast,
+            "broadcast_first" => AIRSIMDOperation::BroadcastFirst,
+            "shuffle" => AIRSIMDOperation::Shuffle,
+            "shuffle_xor" => AIRSIMDOperation::ShuffleXor,
+            "shuffle_up" => AIRSIMDOperation::ShuffleUp,
+            "shuffle_down" => AIRSIMDOperation::ShuffleDown,
+            o => return Err(anyhow!("SIMD-group function `{}` not implemented.", o)),
+        };
+
+        let ty = match parts[2..].split_last() {
+            Some((suffix, markers)) => AIRType::from_suffix(suffix, markers.contains(&"s"))?,
+            // `air.simd_is_first` isn't overloaded.
+            None if operation == AIRSIMDOperation::IsFirst => AIRType::Scalar(AIRScalarType::Bool),
+            None => return Err(anyhow!("`{}` has no type suffix.", name)),
+        };
+
+        Ok(Self {
+            scope,
+            operation,
+            ty,
+        })
+    }
+}
+
+/// A lane index or mask operand. Several SPIR-V instructions only accept a
+/// constant here, so the translator keeps that information around.
+#[derive(Debug, Clone, Copy)]
+pub enum AIRLane {
+    Constant(u32),
+    /// Id of a 32-bit unsigned integer value.
+    Dynamic(u32),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AIRSIMDOperands {
+    pub value: Option<u32>,
+    pub lane: Option<AIRLane>,
+}
+
+/// Emits the GroupNonUniform instructions for `function` and returns the id
+/// of the resulting value.
+///
+/// Vulkan leaves lanes of `shuffle_up`/`shuffle_down` that read outside the
+/// subgroup undefined, while Metal returns the lane's own value.
+pub fn lower_simd_function(
+    module: &mut SPIRVModule,
+    features: &SPIRVTargetFeatures,
+    stage: AIRShaderStage,
+    function: &AIRSIMDFunction,
+    operands: &AIRSIMDOperands,
+) -> Result<u32> {
+    let subgroup = &features.subgroup;
+
+    require_stage(subgroup, stage)?;
+
+    let value = || {
+        operands
+            .value
+            .ok_or_else(|| anyhow!("{:?} requires a value operand.", function.operation))
+    };
+    let lane = || {
+        operands
+            .lane
+            .ok_or_else(|| anyhow!("{:?} requires a lane operand.", function.operation))
+    };
+
+    let result_type = function.ty.to_spirv(module, features);
+    let scope = module.constant_u32(SPIRVScope::Subgroup as u32);
+
+    use AIRSIMDOperation as Op;
+
+    match (function.scope, function.operation) {
+        (
+            scope_kind,
+            operation @ (Op::Sum | Op::Product | Op::Min | Op::Max | Op::And | Op::Or | Op::Xor),
+        ) => {
+            let op = reduction_op(operation, function.ty.scalar(), features)?;
+
+            match scope_kind {
+                AIRSIMDScope::SIMDGroup => {
+                    require(
+                        module,
+                        subgroup.arithmetic,
+                        SPIRVCapability::GroupNonUniformArithmetic,
+                    )?;
+
+                    let result = module.emit_value(
+                        op,
+                        result_type,
+                        &[scope, SPIRVGroupOperation::Reduce as u32, value()?],
+                    );
+
+                    Ok(function.ty.narrow_emulated(module, features, result))
+                }
+                AIRSIMDScope::QuadGroup => {
+                    require(
+                        module,
+                        subgroup.clustered,
+                        SPIRVCapability::GroupNonUniformClustered,
+                    )?;
+
+                    let cluster_size = module.constant_u32(4);
+
+                    let result = module.emit_value(
+                        op,
+                        result_type,
+                        &[
+                            scope,
+                            SPIRVGroupOperation::ClusteredReduce as u32,
+                            value()?,
+                            cluster_size,
+                        ],
+                    );
+
+                    Ok(function.ty.narrow_emulated(module, features, result))
+                }
+            }
+        }
+        (
+            AIRSIMDScope::SIMDGroup,
+            operation @ (Op::PrefixInclusiveSum
+            | Op::PrefixExclusiveSum
+            | Op::PrefixInclusiveProduct
+            | Op::PrefixExclusiveProduct),
+        ) => {
+            require(
+                module,
+                subgroup.arithmetic,
+                SPIRVCapability::GroupNonUniformArithmetic,
+            )?;
+
+            let (reduction, group_operation) = match operation {
+                Op::PrefixInclusiveSum => (Op::Sum, SPIRVGroupOperation::InclusiveScan),
+                Op::PrefixExclusiveSum => (Op::Sum, SPIRVGroupOperation::ExclusiveScan),
+                Op::PrefixInclusiveProduct => (Op::Product, SPIRVGroupOperation::InclusiveScan),
+                _ => (Op::Product, SPIRVGroupOperation::ExclusiveScan),
+            };
+            let op = reduction_op(reduction, function.ty.scalar(), features)?;
+
+            let result =
+                module.emit_value(op, result_type, &[scope, group_operation as u32, value()?]);
+
+            Ok(function.ty.narrow_emulated(module, features, result))
+        }
+        (AIRSIMDScope::SIMDGroup, operation @ (Op::All | Op::Any)) => {
+            require(module, subgroup.vote, SPIRVCapability::GroupNonUniformVote)?;
+
+            let op = match operation {
+                Op::All => SPIRVOp::GroupNonUniformAll,
+                _ => SPIRVOp::GroupNonUniformAny,
+            };
+
+            Ok(module.emit_value(op, result_type, &[scope, value()?]))
+        }
+        (AIRSIMDScope::QuadGroup, operation @ (Op::All | Op::Any)) => {
+            require(
+                module,
+                subgroup.clustered,
+                SPIRVCapability::GroupNonUniformClustered,
+            )?;
+
+            let op = match operation {
+                Op::All => SPIRVOp::GroupNonUniformLogicalAnd,
+                _ => SPIRVOp::GroupNonUniformLogicalOr,
+            };
+            let cluster_size = module.constant_u32(4);
+
+            Ok(module.emit_value(
+                op,
+                result_type,
+                &[
+                    scope,
+                    SPIRVGroupOperation::ClusteredReduce as u32,
+                    value()?,
+                    cluster_size,
+                ],
+            ))
+        }
+        (AIRSIMDScope::SIMDGroup, operation @ (Op::Ballot | Op::ActiveThreadsMask)) => {
+            require(
+                module,
+                subgroup.ballot,
+                SPIRVCapability::GroupNonUniformBallot,
+            )?;
+
+            let predicate = match operation {
+                Op::Ballot => value()?,
+                _ => module.constant_bool(true),
+            };
+
+            let u32_type = module.type_int(32, false);
+            let uvec4_type = module.type_vector(u32_type, 4);
+            let ballot = module.emit_value(
+                SPIRVOp::GroupNonUniformBallot,
+                uvec4_type,
+                &[scope, predicate],
+            );
+
+            // Metal's `simd_vote` is 64 bits wide, Vulkan ballots are always
+            // 128 bits, of which only the low bits can be set in practice.
+            match function.ty {
+                AIRType::Scalar(scalar @ AIRScalarType::Int { width: 64, .. }) => {
+                    let uvec2_type = module.type_vector(u32_type, 2);
+                    let low = module.emit_value(
+                        SPIRVOp::VectorShuffle,
+                        uvec2_type,
+                        &[ballot, ballot, 0, 1],
+                    );
+
+                    // An emulated `ulong` is the `uint2` already.
+                    match scalar.representation(features) {
+                        SPIRVScalarRepresentation::Split => Ok(low),
+                        _ => Ok(module.emit_value(SPIRVOp::Bitcast, result_type, &[low])),
+                    }
+                }
+                AIRType::Scalar(AIRScalarType::Int { width: 32, .. }) => {
+                    Ok(module.emit_value(SPIRVOp::CompositeExtract, result_type, &[ballot, 0]))
+                }
+                ty => Err(anyhow!("Ballot into {:?} not implemented.", ty)),
+            }
+        }
+        (AIRSIMDScope::SIMDGroup, Op::IsFirst) => {
+            require(module, subgroup.basic, SPIRVCapability::GroupNonUniform)?;
+
+            Ok(module.emit_value(SPIRVOp::GroupNonUniformElect, result_type, &[scope]))
+        }
+        (AIRSIMDScope::SIMDGroup, Op::BroadcastFirst) => {
+            require(
+                module,
+                subgroup.ballot,
+                SPIRVCapability::GroupNonUniformBallot,
+            )?;
+
+            Ok(module.emit_value(
+                SPIRVOp::GroupNonUniformBroadcastFirst,
+                result_type,
+                &[scope, value()?],
+            ))
+        }
+        // Before SPIR-V 1.5 `OpGroupNonUniformBroadcast` needs a constant
+        // lane, a dynamic one is the same as a shuffle.
+        (AIRSIMDScope::SIMDGroup, Op::Broadcast) => match lane()? {
+            AIRLane::Constant(index) => {
+                require(
+                    module,
+                    subgroup.ballot,
+                    SPIRVCapability::GroupNonUniformBallot,
+                )?;
+
+                let index = module.constant_u32(index);
+
+                Ok(module.emit_value(
+                    SPIRVOp::GroupNonUniformBroadcast,
+                    result_type,
+                    &[scope, value()?, index],
+                ))
+            }
+            AIRLane::Dynamic(index) => {
+                require(
+                    module,
+                    subgroup.shuffle,
+                    SPIRVCapability::GroupNonUniformShuffle,
+                )?;
+
+                Ok(module.emit_value(
+                    SPIRVOp::GroupNonUniformShuffle,
+                    result_type,
+                    &[scope, value()?, index],
+                ))
+            }
+        },
+        (AIRSIMDScope::SIMDGroup, operation @ (Op::Shuffle | Op::ShuffleXor)) => {
+            require(
+                module,
+                subgroup.shuffle,
+                SPIRVCapability::GroupNonUniformShuffle,
+            )?;
+
+            let op = match operation {
+                Op::Shuffle => SPIRVOp::GroupNonUniformShuffle,
+                _ => SPIRVOp::GroupNonUniformShuffleXor,
+            };
+            let lane = lane_id(module, lane()?);
+
+            Ok(module.emit_value(op, result_type, &[scope, value()?, lane]))
+        }
+        (AIRSIMDScope::SIMDGroup, operation @ (Op::ShuffleUp | Op::ShuffleDown)) => {
+            require(
+                module,
+                subgroup.shuffle_relative,
+                SPIRVCapability::GroupNonUniformShuffleRelative,
+            )?;
+
+            let op = match operation {
+                Op::ShuffleUp => SPIRVOp::GroupNonUniformShuffleUp,
+                _ => SPIRVOp::GroupNonUniformShuffleDown,
+            };
+            let delta = lane_id(module, lane()?);
+
+            Ok(module.emit_value(op, result_type, &[scope, value()?, delta]))
+        }
+        (AIRSIMDScope::QuadGroup, Op::Broadcast) => match lane()? {
+            AIRLane::Constant(index) => {
+                require(module, subgroup.quad, SPIRVCapability::GroupNonUniformQuad)?;
+
+                let index = module.constant_u32(index);
+
+                Ok(module.emit_value(
+                    SPIRVOp::GroupNonUniformQuadBroadcast,
+                    result_type,
+                    &[scope, value()?, index],
+                ))
+            }
+            lane => lower_quad_shuffle(module, subgroup, result_type, value()?, lane),
+        },
+        (AIRSIMDScope::QuadGroup, Op::Shuffle) => {
+            lower_quad_shuffle(module, subgroup, result_type, value()?, lane()?)
+        }
+        (AIRSIMDScope::QuadGroup, Op::ShuffleXor) => match lane()? {
+            // Directions of `OpGroupNonUniformQuadSwap`: horizontal (lane ^ 1),
+            // vertical (lane ^ 2) and diagonal (lane ^ 3).
+            AIRLane::Constant(mask @ 1..=3) => {
+                require(module, subgroup.quad, SPIRVCapability::GroupNonUniformQuad)?;
+
+                let direction = module.constant_u32(mask - 1);
+
+                Ok(module.emit_value(
+                    SPIRVOp::GroupNonUniformQuadSwap,
+                    result_type,
+                    &[scope, value()?, direction],
+                ))
+            }
+            lane => {
+                require(
+                    module,
+                    subgroup.shuffle,
+                    SPIRVCapability::GroupNonUniformShuffle,
+                )?;
+
+                // Masks below 4 keep the lane inside its quad.
+                let mask = lane_id(module, lane);
+                let three = module.constant_u32(3);
+                let u32_type = module.type_int(32, false);
+                let mask = module.emit_value(SPIRVOp::BitwiseAnd, u32_type, &[mask, three]);
+
+                Ok(module.emit_value(
+                    SPIRVOp::GroupNonUniformShuffleXor,
+                    result_type,
+                    &[scope, value()?, mask],
+                ))
+            }
+        },
+        (scope_kind, operation) => Err(anyhow!(
+            "{:?} is not implemented for {:?}.",
+            operation,
+            scope_kind
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRSIMDBuiltIn {
+    ThreadIndexInSIMDGroup,
+    ThreadsPerSIMDGroup,
+    SIMDGroupIndexInThreadgroup,
+    SIMDGroupsPerThreadgroup,
+    ThreadIndexInQuadGroup,
+}
+
+impl AIRSIMDBuiltIn {
+    pub fn from_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "air.thread_index_in_simdgroup" => Self::ThreadIndexInSIMDGroup,
+            "air.threads_per_simdgroup" => Self::ThreadsPerSIMDGroup,
+            "air.simdgroup_index_in_threadgroup" => Self::SIMDGroupIndexInThreadgroup,
+            "air.simdgroups_per_threadgroup" => Self::SIMDGroupsPerThreadgroup,
+            "air.thread_index_in_quadgroup" => Self::ThreadIndexInQuadGroup,
+            _ => return Err(anyhow!("`{}` is not a SIMD-group built-in.", name)),
+        })
+    }
+}
+
+/// Loads the value of a SIMD-group built-in as a 32-bit unsigned integer.
+pub fn lower_simd_builtin(
+    module: &mut SPIRVModule,
+    features: &SPIRVTargetFeatures,
+    stage: AIRShaderStage,
+    builtin: AIRSIMDBuiltIn,
+) -> Result<u32> {
+    require_stage(&features.subgroup, stage)?;
+    require(
+        module,
+        features.subgroup.basic,
+        SPIRVCapability::GroupNonUniform,
+    )?;
+
+    let u32_type = module.type_int(32, false);
+
+    let spirv_builtin = match builtin {
+        AIRSIMDBuiltIn::ThreadIndexInSIMDGroup | AIRSIMDBuiltIn::ThreadIndexInQuadGroup => {
+            SPIRVBuiltIn::SubgroupLocalInvocationId
+        }
+        AIRSIMDBuiltIn::ThreadsPerSIMDGroup => SPIRVBuiltIn::SubgroupSize,
+        // Vulkan only exposes these to compute shaders.
+        AIRSIMDBuiltIn::SIMDGroupIndexInThreadgroup | AIRSIMDBuiltIn::SIMDGroupsPerThreadgroup
+            if stage != AIRShaderStage::Kernel =>
+        {
+            return Err(anyhow!("{:?} is only available in kernels.", builtin));
+        }
+        AIRSIMDBuiltIn::SIMDGroupIndexInThreadgroup => SPIRVBuiltIn::SubgroupId,
+        AIRSIMDBuiltIn::SIMDGroupsPerThreadgroup => SPIRVBuiltIn::NumSubgroups,
+    };
+
+    let value = module.load_builtin_input(spirv_builtin, u32_type);
+
+    if builtin == AIRSIMDBuiltIn::ThreadIndexInQuadGroup {
+        let three = module.constant_u32(3);
+        return Ok(module.emit_value(SPIRVOp::BitwiseAnd, u32_type, &[value, three]));
+    }
+
+    Ok(value)
+}
+
+/// `quad_shuffle` and `quad_broadcast` with a dynamic lane, as a subgroup
+/// shuffle from `(lane_in_subgroup & ~3) + lane`.
+fn lower_quad_shuffle(
+    module: &mut SPIRVModule,
+    subgroup: &SPIRVSubgroupFeatures,
+    result_type: u32,
+    value: u32,
+    lane: AIRLane,
+) -> Result<u32> {
+    require(
+        module,
+        subgroup.shuffle,
+        SPIRVCapability::GroupNonUniformShuffle,
+    )?;
+
+    let u32_type = module.type_int(32, false);
+    let scope = module.constant_u32(SPIRVScope::Subgroup as u32);
+
+    let local = module.load_builtin_input(SPIRVBuiltIn::SubgroupLocalInvocationId, u32_type);
+    let quad_mask = module.constant_u32(!3);
+    let quad_base = module.emit_value(SPIRVOp::BitwiseAnd, u32_type, &[local, quad_mask]);
+
+    let lane = lane_id(module, lane);
+    let three = module.constant_u32(3);
+    let lane = module.emit_value(SPIRVOp::BitwiseAnd, u32_type, &[lane, three]);
+    let index = module.emit_value(SPIRVOp::IAdd, u32_type, &[quad_base, lane]);
+
+    Ok(module.emit_value(
+        SPIRVOp::GroupNonUniformShuffle,
+        result_type,
+        &[scope, value, index],
+    ))
+}
+
+fn reduction_op(
+    operation: AIRSIMDOperation,
+    scalar: AIRScalarType,
+    features: &SPIRVTargetFeatures,
+) -> Result<SPIRVOp> {
+    use AIRSIMDOperation as Op;
+
+    if scalar.representation(features) == SPIRVScalarRepresentation::Split {
+        return Err(anyhow!(
+            "{:?} on {} needs shaderInt64, which this device lacks.",
+            operation,
+            scalar.metal_name()
+        ));
+    }
+
+    Ok(match (operation, scalar) {
+        (Op::Sum, AIRScalarType::Int { .. }) => SPIRVOp::GroupNonUniformIAdd,
+        (Op::Sum, AIRScalarType::Float { .. } | AIRScalarType::BFloat) => {
+            SPIRVOp::GroupNonUniformFAdd
+        }
+        (Op::Product, AIRScalarType::Int { .. }) => SPIRVOp::GroupNonUniformIMul,
+        (Op::Product, AIRScalarType::Float { .. } | AIRScalarType::BFloat) => {
+            SPIRVOp::GroupNonUniformFMul
+        }
+        (Op::Min, AIRScalarType::Int { signed: true, .. }) => SPIRVOp::GroupNonUniformSMin,
+        (Op::Min, AIRScalarType::Int { signed: false, .. }) => SPIRVOp::GroupNonUniformUMin,
+        (Op::Min, AIRScalarType::Float { .. } | AIRScalarType::BFloat) => {
+            SPIRVOp::GroupNonUniformFMin
+        }
+        (Op::Max, AIRScalarType::Int { signed: true, .. }) => SPIRVOp::GroupNonUniformSMax,
+        (Op::Max, AIRScalarType::Int { signed: false, .. }) => SPIRVOp::GroupNonUniformUMax,
+        (Op::Max, AIRScalarType::Float { .. } | AIRScalarType::BFloat) => {
+            SPIRVOp::GroupNonUniformFMax
+        }
+        (Op::And, AIRScalarType::Int { .. }) => SPIRVOp::GroupNonUniformBitwiseAnd,
+        (Op::And, AIRScalarType::Bool) => SPIRVOp::GroupNonUniformLogicalAnd,
+        (Op::Or, AIRScalarType::Int { .. }) => SPIRVOp::GroupNonUniformBitwiseOr,
+        (Op::Or, AIRScalarType::Bool) => SPIRVOp::GroupNonUniformLogicalOr,
+        (Op::Xor, AIRScalarType::Int { .. }) => SPIRVOp::GroupNonUniformBitwiseXor,
+        (Op::Xor, AIRScalarType::Bool) => SPIRVOp::GroupNonUniformLogicalXor,
+        (operation, scalar) => {
+            return Err(anyhow!("{:?} on {:?} not implemented.", operation, scalar));
+        }
+    })
+}
+
+fn lane_id(module: &mut SPIRVModule, lane: AIRLane) -> u32 {
+    match lane {
+        AIRLane::Constant(value) => module.constant_u32(value),
+        AIRLane::Dynamic(id) => id,
+    }
+}
+
+/// Enables `capability` if the device reports the matching operation class.
+/// Every class implies the basic one.
+fn require(module: &mut SPIRVModule, supported: bool, capability: SPIRVCapability) -> Result<()> {
+    if !supported {
+        return Err(anyhow!("{:?} is not supported by this device.", capability));
+    }
+
+    module.capability(SPIRVCapability::GroupNonUniform);
+    module.capability(capability);
+
+    Ok(())
+}
+
+fn require_stage(subgroup: &SPIRVSubgroupFeatures, stage: AIRShaderStage) -> Result<()> {
+    let supported = match stage {
+        AIRShaderStage::Vertex => subgroup.in_vertex,
+        AIRShaderStage::Fragment => subgroup.in_fragment,
+        AIRShaderStage::Kernel => subgroup.in_kernel,
+    };
+
+    if !supported {
+        return Err(anyhow!(
+            "Subgroup operations are not supported in {:?} shaders on this device.",
+            stage
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_intrinsic_names() {
+        assert_eq!(
+            AIRSIMDFunction::from_intrinsic("air.simd_max.s.i32").unwrap(),
+            AIRSIMDFunction {
+                scope: AIRSIMDScope::SIMDGroup,
+                operation: AIRSIMDOperation::Max,
+                ty: AIRType::Scalar(AIRScalarType::Int {
+                    width: 32,
+                    signed: true
+                }),
+            }
+        );
+        assert_eq!(
+            AIRSIMDFunction::from_intrinsic("air.quad_shuffle_xor.v4f16").unwrap(),
+            AIRSIMDFunction {
+                scope: AIRSIMDScope::QuadGroup,
+                operation: AIRSIMDOperation::ShuffleXor,
+                ty: AIRType::Vector(AIRScalarType::Float { width: 16 }, 4),
+            }
+        );
+        assert_eq!(
+            AIRSIMDFunction::from_intrinsic("air.simd_is_first")
+                .unwrap()
+                .ty,
+            AIRType::Scalar(AIRScalarType::Bool)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_names() {
+        for name in [
+            "air.simd_sum",
+            "air.simd_sum.",
+            "air.simd_frobnicate.f32",
+            "air.wg.barrier",
+            "llvm.simd_sum.f32",
+            "air",
+        ] {
+            assert!(
+                AIRSIMDFunction::from_intrinsic(name).is_err(),
+                "{} was accepted.",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,960 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+pub const SPIRV_MAGIC: u32 = 0x07230203;
+pub const SPIRV_VERSION_1_3: u32 = 0x00010300;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVOp {
+    Undef = 1,
+    Source = 3,
+    String = 7,
+    Line = 8,
+    Extension = 10,
+    ExtInstImport = 11,
+    ExtInst = 12,
+    MemoryModel = 14,
+    EntryPoint = 15,
+    ExecutionMode = 16,
+    Capability = 17,
+    TypeVoid = 19,
+    TypeBool = 20,
+    TypeInt = 21,
+    TypeFloat = 22,
+    TypeVector = 23,
+    TypeArray = 28,
+    TypeRuntimeArray = 29,
+    TypeStruct = 30,
+    TypePointer = 32,
+    TypeFunction = 33,
+    ConstantTrue = 41,
+    ConstantFalse = 42,
+    Constant = 43,
+    ConstantComposite = 44,
+    ConstantNull = 46,
+    SpecConstant = 50,
+    SpecConstantComposite = 51,
+    Function = 54,
+    FunctionEnd = 56,
+    Variable = 59,
+    ImageTexelPointer = 60,
+    Load = 61,
+    Store = 62,
+    AccessChain = 65,
+    Decorate = 71,
+    MemberDecorate = 72,
+    VectorExtractDynamic = 77,
+    VectorInsertDynamic = 78,
+    VectorShuffle = 79,
+    CompositeConstruct = 80,
+    CompositeExtract = 81,
+    CompositeInsert = 82,
+    ConvertFToU = 109,
+    ConvertFToS = 110,
+    ConvertSToF = 111,
+    ConvertUToF = 112,
+    UConvert = 113,
+    SConvert = 114,
+    FConvert = 115,
+    QuantizeToF16 = 116,
+    Bitcast = 124,
+    SNegate = 126,
+    FNegate = 127,
+    IAdd = 128,
+    FAdd = 129,
+    ISub = 130,
+    FSub = 131,
+    IMul = 132,
+    FMul = 133,
+    UDiv = 134,
+    SDiv = 135,
+    FDiv = 136,
+    UMod = 137,
+    SRem = 138,
+    FRem = 140,
+    Dot = 148,
+    IsNan = 156,
+    IsInf = 157,
+    LogicalEqual = 164,
+    LogicalNotEqual = 165,
+    LogicalOr = 166,
+    LogicalAnd = 167,
+    LogicalNot = 168,
+    Select = 169,
+    IEqual = 170,
+    INotEqual = 171,
+    UGreaterThan = 172,
+    SGreaterThan = 173,
+    UGreaterThanEqual = 174,
+    SGreaterThanEqual = 175,
+    ULessThan = 176,
+    SLessThan = 177,
+    ULessThanEqual = 178,
+    SLessThanEqual = 179,
+    FOrdEqual = 180,
+    FUnordEqual = 181,
+    FOrdNotEqual = 182,
+    FUnordNotEqual = 183,
+    FOrdLessThan = 184,
+    FUnordLessThan = 185,
+    FOrdGreaterThan = 186,
+    FUnordGreaterThan = 187,
+    FOrdLessThanEqual = 188,
+    FUnordLessThanEqual = 189,
+    FOrdGreaterThanEqual = 190,
+    FUnordGreaterThanEqual = 191,
+    ShiftRightLogical = 194,
+    ShiftRightArithmetic = 195,
+    ShiftLeftLogical = 196,
+    BitwiseOr = 197,
+    BitwiseXor = 198,
+    BitwiseAnd = 199,
+    Not = 200,
+    BitCount = 205,
+    ControlBarrier = 224,
+    MemoryBarrier = 225,
+    Phi = 245,
+    LoopMerge = 246,
+    SelectionMerge = 247,
+    Label = 248,
+    Branch = 249,
+    BranchConditional = 250,
+    Switch = 251,
+    Kill = 252,
+    Return = 253,
+    ReturnValue = 254,
+    Unreachable = 255,
+    NoLine = 317,
+    AtomicLoad = 227,
+    AtomicStore = 228,
+    AtomicExchange = 229,
+    AtomicCompareExchange = 230,
+    AtomicIAdd = 234,
+    AtomicISub = 235,
+    AtomicSMin = 236,
+    AtomicUMin = 237,
+    AtomicSMax = 238,
+    AtomicUMax = 239,
+    AtomicAnd = 240,
+    AtomicOr = 241,
+    AtomicXor = 242,
+    GroupNonUniformElect = 333,
+    GroupNonUniformAll = 334,
+    GroupNonUniformAny = 335,
+    GroupNonUniformBroadcast = 337,
+    GroupNonUniformBroadcastFirst = 338,
+    GroupNonUniformBallot = 339,
+    GroupNonUniformShuffle = 345,
+    GroupNonUniformShuffleXor = 346,
+    GroupNonUniformShuffleUp = 347,
+    GroupNonUniformShuffleDown = 348,
+    GroupNonUniformIAdd = 349,
+    GroupNonUniformFAdd = 350,
+    GroupNonUniformIMul = 351,
+    GroupNonUniformFMul = 352,
+    GroupNonUniformSMin = 353,
+    GroupNonUniformUMin = 354,
+    GroupNonUniformFMin = 355,
+    GroupNonUniformSMax = 356,
+    GroupNonUniformUMax = 357,
+    GroupNonUniformFMax = 358,
+    GroupNonUniformBitwiseAnd = 359,
+    GroupNonUniformBitwiseOr = 360,
+    GroupNonUniformBitwiseXor = 361,
+    GroupNonUniformLogicalAnd = 362,
+    GroupNonUniformLogicalOr = 363,
+    GroupNonUniformLogicalXor = 364,
+    GroupNonUniformQuadBroadcast = 365,
+    GroupNonUniformQuadSwap = 366,
+    DemoteToHelperInvocationEXT = 5380,
+    AtomicFAddEXT = 6035,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SPIRVCapability {
+    Shader = 1,
+    Float16 = 9,
+    Float64 = 10,
+    Int64 = 11,
+    Int64Atomics = 12,
+    Int16 = 22,
+    SampleRateShading = 35,
+    Int8 = 39,
+    GroupNonUniform = 61,
+    GroupNonUniformVote = 62,
+    GroupNonUniformArithmetic = 63,
+    GroupNonUniformBallot = 64,
+    GroupNonUniformShuffle = 65,
+    GroupNonUniformShuffleRelative = 66,
+    GroupNonUniformClustered = 67,
+    GroupNonUniformQuad = 68,
+    DrawParameters = 4427,
+    StorageBuffer16BitAccess = 4433,
+    StorageBuffer8BitAccess = 4448,
+    StencilExportEXT = 5013,
+    ShaderNonUniform = 5301,
+    RuntimeDescriptorArray = 5302,
+    PhysicalStorageBufferAddresses = 5347,
+    DemoteToHelperInvocationEXT = 5379,
+    Int64ImageEXT = 5016,
+    AtomicFloat32AddEXT = 6033,
+    AtomicFloat64AddEXT = 6034,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVStorageClass {
+    UniformConstant = 0,
+    Input = 1,
+    Uniform = 2,
+    Output = 3,
+    Workgroup = 4,
+    Private = 6,
+    Function = 7,
+    PushConstant = 9,
+    Image = 11,
+    StorageBuffer = 12,
+    PhysicalStorageBuffer = 5349,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVScope {
+    CrossDevice = 0,
+    Device = 1,
+    Workgroup = 2,
+    Subgroup = 3,
+    Invocation = 4,
+    QueueFamily = 5,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVDecoration {
+    SpecId = 1,
+    Block = 2,
+    ArrayStride = 6,
+    BuiltIn = 11,
+    NoPerspective = 13,
+    Flat = 14,
+    Centroid = 16,
+    Sample = 17,
+    NonWritable = 24,
+    Location = 30,
+    Binding = 33,
+    DescriptorSet = 34,
+    Offset = 35,
+    NonUniform = 5300,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SPIRVBuiltIn {
+    Position = 0,
+    PointSize = 1,
+    FragCoord = 15,
+    PointCoord = 16,
+    FrontFacing = 17,
+    SampleId = 18,
+    SampleMask = 20,
+    FragDepth = 22,
+    NumWorkgroups = 24,
+    WorkgroupSize = 25,
+    WorkgroupId = 26,
+    LocalInvocationId = 27,
+    GlobalInvocationId = 28,
+    LocalInvocationIndex = 29,
+    SubgroupSize = 36,
+    NumSubgroups = 38,
+    SubgroupId = 40,
+    SubgroupLocalInvocationId = 41,
+    VertexIndex = 42,
+    InstanceIndex = 43,
+    BaseVertex = 4424,
+    BaseInstance = 4425,
+    FragStencilRefEXT = 5014,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVExecutionModel {
+    Vertex = 0,
+    Fragment = 4,
+    GLCompute = 5,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVExecutionMode {
+    OriginUpperLeft = 7,
+    EarlyFragmentTests = 9,
+    DepthReplacing = 12,
+    DepthGreater = 14,
+    DepthLess = 15,
+    DepthUnchanged = 16,
+    LocalSize = 17,
+    StencilRefReplacingEXT = 5027,
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPIRVGroupOperation {
+    Reduce = 0,
+    InclusiveScan = 1,
+    ExclusiveScan = 2,
+    ClusteredReduce = 3,
+}
+
+/// Bit flags for the `Memory Semantics <id>` operand of barriers and atomics.
+pub mod memory_semantics {
+    pub const NONE: u32 = 0x0;
+    pub const ACQUIRE: u32 = 0x2;
+    pub const RELEASE: u32 = 0x4;
+    pub const ACQUIRE_RELEASE: u32 = 0x8;
+    pub const SEQUENTIALLY_CONSISTENT: u32 = 0x10;
+    pub const UNIFORM_MEMORY: u32 = 0x40;
+    pub const SUBGROUP_MEMORY: u32 = 0x80;
+    pub const WORKGROUP_MEMORY: u32 = 0x100;
+    pub const IMAGE_MEMORY: u32 = 0x800;
+}
+
+/// What the target Vulkan device can execute. Lowering consults this
+/// before emitting instructions that need an optional capability.
+#[derive(Debug, Default, Clone)]
+pub struct SPIRVTargetFeatures {
+    pub buffer_int64_atomics: bool,
+    pub shared_int64_atomics: bool,
+    pub image_int64_atomics: bool,
+    pub buffer_float32_atomics: bool,
+    pub shared_float32_atomics: bool,
+    pub image_float32_atomics: bool,
+    pub buffer_float32_atomic_add: bool,
+    pub shared_float32_atomic_add: bool,
+    pub image_float32_atomic_add: bool,
+    pub buffer_float64_atomic_add: bool,
+    pub shared_float64_atomic_add: bool,
+    pub subgroup: SPIRVSubgroupFeatures,
+    /// Unsized, partially bound descriptor arrays with a variable count.
+    pub runtime_descriptor_arrays: bool,
+    /// Non-uniform indexing into sampled image, storage image and storage
+    /// buffer arrays.
+    pub non_uniform_indexing: bool,
+    pub buffer_device_address: bool,
+    /// `OpDemoteToHelperInvocationEXT`, which keeps discarded fragments
+    /// around for derivatives like Metal does.
+    pub demote_to_helper_invocation: bool,
+    /// `VK_EXT_shader_stencil_export`.
+    pub stencil_export: bool,
+    /// Arithmetic on 8, 16 and 64-bit types. Without these, types are
+    /// emulated in 32 bits.
+    pub int8: bool,
+    pub int16: bool,
+    pub int64: bool,
+    pub float16: bool,
+    /// 8 and 16-bit types in storage buffers.
+    pub storage_buffer_8bit: bool,
+    pub storage_buffer_16bit: bool,
+    /// Buffer members aligned to their scalar, needed for packed vectors.
+    pub scalar_block_layout: bool,
+    /// `VK_KHR_shader_non_semantic_info`, for source-level debug info.
+    pub non_semantic_info: bool,
+}
+
+/// Mirrors `VkPhysicalDeviceSubgroupProperties`: the subgroup size, the
+/// operation classes the device implements and the stages they work in.
+#[derive(Debug, Default, Clone)]
+pub struct SPIRVSubgroupFeatures {
+    pub size: u32,
+    pub basic: bool,
+    pub vote: bool,
+    pub arithmetic: bool,
+    pub ballot: bool,
+    pub shuffle: bool,
+    pub shuffle_relative: bool,
+    pub clustered: bool,
+    pub quad: bool,
+    pub in_vertex: bool,
+    pub in_fragment: bool,
+    pub in_kernel: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SPIRVInstruction {
+    pub op: SPIRVOp,
+    pub operands: Vec<u32>,
+}
+
+impl SPIRVInstruction {
+    pub fn new(op: SPIRVOp, operands: Vec<u32>) -> Self {
+        Self { op, operands }
+    }
+
+    pub fn write_words(&self, words: &mut Vec<u32>) {
+        let word_count = self.operands.len() as u32 + 1;
+
+        words.push((word_count << 16) | self.op as u32);
+        words.extend_from_slice(&self.operands);
+    }
+}
+
+/// Encodes a literal string operand: UTF-8, nul-terminated and padded to
+/// a whole number of words.
+pub fn string_operands(value: &str) -> Vec<u32> {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize((bytes.len() + 1).next_multiple_of(4), 0);
+
+    bytes
+        .chunks(4)
+        .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// A SPIR-V module under construction. Instructions are kept per logical
+/// section and only laid out in the order the spec requires in `words`.
+#[derive(Debug)]
+pub struct SPIRVModule {
+    bound: u32,
+    capabilities: BTreeSet<SPIRVCapability>,
+    extensions: BTreeSet<String>,
+    ext_inst_imports: BTreeMap<String, u32>,
+    entry_points: Vec<SPIRVInstruction>,
+    execution_modes: Vec<SPIRVInstruction>,
+    debug: Vec<SPIRVInstruction>,
+    strings: HashMap<String, u32>,
+    annotations: Vec<SPIRVInstruction>,
+    globals: Vec<SPIRVInstruction>,
+    functions: Vec<SPIRVInstruction>,
+    global_cache: HashMap<(SPIRVOp, Vec<u32>), u32>,
+    builtin_inputs: BTreeMap<SPIRVBuiltIn, u32>,
+    spec_constants: BTreeMap<u32, u32>,
+    interface: Vec<u32>,
+    physical_addressing: bool,
+}
+
+impl Default for SPIRVModule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SPIRVModule {
+    pub fn new() -> Self {
+        let mut capabilities = BTreeSet::new();
+        capabilities.insert(SPIRVCapability::Shader);
+
+        Self {
+            bound: 1,
+            capabilities,
+            extensions: BTreeSet::new(),
+            ext_inst_imports: BTreeMap::new(),
+            entry_points: vec![],
+            execution_modes: vec![],
+            debug: vec![],
+            strings: HashMap::new(),
+            annotations: vec![],
+            globals: vec![],
+            functions: vec![],
+            global_cache: HashMap::new(),
+            builtin_inputs: BTreeMap::new(),
+            spec_constants: BTreeMap::new(),
+            interface: vec![],
+            physical_addressing: false,
+        }
+    }
+
+    pub fn id(&mut self) -> u32 {
+        let id = self.bound;
+        self.bound += 1;
+        id
+    }
+
+    pub fn capability(&mut self, capability: SPIRVCapability) {
+        self.capabilities.insert(capability);
+    }
+
+    pub fn has_capability(&self, capability: SPIRVCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    pub fn extension(&mut self, name: &str) {
+        self.extensions.insert(name.to_string());
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.contains(name)
+    }
+
+    /// Imports an extended instruction set like `GLSL.std.450`, returning
+    /// the existing id if it was imported before.
+    pub fn ext_inst_import(&mut self, name: &str) -> u32 {
+        if let Some(id) = self.ext_inst_imports.get(name) {
+            return *id;
+        }
+
+        let id = self.id();
+        self.ext_inst_imports.insert(name.to_string(), id);
+
+        id
+    }
+
+    /// Declares an `OpString`, deduplicated.
+    pub fn string(&mut self, value: &str) -> u32 {
+        if let Some(id) = self.strings.get(value) {
+            return *id;
+        }
+
+        let id = self.id();
+
+        let mut operands = vec![id];
+        operands.extend(string_operands(value));
+
+        self.debug
+            .push(SPIRVInstruction::new(SPIRVOp::String, operands));
+        self.strings.insert(value.to_string(), id);
+
+        id
+    }
+
+    /// Appends an instruction to the debug section, after `OpString`s.
+    pub fn debug(&mut self, op: SPIRVOp, operands: Vec<u32>) {
+        self.debug.push(SPIRVInstruction::new(op, operands));
+    }
+
+    /// Emits a module-scope extended instruction returning `void`, which
+    /// only non-semantic instruction sets allow.
+    pub fn global_ext_inst(&mut self, set: u32, instruction: u32, operands: &[u32]) -> u32 {
+        let void = self.type_void();
+        let id = self.id();
+
+        let mut words = vec![void, id, set, instruction];
+        words.extend_from_slice(operands);
+
+        self.globals
+            .push(SPIRVInstruction::new(SPIRVOp::ExtInst, words));
+
+        id
+    }
+
+    /// Emits an extended instruction in the function section.
+    pub fn ext_inst(
+        &mut self,
+        result_type: u32,
+        set: u32,
+        instruction: u32,
+        operands: &[u32],
+    ) -> u32 {
+        let mut words = vec![set, instruction];
+        words.extend_from_slice(operands);
+
+        self.emit_value(SPIRVOp::ExtInst, result_type, &words)
+    }
+
+    /// Switches the module to the `PhysicalStorageBuffer64` addressing model
+    /// so it can dereference buffer device addresses.
+    pub fn enable_physical_storage_buffer(&mut self) {
+        self.capability(SPIRVCapability::PhysicalStorageBufferAddresses);
+        self.extension("SPV_KHR_physical_storage_buffer");
+        self.physical_addressing = true;
+    }
+
+    /// Emits a type, returning the existing id if an identical declaration
+    /// was already made.
+    pub fn global(&mut self, op: SPIRVOp, operands: Vec<u32>) -> u32 {
+        let key = (op, operands);
+
+        if let Some(id) = self.global_cache.get(&key) {
+            return *id;
+        }
+
+        let id = self.id();
+
+        let mut words = vec![id];
+        words.extend_from_slice(&key.1);
+
+        self.globals.push(SPIRVInstruction::new(op, words));
+        self.global_cache.insert(key, id);
+
+        id
+    }
+
+    /// Emits a constant, deduplicated like `global`. Unlike types, constants
+    /// carry their result type before the result id.
+    pub fn constant(&mut self, op: SPIRVOp, result_type: u32, literals: &[u32]) -> u32 {
+        let mut key_operands = vec![result_type];
+        key_operands.extend_from_slice(literals);
+        let key = (op, key_operands);
+
+        if let Some(id) = self.global_cache.get(&key) {
+            return *id;
+        }
+
+        let id = self.id();
+
+        let mut words = vec![result_type, id];
+        words.extend_from_slice(literals);
+
+        self.globals.push(SPIRVInstruction::new(op, words));
+        self.global_cache.insert(key, id);
+
+        id
+    }
+
+    /// Declares a module-scope variable. Every call creates a new variable.
+    pub fn variable(&mut self, storage_class: SPIRVStorageClass, pointee: u32) -> u32 {
+        let pointer_type = self.type_pointer(storage_class, pointee);
+        let id = self.id();
+
+        self.globals.push(SPIRVInstruction::new(
+            SPIRVOp::Variable,
+            vec![pointer_type, id, storage_class as u32],
+        ));
+
+        if matches!(
+            storage_class,
+            SPIRVStorageClass::Input | SPIRVStorageClass::Output
+        ) {
+            self.interface.push(id);
+        }
+
+        id
+    }
+
+    /// Declares a module-scope variable with a constant initializer, like
+    /// the `Private` copies of `constant` globals.
+    pub fn variable_with_initializer(
+        &mut self,
+        storage_class: SPIRVStorageClass,
+        pointee: u32,
+        initializer: u32,
+    ) -> u32 {
+        let pointer_type = self.type_pointer(storage_class, pointee);
+        let id = self.id();
+
+        self.globals.push(SPIRVInstruction::new(
+            SPIRVOp::Variable,
+            vec![pointer_type, id, storage_class as u32, initializer],
+        ));
+
+        id
+    }
+
+    /// Loads a built-in input, declaring its variable on first use.
+    pub fn load_builtin_input(&mut self, builtin: SPIRVBuiltIn, ty: u32) -> u32 {
+        let variable = match self.builtin_inputs.get(&builtin) {
+            Some(v) => *v,
+            None => {
+                let v = self.variable(SPIRVStorageClass::Input, ty);
+                self.decorate(v, SPIRVDecoration::BuiltIn, &[builtin as u32]);
+                self.builtin_inputs.insert(builtin, v);
+                v
+            }
+        };
+
+        self.emit_value(SPIRVOp::Load, ty, &[variable])
+    }
+
+    /// Decorates the integer built-in inputs `Flat`, which fragment shaders
+    /// need even though they aren't interpolated.
+    pub fn decorate_flat_builtin_inputs(&mut self) {
+        let flat = self
+            .builtin_inputs
+            .iter()
+            .filter(|(builtin, _)| {
+                matches!(
+                    builtin,
+                    SPIRVBuiltIn::SampleId
+                        | SPIRVBuiltIn::SampleMask
+                        | SPIRVBuiltIn::SubgroupLocalInvocationId
+                        | SPIRVBuiltIn::SubgroupSize
+                )
+            })
+            .map(|(_, variable)| *variable)
+            .collect::<Vec<_>>();
+
+        for variable in flat {
+            self.decorate(variable, SPIRVDecoration::Flat, &[]);
+        }
+    }
+
+    /// The `Input`/`Output` variables an `OpEntryPoint` has to list.
+    pub fn interface(&self) -> &[u32] {
+        &self.interface
+    }
+
+    pub fn type_void(&mut self) -> u32 {
+        self.global(SPIRVOp::TypeVoid, vec![])
+    }
+
+    pub fn type_bool(&mut self) -> u32 {
+        self.global(SPIRVOp::TypeBool, vec![])
+    }
+
+    pub fn type_int(&mut self, width: u32, signed: bool) -> u32 {
+        match width {
+            8 => self.capability(SPIRVCapability::Int8),
+            16 => self.capability(SPIRVCapability::Int16),
+            64 => self.capability(SPIRVCapability::Int64),
+            _ => {}
+        }
+
+        self.global(SPIRVOp::TypeInt, vec![width, signed as u32])
+    }
+
+    pub fn type_float(&mut self, width: u32) -> u32 {
+        match width {
+            16 => self.capability(SPIRVCapability::Float16),
+            64 => self.capability(SPIRVCapability::Float64),
+            _ => {}
+        }
+
+        self.global(SPIRVOp::TypeFloat, vec![width])
+    }
+
+    /// An 8 or 16-bit integer type used only for loads, stores and
+    /// conversions, which needs the storage capabilities instead of `Int8`
+    /// or `Int16`.
+    pub fn type_int_storage(&mut self, width: u32, signed: bool) -> u32 {
+        self.global(SPIRVOp::TypeInt, vec![width, signed as u32])
+    }
+
+    /// Like `type_int_storage`, for `half`.
+    pub fn type_float_storage(&mut self, width: u32) -> u32 {
+        self.global(SPIRVOp::TypeFloat, vec![width])
+    }
+
+    pub fn type_vector(&mut self, component: u32, count: u32) -> u32 {
+        self.global(SPIRVOp::TypeVector, vec![component, count])
+    }
+
+    pub fn type_pointer(&mut self, storage_class: SPIRVStorageClass, pointee: u32) -> u32 {
+        self.global(SPIRVOp::TypePointer, vec![storage_class as u32, pointee])
+    }
+
+    pub fn type_array(&mut self, element: u32, length: u32) -> u32 {
+        let length = self.constant_u32(length);
+        self.global(SPIRVOp::TypeArray, vec![element, length])
+    }
+
+    /// An array laid out with `stride` bytes between elements. Unlike
+    /// `type_array` this isn't deduplicated, so the array of the same element
+    /// used outside of buffers stays free of layout decorations.
+    pub fn type_array_with_stride(&mut self, element: u32, length: u32, stride: u32) -> u32 {
+        let length = self.constant_u32(length);
+        let id = self.id();
+
+        self.globals.push(SPIRVInstruction::new(
+            SPIRVOp::TypeArray,
+            vec![id, element, length],
+        ));
+        self.decorate(id, SPIRVDecoration::ArrayStride, &[stride]);
+
+        id
+    }
+
+    pub fn type_function(&mut self, result: u32, parameters: &[u32]) -> u32 {
+        let mut operands = vec![result];
+        operands.extend_from_slice(parameters);
+
+        self.global(SPIRVOp::TypeFunction, operands)
+    }
+
+    pub fn type_runtime_array(&mut self, element: u32) -> u32 {
+        self.global(SPIRVOp::TypeRuntimeArray, vec![element])
+    }
+
+    /// Struct types are never deduplicated, two structs with the same
+    /// members can carry different decorations.
+    pub fn type_struct(&mut self, members: &[u32]) -> u32 {
+        let id = self.id();
+
+        let mut words = vec![id];
+        words.extend_from_slice(members);
+
+        self.globals
+            .push(SPIRVInstruction::new(SPIRVOp::TypeStruct, words));
+
+        id
+    }
+
+    /// Declares a specialization constant the runtime can override through
+    /// `VkSpecializationInfo` with `spec_id`, returning the existing id if
+    /// `spec_id` was already declared.
+    pub fn spec_constant(&mut self, result_type: u32, default: &[u32], spec_id: u32) -> u32 {
+        if let Some(id) = self.spec_constants.get(&spec_id) {
+            return *id;
+        }
+
+        let id = self.id();
+        self.spec_constants.insert(spec_id, id);
+
+        let mut words = vec![result_type, id];
+        words.extend_from_slice(default);
+
+        self.globals
+            .push(SPIRVInstruction::new(SPIRVOp::SpecConstant, words));
+        self.decorate(id, SPIRVDecoration::SpecId, &[spec_id]);
+
+        id
+    }
+
+    pub fn constant_u32(&mut self, value: u32) -> u32 {
+        let ty = self.type_int(32, false);
+        self.constant(SPIRVOp::Constant, ty, &[value])
+    }
+
+    pub fn constant_f32(&mut self, value: f32) -> u32 {
+        let ty = self.type_float(32);
+        self.constant(SPIRVOp::Constant, ty, &[value.to_bits()])
+    }
+
+    pub fn constant_null(&mut self, result_type: u32) -> u32 {
+        self.constant(SPIRVOp::ConstantNull, result_type, &[])
+    }
+
+    /// An `OpUndef` at module scope, shared by every use of its type.
+    pub fn undef(&mut self, result_type: u32) -> u32 {
+        self.constant(SPIRVOp::Undef, result_type, &[])
+    }
+
+    pub fn constant_composite(&mut self, result_type: u32, constituents: &[u32]) -> u32 {
+        self.constant(SPIRVOp::ConstantComposite, result_type, constituents)
+    }
+
+    pub fn constant_bool(&mut self, value: bool) -> u32 {
+        let ty = self.type_bool();
+
+        match value {
+            true => self.constant(SPIRVOp::ConstantTrue, ty, &[]),
+            false => self.constant(SPIRVOp::ConstantFalse, ty, &[]),
+        }
+    }
+
+    pub fn decorate(&mut self, target: u32, decoration: SPIRVDecoration, literals: &[u32]) {
+        let mut operands = vec![target, decoration as u32];
+        operands.extend_from_slice(literals);
+
+        self.annotations
+            .push(SPIRVInstruction::new(SPIRVOp::Decorate, operands));
+    }
+
+    /// Decorates `target` unless it already carries the same decoration,
+    /// for types that are deduplicated and may be decorated more than once.
+    pub fn decorate_unique(&mut self, target: u32, decoration: SPIRVDecoration, literals: &[u32]) {
+        let mut operands = vec![target, decoration as u32];
+        operands.extend_from_slice(literals);
+
+        let exists = self
+            .annotations
+            .iter()
+            .any(|i| i.op == SPIRVOp::Decorate && i.operands == operands);
+
+        if !exists {
+            self.annotations
+                .push(SPIRVInstruction::new(SPIRVOp::Decorate, operands));
+        }
+    }
+
+    pub fn member_decorate(
+        &mut self,
+        structure: u32,
+        member: u32,
+        decoration: SPIRVDecoration,
+        literals: &[u32],
+    ) {
+        let mut operands = vec![structure, member, decoration as u32];
+        operands.extend_from_slice(literals);
+
+        self.annotations
+            .push(SPIRVInstruction::new(SPIRVOp::MemberDecorate, operands));
+    }
+
+    pub fn entry_point(&mut self, instruction: SPIRVInstruction) {
+        self.entry_points.push(instruction);
+    }
+
+    pub fn execution_mode(&mut self, entry_point: u32, mode: SPIRVExecutionMode, literals: &[u32]) {
+        let mut operands = vec![entry_point, mode as u32];
+        operands.extend_from_slice(literals);
+
+        self.execution_modes
+            .push(SPIRVInstruction::new(SPIRVOp::ExecutionMode, operands));
+    }
+
+    pub fn store(&mut self, pointer: u32, value: u32) {
+        self.emit(SPIRVOp::Store, vec![pointer, value]);
+    }
+
+    /// Appends an instruction to the function section.
+    pub fn emit(&mut self, op: SPIRVOp, operands: Vec<u32>) {
+        self.functions.push(SPIRVInstruction::new(op, operands));
+    }
+
+    /// Appends a value-producing instruction (`<result type> <result id> ...`)
+    /// to the function section and returns its result id.
+    pub fn emit_value(&mut self, op: SPIRVOp, result_type: u32, operands: &[u32]) -> u32 {
+        let id = self.id();
+
+        let mut words = vec![result_type, id];
+        words.extend_from_slice(operands);
+
+        self.emit(op, words);
+
+        id
+    }
+
+    /// Where the next instruction of the function section goes, to patch
+    /// it with `set_function_operands` once forward references are known.
+    pub fn function_position(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn set_function_operands(&mut self, position: usize, operands: Vec<u32>) {
+        self.functions[position].operands = operands;
+    }
+
+    pub fn functions(&self) -> &[SPIRVInstruction] {
+        &self.functions
+    }
+
+    pub fn words(&self) -> Vec<u32> {
+        let mut words = vec![SPIRV_MAGIC, SPIRV_VERSION_1_3, 0, self.bound, 0];
+
+        for capability in &self.capabilities {
+            SPIRVInstruction::new(SPIRVOp::Capability, vec![*capability as u32])
+                .write_words(&mut words);
+        }
+
+        for extension in &self.extensions {
+            SPIRVInstruction::new(SPIRVOp::Extension, string_operands(extension))
+                .write_words(&mut words);
+        }
+
+        for (name, id) in &self.ext_inst_imports {
+            let mut operands = vec![*id];
+            operands.extend(string_operands(name));
+
+            SPIRVInstruction::new(SPIRVOp::ExtInstImport, operands).write_words(&mut words);
+        }
+
+        // Logical or PhysicalStorageBuffer64 addressing, GLSL450 memory model.
+        let addressing_model = if self.physical_addressing { 5348 } else { 0 };
+        SPIRVInstruction::new(SPIRVOp::MemoryModel, vec![addressing_model, 1])
+            .write_words(&mut words);
+
+        for section in [
+            &self.entry_points,
+            &self.execution_modes,
+            &self.debug,
+            &self.annotations,
+            &self.globals,
+            &self.functions,
+        ] {
+            for instruction in section {
+                instruction.write_words(&mut words);
+            }
+        }
+
+        words
+    }
+}
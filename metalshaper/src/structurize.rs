@@ -0,0 +1,969 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Result, anyhow};
+
+use crate::function::{
+    AIRBasicBlock, AIRConstant, AIRFunction, AIRInstruction, AIRInstructionKind, AIRPredicate,
+    AIRTypeTable, AIRValueType,
+};
+
+/// The merge instruction a block needs in SPIR-V's structured control flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRMerge {
+    Selection {
+        merge: usize,
+    },
+    Loop {
+        merge: usize,
+        continue_target: usize,
+    },
+}
+
+/// The control flow of a function rewritten to the shape SPIR-V requires:
+/// every loop has a single back edge and a single exit, every conditional
+/// branch that isn't a break or continue has a merge block it dominates.
+#[derive(Debug, Clone, Default)]
+pub struct AIRControlFlow {
+    /// The blocks to emit, in an order where every block comes after its
+    /// dominators.
+    pub order: Vec<usize>,
+    pub merges: BTreeMap<usize, AIRMerge>,
+}
+
+/// Rewrites the blocks of `function` into structured control flow.
+///
+/// LLVM's CFGs are arbitrary, this only handles the reducible ones clang
+/// emits for structured MSL. Loop exits are funneled through a single merge
+/// block, dispatching to the original targets if there were several, and
+/// selections are given forwarding blocks as merges where the natural merge
+/// is shared. Early exits from a selection into the middle of another
+/// construct are rejected.
+pub fn structurize(function: &mut AIRFunction, types: &mut AIRTypeTable) -> Result<AIRControlFlow> {
+    if function.blocks.is_empty() {
+        return Err(anyhow!("Function {} has no body.", function.name));
+    }
+
+    remove_unreachable_blocks(function);
+    unify_returns(function);
+
+    let mut loops = BTreeMap::new();
+
+    while let Some(header) = {
+        let cfg = AIRCFGAnalysis::new(function)?;
+        cfg.order
+            .iter()
+            .find(|block| cfg.is_loop_header(**block) && !loops.contains_key(*block))
+            .copied()
+    } {
+        let l = structurize_loop(function, types, header)?;
+        loops.insert(header, l);
+    }
+
+    let mut merges = loops
+        .iter()
+        .map(|(header, l)| {
+            (
+                *header,
+                AIRMerge::Loop {
+                    merge: l.merge,
+                    continue_target: l.continue_target,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let mut claimed = loops
+        .values()
+        .flat_map(|l| [l.merge, l.continue_target])
+        .collect::<BTreeSet<_>>();
+
+    loop {
+        let cfg = AIRCFGAnalysis::new(function)?;
+
+        let Some(header) = cfg.order.iter().copied().find(|block| {
+            !merges.contains_key(block) && needs_selection_merge(function, &loops, &cfg, *block)
+        }) else {
+            break;
+        };
+
+        let region = innermost_loop(&loops, &cfg, header);
+        let merge = match region_post_dominator(function, &cfg, region, header) {
+            None => add_block(function, AIRInstructionKind::Unreachable),
+            Some(merge)
+                if claimed.contains(&merge)
+                    || loops.contains_key(&merge)
+                    || !cfg.dominates(header, merge) =>
+            {
+                let predecessors = cfg.predecessors[merge]
+                    .iter()
+                    .copied()
+                    .filter(|block| cfg.dominates(header, *block))
+                    .collect::<Vec<_>>();
+
+                if predecessors.is_empty() {
+                    return Err(anyhow!(
+                        "Control flow of {} can't be structured, early exits from nested selections are not implemented.",
+                        function.name
+                    ));
+                }
+
+                let forward = add_block(function, AIRInstructionKind::Branch(merge));
+                redirect(function, merge, &predecessors, forward)?;
+                forward
+            }
+            Some(merge) => merge,
+        };
+
+        claimed.insert(merge);
+        merges.insert(header, AIRMerge::Selection { merge });
+    }
+
+    // Merges of constructs that never complete are unreachable, but still
+    // have to be emitted.
+    let mut order = AIRCFGAnalysis::new(function)?.order;
+
+    for merge in merges.values() {
+        let blocks = match merge {
+            AIRMerge::Selection { merge } => vec![*merge],
+            AIRMerge::Loop {
+                merge,
+                continue_target,
+            } => vec![*merge, *continue_target],
+        };
+
+        for block in blocks {
+            if !order.contains(&block) {
+                order.push(block);
+            }
+        }
+    }
+
+    Ok(AIRControlFlow { order, merges })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AIRLoop {
+    header: usize,
+    merge: usize,
+    continue_target: usize,
+}
+
+/// Dominance of the blocks reachable from the entry.
+struct AIRCFGAnalysis {
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+    /// Reverse post-order.
+    order: Vec<usize>,
+    dominators: Vec<Option<usize>>,
+}
+
+impl AIRCFGAnalysis {
+    fn new(function: &AIRFunction) -> Result<Self> {
+        let successors = function
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut successors = block.successors();
+                let mut seen = BTreeSet::new();
+                successors.retain(|successor| seen.insert(*successor));
+                successors
+            })
+            .collect::<Vec<_>>();
+        let predecessors = function.predecessors();
+
+        let order = reverse_post_order(&successors, 0);
+        let dominators = immediate_dominators(&order, &predecessors);
+
+        let cfg = Self {
+            successors,
+            predecessors,
+            order,
+            dominators,
+        };
+
+        // A retreating edge to a block that doesn't dominate its source
+        // enters a cycle somewhere other than its header.
+        let position = cfg.positions();
+
+        for block in &cfg.order {
+            for successor in &cfg.successors[*block] {
+                if position[*successor] <= position[*block] && !cfg.dominates(*successor, *block) {
+                    return Err(anyhow!(
+                        "Function {} has irreducible control flow, which is not implemented.",
+                        function.name
+                    ));
+                }
+            }
+        }
+
+        Ok(cfg)
+    }
+
+    fn positions(&self) -> Vec<usize> {
+        let mut position = vec![usize::MAX; self.successors.len()];
+
+        for (index, block) in self.order.iter().enumerate() {
+            position[*block] = index;
+        }
+
+        position
+    }
+
+    fn dominates(&self, a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+
+            match self.dominators.get(b).copied().flatten() {
+                Some(dominator) if dominator != b => b = dominator,
+                _ => return false,
+            }
+        }
+    }
+
+    fn latches(&self, header: usize) -> Vec<usize> {
+        self.predecessors[header]
+            .iter()
+            .copied()
+            .filter(|block| self.dominates(header, *block))
+            .collect()
+    }
+
+    fn is_loop_header(&self, block: usize) -> bool {
+        !self.latches(block).is_empty()
+    }
+
+    /// The blocks of the natural loop of `header`.
+    fn loop_body(&self, header: usize) -> BTreeSet<usize> {
+        let mut body = BTreeSet::from([header]);
+        let mut stack = self.latches(header);
+
+        while let Some(block) = stack.pop() {
+            if body.insert(block) {
+                stack.extend(self.predecessors[block].iter().copied());
+            }
+        }
+
+        body
+    }
+}
+
+fn reverse_post_order(successors: &[Vec<usize>], entry: usize) -> Vec<usize> {
+    let mut visited = vec![false; successors.len()];
+    let mut order = vec![];
+    let mut stack = vec![(entry, 0)];
+    visited[entry] = true;
+
+    while let Some((block, next)) = stack.pop() {
+        match successors[block].get(next) {
+            Some(successor) => {
+                stack.push((block, next + 1));
+
+                if !visited[*successor] {
+                    visited[*successor] = true;
+                    stack.push((*successor, 0));
+                }
+            }
+            None => order.push(block),
+        }
+    }
+
+    order.reverse();
+    order
+}
+
+/// Cooper, Harvey and Kennedy's iterative algorithm. `order` is a reverse
+/// post-order from the root, the root is its own dominator.
+fn immediate_dominators(order: &[usize], predecessors: &[Vec<usize>]) -> Vec<Option<usize>> {
+    let mut position = vec![usize::MAX; predecessors.len()];
+    for (index, block) in order.iter().enumerate() {
+        position[*block] = index;
+    }
+
+    let mut dominators = vec![None; predecessors.len()];
+    dominators[order[0]] = Some(order[0]);
+
+    let intersect = |dominators: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while position[a] > position[b] {
+                a = dominators[a].unwrap_or(order[0]);
+            }
+            while position[b] > position[a] {
+                b = dominators[b].unwrap_or(order[0]);
+            }
+        }
+        a
+    };
+
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for block in order.iter().skip(1) {
+            let mut dominator = None;
+
+            for predecessor in &predecessors[*block] {
+                if dominators[*predecessor].is_none() {
+                    continue;
+                }
+
+                dominator = Some(match dominator {
+                    None => *predecessor,
+                    Some(d) => intersect(&dominators, *predecessor, d),
+                });
+            }
+
+            if dominator.is_some() && dominators[*block] != dominator {
+                dominators[*block] = dominator;
+                changed = true;
+            }
+        }
+    }
+
+    dominators
+}
+
+fn add_block(function: &mut AIRFunction, terminator: AIRInstructionKind) -> usize {
+    function.blocks.push(AIRBasicBlock {
+        instructions: vec![AIRInstruction {
+            result: None,
+            kind: terminator,
+            location: None,
+        }],
+    });
+
+    function.blocks.len() - 1
+}
+
+/// Inserts instructions before the terminator of `block`.
+fn insert_before_terminator(
+    function: &mut AIRFunction,
+    block: usize,
+    instructions: impl IntoIterator<Item = AIRInstruction>,
+) {
+    let instructions = instructions.into_iter().collect::<Vec<_>>();
+    let block = &mut function.blocks[block].instructions;
+    let at = block.len().saturating_sub(1);
+
+    block.splice(at..at, instructions);
+}
+
+/// Inserts phis at the start of `block`.
+fn insert_phis(function: &mut AIRFunction, block: usize, phis: Vec<AIRInstruction>) {
+    function.blocks[block].instructions.splice(0..0, phis);
+}
+
+fn phi(result: u64, incoming: Vec<(u64, usize)>) -> AIRInstruction {
+    AIRInstruction {
+        result: Some(result),
+        kind: AIRInstructionKind::Phi { incoming },
+        location: None,
+    }
+}
+
+/// Points the edges from `predecessors` to `target` at `block` instead,
+/// which branches to `target`. Phis of `target` get their values for those
+/// edges from new phis in `block`.
+fn redirect(
+    function: &mut AIRFunction,
+    target: usize,
+    predecessors: &[usize],
+    block: usize,
+) -> Result<()> {
+    for predecessor in predecessors {
+        function.blocks[*predecessor]
+            .terminator_mut()?
+            .kind
+            .replace_successor(target, block);
+    }
+
+    let mut phis = vec![];
+
+    for index in 0..function.blocks[target].instructions.len() {
+        let instruction = &function.blocks[target].instructions[index];
+
+        let (Some(result), AIRInstructionKind::Phi { incoming }) =
+            (instruction.result, &instruction.kind)
+        else {
+            continue;
+        };
+
+        let (moved, kept): (Vec<_>, Vec<_>) = incoming
+            .iter()
+            .copied()
+            .partition(|(_, from)| predecessors.contains(from));
+
+        if moved.is_empty() {
+            continue;
+        }
+
+        let value = match moved.as_slice() {
+            [(value, _)] => *value,
+            _ => {
+                let ty = function.value(result)?.ty;
+                let value = function.add_value(ty);
+                phis.push(phi(value, moved));
+                value
+            }
+        };
+
+        let mut incoming = kept;
+        incoming.push((value, block));
+
+        function.blocks[target].instructions[index].kind = AIRInstructionKind::Phi { incoming };
+    }
+
+    insert_phis(function, block, phis);
+
+    Ok(())
+}
+
+/// Drops blocks the entry can't reach from the phis of the others and
+/// empties them, so nothing refers to them anymore.
+fn remove_unreachable_blocks(function: &mut AIRFunction) {
+    let successors = function
+        .blocks
+        .iter()
+        .map(AIRBasicBlock::successors)
+        .collect::<Vec<_>>();
+    let reachable = reverse_post_order(&successors, 0)
+        .into_iter()
+        .collect::<BTreeSet<_>>();
+
+    for (index, block) in function.blocks.iter_mut().enumerate() {
+        if !reachable.contains(&index) {
+            *block = AIRBasicBlock {
+                instructions: vec![AIRInstruction {
+                    result: None,
+                    kind: AIRInstructionKind::Unreachable,
+                    location: None,
+                }],
+            };
+            continue;
+        }
+
+        for instruction in &mut block.instructions {
+            if let AIRInstructionKind::Phi { incoming } = &mut instruction.kind {
+                incoming.retain(|(_, from)| reachable.contains(from));
+            }
+        }
+    }
+}
+
+/// Makes every `ret` branch to a single returning block, so returning
+/// doesn't count as an exit of the constructs around it.
+fn unify_returns(function: &mut AIRFunction) {
+    let successors = function
+        .blocks
+        .iter()
+        .map(AIRBasicBlock::successors)
+        .collect::<Vec<_>>();
+
+    let returns = reverse_post_order(&successors, 0)
+        .into_iter()
+        .filter_map(|block| match function.blocks[block].terminator() {
+            Ok(AIRInstruction {
+                kind: AIRInstructionKind::Return(value),
+                ..
+            }) => Some((block, *value)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if returns.len() < 2 {
+        return;
+    }
+
+    let block = add_block(function, AIRInstructionKind::Unreachable);
+
+    let value = match returns[0].1 {
+        Some(first) => {
+            let ty = function.values[first as usize].ty;
+            let value = function.add_value(ty);
+            let incoming = returns
+                .iter()
+                .map(|(from, value)| (value.unwrap_or(first), *from))
+                .collect();
+
+            insert_phis(function, block, vec![phi(value, incoming)]);
+            Some(value)
+        }
+        None => None,
+    };
+
+    if let Ok(terminator) = function.blocks[block].terminator_mut() {
+        terminator.kind = AIRInstructionKind::Return(value);
+    }
+
+    for (from, _) in returns {
+        if let Ok(terminator) = function.blocks[from].terminator_mut() {
+            terminator.kind = AIRInstructionKind::Branch(block);
+        }
+    }
+}
+
+/// Gives the loop of `header` a single exit, its merge, and a single back
+/// edge from a block that only branches to the header or the merge, its
+/// continue target.
+fn structurize_loop(
+    function: &mut AIRFunction,
+    types: &mut AIRTypeTable,
+    header: usize,
+) -> Result<AIRLoop> {
+    let cfg = AIRCFGAnalysis::new(function)?;
+    let body = cfg.loop_body(header);
+
+    // Exit edges, grouped by target in the order they're found.
+    let mut exits: Vec<(usize, Vec<usize>)> = vec![];
+
+    for block in &body {
+        for successor in &cfg.successors[*block] {
+            if body.contains(successor) {
+                continue;
+            }
+
+            match exits.iter_mut().find(|(target, _)| target == successor) {
+                Some((_, from)) => from.push(*block),
+                None => exits.push((*successor, vec![*block])),
+            }
+        }
+    }
+
+    let merge = match exits.as_slice() {
+        [] => add_block(function, AIRInstructionKind::Unreachable),
+        [(target, from)]
+            if cfg.dominates(header, *target)
+                && cfg.predecessors[*target]
+                    .iter()
+                    .all(|block| from.contains(block)) =>
+        {
+            *target
+        }
+        [(target, from)] => {
+            let merge = add_block(function, AIRInstructionKind::Branch(*target));
+            redirect(function, *target, from, merge)?;
+            merge
+        }
+        _ => dispatch_exits(function, types, &exits)?,
+    };
+
+    // A dedicated continue target unless the only back edge already comes
+    // from a block that can only loop or leave.
+    let latches = cfg.latches(header);
+
+    let continue_target = match latches.as_slice() {
+        [latch]
+            if function.blocks[*latch]
+                .successors()
+                .iter()
+                .all(|block| *block == header || *block == merge) =>
+        {
+            *latch
+        }
+        _ => {
+            let latch = add_block(function, AIRInstructionKind::Branch(header));
+            redirect(function, header, &latches, latch)?;
+            latch
+        }
+    };
+
+    // The header's own branch can't have a selection merge next to the
+    // loop merge, so it moves to a block of its own unless it only decides
+    // whether to stay in the loop.
+    let header_successors = function.blocks[header].successors();
+    let simple_header = match header_successors.as_slice() {
+        [_] => true,
+        [a, b] => {
+            header == continue_target
+                || *a == merge
+                || *b == merge
+                || *a == continue_target
+                || *b == continue_target
+        }
+        _ => false,
+    };
+
+    if !simple_header {
+        split_terminator(function, header)?;
+    }
+
+    Ok(AIRLoop {
+        header,
+        merge,
+        continue_target,
+    })
+}
+
+/// Funnels the exits of a loop with several exit targets through one merge
+/// block, which remembers the exit taken in a selector and branches on it.
+fn dispatch_exits(
+    function: &mut AIRFunction,
+    types: &mut AIRTypeTable,
+    exits: &[(usize, Vec<usize>)],
+) -> Result<usize> {
+    let int = types.intern(AIRValueType::Int(32));
+    let bool = types.intern(AIRValueType::Int(1));
+
+    let merge = add_block(function, AIRInstructionKind::Unreachable);
+    let mut phis = vec![];
+
+    let edges = exits
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (_, from))| from.iter().map(move |block| (index, *block)))
+        .collect::<Vec<_>>();
+
+    let selector = function.add_value(int);
+    let selector_incoming = edges
+        .iter()
+        .map(|(index, from)| {
+            let constant = function.add_constant(int, AIRConstant::Integer(*index as i64));
+            (constant, *from)
+        })
+        .collect();
+    phis.push(phi(selector, selector_incoming));
+
+    // The block that branches to each target: a chain of comparisons on
+    // the selector, the last target taking the final else.
+    let mut dispatchers = vec![merge];
+    for _ in 2..exits.len() {
+        dispatchers.push(add_block(function, AIRInstructionKind::Unreachable));
+    }
+
+    for (index, dispatcher) in dispatchers.iter().enumerate() {
+        let constant = function.add_constant(int, AIRConstant::Integer(index as i64));
+        let condition = function.add_value(bool);
+
+        let false_target = match dispatchers.get(index + 1) {
+            Some(next) => *next,
+            None => exits[index + 1].0,
+        };
+
+        insert_before_terminator(
+            function,
+            *dispatcher,
+            [AIRInstruction {
+                result: Some(condition),
+                kind: AIRInstructionKind::Compare {
+                    predicate: AIRPredicate(AIRPredicate::ICMP_EQ),
+                    lhs: selector,
+                    rhs: constant,
+                },
+                location: None,
+            }],
+        );
+
+        function.blocks[*dispatcher].terminator_mut()?.kind =
+            AIRInstructionKind::ConditionalBranch {
+                condition,
+                true_target: exits[index].0,
+                false_target,
+            };
+    }
+
+    let branching_block = |index: usize| dispatchers[index.min(dispatchers.len() - 1)];
+
+    for (index, (target, from)) in exits.iter().enumerate() {
+        for block in from {
+            function.blocks[*block]
+                .terminator_mut()?
+                .kind
+                .replace_successor(*target, merge);
+        }
+
+        for position in 0..function.blocks[*target].instructions.len() {
+            let instruction = &function.blocks[*target].instructions[position];
+
+            let (Some(result), AIRInstructionKind::Phi { incoming }) =
+                (instruction.result, &instruction.kind)
+            else {
+                continue;
+            };
+
+            let (moved, mut kept): (Vec<_>, Vec<_>) = incoming
+                .iter()
+                .copied()
+                .partition(|(_, block)| from.contains(block));
+
+            if moved.is_empty() {
+                continue;
+            }
+
+            let ty = function.value(result)?.ty;
+            let undef = function.add_constant(ty, AIRConstant::Undef);
+            let value = function.add_value(ty);
+
+            let incoming = edges
+                .iter()
+                .map(|(_, edge)| {
+                    let value = moved
+                        .iter()
+                        .find(|(_, block)| block == edge)
+                        .map_or(undef, |(value, _)| *value);
+                    (value, *edge)
+                })
+                .collect();
+            phis.push(phi(value, incoming));
+
+            kept.push((value, branching_block(index)));
+            function.blocks[*target].instructions[position].kind =
+                AIRInstructionKind::Phi { incoming: kept };
+        }
+    }
+
+    // Exit edges that share a source only need one phi entry.
+    for instruction in &mut phis {
+        if let AIRInstructionKind::Phi { incoming } = &mut instruction.kind {
+            let mut seen = BTreeSet::new();
+            incoming.retain(|(_, block)| seen.insert(*block));
+        }
+    }
+
+    insert_phis(function, merge, phis);
+
+    Ok(merge)
+}
+
+/// Moves the terminator of `block` into a new block it branches to.
+fn split_terminator(function: &mut AIRFunction, block: usize) -> Result<usize> {
+    let successors = function.blocks[block].successors();
+    let terminator = function.blocks[block].terminator()?.kind.clone();
+    let split = add_block(function, terminator);
+
+    function.blocks[block].terminator_mut()?.kind = AIRInstructionKind::Branch(split);
+
+    for successor in successors {
+        for instruction in &mut function.blocks[successor].instructions {
+            if let AIRInstructionKind::Phi { incoming } = &mut instruction.kind {
+                for (_, from) in incoming.iter_mut() {
+                    if *from == block {
+                        *from = split;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(split)
+}
+
+/// Whether `block` ends in a branch that needs an `OpSelectionMerge`: a
+/// switch, or a conditional branch that isn't a loop header's and where
+/// neither target is a break or continue.
+fn needs_selection_merge(
+    function: &AIRFunction,
+    loops: &BTreeMap<usize, AIRLoop>,
+    cfg: &AIRCFGAnalysis,
+    block: usize,
+) -> bool {
+    if loops.contains_key(&block) {
+        return false;
+    }
+
+    let Ok(terminator) = function.blocks[block].terminator() else {
+        return false;
+    };
+
+    match &terminator.kind {
+        AIRInstructionKind::Switch { .. } => true,
+        AIRInstructionKind::ConditionalBranch {
+            true_target,
+            false_target,
+            ..
+        } if true_target != false_target => {
+            let exits = match innermost_loop(loops, cfg, block) {
+                Some(l) => vec![l.merge, l.continue_target],
+                None => vec![],
+            };
+
+            !exits.contains(true_target) && !exits.contains(false_target)
+        }
+        _ => false,
+    }
+}
+
+/// Bodies are recomputed, blocks were added since the loops were
+/// structured.
+fn innermost_loop<'a>(
+    loops: &'a BTreeMap<usize, AIRLoop>,
+    cfg: &AIRCFGAnalysis,
+    block: usize,
+) -> Option<&'a AIRLoop> {
+    loops
+        .iter()
+        .filter(|(header, _)| cfg.loop_body(**header).contains(&block))
+        .min_by_key(|(header, _)| cfg.loop_body(**header).len())
+        .map(|(_, l)| l)
+}
+
+/// The immediate post-dominator of `block` within `region`, where leaving
+/// the region, looping back and the region's continue target count as the
+/// exit. `None` if that's the exit itself.
+fn region_post_dominator(
+    function: &AIRFunction,
+    cfg: &AIRCFGAnalysis,
+    region: Option<&AIRLoop>,
+    block: usize,
+) -> Option<usize> {
+    let count = function.blocks.len();
+    let exit = count;
+
+    let body = match region {
+        Some(l) => cfg.loop_body(l.header),
+        None => cfg.order.iter().copied().collect(),
+    };
+
+    // The reversed graph, rooted at the exit.
+    let mut reversed = vec![vec![]; count + 1];
+    let mut forward = vec![vec![]; count + 1];
+
+    for from in &body {
+        let continue_target = region.is_some_and(|l| l.continue_target == *from);
+        let successors = match continue_target {
+            true => vec![],
+            false => cfg.successors[*from].clone(),
+        };
+
+        let mut exits = successors.is_empty();
+
+        for to in successors {
+            if body.contains(&to) && region.is_none_or(|l| l.header != to) {
+                reversed[to].push(*from);
+                forward[*from].push(to);
+            } else {
+                exits = true;
+            }
+        }
+
+        if exits {
+            reversed[exit].push(*from);
+            forward[*from].push(exit);
+        }
+    }
+
+    let order = reverse_post_order(&reversed, exit);
+    let dominators = immediate_dominators(&order, &forward);
+
+    dominators[block].filter(|dominator| *dominator != exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::{AIRValue, AIRValueKind};
+
+    /// A function of `terminators` whose only value is the `i1` argument 0
+    /// they branch on.
+    fn function(types: &mut AIRTypeTable, terminators: Vec<AIRInstructionKind>) -> AIRFunction {
+        let bool = types.intern(AIRValueType::Int(1));
+
+        AIRFunction {
+            name: "test".to_string(),
+            function_type: 0,
+            values: vec![AIRValue {
+                ty: bool,
+                kind: AIRValueKind::Argument(0),
+            }],
+            blocks: terminators
+                .into_iter()
+                .map(|kind| AIRBasicBlock {
+                    instructions: vec![AIRInstruction {
+                        result: None,
+                        kind,
+                        location: None,
+                    }],
+                })
+                .collect(),
+            metadata: vec![],
+        }
+    }
+
+    fn branch(condition: u64, true_target: usize, false_target: usize) -> AIRInstructionKind {
+        AIRInstructionKind::ConditionalBranch {
+            condition,
+            true_target,
+            false_target,
+        }
+    }
+
+    #[test]
+    fn merges_if_else() {
+        let mut types = AIRTypeTable::default();
+        let mut function = function(
+            &mut types,
+            vec![
+                branch(0, 1, 2),
+                AIRInstructionKind::Branch(3),
+                AIRInstructionKind::Branch(3),
+                AIRInstructionKind::Return(None),
+            ],
+        );
+
+        let control_flow = structurize(&mut function, &mut types).unwrap();
+
+        assert_eq!(control_flow.order[0], 0);
+        assert_eq!(control_flow.order.last(), Some(&3));
+        assert_eq!(
+            control_flow.merges.get(&0),
+            Some(&AIRMerge::Selection { merge: 3 })
+        );
+    }
+
+    #[test]
+    fn merges_loop() {
+        let mut types = AIRTypeTable::default();
+        let mut function = function(
+            &mut types,
+            vec![
+                AIRInstructionKind::Branch(1),
+                branch(0, 2, 3),
+                AIRInstructionKind::Branch(1),
+                AIRInstructionKind::Return(None),
+            ],
+        );
+
+        let control_flow = structurize(&mut function, &mut types).unwrap();
+
+        assert_eq!(
+            control_flow.merges.get(&1),
+            Some(&AIRMerge::Loop {
+                merge: 3,
+                continue_target: 2
+            })
+        );
+        assert_eq!(control_flow.merges.len(), 1);
+    }
+
+    #[test]
+    fn unifies_returns() {
+        let mut types = AIRTypeTable::default();
+        let mut function = function(
+            &mut types,
+            vec![
+                branch(0, 1, 2),
+                AIRInstructionKind::Return(None),
+                AIRInstructionKind::Return(None),
+            ],
+        );
+
+        let control_flow = structurize(&mut function, &mut types).unwrap();
+        let returns = function
+            .blocks
+            .iter()
+            .filter(|block| {
+                matches!(
+                    block.terminator().unwrap().kind,
+                    AIRInstructionKind::Return(_)
+                )
+            })
+            .count();
+
+        assert_eq!(returns, 1);
+        assert_eq!(
+            control_flow.merges.get(&0),
+            Some(&AIRMerge::Selection { merge: 3 })
+        );
+    }
+}
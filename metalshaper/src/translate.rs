@@ -0,0 +1,3724 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+
+use crate::apple_ir::{AIRModule, BlockType};
+use crate::argument_buffer::{AIRArgumentBuffer, AIRArgumentKind, argument_buffer_layouts};
+use crate::atomic::{
+    AIRAtomic, AIRAtomicAddress, AIRAtomicKind, AIRAtomicOperands, AIRAtomicTarget, AIRAtomicType,
+    AIRMemoryOrder, lower_atomic,
+};
+use crate::coordinates::{
+    SPIRVCoordinateFixups, declare_fragment_origin, fixup_vertex_position, load_frag_coord,
+    load_point_coord,
+};
+use crate::debug_info::{AIRDebugInfo, SPIRVDebugInfoEmitter, SPIRVDebugInfoLevel, cite_source};
+use crate::fragment::{
+    AIRDepthQualifier, AIRFragmentInput, AIRFragmentOutput, declare_early_fragment_tests,
+    declare_fragment_output, load_fragment_input, lower_discard, store_fragment_output,
+};
+use crate::function::{
+    AIRBinaryOp, AIRCastOp, AIRConstant, AIRFunction, AIRInstruction, AIRInstructionKind,
+    AIRPredicate, AIRTypeTable, AIRValueKind, AIRValueType,
+};
+use crate::intrinsics::{AIRMathFunction, AIRMathOp, lower_math_function};
+use crate::reflection::{
+    AIRArgumentReflection, AIRFunctionReflection, SPIRVArgumentBufferLayout, SPIRVArgumentLocation,
+};
+use crate::simdgroup::{
+    AIRLane, AIRSIMDBuiltIn, AIRSIMDFunction, AIRSIMDOperands, AIRSIMDOperation,
+    lower_simd_builtin, lower_simd_function,
+};
+use crate::spirv::{
+    SPIRVBuiltIn, SPIRVCapability, SPIRVDecoration, SPIRVExecutionMode, SPIRVExecutionModel,
+    SPIRVInstruction, SPIRVModule, SPIRVOp, SPIRVScope, SPIRVStorageClass, SPIRVTargetFeatures,
+    memory_semantics, string_operands,
+};
+use crate::structurize::{AIRControlFlow, AIRMerge, structurize};
+use crate::types::{AIRScalarType, AIRType, SPIRVScalarRepresentation};
+use crate::{AIRAddressSpace, AIRShaderStage};
+
+/// The `VkSpecializationInfo` constant ids of a kernel's threadgroup size,
+/// which Metal only knows at dispatch.
+pub const SPIRV_THREADGROUP_SIZE_SPEC_IDS: [u32; 3] = [0xFFFF_0001, 0xFFFF_0002, 0xFFFF_0003];
+
+/// What a translated shader may use and how it's fixed up for Vulkan.
+#[derive(Debug, Clone, Default)]
+pub struct SPIRVTranslationOptions {
+    pub features: SPIRVTargetFeatures,
+    pub coordinate_fixups: SPIRVCoordinateFixups,
+    pub debug_info: SPIRVDebugInfoLevel,
+}
+
+/// Translates the entry point `name` of `module` to SPIR-V words.
+///
+/// `[[buffer(n)]]` arguments are storage buffers at binding `n` of
+/// descriptor set 0, `constant` ones marked `NonWritable`. Argument buffers
+/// take the sets `argument_buffer_layouts` assigns them. Textures and
+/// samplers aren't implemented yet, nor are calls to anything but the
+/// intrinsics, as clang inlines everything else into entry points.
+pub fn translate(
+    module: &AIRModule,
+    name: &str,
+    options: &SPIRVTranslationOptions,
+) -> Result<Vec<u32>> {
+    module.version.check_supported()?;
+
+    let reflection = AIRFunctionReflection::from_module(module)?
+        .into_iter()
+        .find(|function| function.name == name)
+        .ok_or_else(|| anyhow!("AIR module has no entry point named {}.", name))?;
+
+    let mut types = AIRTypeTable::from_module(module)?;
+    let function = AIRFunction::from_module(module, &mut types, name)?;
+
+    // Function-local metadata ids continue after the module's.
+    let mut records = vec![];
+    for block in module.module_block()?.blocks(BlockType::Metadata) {
+        records.extend(block.metadata_records()?);
+    }
+    records.extend(function.metadata.iter().cloned());
+
+    let debug_info = AIRDebugInfo::from_records(&records)?;
+
+    translate_function(&reflection, types, function, debug_info, options)
+}
+
+/// Translates a decoded entry point.
+fn translate_function(
+    reflection: &AIRFunctionReflection,
+    mut types: AIRTypeTable,
+    mut function: AIRFunction,
+    debug_info: AIRDebugInfo,
+    options: &SPIRVTranslationOptions,
+) -> Result<Vec<u32>> {
+    let control_flow = structurize(&mut function, &mut types)?;
+    let mut debug = SPIRVDebugInfoEmitter::new(options.debug_info, &options.features)?;
+
+    let mut spirv = SPIRVModule::new();
+    debug.declare_sources(
+        &mut spirv,
+        &debug_info,
+        options.debug_info == SPIRVDebugInfoLevel::Full,
+    );
+
+    let entry_point = spirv.id();
+
+    let argument_buffers = argument_buffer_layouts(reflection, &options.features)?
+        .into_iter()
+        .map(|(argument_buffer, layout)| (layout.index, (argument_buffer, layout)))
+        .collect();
+
+    let mut translator = SPIRVTranslator {
+        options,
+        reflection,
+        spirv,
+        types,
+        function,
+        debug,
+        debug_info,
+        entry_point,
+        type_cache: HashMap::new(),
+        values: HashMap::new(),
+        buffers: HashMap::new(),
+        argument_buffers,
+        argument_blocks: HashMap::new(),
+        labels: vec![],
+        phis: vec![],
+        outputs: vec![],
+        workgroup_size: None,
+    };
+
+    translator.translate(&control_flow)?;
+
+    Ok(translator.spirv.words())
+}
+
+/// How a type is laid out: `Logical` for values and private memory,
+/// `Explicit` with offsets and strides for buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SPIRVLayout {
+    Logical,
+    Explicit,
+}
+
+/// An index of an access chain, kept as a literal while it's constant so
+/// that pointer arithmetic on it folds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SPIRVIndex {
+    Constant(u32),
+    /// A 32-bit integer id.
+    Dynamic(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SPIRVPointerRoot {
+    Variable {
+        id: u32,
+        storage_class: SPIRVStorageClass,
+    },
+    /// A `[[buffer(n)]]` or a buffer in an argument buffer, seen as a
+    /// runtime array of `element`. Several variables may alias the binding
+    /// when it's accessed as different types.
+    Buffer {
+        set: u32,
+        binding: u32,
+        address_space: AIRAddressSpace,
+        element: Option<usize>,
+    },
+    /// The argument buffer at `[[buffer(index)]]`, which can only be loaded
+    /// from member by member.
+    ArgumentBuffer { index: u32 },
+}
+
+/// LLVM pointers become an access chain that is only materialized when the
+/// pointer is dereferenced, SPIR-V's logical addressing has no pointer
+/// arithmetic.
+#[derive(Debug, Clone)]
+struct SPIRVPointer {
+    root: SPIRVPointerRoot,
+    /// The indices from the root, each with the type it indexes into. That
+    /// is `None` for the runtime array of a buffer.
+    path: Vec<(SPIRVIndex, Option<usize>)>,
+    /// `None` for opaque buffer pointers that weren't accessed yet.
+    pointee: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum SPIRVValue {
+    Id(u32),
+    Pointer(SPIRVPointer),
+}
+
+/// An `OpPhi` emitted before all of its incoming values were, patched once
+/// the function is complete.
+#[derive(Debug)]
+struct SPIRVPendingPhi {
+    position: usize,
+    result_type: u32,
+    result: u32,
+    incoming: Vec<(u64, usize)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SPIRVOutputKind {
+    Plain,
+    Position,
+    Fragment(AIRFragmentOutput),
+}
+
+/// The `Output` variable a member of the returned value is stored to.
+#[derive(Debug, Clone, Copy)]
+struct SPIRVOutput {
+    variable: u32,
+    kind: SPIRVOutputKind,
+}
+
+struct SPIRVTranslator<'a> {
+    options: &'a SPIRVTranslationOptions,
+    reflection: &'a AIRFunctionReflection,
+    spirv: SPIRVModule,
+    types: AIRTypeTable,
+    function: AIRFunction,
+    debug: SPIRVDebugInfoEmitter,
+    debug_info: AIRDebugInfo,
+    entry_point: u32,
+    type_cache: HashMap<(usize, SPIRVLayout), u32>,
+    values: HashMap<u64, SPIRVValue>,
+    /// The block variable of a buffer's set and binding by its element
+    /// type.
+    buffers: HashMap<(u32, u32, usize), u32>,
+    argument_buffers: HashMap<u32, (AIRArgumentBuffer, SPIRVArgumentBufferLayout)>,
+    /// The variable of an argument block's set reading a type at an offset.
+    argument_blocks: HashMap<(u32, u32, usize), u32>,
+    labels: Vec<u32>,
+    phis: Vec<SPIRVPendingPhi>,
+    outputs: Vec<SPIRVOutput>,
+    workgroup_size: Option<u32>,
+}
+
+impl SPIRVTranslator<'_> {
+    fn translate(&mut self, control_flow: &AIRControlFlow) -> Result<()> {
+        let model = match self.reflection.stage {
+            AIRShaderStage::Vertex => SPIRVExecutionModel::Vertex,
+            AIRShaderStage::Fragment => {
+                declare_fragment_origin(&mut self.spirv, self.entry_point);
+
+                let attributes = &self.reflection.attributes;
+                if attributes.iter().any(|a| a == "air.early_fragment_tests") {
+                    declare_early_fragment_tests(&mut self.spirv, self.entry_point);
+                }
+
+                SPIRVExecutionModel::Fragment
+            }
+            AIRShaderStage::Kernel => {
+                self.spirv.execution_mode(
+                    self.entry_point,
+                    SPIRVExecutionMode::LocalSize,
+                    &[1, 1, 1],
+                );
+                self.workgroup_size();
+                SPIRVExecutionModel::GLCompute
+            }
+        };
+
+        self.declare_outputs()?;
+
+        let void = self.spirv.type_void();
+        let function_type = self.spirv.type_function(void, &[]);
+        self.spirv.emit(
+            SPIRVOp::Function,
+            vec![void, self.entry_point, 0, function_type],
+        );
+
+        let block_count = self.function.blocks.len();
+        self.labels = (0..block_count).map(|_| self.spirv.id()).collect();
+
+        for (position, &block) in control_flow.order.iter().enumerate() {
+            self.spirv.emit(SPIRVOp::Label, vec![self.labels[block]]);
+            self.debug.start_block();
+
+            if position == 0 {
+                self.declare_locals()?;
+                self.declare_arguments()?;
+            }
+
+            let instructions = self.function.blocks[block].instructions.clone();
+
+            for instruction in &instructions {
+                let location = instruction
+                    .location
+                    .and_then(|l| self.debug_info.resolve(l.line, l.column, l.scope));
+
+                // Nothing may come before phis and local variables.
+                if !matches!(
+                    instruction.kind,
+                    AIRInstructionKind::Phi { .. } | AIRInstructionKind::Alloca { .. }
+                ) {
+                    self.debug.line(&mut self.spirv, location.as_ref());
+                }
+
+                if instruction.kind.is_terminator() {
+                    self.merge(control_flow.merges.get(&block));
+                }
+
+                cite_source(self.lower(instruction), location.as_ref())?;
+            }
+        }
+
+        self.spirv.emit(SPIRVOp::FunctionEnd, vec![]);
+        self.resolve_phis()?;
+
+        if self.reflection.stage == AIRShaderStage::Fragment {
+            self.spirv.decorate_flat_builtin_inputs();
+        }
+
+        let mut operands = vec![model as u32, self.entry_point];
+        operands.extend(string_operands(&self.reflection.name));
+        operands.extend_from_slice(self.spirv.interface());
+
+        self.spirv
+            .entry_point(SPIRVInstruction::new(SPIRVOp::EntryPoint, operands));
+
+        Ok(())
+    }
+
+    fn merge(&mut self, merge: Option<&AIRMerge>) {
+        match merge {
+            Some(AIRMerge::Selection { merge }) => self
+                .spirv
+                .emit(SPIRVOp::SelectionMerge, vec![self.labels[*merge], 0]),
+            Some(AIRMerge::Loop {
+                merge,
+                continue_target,
+            }) => self.spirv.emit(
+                SPIRVOp::LoopMerge,
+                vec![self.labels[*merge], self.labels[*continue_target], 0],
+            ),
+            None => {}
+        }
+    }
+
+    /// Declares every `alloca` as a `Function` variable, which have to be
+    /// at the start of the entry block.
+    fn declare_locals(&mut self) -> Result<()> {
+        let allocas: Vec<(u64, usize)> = self
+            .function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(
+                |instruction| match (instruction.result, &instruction.kind) {
+                    (Some(result), AIRInstructionKind::Alloca { allocated }) => {
+                        Some((result, *allocated))
+                    }
+                    _ => None,
+                },
+            )
+            .collect();
+
+        for (result, allocated) in allocas {
+            let ty = self.spirv_type(allocated, SPIRVLayout::Logical)?;
+            let pointer_type = self.spirv.type_pointer(SPIRVStorageClass::Function, ty);
+            let id = self.spirv.emit_value(
+                SPIRVOp::Variable,
+                pointer_type,
+                &[SPIRVStorageClass::Function as u32],
+            );
+
+            self.values.insert(
+                result,
+                SPIRVValue::Pointer(SPIRVPointer {
+                    root: SPIRVPointerRoot::Variable {
+                        id,
+                        storage_class: SPIRVStorageClass::Function,
+                    },
+                    path: vec![],
+                    pointee: Some(allocated),
+                }),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Declares the `Output` variables the returned value is stored to.
+    fn declare_outputs(&mut self) -> Result<()> {
+        let reflection = self.reflection;
+
+        let AIRValueType::Function { result, .. } =
+            self.types.get(self.function.function_type)?.clone()
+        else {
+            return Err(anyhow!("{} doesn't have a function type.", reflection.name));
+        };
+
+        let returns_struct = matches!(self.types.get(result)?, AIRValueType::Struct { .. });
+
+        for (index, output) in reflection.outputs.iter().enumerate() {
+            let ty = match returns_struct {
+                true => self.types.member(result, index as u64)?,
+                false => result,
+            };
+
+            let fragment_output = match output.kind.as_str() {
+                "air.depth" => Some(AIRFragmentOutput::Depth(
+                    output
+                        .qualifiers
+                        .iter()
+                        .find_map(|q| AIRDepthQualifier::from_metadata(q).ok())
+                        .unwrap_or(AIRDepthQualifier::Any),
+                )),
+                "air.stencil" => Some(AIRFragmentOutput::Stencil),
+                "air.sample_mask" => Some(AIRFragmentOutput::SampleMask),
+                _ => None,
+            };
+
+            if let Some(fragment_output) = fragment_output {
+                let variable = declare_fragment_output(
+                    &mut self.spirv,
+                    &self.options.features,
+                    self.entry_point,
+                    fragment_output,
+                )?;
+
+                self.outputs.push(SPIRVOutput {
+                    variable,
+                    kind: SPIRVOutputKind::Fragment(fragment_output),
+                });
+                continue;
+            }
+
+            let spirv_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+
+            let (builtin, location, kind) = match output.kind.as_str() {
+                "air.position" => (
+                    Some(SPIRVBuiltIn::Position),
+                    None,
+                    SPIRVOutputKind::Position,
+                ),
+                "air.point_size" => (Some(SPIRVBuiltIn::PointSize), None, SPIRVOutputKind::Plain),
+                "air.vertex_output" => (None, Some(user_location(output)?), SPIRVOutputKind::Plain),
+                "air.render_target" => {
+                    let location = output.location_index.ok_or_else(|| {
+                        anyhow!("Render target output {:?} has no index.", output.name)
+                    })?;
+
+                    (None, Some(location), SPIRVOutputKind::Plain)
+                }
+                kind => return Err(anyhow!("{} outputs not implemented.", kind)),
+            };
+
+            let variable = self.spirv.variable(SPIRVStorageClass::Output, spirv_type);
+
+            if let Some(builtin) = builtin {
+                self.spirv
+                    .decorate(variable, SPIRVDecoration::BuiltIn, &[builtin as u32]);
+            }
+
+            if let Some(location) = location {
+                self.spirv
+                    .decorate(variable, SPIRVDecoration::Location, &[location]);
+            }
+
+            self.outputs.push(SPIRVOutput { variable, kind });
+        }
+
+        Ok(())
+    }
+
+    /// Binds the function's parameters to what Vulkan passes instead.
+    fn declare_arguments(&mut self) -> Result<()> {
+        let reflection = self.reflection;
+
+        let AIRValueType::Function { parameters, .. } =
+            self.types.get(self.function.function_type)?.clone()
+        else {
+            return Err(anyhow!("{} doesn't have a function type.", reflection.name));
+        };
+
+        let Some(first) = self
+            .function
+            .values
+            .iter()
+            .position(|value| matches!(value.kind, AIRValueKind::Argument(_)))
+        else {
+            return Ok(());
+        };
+
+        for argument in &reflection.arguments {
+            let Some(index) = argument.index else {
+                continue;
+            };
+
+            let ty = *parameters
+                .get(index as usize)
+                .ok_or_else(|| anyhow!("{} has no parameter {}.", reflection.name, index))?;
+
+            let value = self
+                .declare_argument(argument, ty)
+                .map_err(|e| e.context(format!("Argument {:?}", argument.name)))?;
+
+            self.values.insert(first as u64 + index as u64, value);
+        }
+
+        Ok(())
+    }
+
+    fn declare_argument(
+        &mut self,
+        argument: &AIRArgumentReflection,
+        ty: usize,
+    ) -> Result<SPIRVValue> {
+        let stage = self.reflection.stage;
+        let fixups = self.options.coordinate_fixups;
+
+        let value = match argument.kind.as_str() {
+            "air.buffer" => {
+                let AIRValueType::Pointer {
+                    address_space,
+                    pointee,
+                } = self.types.get(ty)?.clone()
+                else {
+                    return Err(anyhow!("Buffer argument isn't a pointer."));
+                };
+
+                if !matches!(
+                    address_space,
+                    AIRAddressSpace::Device | AIRAddressSpace::Constant
+                ) {
+                    return Err(anyhow!(
+                        "Buffers in the {:?} address space not implemented.",
+                        address_space
+                    ));
+                }
+
+                let binding = argument
+                    .location_index
+                    .ok_or_else(|| anyhow!("Buffer argument has no index."))?;
+
+                return Ok(SPIRVValue::Pointer(SPIRVPointer {
+                    root: SPIRVPointerRoot::Buffer {
+                        set: 0,
+                        binding,
+                        address_space,
+                        element: pointee,
+                    },
+                    path: vec![(SPIRVIndex::Constant(0), None)],
+                    pointee,
+                }));
+            }
+            "air.indirect_buffer" => {
+                let AIRValueType::Pointer { pointee, .. } = self.types.get(ty)?.clone() else {
+                    return Err(anyhow!("Argument buffer isn't a pointer."));
+                };
+
+                let index = argument
+                    .location_index
+                    .filter(|index| self.argument_buffers.contains_key(index))
+                    .ok_or_else(|| anyhow!("Argument buffer has no resources or no index."))?;
+
+                return Ok(SPIRVValue::Pointer(SPIRVPointer {
+                    root: SPIRVPointerRoot::ArgumentBuffer { index },
+                    path: vec![],
+                    pointee,
+                }));
+            }
+            "air.vertex_id" => self.builtin_input(SPIRVBuiltIn::VertexIndex, 1, ty)?,
+            "air.instance_id" => self.builtin_input(SPIRVBuiltIn::InstanceIndex, 1, ty)?,
+            "air.base_vertex" => {
+                self.spirv.capability(SPIRVCapability::DrawParameters);
+                self.builtin_input(SPIRVBuiltIn::BaseVertex, 1, ty)?
+            }
+            "air.base_instance" => {
+                self.spirv.capability(SPIRVCapability::DrawParameters);
+                self.builtin_input(SPIRVBuiltIn::BaseInstance, 1, ty)?
+            }
+            "air.vertex_input" => {
+                let location = argument
+                    .location_index
+                    .ok_or_else(|| anyhow!("Vertex input has no attribute index."))?;
+
+                self.load_input(ty, location, &[])?
+            }
+            "air.position" if stage == AIRShaderStage::Fragment => {
+                let frag_coord = load_frag_coord(&mut self.spirv, &fixups);
+                self.convert_float(frag_coord, ty)?
+            }
+            kind @ ("air.sample_id" | "air.front_facing" | "air.sample_mask_in")
+                if stage == AIRShaderStage::Fragment =>
+            {
+                let input = AIRFragmentInput::from_metadata(kind)
+                    .ok_or_else(|| anyhow!("{} arguments not implemented.", kind))?;
+                let value = load_fragment_input(&mut self.spirv, input);
+
+                match input {
+                    AIRFragmentInput::FrontFacing => value,
+                    _ => self.convert_uint(value, 1, ty)?,
+                }
+            }
+            "air.point_coord" => {
+                let point_coord = load_point_coord(&mut self.spirv, &fixups);
+                self.convert_float(point_coord, ty)?
+            }
+            "air.fragment_input" => {
+                let mut decorations = vec![];
+
+                for qualifier in &argument.qualifiers {
+                    decorations.push(match qualifier.as_str() {
+                        "air.flat" => SPIRVDecoration::Flat,
+                        "air.no_perspective" => SPIRVDecoration::NoPerspective,
+                        "air.centroid" => SPIRVDecoration::Centroid,
+                        "air.sample" => {
+                            self.spirv.capability(SPIRVCapability::SampleRateShading);
+                            SPIRVDecoration::Sample
+                        }
+                        _ => continue,
+                    });
+                }
+
+                // Vulkan can't interpolate integers.
+                if !self.types.is_float(ty) && !decorations.contains(&SPIRVDecoration::Flat) {
+                    decorations.push(SPIRVDecoration::Flat);
+                }
+
+                self.load_input(ty, user_location(argument)?, &decorations)?
+            }
+            "air.thread_position_in_grid" => {
+                self.builtin_input(SPIRVBuiltIn::GlobalInvocationId, 3, ty)?
+            }
+            "air.thread_position_in_threadgroup" => {
+                self.builtin_input(SPIRVBuiltIn::LocalInvocationId, 3, ty)?
+            }
+            "air.threadgroup_position_in_grid" => {
+                self.builtin_input(SPIRVBuiltIn::WorkgroupId, 3, ty)?
+            }
+            "air.threadgroups_per_grid" => {
+                self.builtin_input(SPIRVBuiltIn::NumWorkgroups, 3, ty)?
+            }
+            "air.thread_index_in_threadgroup" => {
+                self.builtin_input(SPIRVBuiltIn::LocalInvocationIndex, 1, ty)?
+            }
+            "air.threads_per_threadgroup" => {
+                let size = self.workgroup_size();
+                self.convert_uint(size, 3, ty)?
+            }
+            "air.threads_per_grid" => {
+                let uint = self.spirv.type_int(32, false);
+                let uint3 = self.spirv.type_vector(uint, 3);
+
+                let groups = self
+                    .spirv
+                    .load_builtin_input(SPIRVBuiltIn::NumWorkgroups, uint3);
+                let size = self.workgroup_size();
+                let threads = self.spirv.emit_value(SPIRVOp::IMul, uint3, &[groups, size]);
+
+                self.convert_uint(threads, 3, ty)?
+            }
+            kind @ ("air.thread_index_in_simdgroup"
+            | "air.threads_per_simdgroup"
+            | "air.simdgroup_index_in_threadgroup"
+            | "air.simdgroups_per_threadgroup"
+            | "air.thread_index_in_quadgroup") => {
+                let builtin = AIRSIMDBuiltIn::from_name(kind)?;
+                let value =
+                    lower_simd_builtin(&mut self.spirv, &self.options.features, stage, builtin)?;
+
+                self.convert_uint(value, 1, ty)?
+            }
+            kind => return Err(anyhow!("{} arguments not implemented.", kind)),
+        };
+
+        Ok(SPIRVValue::Id(value))
+    }
+
+    /// Loads a 32-bit unsigned built-in of `components` components as `ty`.
+    fn builtin_input(&mut self, builtin: SPIRVBuiltIn, components: u32, ty: usize) -> Result<u32> {
+        let uint = self.spirv.type_int(32, false);
+        let input_type = match components {
+            1 => uint,
+            _ => self.spirv.type_vector(uint, components),
+        };
+
+        let value = self.spirv.load_builtin_input(builtin, input_type);
+        self.convert_uint(value, components, ty)
+    }
+
+    /// Converts a `uint` or `uint3` built-in to the type of the parameter,
+    /// which may have fewer components or be narrower.
+    fn convert_uint(&mut self, value: u32, components: u32, ty: usize) -> Result<u32> {
+        let count = self.types.component_count(ty);
+        let uint = self.spirv.type_int(32, false);
+
+        let (value, uint_type) = match (components, count) {
+            (c, n) if c == n => (value, self.vector_type(uint, n)),
+            (_, 1) => (
+                self.spirv
+                    .emit_value(SPIRVOp::CompositeExtract, uint, &[value, 0]),
+                uint,
+            ),
+            (_, n) => {
+                let vector = self.spirv.type_vector(uint, n);
+                let mut operands = vec![value, value];
+                operands.extend(0..n);
+
+                (
+                    self.spirv
+                        .emit_value(SPIRVOp::VectorShuffle, vector, &operands),
+                    vector,
+                )
+            }
+        };
+
+        let target = self.spirv_type(ty, SPIRVLayout::Logical)?;
+
+        Ok(match target == uint_type {
+            true => value,
+            false => self.spirv.emit_value(SPIRVOp::UConvert, target, &[value]),
+        })
+    }
+
+    /// `component` itself for a single component.
+    fn vector_type(&mut self, component: u32, count: u32) -> u32 {
+        match count {
+            1 => component,
+            _ => self.spirv.type_vector(component, count),
+        }
+    }
+
+    /// Converts a `float` vector input to a `half` parameter.
+    fn convert_float(&mut self, value: u32, ty: usize) -> Result<u32> {
+        let float = self.spirv.type_float(32);
+        let float_type = self.vector_type(float, self.types.component_count(ty));
+        let target = self.spirv_type(ty, SPIRVLayout::Logical)?;
+
+        Ok(match target == float_type {
+            true => value,
+            false => self.spirv.emit_value(SPIRVOp::FConvert, target, &[value]),
+        })
+    }
+
+    fn load_input(
+        &mut self,
+        ty: usize,
+        location: u32,
+        decorations: &[SPIRVDecoration],
+    ) -> Result<u32> {
+        let input_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+        let variable = self.spirv.variable(SPIRVStorageClass::Input, input_type);
+
+        self.spirv
+            .decorate(variable, SPIRVDecoration::Location, &[location]);
+
+        for decoration in decorations {
+            self.spirv.decorate(variable, *decoration, &[]);
+        }
+
+        Ok(self
+            .spirv
+            .emit_value(SPIRVOp::Load, input_type, &[variable]))
+    }
+
+    /// The `WorkgroupSize` built-in, specialized by the runtime with the
+    /// threadgroup size of each dispatch.
+    fn workgroup_size(&mut self) -> u32 {
+        if let Some(size) = self.workgroup_size {
+            return size;
+        }
+
+        let uint = self.spirv.type_int(32, false);
+        let uint3 = self.spirv.type_vector(uint, 3);
+
+        let components: Vec<u32> = SPIRV_THREADGROUP_SIZE_SPEC_IDS
+            .iter()
+            .map(|spec_id| self.spirv.spec_constant(uint, &[1], *spec_id))
+            .collect();
+
+        let size = self
+            .spirv
+            .constant(SPIRVOp::SpecConstantComposite, uint3, &components);
+        self.spirv.decorate(
+            size,
+            SPIRVDecoration::BuiltIn,
+            &[SPIRVBuiltIn::WorkgroupSize as u32],
+        );
+
+        self.workgroup_size = Some(size);
+        size
+    }
+
+    fn lower(&mut self, instruction: &AIRInstruction) -> Result<()> {
+        let ty = match instruction.result {
+            Some(result) => self.function.value(result)?.ty,
+            None => 0,
+        };
+
+        let value = match &instruction.kind {
+            AIRInstructionKind::Binary { op, lhs, rhs } => {
+                Some(SPIRVValue::Id(self.binary(*op, *lhs, *rhs, ty)?))
+            }
+            AIRInstructionKind::FNeg(operand) => {
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let operand = self.value(*operand)?;
+
+                Some(SPIRVValue::Id(self.spirv.emit_value(
+                    SPIRVOp::FNegate,
+                    result_type,
+                    &[operand],
+                )))
+            }
+            AIRInstructionKind::Cast { op, operand } => Some(self.cast(*op, *operand, ty)?),
+            AIRInstructionKind::GetElementPtr {
+                source,
+                base,
+                indices,
+            } => {
+                let base = self.pointer(*base)?;
+                Some(SPIRVValue::Pointer(
+                    self.element_pointer(base, *source, indices)?,
+                ))
+            }
+            AIRInstructionKind::Select {
+                condition,
+                true_value,
+                false_value,
+            } => Some(SPIRVValue::Id(self.select(
+                *condition,
+                *true_value,
+                *false_value,
+                ty,
+            )?)),
+            AIRInstructionKind::ExtractElement { vector, index } => {
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let vector = self.value(*vector)?;
+
+                Some(SPIRVValue::Id(
+                    match self.function.constant_integer(*index) {
+                        Some(index) => self.spirv.emit_value(
+                            SPIRVOp::CompositeExtract,
+                            result_type,
+                            &[vector, index as u32],
+                        ),
+                        None => {
+                            let index = self.value(*index)?;
+                            self.spirv.emit_value(
+                                SPIRVOp::VectorExtractDynamic,
+                                result_type,
+                                &[vector, index],
+                            )
+                        }
+                    },
+                ))
+            }
+            AIRInstructionKind::InsertElement {
+                vector,
+                element,
+                index,
+            } => {
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let vector = self.value(*vector)?;
+                let element = self.value(*element)?;
+
+                Some(SPIRVValue::Id(
+                    match self.function.constant_integer(*index) {
+                        Some(index) => self.spirv.emit_value(
+                            SPIRVOp::CompositeInsert,
+                            result_type,
+                            &[element, vector, index as u32],
+                        ),
+                        None => {
+                            let index = self.value(*index)?;
+                            self.spirv.emit_value(
+                                SPIRVOp::VectorInsertDynamic,
+                                result_type,
+                                &[vector, element, index],
+                            )
+                        }
+                    },
+                ))
+            }
+            AIRInstructionKind::ShuffleVector {
+                first,
+                second,
+                mask,
+            } => {
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let mut operands = vec![self.value(*first)?, self.value(*second)?];
+                operands.extend(self.shuffle_mask(*mask)?);
+
+                Some(SPIRVValue::Id(self.spirv.emit_value(
+                    SPIRVOp::VectorShuffle,
+                    result_type,
+                    &operands,
+                )))
+            }
+            AIRInstructionKind::Compare {
+                predicate,
+                lhs,
+                rhs,
+            } => Some(SPIRVValue::Id(self.compare(*predicate, *lhs, *rhs, ty)?)),
+            AIRInstructionKind::Phi { incoming } => {
+                if self.types.is_pointer(ty) {
+                    return Err(anyhow!("Phis of pointers not implemented."));
+                }
+
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let result = self.spirv.id();
+
+                self.phis.push(SPIRVPendingPhi {
+                    position: self.spirv.function_position(),
+                    result_type,
+                    result,
+                    incoming: incoming.clone(),
+                });
+                self.spirv.emit(SPIRVOp::Phi, vec![result_type, result]);
+
+                Some(SPIRVValue::Id(result))
+            }
+            // Declared by `declare_locals`.
+            AIRInstructionKind::Alloca { .. } => None,
+            AIRInstructionKind::Load {
+                pointer,
+                ordering: None,
+            } => Some(self.load(*pointer, ty)?),
+            AIRInstructionKind::Store {
+                pointer,
+                value,
+                ordering: None,
+            } => {
+                self.store(*pointer, *value)?;
+                None
+            }
+            AIRInstructionKind::ExtractValue { aggregate, indices } => {
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let mut operands = vec![self.value(*aggregate)?];
+                operands.extend(indices);
+
+                Some(SPIRVValue::Id(self.spirv.emit_value(
+                    SPIRVOp::CompositeExtract,
+                    result_type,
+                    &operands,
+                )))
+            }
+            AIRInstructionKind::InsertValue {
+                aggregate,
+                value,
+                indices,
+            } => {
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let mut operands = vec![self.value(*value)?, self.value(*aggregate)?];
+                operands.extend(indices);
+
+                Some(SPIRVValue::Id(self.spirv.emit_value(
+                    SPIRVOp::CompositeInsert,
+                    result_type,
+                    &operands,
+                )))
+            }
+            AIRInstructionKind::Call { callee, arguments } => {
+                self.call(*callee, arguments, ty)?.map(SPIRVValue::Id)
+            }
+            AIRInstructionKind::Load {
+                pointer,
+                ordering: Some(ordering),
+            } => {
+                let order = AIRMemoryOrder::from_llvm(*ordering)?;
+                let atomic = self.llvm_atomic(AIRAtomicKind::Load, *pointer, ty)?;
+
+                self.atomic(&atomic, order, *pointer, None, None, None)?
+                    .map(SPIRVValue::Id)
+            }
+            AIRInstructionKind::Store {
+                pointer,
+                value,
+                ordering: Some(ordering),
+            } => {
+                let order = AIRMemoryOrder::from_llvm(*ordering)?;
+                let value_type = self.function.value(*value)?.ty;
+                let atomic = self.llvm_atomic(AIRAtomicKind::Store, *pointer, value_type)?;
+                let value = self.value(*value)?;
+
+                self.atomic(&atomic, order, *pointer, Some(value), None, None)?;
+                None
+            }
+            AIRInstructionKind::AtomicRMW {
+                operation,
+                pointer,
+                value,
+                ordering,
+            } => {
+                let order = AIRMemoryOrder::from_llvm(*ordering)?;
+                let address_space = self.address_space(*pointer)?;
+                let atomic =
+                    AIRAtomic::from_atomic_rmw(*operation, address_space, self.atomic_type(ty)?)?;
+                let value = self.value(*value)?;
+
+                self.atomic(&atomic, order, *pointer, Some(value), None, None)?
+                    .map(SPIRVValue::Id)
+            }
+            AIRInstructionKind::CompareExchange {
+                pointer,
+                comparator,
+                value,
+                success,
+                failure,
+            } => {
+                let order = AIRMemoryOrder::from_llvm(*success)?;
+                let failure = AIRMemoryOrder::from_llvm(*failure)?;
+                let value_type = self.function.value(*value)?.ty;
+                let atomic =
+                    self.llvm_atomic(AIRAtomicKind::CompareExchange, *pointer, value_type)?;
+
+                let comparator = self.value(*comparator)?;
+                let value = self.value(*value)?;
+
+                let original = self
+                    .atomic(
+                        &atomic,
+                        order,
+                        *pointer,
+                        Some(value),
+                        Some(comparator),
+                        Some(failure),
+                    )?
+                    .ok_or_else(|| anyhow!("cmpxchg produced no value."))?;
+
+                // `{ original, success }`.
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let bool_type = self.spirv.type_bool();
+                let exchanged =
+                    self.spirv
+                        .emit_value(SPIRVOp::IEqual, bool_type, &[original, comparator]);
+
+                Some(SPIRVValue::Id(self.spirv.emit_value(
+                    SPIRVOp::CompositeConstruct,
+                    result_type,
+                    &[original, exchanged],
+                )))
+            }
+            AIRInstructionKind::Fence { ordering } => {
+                let order = AIRMemoryOrder::from_llvm(*ordering)?;
+                let semantics = order.semantics(AIRAtomicTarget::Memory(AIRAddressSpace::Device))
+                    | memory_semantics::WORKGROUP_MEMORY
+                    | memory_semantics::IMAGE_MEMORY;
+
+                let operands = vec![
+                    self.spirv.constant_u32(SPIRVScope::Device as u32),
+                    self.spirv.constant_u32(semantics),
+                ];
+                self.spirv.emit(SPIRVOp::MemoryBarrier, operands);
+
+                None
+            }
+            AIRInstructionKind::Freeze(operand) => Some(SPIRVValue::Id(self.value(*operand)?)),
+            AIRInstructionKind::Return(value) => {
+                self.lower_return(*value)?;
+                None
+            }
+            AIRInstructionKind::Branch(target) => {
+                self.spirv.emit(SPIRVOp::Branch, vec![self.labels[*target]]);
+                None
+            }
+            AIRInstructionKind::ConditionalBranch {
+                condition,
+                true_target,
+                false_target,
+            } => {
+                match true_target == false_target {
+                    true => self
+                        .spirv
+                        .emit(SPIRVOp::Branch, vec![self.labels[*true_target]]),
+                    false => {
+                        let condition = self.value(*condition)?;
+                        self.spirv.emit(
+                            SPIRVOp::BranchConditional,
+                            vec![
+                                condition,
+                                self.labels[*true_target],
+                                self.labels[*false_target],
+                            ],
+                        );
+                    }
+                }
+
+                None
+            }
+            AIRInstructionKind::Switch {
+                condition,
+                default,
+                cases,
+            } => {
+                let condition_type = self.function.value(*condition)?.ty;
+                let selector = self.value(*condition)?;
+                let mut operands = vec![selector, self.labels[*default]];
+
+                for (value, target) in cases {
+                    let value = self
+                        .function
+                        .constant_integer(*value)
+                        .ok_or_else(|| anyhow!("Switch case {} isn't a constant.", value))?;
+
+                    operands.extend(self.int_literals(condition_type, value as u64)?);
+                    operands.push(self.labels[*target]);
+                }
+
+                self.spirv.emit(SPIRVOp::Switch, operands);
+                None
+            }
+            AIRInstructionKind::Unreachable => {
+                self.spirv.emit(SPIRVOp::Unreachable, vec![]);
+                None
+            }
+        };
+
+        if let (Some(result), Some(value)) = (instruction.result, value) {
+            self.values.insert(result, value);
+        }
+
+        Ok(())
+    }
+
+    fn resolve_phis(&mut self) -> Result<()> {
+        for phi in std::mem::take(&mut self.phis) {
+            let mut operands = vec![phi.result_type, phi.result];
+            let mut seen = vec![];
+
+            for (value, block) in phi.incoming {
+                if seen.contains(&block) {
+                    continue;
+                }
+
+                seen.push(block);
+                operands.push(self.value(value)?);
+                operands.push(self.labels[block]);
+            }
+
+            self.spirv.set_function_operands(phi.position, operands);
+        }
+
+        Ok(())
+    }
+
+    fn lower_return(&mut self, value: Option<u64>) -> Result<()> {
+        if let Some(value) = value {
+            let ty = self.function.value(value)?.ty;
+            let id = self.value(value)?;
+            let returns_struct = matches!(self.types.get(ty)?, AIRValueType::Struct { .. });
+
+            for (index, output) in self.outputs.clone().into_iter().enumerate() {
+                let member = match returns_struct {
+                    true => {
+                        let member_type = self.types.member(ty, index as u64)?;
+                        let member_type = self.spirv_type(member_type, SPIRVLayout::Logical)?;
+
+                        self.spirv.emit_value(
+                            SPIRVOp::CompositeExtract,
+                            member_type,
+                            &[id, index as u32],
+                        )
+                    }
+                    false => id,
+                };
+
+                let member = match output.kind {
+                    SPIRVOutputKind::Position => fixup_vertex_position(
+                        &mut self.spirv,
+                        &self.options.coordinate_fixups,
+                        member,
+                    ),
+                    SPIRVOutputKind::Plain => member,
+                    SPIRVOutputKind::Fragment(fragment_output) => {
+                        store_fragment_output(
+                            &mut self.spirv,
+                            fragment_output,
+                            output.variable,
+                            member,
+                        );
+                        continue;
+                    }
+                };
+
+                self.spirv.store(output.variable, member);
+            }
+        }
+
+        self.spirv.emit(SPIRVOp::Return, vec![]);
+        Ok(())
+    }
+
+    /// The SPIR-V id of a non-pointer value, lowering constants on first use.
+    fn value(&mut self, id: u64) -> Result<u32> {
+        match self.values.get(&id) {
+            Some(SPIRVValue::Id(value)) => return Ok(*value),
+            Some(SPIRVValue::Pointer(_)) => {
+                return Err(anyhow!(
+                    "Pointer {} used as a value, which isn't implemented.",
+                    id
+                ));
+            }
+            None => {}
+        }
+
+        let value = self.function.value(id)?.clone();
+
+        let lowered = match &value.kind {
+            AIRValueKind::Constant(constant) => self.constant(constant, value.ty)?,
+            AIRValueKind::Instruction => {
+                return Err(anyhow!("Value {} is used before it's defined.", id));
+            }
+            kind => {
+                return Err(anyhow!(
+                    "{:?} used as a value, which isn't implemented.",
+                    kind
+                ));
+            }
+        };
+
+        self.values.insert(id, SPIRVValue::Id(lowered));
+        Ok(lowered)
+    }
+
+    fn constant(&mut self, constant: &AIRConstant, ty: usize) -> Result<u32> {
+        let spirv_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+
+        Ok(match constant {
+            AIRConstant::Integer(value) => self.int_constant(ty, *value as u64)?,
+            AIRConstant::Float(bits) => self.float_bits_constant(ty, *bits)?,
+            AIRConstant::Null => self.spirv.constant_null(spirv_type),
+            AIRConstant::Undef | AIRConstant::Poison => self.spirv.undef(spirv_type),
+            AIRConstant::Aggregate(members) => {
+                let members = members
+                    .iter()
+                    .map(|member| self.value(*member))
+                    .collect::<Result<Vec<_>>>()?;
+
+                self.spirv.constant_composite(spirv_type, &members)
+            }
+            AIRConstant::Data(elements) => {
+                let element = self.types.member(ty, 0)?;
+
+                let elements = elements
+                    .iter()
+                    .map(|bits| match self.types.is_float(element) {
+                        true => self.float_bits_constant(element, *bits),
+                        false => self.int_constant(element, *bits),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                self.spirv.constant_composite(spirv_type, &elements)
+            }
+            other => return Err(anyhow!("Constant {:?} not implemented.", other)),
+        })
+    }
+
+    /// A constant of the integer scalar or vector type `ty`, truncated to its
+    /// width.
+    fn int_constant(&mut self, ty: usize, value: u64) -> Result<u32> {
+        let scalar = self.types.scalar(ty)?;
+
+        let constant = match self.types.get(scalar)? {
+            AIRValueType::Int(1) => self.spirv.constant_bool(value & 1 != 0),
+            _ => {
+                let spirv_type = self.spirv_type(scalar, SPIRVLayout::Logical)?;
+                let literals = self.int_literals(scalar, value)?;
+
+                self.spirv
+                    .constant(SPIRVOp::Constant, spirv_type, &literals)
+            }
+        };
+
+        self.splat(ty, constant)
+    }
+
+    /// The literal words of `value` as an integer of type `ty`.
+    fn int_literals(&self, ty: usize, value: u64) -> Result<Vec<u32>> {
+        let Some((width, emulated)) = self.int_widths(ty)? else {
+            return Err(anyhow!("Type {} isn't an integer.", ty));
+        };
+
+        let value = match width.min(emulated) {
+            64 => value,
+            bits => value & ((1 << bits) - 1),
+        };
+
+        Ok(match emulated {
+            64 => vec![value as u32, (value >> 32) as u32],
+            _ => vec![value as u32],
+        })
+    }
+
+    fn float_bits_constant(&mut self, ty: usize, bits: u64) -> Result<u32> {
+        let scalar = self.types.scalar(ty)?;
+        let spirv_type = self.spirv_type(scalar, SPIRVLayout::Logical)?;
+
+        let literals = match self.types.get(scalar)? {
+            AIRValueType::Half if self.options.features.float16 => vec![bits as u32 & 0xFFFF],
+            AIRValueType::Half => vec![half_to_float(bits as u16)],
+            AIRValueType::Float => vec![bits as u32],
+            AIRValueType::Double => vec![bits as u32, (bits >> 32) as u32],
+            other => return Err(anyhow!("{:?} constants not implemented.", other)),
+        };
+
+        let constant = self
+            .spirv
+            .constant(SPIRVOp::Constant, spirv_type, &literals);
+
+        self.splat(ty, constant)
+    }
+
+    /// A float constant of the scalar or vector type `ty`, for small exact
+    /// values.
+    fn float_constant(&mut self, ty: usize, value: f32) -> Result<u32> {
+        let scalar = self.types.scalar(ty)?;
+
+        let bits = match self.types.get(scalar)? {
+            AIRValueType::Half => float_to_half(value) as u64,
+            AIRValueType::Double => (value as f64).to_bits(),
+            _ => value.to_bits() as u64,
+        };
+
+        self.float_bits_constant(ty, bits)
+    }
+
+    /// Repeats a scalar constant for every component of `ty`.
+    fn splat(&mut self, ty: usize, scalar: u32) -> Result<u32> {
+        match self.types.get(ty)? {
+            AIRValueType::Vector { count, .. } => {
+                let constituents = vec![scalar; *count as usize];
+                let spirv_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+
+                Ok(self.spirv.constant_composite(spirv_type, &constituents))
+            }
+            _ => Ok(scalar),
+        }
+    }
+
+    fn shuffle_mask(&self, mask: u64) -> Result<Vec<u32>> {
+        let value = self.function.value(mask)?;
+        let count = self.types.component_count(value.ty) as usize;
+
+        Ok(match &value.kind {
+            AIRValueKind::Constant(AIRConstant::Data(elements)) => {
+                elements.iter().map(|element| *element as u32).collect()
+            }
+            AIRValueKind::Constant(AIRConstant::Aggregate(elements)) => elements
+                .iter()
+                .map(|element| {
+                    self.function
+                        .constant_integer(*element)
+                        .map_or(u32::MAX, |index| index as u32)
+                })
+                .collect(),
+            AIRValueKind::Constant(AIRConstant::Null) => vec![0; count],
+            AIRValueKind::Constant(AIRConstant::Undef | AIRConstant::Poison) => {
+                vec![u32::MAX; count]
+            }
+            other => return Err(anyhow!("Shuffle mask {:?} isn't a constant.", other)),
+        })
+    }
+
+    /// The SPIR-V type of `ty`. Integers are unsigned, operations pick the
+    /// signedness.
+    fn spirv_type(&mut self, ty: usize, layout: SPIRVLayout) -> Result<u32> {
+        if let Some(id) = self.type_cache.get(&(ty, layout)) {
+            return Ok(*id);
+        }
+
+        let features = &self.options.features;
+
+        let id = match self.types.get(ty)?.clone() {
+            AIRValueType::Void => self.spirv.type_void(),
+            AIRValueType::Int(1) if layout == SPIRVLayout::Logical => self.spirv.type_bool(),
+            AIRValueType::Int(width) if layout == SPIRVLayout::Logical => {
+                let width = self.emulated_int_width(width)?;
+                self.spirv.type_int(width, false)
+            }
+            AIRValueType::Int(8) if features.storage_buffer_8bit => {
+                self.spirv
+                    .capability(SPIRVCapability::StorageBuffer8BitAccess);
+                self.spirv.extension("SPV_KHR_8bit_storage");
+                self.spirv.type_int_storage(8, false)
+            }
+            AIRValueType::Int(16) if features.storage_buffer_16bit => {
+                self.spirv
+                    .capability(SPIRVCapability::StorageBuffer16BitAccess);
+                self.spirv.type_int_storage(16, false)
+            }
+            AIRValueType::Int(32) => self.spirv.type_int(32, false),
+            AIRValueType::Int(64) if features.int64 => self.spirv.type_int(64, false),
+            AIRValueType::Int(width) => {
+                return Err(anyhow!(
+                    "{}-bit integers in buffers are not supported by this device.",
+                    width
+                ));
+            }
+            AIRValueType::Half if layout == SPIRVLayout::Logical => match features.float16 {
+                true => self.spirv.type_float(16),
+                false => self.spirv.type_float(32),
+            },
+            AIRValueType::Half if features.storage_buffer_16bit => {
+                self.spirv
+                    .capability(SPIRVCapability::StorageBuffer16BitAccess);
+                self.spirv.type_float_storage(16)
+            }
+            AIRValueType::Half => {
+                return Err(anyhow!("half in buffers is not supported by this device."));
+            }
+            AIRValueType::Float => self.spirv.type_float(32),
+            AIRValueType::Double => self.spirv.type_float(64),
+            AIRValueType::Vector { element, count } => {
+                let element = self.spirv_type(element, layout)?;
+                self.spirv.type_vector(element, count)
+            }
+            AIRValueType::Array { element, length } => {
+                let spirv_element = self.spirv_type(element, layout)?;
+
+                match layout {
+                    SPIRVLayout::Logical => self.spirv.type_array(spirv_element, length),
+                    SPIRVLayout::Explicit => {
+                        let stride = self.types.stride(element)?;
+                        self.spirv
+                            .type_array_with_stride(spirv_element, length, stride)
+                    }
+                }
+            }
+            AIRValueType::Struct { members, .. } => {
+                let members = members
+                    .iter()
+                    .map(|member| self.spirv_type(*member, layout))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let id = self.spirv.type_struct(&members);
+
+                if layout == SPIRVLayout::Explicit {
+                    for (index, offset) in self.types.member_offsets(ty)?.into_iter().enumerate() {
+                        self.spirv.member_decorate(
+                            id,
+                            index as u32,
+                            SPIRVDecoration::Offset,
+                            &[offset],
+                        );
+                    }
+                }
+
+                id
+            }
+            other => return Err(anyhow!("{:?} values not implemented.", other)),
+        };
+
+        self.type_cache.insert((ty, layout), id);
+        Ok(id)
+    }
+
+    /// The width integers of `width` bits are computed in.
+    fn emulated_int_width(&self, width: u32) -> Result<u32> {
+        let features = &self.options.features;
+
+        Ok(match width {
+            8 if features.int8 => 8,
+            16 if features.int16 => 16,
+            64 if features.int64 => 64,
+            // Narrow integers are kept zero-extended in 32 bits. 64-bit ones
+            // are truncated, which is lossy but mostly hits the indices clang
+            // widens.
+            8 | 16 | 32 | 64 => 32,
+            _ => return Err(anyhow!("{}-bit integers not implemented.", width)),
+        })
+    }
+
+    /// The width of an integer scalar or vector type and the width it's
+    /// computed in, `None` for anything else and booleans.
+    fn int_widths(&self, ty: usize) -> Result<Option<(u32, u32)>> {
+        match self.types.get(self.types.scalar(ty)?)? {
+            AIRValueType::Int(1) => Ok(None),
+            AIRValueType::Int(width) => Ok(Some((*width, self.emulated_int_width(*width)?))),
+            _ => Ok(None),
+        }
+    }
+
+    fn is_bool(&self, ty: usize) -> bool {
+        matches!(
+            self.types
+                .scalar(ty)
+                .and_then(|scalar| self.types.get(scalar)),
+            Ok(AIRValueType::Int(1))
+        )
+    }
+
+    /// Clears the bits above an emulated integer's width.
+    fn wrap(&mut self, value: u32, ty: usize) -> Result<u32> {
+        match self.int_widths(ty)? {
+            Some((width, emulated)) if width < emulated => {
+                let spirv_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let mask = self.int_constant(ty, (1 << width) - 1)?;
+
+                Ok(self
+                    .spirv
+                    .emit_value(SPIRVOp::BitwiseAnd, spirv_type, &[value, mask]))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    /// Sign-extends an emulated integer to the width it's computed in.
+    fn sign_extend(&mut self, value: u32, ty: usize) -> Result<u32> {
+        match self.int_widths(ty)? {
+            Some((width, emulated)) if width < emulated => {
+                let spirv_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                let shift = self.int_constant(ty, (emulated - width) as u64)?;
+
+                let shifted =
+                    self.spirv
+                        .emit_value(SPIRVOp::ShiftLeftLogical, spirv_type, &[value, shift]);
+
+                Ok(self.spirv.emit_value(
+                    SPIRVOp::ShiftRightArithmetic,
+                    spirv_type,
+                    &[shifted, shift],
+                ))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn binary(&mut self, op: AIRBinaryOp, lhs: u64, rhs: u64, ty: usize) -> Result<u32> {
+        let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+        let mut lhs = self.value(lhs)?;
+        let mut rhs = self.value(rhs)?;
+
+        if self.types.is_float(ty) {
+            let op = match op {
+                AIRBinaryOp::Add => SPIRVOp::FAdd,
+                AIRBinaryOp::Sub => SPIRVOp::FSub,
+                AIRBinaryOp::Mul => SPIRVOp::FMul,
+                AIRBinaryOp::SDiv => SPIRVOp::FDiv,
+                AIRBinaryOp::SRem => SPIRVOp::FRem,
+                op => return Err(anyhow!("Float {:?} not implemented.", op)),
+            };
+
+            return Ok(self.spirv.emit_value(op, result_type, &[lhs, rhs]));
+        }
+
+        if self.is_bool(ty) {
+            let op = match op {
+                AIRBinaryOp::And | AIRBinaryOp::Mul => SPIRVOp::LogicalAnd,
+                AIRBinaryOp::Or => SPIRVOp::LogicalOr,
+                AIRBinaryOp::Xor | AIRBinaryOp::Add | AIRBinaryOp::Sub => SPIRVOp::LogicalNotEqual,
+                op => return Err(anyhow!("Boolean {:?} not implemented.", op)),
+            };
+
+            return Ok(self.spirv.emit_value(op, result_type, &[lhs, rhs]));
+        }
+
+        if matches!(
+            op,
+            AIRBinaryOp::SDiv | AIRBinaryOp::SRem | AIRBinaryOp::AShr
+        ) {
+            lhs = self.sign_extend(lhs, ty)?;
+        }
+
+        if matches!(op, AIRBinaryOp::SDiv | AIRBinaryOp::SRem) {
+            rhs = self.sign_extend(rhs, ty)?;
+        }
+
+        let spirv_op = match op {
+            AIRBinaryOp::Add => SPIRVOp::IAdd,
+            AIRBinaryOp::Sub => SPIRVOp::ISub,
+            AIRBinaryOp::Mul => SPIRVOp::IMul,
+            AIRBinaryOp::UDiv => SPIRVOp::UDiv,
+            AIRBinaryOp::SDiv => SPIRVOp::SDiv,
+            AIRBinaryOp::URem => SPIRVOp::UMod,
+            AIRBinaryOp::SRem => SPIRVOp::SRem,
+            AIRBinaryOp::Shl => SPIRVOp::ShiftLeftLogical,
+            AIRBinaryOp::LShr => SPIRVOp::ShiftRightLogical,
+            AIRBinaryOp::AShr => SPIRVOp::ShiftRightArithmetic,
+            AIRBinaryOp::And => SPIRVOp::BitwiseAnd,
+            AIRBinaryOp::Or => SPIRVOp::BitwiseOr,
+            AIRBinaryOp::Xor => SPIRVOp::BitwiseXor,
+        };
+
+        let result = self.spirv.emit_value(spirv_op, result_type, &[lhs, rhs]);
+
+        match op {
+            AIRBinaryOp::UDiv
+            | AIRBinaryOp::URem
+            | AIRBinaryOp::LShr
+            | AIRBinaryOp::And
+            | AIRBinaryOp::Or
+            | AIRBinaryOp::Xor => Ok(result),
+            _ => self.wrap(result, ty),
+        }
+    }
+
+    fn compare(&mut self, predicate: AIRPredicate, lhs: u64, rhs: u64, ty: usize) -> Result<u32> {
+        let operand_type = self.function.value(lhs)?.ty;
+
+        if self.types.is_pointer(operand_type) {
+            return Err(anyhow!("Comparing pointers not implemented."));
+        }
+
+        let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+        let mut lhs = self.value(lhs)?;
+        let mut rhs = self.value(rhs)?;
+
+        let op = if self.types.is_float(operand_type) {
+            match predicate.0 {
+                AIRPredicate::FCMP_FALSE => return self.int_constant(ty, 0),
+                AIRPredicate::FCMP_TRUE => return self.int_constant(ty, 1),
+                AIRPredicate::FCMP_ORD | AIRPredicate::FCMP_UNO => {
+                    let lhs_nan = self.spirv.emit_value(SPIRVOp::IsNan, result_type, &[lhs]);
+                    let rhs_nan = self.spirv.emit_value(SPIRVOp::IsNan, result_type, &[rhs]);
+                    let unordered =
+                        self.spirv
+                            .emit_value(SPIRVOp::LogicalOr, result_type, &[lhs_nan, rhs_nan]);
+
+                    return Ok(match predicate.0 {
+                        AIRPredicate::FCMP_UNO => unordered,
+                        _ => self
+                            .spirv
+                            .emit_value(SPIRVOp::LogicalNot, result_type, &[unordered]),
+                    });
+                }
+                AIRPredicate::FCMP_OEQ => SPIRVOp::FOrdEqual,
+                AIRPredicate::FCMP_OGT => SPIRVOp::FOrdGreaterThan,
+                AIRPredicate::FCMP_OGE => SPIRVOp::FOrdGreaterThanEqual,
+                AIRPredicate::FCMP_OLT => SPIRVOp::FOrdLessThan,
+                AIRPredicate::FCMP_OLE => SPIRVOp::FOrdLessThanEqual,
+                AIRPredicate::FCMP_ONE => SPIRVOp::FOrdNotEqual,
+                AIRPredicate::FCMP_UEQ => SPIRVOp::FUnordEqual,
+                AIRPredicate::FCMP_UGT => SPIRVOp::FUnordGreaterThan,
+                AIRPredicate::FCMP_UGE => SPIRVOp::FUnordGreaterThanEqual,
+                AIRPredicate::FCMP_ULT => SPIRVOp::FUnordLessThan,
+                AIRPredicate::FCMP_ULE => SPIRVOp::FUnordLessThanEqual,
+                AIRPredicate::FCMP_UNE => SPIRVOp::FUnordNotEqual,
+                other => return Err(anyhow!("fcmp predicate {} not implemented.", other)),
+            }
+        } else if self.is_bool(operand_type) {
+            match predicate.0 {
+                AIRPredicate::ICMP_EQ => SPIRVOp::LogicalEqual,
+                AIRPredicate::ICMP_NE => SPIRVOp::LogicalNotEqual,
+                other => {
+                    return Err(anyhow!(
+                        "icmp predicate {} on booleans not implemented.",
+                        other
+                    ));
+                }
+            }
+        } else {
+            if (AIRPredicate::ICMP_SGT..=AIRPredicate::ICMP_SLE).contains(&predicate.0) {
+                lhs = self.sign_extend(lhs, operand_type)?;
+                rhs = self.sign_extend(rhs, operand_type)?;
+            }
+
+            match predicate.0 {
+                AIRPredicate::ICMP_EQ => SPIRVOp::IEqual,
+                AIRPredicate::ICMP_NE => SPIRVOp::INotEqual,
+                AIRPredicate::ICMP_UGT => SPIRVOp::UGreaterThan,
+                AIRPredicate::ICMP_UGE => SPIRVOp::UGreaterThanEqual,
+                AIRPredicate::ICMP_ULT => SPIRVOp::ULessThan,
+                AIRPredicate::ICMP_ULE => SPIRVOp::ULessThanEqual,
+                AIRPredicate::ICMP_SGT => SPIRVOp::SGreaterThan,
+                AIRPredicate::ICMP_SGE => SPIRVOp::SGreaterThanEqual,
+                AIRPredicate::ICMP_SLT => SPIRVOp::SLessThan,
+                AIRPredicate::ICMP_SLE => SPIRVOp::SLessThanEqual,
+                other => return Err(anyhow!("icmp predicate {} not implemented.", other)),
+            }
+        };
+
+        Ok(self.spirv.emit_value(op, result_type, &[lhs, rhs]))
+    }
+
+    fn select(
+        &mut self,
+        condition: u64,
+        true_value: u64,
+        false_value: u64,
+        ty: usize,
+    ) -> Result<u32> {
+        match self.types.get(ty)? {
+            AIRValueType::Pointer { .. } => {
+                return Err(anyhow!("Selecting pointers not implemented."));
+            }
+            AIRValueType::Struct { .. } | AIRValueType::Array { .. } => {
+                return Err(anyhow!("Selecting aggregates not implemented."));
+            }
+            _ => {}
+        }
+
+        let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+        let condition_type = self.function.value(condition)?.ty;
+        let mut condition = self.value(condition)?;
+
+        // SPIR-V 1.3 selects vectors by a vector of conditions.
+        let count = self.types.component_count(ty);
+        if count > 1 && self.types.component_count(condition_type) == 1 {
+            let bool_type = self.spirv.type_bool();
+            let vector = self.spirv.type_vector(bool_type, count);
+
+            condition = self.spirv.emit_value(
+                SPIRVOp::CompositeConstruct,
+                vector,
+                &vec![condition; count as usize],
+            );
+        }
+
+        let true_value = self.value(true_value)?;
+        let false_value = self.value(false_value)?;
+
+        Ok(self.spirv.emit_value(
+            SPIRVOp::Select,
+            result_type,
+            &[condition, true_value, false_value],
+        ))
+    }
+
+    fn cast(&mut self, op: AIRCastOp, operand: u64, ty: usize) -> Result<SPIRVValue> {
+        if matches!(op, AIRCastOp::BitCast | AIRCastOp::AddrSpaceCast) && self.types.is_pointer(ty)
+        {
+            let pointer = self.pointer(operand)?;
+            return Ok(SPIRVValue::Pointer(self.cast_pointer(pointer, ty)?));
+        }
+
+        let source_type = self.function.value(operand)?.ty;
+        let value = self.value(operand)?;
+
+        Ok(SPIRVValue::Id(self.convert(op, value, source_type, ty)?))
+    }
+
+    fn convert(&mut self, op: AIRCastOp, value: u32, from: usize, to: usize) -> Result<u32> {
+        let from_type = self.spirv_type(from, SPIRVLayout::Logical)?;
+        let result_type = self.spirv_type(to, SPIRVLayout::Logical)?;
+
+        let emit = |translator: &mut Self, op: SPIRVOp, value: u32| {
+            translator.spirv.emit_value(op, result_type, &[value])
+        };
+
+        Ok(match op {
+            AIRCastOp::Trunc if self.is_bool(to) => {
+                let one = self.int_constant(from, 1)?;
+                let zero = self.int_constant(from, 0)?;
+                let bit = self
+                    .spirv
+                    .emit_value(SPIRVOp::BitwiseAnd, from_type, &[value, one]);
+
+                self.spirv
+                    .emit_value(SPIRVOp::INotEqual, result_type, &[bit, zero])
+            }
+            AIRCastOp::Trunc => {
+                let value = match from_type == result_type {
+                    true => value,
+                    false => emit(self, SPIRVOp::UConvert, value),
+                };
+
+                self.wrap(value, to)?
+            }
+            AIRCastOp::ZExt | AIRCastOp::SExt if self.is_bool(from) => {
+                let one = match op {
+                    AIRCastOp::ZExt => 1,
+                    _ => u64::MAX,
+                };
+
+                let one = self.int_constant(to, one)?;
+                let zero = self.int_constant(to, 0)?;
+
+                self.spirv
+                    .emit_value(SPIRVOp::Select, result_type, &[value, one, zero])
+            }
+            AIRCastOp::ZExt => match from_type == result_type {
+                true => value,
+                false => emit(self, SPIRVOp::UConvert, value),
+            },
+            AIRCastOp::SExt => {
+                let value = self.sign_extend(value, from)?;
+                let value = match from_type == result_type {
+                    true => value,
+                    false => emit(self, SPIRVOp::SConvert, value),
+                };
+
+                self.wrap(value, to)?
+            }
+            AIRCastOp::FPToUI => {
+                let value = emit(self, SPIRVOp::ConvertFToU, value);
+                self.wrap(value, to)?
+            }
+            AIRCastOp::FPToSI => {
+                let value = emit(self, SPIRVOp::ConvertFToS, value);
+                self.wrap(value, to)?
+            }
+            AIRCastOp::UIToFP | AIRCastOp::SIToFP if self.is_bool(from) => {
+                let one = match op {
+                    AIRCastOp::UIToFP => 1.0,
+                    _ => -1.0,
+                };
+
+                let one = self.float_constant(to, one)?;
+                let zero = self.float_constant(to, 0.0)?;
+
+                self.spirv
+                    .emit_value(SPIRVOp::Select, result_type, &[value, one, zero])
+            }
+            AIRCastOp::UIToFP => emit(self, SPIRVOp::ConvertUToF, value),
+            AIRCastOp::SIToFP => {
+                let value = self.sign_extend(value, from)?;
+                emit(self, SPIRVOp::ConvertSToF, value)
+            }
+            // Emulated halves are rounded like real ones would be.
+            AIRCastOp::FPTrunc if from_type == result_type => {
+                emit(self, SPIRVOp::QuantizeToF16, value)
+            }
+            AIRCastOp::FPTrunc | AIRCastOp::FPExt => match from_type == result_type {
+                true => value,
+                false => emit(self, SPIRVOp::FConvert, value),
+            },
+            AIRCastOp::BitCast => {
+                if from_type == result_type {
+                    return Ok(value);
+                }
+
+                if self.emulated_bits(from)? != self.emulated_bits(to)? {
+                    return Err(anyhow!(
+                        "Bitcasting emulated types of different sizes not implemented."
+                    ));
+                }
+
+                emit(self, SPIRVOp::Bitcast, value)
+            }
+            op => return Err(anyhow!("{:?} not implemented.", op)),
+        })
+    }
+
+    /// The size in bits of a scalar or vector as it's computed.
+    fn emulated_bits(&self, ty: usize) -> Result<u32> {
+        let bits = match self.types.get(self.types.scalar(ty)?)? {
+            AIRValueType::Int(width) => self.emulated_int_width(*width)?,
+            AIRValueType::Half if self.options.features.float16 => 16,
+            AIRValueType::Half | AIRValueType::Float => 32,
+            AIRValueType::Double => 64,
+            other => return Err(anyhow!("{:?} has no size in bits.", other)),
+        };
+
+        Ok(bits * self.types.component_count(ty))
+    }
+
+    /// The bits of a scalar, as declared.
+    fn scalar_bits(&self, ty: usize) -> Result<u32> {
+        Ok(match self.types.get(self.types.scalar(ty)?)? {
+            AIRValueType::Int(width) => *width,
+            AIRValueType::Half => 16,
+            AIRValueType::Float => 32,
+            AIRValueType::Double => 64,
+            other => return Err(anyhow!("{:?} has no size in bits.", other)),
+        })
+    }
+
+    fn call(&mut self, callee: u64, arguments: &[u64], ty: usize) -> Result<Option<u32>> {
+        let AIRValueKind::Function { name, .. } = &self.function.value(callee)?.kind else {
+            return Err(anyhow!("Indirect calls not implemented."));
+        };
+        let name = name.clone();
+
+        if name.starts_with("llvm.dbg.")
+            || name.starts_with("llvm.lifetime.")
+            || matches!(
+                name.as_str(),
+                "llvm.assume" | "llvm.donothing" | "llvm.experimental.noalias.scope.decl"
+            )
+        {
+            return Ok(None);
+        }
+
+        if name == "air.wg.barrier" || name == "air.simdgroup.barrier" {
+            self.barrier(&name, arguments)?;
+            return Ok(None);
+        }
+
+        if name == "air.discard_fragment" && self.reflection.stage == AIRShaderStage::Fragment {
+            // Anything after an `OpKill` goes into an unreachable block.
+            if lower_discard(&mut self.spirv, &self.options.features) {
+                let label = self.spirv.id();
+                self.spirv.emit(SPIRVOp::Label, vec![label]);
+                self.debug.start_block();
+            }
+
+            return Ok(None);
+        }
+
+        if let Some(conversion) = name.strip_prefix("air.convert.") {
+            return Ok(Some(self.air_convert(conversion, arguments, ty)?));
+        }
+
+        if name.starts_with("air.atomic.") {
+            return self.atomic_intrinsic(&name, arguments, ty);
+        }
+
+        if name.starts_with("air.simd_") || name.starts_with("air.quad_") {
+            return Ok(Some(self.simd(&name, arguments, ty)?));
+        }
+
+        if let Some(function) = AIRMathFunction::from_intrinsic(&name) {
+            return Ok(Some(self.math(&function, arguments, ty)?));
+        }
+
+        Err(anyhow!(
+            "Call to {} not implemented, entry points are expected to be fully inlined.",
+            name
+        ))
+    }
+
+    /// `threadgroup_barrier` and `simdgroup_barrier`, whose first argument
+    /// is a `mem_flags`.
+    fn barrier(&mut self, name: &str, arguments: &[u64]) -> Result<()> {
+        let flags = arguments
+            .first()
+            .and_then(|flags| self.function.constant_integer(*flags))
+            .ok_or_else(|| anyhow!("{} without constant memory flags not implemented.", name))?;
+
+        let scope = match name {
+            "air.wg.barrier" => SPIRVScope::Workgroup,
+            _ => SPIRVScope::Subgroup,
+        };
+
+        let mut semantics = memory_semantics::NONE;
+        let mut memory_scope = scope;
+
+        // mem_device, mem_threadgroup and mem_texture.
+        if flags & 0x1 != 0 {
+            semantics |= memory_semantics::UNIFORM_MEMORY;
+            memory_scope = SPIRVScope::Device;
+        }
+
+        if flags & 0x2 != 0 {
+            semantics |= memory_semantics::WORKGROUP_MEMORY;
+        }
+
+        if flags & 0x4 != 0 {
+            semantics |= memory_semantics::IMAGE_MEMORY;
+            memory_scope = SPIRVScope::Device;
+        }
+
+        if semantics != memory_semantics::NONE {
+            semantics |= memory_semantics::ACQUIRE_RELEASE;
+        }
+
+        let operands = vec![
+            self.spirv.constant_u32(scope as u32),
+            self.spirv.constant_u32(memory_scope as u32),
+            self.spirv.constant_u32(semantics),
+        ];
+
+        self.spirv.emit(SPIRVOp::ControlBarrier, operands);
+        Ok(())
+    }
+
+    /// `air.convert.<to>.<type>.<from>.<type>`, `f`, `u` or `s` telling the
+    /// kind of each side.
+    fn air_convert(&mut self, conversion: &str, arguments: &[u64], ty: usize) -> Result<u32> {
+        let parts: Vec<&str> = conversion.split('.').collect();
+
+        let [to_kind, _, from_kind, _] = parts[..] else {
+            return Err(anyhow!(
+                "Conversion air.convert.{} not implemented.",
+                conversion
+            ));
+        };
+
+        let operand = *arguments
+            .first()
+            .ok_or_else(|| anyhow!("air.convert.{} has no operand.", conversion))?;
+        let from = self.function.value(operand)?.ty;
+        let value = self.value(operand)?;
+
+        let (from_bits, to_bits) = (self.scalar_bits(from)?, self.scalar_bits(ty)?);
+
+        let op = match (to_kind, from_kind) {
+            ("f", "f") if to_bits < from_bits => AIRCastOp::FPTrunc,
+            ("f", "f") => AIRCastOp::FPExt,
+            ("f", "u") => AIRCastOp::UIToFP,
+            ("f", "s") => AIRCastOp::SIToFP,
+            ("u", "f") => AIRCastOp::FPToUI,
+            ("s", "f") => AIRCastOp::FPToSI,
+            (_, "u") | (_, "s") if to_bits < from_bits => AIRCastOp::Trunc,
+            (_, "u") => AIRCastOp::ZExt,
+            (_, "s") => AIRCastOp::SExt,
+            _ => {
+                return Err(anyhow!(
+                    "Conversion air.convert.{} not implemented.",
+                    conversion
+                ));
+            }
+        };
+
+        self.convert(op, value, from, ty)
+    }
+
+    fn math(&mut self, function: &AIRMathFunction, arguments: &[u64], ty: usize) -> Result<u32> {
+        let mut operands = vec![];
+
+        for argument in arguments.iter().take(function.operands) {
+            let argument_type = self.function.value(*argument)?.ty;
+            let value = self.value(*argument)?;
+
+            operands.push(match function.signed {
+                true => self.sign_extend(value, argument_type)?,
+                false => value,
+            });
+        }
+
+        if function.op == AIRMathOp::Saturate {
+            operands.push(self.float_constant(ty, 0.0)?);
+            operands.push(self.float_constant(ty, 1.0)?);
+        }
+
+        let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+        let result = lower_math_function(&mut self.spirv, function, result_type, &operands);
+
+        match function.signed {
+            true => self.wrap(result, ty),
+            false => Ok(result),
+        }
+    }
+
+    /// The pointer a value id stands for: a global, an argument or an
+    /// instruction's result.
+    fn pointer(&mut self, id: u64) -> Result<SPIRVPointer> {
+        match self.values.get(&id) {
+            Some(SPIRVValue::Pointer(pointer)) => return Ok(pointer.clone()),
+            Some(SPIRVValue::Id(_)) => return Err(anyhow!("Value {} is not a pointer.", id)),
+            None => {}
+        }
+
+        let value = self.function.value(id)?.clone();
+
+        let pointer = match value.kind {
+            AIRValueKind::GlobalVariable {
+                name,
+                address_space,
+                value_type,
+                initializer,
+            } => self
+                .global_variable(address_space, value_type, initializer)
+                .map_err(|e| e.context(format!("Global {}", name)))?,
+            AIRValueKind::Constant(AIRConstant::GetElementPtr {
+                source,
+                base,
+                indices,
+            }) => {
+                let base = self.pointer(base)?;
+                self.element_pointer(base, source, &indices)?
+            }
+            AIRValueKind::Constant(AIRConstant::Cast {
+                opcode: AIRCastOp::BitCast | AIRCastOp::AddrSpaceCast,
+                operand,
+            }) => {
+                let pointer = self.pointer(operand)?;
+                self.cast_pointer(pointer, value.ty)?
+            }
+            kind => return Err(anyhow!("{:?} as a pointer not implemented.", kind)),
+        };
+
+        self.values.insert(id, SPIRVValue::Pointer(pointer.clone()));
+
+        Ok(pointer)
+    }
+
+    /// Declares a module-scope variable. `constant` globals become `Private`
+    /// copies of their initializer, as SPIR-V can't index constants.
+    fn global_variable(
+        &mut self,
+        address_space: AIRAddressSpace,
+        value_type: usize,
+        initializer: Option<u64>,
+    ) -> Result<SPIRVPointer> {
+        let storage_class = match address_space {
+            AIRAddressSpace::Thread | AIRAddressSpace::Constant => SPIRVStorageClass::Private,
+            AIRAddressSpace::Threadgroup => SPIRVStorageClass::Workgroup,
+            AIRAddressSpace::Device => {
+                return Err(anyhow!("device globals not implemented."));
+            }
+        };
+
+        let spirv_type = self.spirv_type(value_type, SPIRVLayout::Logical)?;
+
+        let initializer = match (storage_class, initializer) {
+            (SPIRVStorageClass::Private, Some(initializer)) => {
+                match &self.function.value(initializer)?.kind {
+                    AIRValueKind::Constant(AIRConstant::Undef | AIRConstant::Poison) => None,
+                    _ => Some(self.value(initializer)?),
+                }
+            }
+            _ => None,
+        };
+
+        let id = match initializer {
+            Some(initializer) => {
+                self.spirv
+                    .variable_with_initializer(storage_class, spirv_type, initializer)
+            }
+            None => self.spirv.variable(storage_class, spirv_type),
+        };
+
+        Ok(SPIRVPointer {
+            root: SPIRVPointerRoot::Variable { id, storage_class },
+            path: vec![],
+            pointee: Some(value_type),
+        })
+    }
+
+    /// `getelementptr`: the first index steps over whole `source`s, the rest
+    /// index into it.
+    fn element_pointer(
+        &mut self,
+        pointer: SPIRVPointer,
+        source: usize,
+        indices: &[u64],
+    ) -> Result<SPIRVPointer> {
+        let mut pointer = self.retype(pointer, source)?;
+
+        let Some((first, rest)) = indices.split_first() else {
+            return Ok(pointer);
+        };
+
+        if self.function.constant_integer(*first) != Some(0) {
+            let step = self.index(*first)?;
+
+            let Some(&(last, container)) = pointer.path.last() else {
+                return Err(anyhow!(
+                    "Pointer arithmetic past a variable not implemented."
+                ));
+            };
+
+            let steps_elements = match container {
+                None => true,
+                Some(container) => matches!(
+                    self.types.get(container)?,
+                    AIRValueType::Array { .. } | AIRValueType::Vector { .. }
+                ),
+            };
+
+            if !steps_elements {
+                return Err(anyhow!(
+                    "Pointer arithmetic across struct members not implemented."
+                ));
+            }
+
+            let sum = self.add_indices(last, step);
+            let position = pointer.path.len() - 1;
+            pointer.path[position].0 = sum;
+        }
+
+        let mut ty = source;
+
+        for index in rest {
+            let index = self.index(*index)?;
+
+            let member = match index {
+                SPIRVIndex::Constant(member) => self.types.member(ty, member as u64)?,
+                SPIRVIndex::Dynamic(_) => match self.types.get(ty)? {
+                    AIRValueType::Struct { .. } => {
+                        return Err(anyhow!("Struct {} indexed dynamically.", ty));
+                    }
+                    _ => self.types.member(ty, 0)?,
+                },
+            };
+
+            pointer.path.push((index, Some(ty)));
+            ty = member;
+        }
+
+        pointer.pointee = Some(ty);
+        Ok(pointer)
+    }
+
+    /// A pointer to the same memory as `pointer`, cast to `ty`.
+    fn cast_pointer(&mut self, pointer: SPIRVPointer, ty: usize) -> Result<SPIRVPointer> {
+        match self.types.get(ty)? {
+            AIRValueType::Pointer {
+                pointee: Some(pointee),
+                ..
+            } => self.retype(pointer, *pointee),
+            // Opaque pointers are retyped when they're accessed.
+            _ => Ok(pointer),
+        }
+    }
+
+    /// Makes `pointer` point to a `ty`: buffers are reinterpreted as arrays
+    /// of `ty`, anything else has to start with a `ty`.
+    fn retype(&mut self, mut pointer: SPIRVPointer, ty: usize) -> Result<SPIRVPointer> {
+        if pointer.pointee == Some(ty) {
+            return Ok(pointer);
+        }
+
+        if let SPIRVPointerRoot::ArgumentBuffer { .. } = pointer.root
+            && pointer.path.is_empty()
+        {
+            pointer.pointee = Some(ty);
+            return Ok(pointer);
+        }
+
+        if let SPIRVPointerRoot::Buffer {
+            set,
+            binding,
+            address_space,
+            element,
+        } = pointer.root
+            && pointer.path.len() == 1
+        {
+            let index = pointer.path[0].0;
+
+            if let Some(element) = element
+                && index != SPIRVIndex::Constant(0)
+            {
+                let (old_stride, new_stride) =
+                    (self.types.stride(element)?, self.types.stride(ty)?);
+
+                if new_stride == 0 || old_stride % new_stride != 0 {
+                    return Err(anyhow!(
+                        "Reinterpreting buffer {} at a dynamic offset not implemented.",
+                        binding
+                    ));
+                }
+
+                pointer.path[0].0 = self.scale_index(index, old_stride / new_stride);
+            }
+
+            pointer.root = SPIRVPointerRoot::Buffer {
+                set,
+                binding,
+                address_space,
+                element: Some(ty),
+            };
+            pointer.pointee = Some(ty);
+
+            return Ok(pointer);
+        }
+
+        // Decay to the first member, like a pointer to an array to its first
+        // element.
+        let mut current = pointer.pointee;
+
+        while let Some(container) = current
+            && container != ty
+        {
+            match self.types.get(container)? {
+                AIRValueType::Struct { .. }
+                | AIRValueType::Array { .. }
+                | AIRValueType::Vector { .. } => {
+                    pointer
+                        .path
+                        .push((SPIRVIndex::Constant(0), Some(container)));
+                    current = Some(self.types.member(container, 0)?);
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Reinterpreting a pointer to {:?} as {:?} not implemented.",
+                        self.types.get(pointer.pointee.unwrap_or(container))?,
+                        self.types.get(ty)?
+                    ));
+                }
+            }
+        }
+
+        pointer.pointee = Some(ty);
+        Ok(pointer)
+    }
+
+    /// An index operand as a 32-bit integer.
+    fn index(&mut self, id: u64) -> Result<SPIRVIndex> {
+        if let Some(index) = self.function.constant_integer(id) {
+            return Ok(SPIRVIndex::Constant(index as u32));
+        }
+
+        let ty = self.function.value(id)?.ty;
+        let value = self.value(id)?;
+        let value = self.sign_extend(value, ty)?;
+
+        let uint = self.spirv.type_int(32, false);
+
+        Ok(SPIRVIndex::Dynamic(match self.int_widths(ty)? {
+            Some((_, 32)) => value,
+            Some(_) => self.spirv.emit_value(SPIRVOp::SConvert, uint, &[value]),
+            None => return Err(anyhow!("Index {} isn't an integer.", id)),
+        }))
+    }
+
+    fn index_id(&mut self, index: SPIRVIndex) -> u32 {
+        match index {
+            SPIRVIndex::Constant(index) => self.spirv.constant_u32(index),
+            SPIRVIndex::Dynamic(id) => id,
+        }
+    }
+
+    fn add_indices(&mut self, lhs: SPIRVIndex, rhs: SPIRVIndex) -> SPIRVIndex {
+        match (lhs, rhs) {
+            (SPIRVIndex::Constant(lhs), SPIRVIndex::Constant(rhs)) => {
+                SPIRVIndex::Constant(lhs.wrapping_add(rhs))
+            }
+            (SPIRVIndex::Constant(0), index) | (index, SPIRVIndex::Constant(0)) => index,
+            (lhs, rhs) => {
+                let uint = self.spirv.type_int(32, false);
+                let (lhs, rhs) = (self.index_id(lhs), self.index_id(rhs));
+
+                SPIRVIndex::Dynamic(self.spirv.emit_value(SPIRVOp::IAdd, uint, &[lhs, rhs]))
+            }
+        }
+    }
+
+    fn scale_index(&mut self, index: SPIRVIndex, factor: u32) -> SPIRVIndex {
+        match index {
+            SPIRVIndex::Constant(index) => SPIRVIndex::Constant(index.wrapping_mul(factor)),
+            _ if factor == 1 => index,
+            SPIRVIndex::Dynamic(id) => {
+                let uint = self.spirv.type_int(32, false);
+                let factor = self.spirv.constant_u32(factor);
+
+                SPIRVIndex::Dynamic(self.spirv.emit_value(SPIRVOp::IMul, uint, &[id, factor]))
+            }
+        }
+    }
+
+    /// Emits the access chain of `pointer`, returning it with the layout
+    /// of the memory it points to.
+    fn access(&mut self, pointer: &SPIRVPointer) -> Result<(u32, SPIRVLayout)> {
+        let pointee = pointer
+            .pointee
+            .ok_or_else(|| anyhow!("Buffer accessed before its type is known."))?;
+
+        let (base, storage_class, layout, mut indices) = match pointer.root {
+            SPIRVPointerRoot::Variable {
+                id,
+                storage_class: SPIRVStorageClass::StorageBuffer,
+            } => (
+                id,
+                SPIRVStorageClass::StorageBuffer,
+                SPIRVLayout::Explicit,
+                vec![],
+            ),
+            SPIRVPointerRoot::Variable { id, storage_class } => {
+                (id, storage_class, SPIRVLayout::Logical, vec![])
+            }
+            SPIRVPointerRoot::Buffer {
+                set,
+                binding,
+                address_space,
+                element,
+            } => {
+                let element =
+                    element.ok_or_else(|| anyhow!("Buffer accessed before its type is known."))?;
+                let variable = self.buffer_variable(set, binding, address_space, element)?;
+
+                (
+                    variable,
+                    SPIRVStorageClass::StorageBuffer,
+                    SPIRVLayout::Explicit,
+                    vec![self.spirv.constant_u32(0)],
+                )
+            }
+            SPIRVPointerRoot::ArgumentBuffer { index } => {
+                return Err(anyhow!(
+                    "Argument buffer {} is only loaded from, a member at a time.",
+                    index
+                ));
+            }
+        };
+
+        if pointer.path.is_empty() {
+            return Ok((base, layout));
+        }
+
+        for (index, _) in &pointer.path {
+            indices.push(self.index_id(*index));
+        }
+
+        let pointee_type = self.spirv_type(pointee, layout)?;
+        let pointer_type = self.spirv.type_pointer(storage_class, pointee_type);
+
+        let mut operands = vec![base];
+        operands.extend(indices);
+
+        Ok((
+            self.spirv
+                .emit_value(SPIRVOp::AccessChain, pointer_type, &operands),
+            layout,
+        ))
+    }
+
+    /// The `StorageBuffer` variable for a binding seen as a runtime array of
+    /// `element`.
+    fn buffer_variable(
+        &mut self,
+        set: u32,
+        binding: u32,
+        address_space: AIRAddressSpace,
+        element: usize,
+    ) -> Result<u32> {
+        if let Some(variable) = self.buffers.get(&(set, binding, element)) {
+            return Ok(*variable);
+        }
+
+        let element_type = self.spirv_type(element, SPIRVLayout::Explicit)?;
+        let stride = self.types.stride(element)?;
+
+        let array = self.spirv.type_runtime_array(element_type);
+        self.spirv
+            .decorate_unique(array, SPIRVDecoration::ArrayStride, &[stride]);
+
+        let block = self.spirv.type_struct(&[array]);
+        self.spirv.decorate(block, SPIRVDecoration::Block, &[]);
+        self.spirv
+            .member_decorate(block, 0, SPIRVDecoration::Offset, &[0]);
+
+        if address_space == AIRAddressSpace::Constant {
+            self.spirv
+                .member_decorate(block, 0, SPIRVDecoration::NonWritable, &[]);
+        }
+
+        let variable = self.spirv.variable(SPIRVStorageClass::StorageBuffer, block);
+        self.spirv
+            .decorate(variable, SPIRVDecoration::DescriptorSet, &[set]);
+        self.spirv
+            .decorate(variable, SPIRVDecoration::Binding, &[binding]);
+
+        self.buffers.insert((set, binding, element), variable);
+        Ok(variable)
+    }
+
+    /// The `StorageBuffer` variable reading the argument block of `set` as a
+    /// `ty` at `offset`. Like buffers, several variables may alias a block.
+    fn argument_block_variable(&mut self, set: u32, offset: u32, ty: usize) -> Result<u32> {
+        if let Some(variable) = self.argument_blocks.get(&(set, offset, ty)) {
+            return Ok(*variable);
+        }
+
+        let member_type = self.spirv_type(ty, SPIRVLayout::Explicit)?;
+
+        let block = self.spirv.type_struct(&[member_type]);
+        self.spirv.decorate(block, SPIRVDecoration::Block, &[]);
+        self.spirv
+            .member_decorate(block, 0, SPIRVDecoration::Offset, &[offset]);
+        self.spirv
+            .member_decorate(block, 0, SPIRVDecoration::NonWritable, &[]);
+
+        let variable = self.spirv.variable(SPIRVStorageClass::StorageBuffer, block);
+        self.spirv
+            .decorate(variable, SPIRVDecoration::DescriptorSet, &[set]);
+        self.spirv
+            .decorate(variable, SPIRVDecoration::Binding, &[0]);
+
+        self.argument_blocks.insert((set, offset, ty), variable);
+        Ok(variable)
+    }
+
+    fn load(&mut self, pointer: u64, ty: usize) -> Result<SPIRVValue> {
+        let pointer = self.pointer(pointer)?;
+
+        if let SPIRVPointerRoot::ArgumentBuffer { index } = pointer.root {
+            return self.load_argument(index, &pointer, ty);
+        }
+
+        Ok(SPIRVValue::Id(self.load_pointer(pointer, ty)?))
+    }
+
+    /// Loads a member of the argument buffer at `[[buffer(index)]]`. Buffers
+    /// become pointers to their descriptor, plain data is read from the
+    /// argument block.
+    fn load_argument(
+        &mut self,
+        index: u32,
+        pointer: &SPIRVPointer,
+        ty: usize,
+    ) -> Result<SPIRVValue> {
+        let (argument_buffer, layout) = &self.argument_buffers[&index];
+        let set = layout.set;
+
+        let (position, member_type, rest) = match pointer.path.split_first() {
+            Some(((SPIRVIndex::Constant(position), Some(container)), rest)) => (
+                *position,
+                self.types.member(*container, *position as u64)?,
+                rest,
+            ),
+            Some(_) => {
+                return Err(anyhow!("Argument buffer {} indexed dynamically.", index));
+            }
+            // Loads from the argument buffer itself read its first member.
+            None => (0, ty, &[][..]),
+        };
+
+        let member = argument_buffer
+            .members
+            .get(position as usize)
+            .ok_or_else(|| anyhow!("Argument buffer {} has no member {}.", index, position))?;
+        let location = layout
+            .entry(member.id)
+            .map(|entry| entry.location.clone())
+            .ok_or_else(|| anyhow!("`{}` wasn't laid out.", member.name))?;
+        let (name, kind) = (member.name.clone(), member.kind);
+
+        match (kind, location) {
+            (
+                AIRArgumentKind::Buffer(address_space),
+                SPIRVArgumentLocation::Descriptor {
+                    binding,
+                    count: Some(1),
+                    ..
+                },
+            ) if rest.is_empty() => {
+                let AIRValueType::Pointer { pointee, .. } = self.types.get(ty)?.clone() else {
+                    return Err(anyhow!("Buffer `{}` isn't loaded as a pointer.", name));
+                };
+
+                Ok(SPIRVValue::Pointer(SPIRVPointer {
+                    root: SPIRVPointerRoot::Buffer {
+                        set,
+                        binding,
+                        address_space,
+                        element: pointee,
+                    },
+                    path: vec![(SPIRVIndex::Constant(0), None)],
+                    pointee,
+                }))
+            }
+            (AIRArgumentKind::Data { .. }, SPIRVArgumentLocation::Data { offset, .. }) => {
+                let variable = self.argument_block_variable(set, offset, member_type)?;
+
+                let mut path = vec![(SPIRVIndex::Constant(0), None)];
+                path.extend_from_slice(rest);
+
+                let data = SPIRVPointer {
+                    root: SPIRVPointerRoot::Variable {
+                        id: variable,
+                        storage_class: SPIRVStorageClass::StorageBuffer,
+                    },
+                    path,
+                    pointee: match rest {
+                        [] => Some(member_type),
+                        _ => pointer.pointee,
+                    },
+                };
+
+                Ok(SPIRVValue::Id(self.load_pointer(data, ty)?))
+            }
+            (kind, _) => Err(anyhow!(
+                "Loading {:?} `{}` from an argument buffer not implemented.",
+                kind,
+                name
+            )),
+        }
+    }
+
+    fn load_pointer(&mut self, pointer: SPIRVPointer, ty: usize) -> Result<u32> {
+        let pointer = self.retype(pointer, ty)?;
+        let (access, layout) = self.access(&pointer)?;
+
+        let spirv_type = self.spirv_type(ty, layout)?;
+        let value = self.spirv.emit_value(SPIRVOp::Load, spirv_type, &[access]);
+
+        self.convert_layout(value, ty, layout, SPIRVLayout::Logical)
+    }
+
+    fn store(&mut self, pointer: u64, value: u64) -> Result<()> {
+        let ty = self.function.value(value)?.ty;
+        let value = self.value(value)?;
+
+        let pointer = self.pointer(pointer)?;
+        let pointer = self.retype(pointer, ty)?;
+        let (access, layout) = self.access(&pointer)?;
+
+        let value = self.convert_layout(value, ty, SPIRVLayout::Logical, layout)?;
+        self.spirv.store(access, value);
+
+        Ok(())
+    }
+
+    /// The address space a pointer value points into.
+    fn address_space(&self, pointer: u64) -> Result<AIRAddressSpace> {
+        let ty = self.function.value(pointer)?.ty;
+
+        match self.types.get(ty)? {
+            AIRValueType::Pointer { address_space, .. } => Ok(*address_space),
+            other => Err(anyhow!("{:?} is not a pointer.", other)),
+        }
+    }
+
+    fn atomic_type(&self, ty: usize) -> Result<AIRAtomicType> {
+        Ok(match self.types.get(ty)? {
+            AIRValueType::Int(width @ (32 | 64)) => AIRAtomicType::Int {
+                width: *width,
+                signed: false,
+            },
+            AIRValueType::Float => AIRAtomicType::Float { width: 32 },
+            AIRValueType::Double => AIRAtomicType::Float { width: 64 },
+            other => return Err(anyhow!("Atomics on {:?} not implemented.", other)),
+        })
+    }
+
+    /// An atomic `load`, `store` or `cmpxchg` on a value of type `ty`.
+    fn llvm_atomic(&self, kind: AIRAtomicKind, pointer: u64, ty: usize) -> Result<AIRAtomic> {
+        Ok(AIRAtomic {
+            kind,
+            target: AIRAtomicTarget::Memory(self.address_space(pointer)?),
+            ty: self.atomic_type(ty)?,
+        })
+    }
+
+    /// `air.atomic.*` calls. Their arguments are the pointer, the operands,
+    /// the memory orders, the scope and a volatile flag; the scope follows
+    /// from the address space instead.
+    fn atomic_intrinsic(
+        &mut self,
+        name: &str,
+        arguments: &[u64],
+        ty: usize,
+    ) -> Result<Option<u32>> {
+        let atomic = AIRAtomic::from_intrinsic(name)?;
+
+        if atomic.target == AIRAtomicTarget::Texture {
+            return Err(anyhow!("Texture atomics not implemented."));
+        }
+
+        let argument = |index: usize| {
+            arguments
+                .get(index)
+                .copied()
+                .ok_or_else(|| anyhow!("{} has no argument {}.", name, index))
+        };
+
+        let order = |translator: &Self, index: usize| {
+            let order = argument(index)?;
+            let order = translator.function.constant_integer(order).ok_or_else(|| {
+                anyhow!("{} with a non-constant memory order not implemented.", name)
+            })?;
+
+            AIRMemoryOrder::from_metal(order as u64)
+        };
+
+        let pointer = argument(0)?;
+
+        match atomic.kind {
+            AIRAtomicKind::Load => {
+                let order = order(self, 1)?;
+                self.atomic(&atomic, order, pointer, None, None, None)
+            }
+            AIRAtomicKind::CompareExchange => {
+                // The expected value is passed by pointer and updated with
+                // the original one, the call returns whether it matched.
+                let expected = self.pointer(argument(1)?)?;
+                let desired = argument(2)?;
+                let value_type = self.function.value(desired)?.ty;
+
+                let (success, failure) = (order(self, 3)?, order(self, 4)?);
+
+                let expected = self.retype(expected, value_type)?;
+                let (access, layout) = self.access(&expected)?;
+                let spirv_type = self.spirv_type(value_type, layout)?;
+                let comparator = self.spirv.emit_value(SPIRVOp::Load, spirv_type, &[access]);
+                let desired = self.value(desired)?;
+
+                let original = self
+                    .atomic(
+                        &atomic,
+                        success,
+                        pointer,
+                        Some(desired),
+                        Some(comparator),
+                        Some(failure),
+                    )?
+                    .ok_or_else(|| anyhow!("{} produced no value.", name))?;
+
+                self.spirv.store(access, original);
+
+                let result_type = self.spirv_type(ty, SPIRVLayout::Logical)?;
+                Ok(Some(self.spirv.emit_value(
+                    SPIRVOp::IEqual,
+                    result_type,
+                    &[original, comparator],
+                )))
+            }
+            _ => {
+                let order = order(self, 2)?;
+                let value = self.value(argument(1)?)?;
+
+                self.atomic(&atomic, order, pointer, Some(value), None, None)
+            }
+        }
+    }
+
+    fn atomic(
+        &mut self,
+        atomic: &AIRAtomic,
+        order: AIRMemoryOrder,
+        pointer: u64,
+        value: Option<u32>,
+        comparator: Option<u32>,
+        failure_order: Option<AIRMemoryOrder>,
+    ) -> Result<Option<u32>> {
+        let ty = match atomic.ty {
+            AIRAtomicType::Int { width, .. } => self.types.intern(AIRValueType::Int(width)),
+            AIRAtomicType::Float { width: 32 } => self.types.intern(AIRValueType::Float),
+            AIRAtomicType::Float { .. } => self.types.intern(AIRValueType::Double),
+        };
+
+        let pointer = self.pointer(pointer)?;
+        let pointer = self.retype(pointer, ty)?;
+        let (access, _) = self.access(&pointer)?;
+
+        lower_atomic(
+            &mut self.spirv,
+            &self.options.features,
+            atomic,
+            order,
+            &AIRAtomicOperands {
+                address: AIRAtomicAddress::Pointer(access),
+                value,
+                comparator,
+                failure_order,
+            },
+        )
+    }
+
+    /// `air.simd_*` and `air.quad_*` calls. Their arguments are the value
+    /// and, for broadcasts and shuffles, a `ushort` lane, delta or mask.
+    fn simd(&mut self, name: &str, arguments: &[u64], ty: usize) -> Result<u32> {
+        let mut function = AIRSIMDFunction::from_intrinsic(name)?;
+        let features = &self.options.features;
+
+        match (function.ty.scalar(), function.operation) {
+            // `simd_vote` is a `ulong`, which is truncated like any other
+            // without `Int64`. Only its low bits can be set in practice.
+            (
+                AIRScalarType::Int { width: 64, .. },
+                AIRSIMDOperation::Ballot | AIRSIMDOperation::ActiveThreadsMask,
+            ) if !features.int64 => {
+                function.ty = AIRType::Scalar(AIRScalarType::Int {
+                    width: 32,
+                    signed: false,
+                });
+            }
+            (scalar, _) if scalar.representation(features) == SPIRVScalarRepresentation::Split => {
+                return Err(anyhow!("{} on 64-bit integers needs Int64.", name));
+            }
+            (AIRScalarType::BFloat, _) => {
+                return Err(anyhow!("{} on bfloat not implemented.", name));
+            }
+            _ => {}
+        }
+
+        let mut operands = AIRSIMDOperands::default();
+
+        if let Some(&value) = arguments.first() {
+            let value_type = self.function.value(value)?.ty;
+            let value = self.value(value)?;
+
+            // Emulated integers are zero-extended, signed comparisons need
+            // them sign-extended.
+            operands.value = Some(match function.ty.scalar() {
+                AIRScalarType::Int { signed: true, .. }
+                    if matches!(
+                        function.operation,
+                        AIRSIMDOperation::Min | AIRSIMDOperation::Max
+                    ) =>
+                {
+                    self.sign_extend(value, value_type)?
+                }
+                _ => value,
+            });
+        }
+
+        if let Some(&lane) = arguments.get(1) {
+            operands.lane = Some(match self.function.constant_integer(lane) {
+                Some(lane) => AIRLane::Constant(lane as u32),
+                None => {
+                    let lane_type = self.function.value(lane)?.ty;
+                    let value = self.value(lane)?;
+                    let uint = self.spirv.type_int(32, false);
+
+                    AIRLane::Dynamic(match self.int_widths(lane_type)? {
+                        Some((_, 32)) => value,
+                        Some(_) => self.spirv.emit_value(SPIRVOp::UConvert, uint, &[value]),
+                        None => return Err(anyhow!("Lane {} isn't an integer.", lane)),
+                    })
+                }
+            });
+        }
+
+        let result = lower_simd_function(
+            &mut self.spirv,
+            &self.options.features,
+            self.reflection.stage,
+            &function,
+            &operands,
+        )?;
+
+        self.wrap(result, ty)
+    }
+
+    /// Rebuilds a value of `ty` in another layout, member by member where
+    /// the types differ. SPIR-V 1.3 has no `OpCopyLogical`.
+    fn convert_layout(
+        &mut self,
+        value: u32,
+        ty: usize,
+        from: SPIRVLayout,
+        to: SPIRVLayout,
+    ) -> Result<u32> {
+        let from_type = self.spirv_type(ty, from)?;
+        let to_type = self.spirv_type(ty, to)?;
+
+        if from_type == to_type {
+            return Ok(value);
+        }
+
+        let members = match self.types.get(ty)?.clone() {
+            AIRValueType::Struct { members, .. } => members,
+            AIRValueType::Array { element, length } => vec![element; length as usize],
+            _ => {
+                let op = match self.types.is_float(ty) {
+                    true => SPIRVOp::FConvert,
+                    false => SPIRVOp::UConvert,
+                };
+
+                return Ok(self.spirv.emit_value(op, to_type, &[value]));
+            }
+        };
+
+        let mut constituents = vec![];
+
+        for (index, member) in members.into_iter().enumerate() {
+            let member_type = self.spirv_type(member, from)?;
+            let extracted = self.spirv.emit_value(
+                SPIRVOp::CompositeExtract,
+                member_type,
+                &[value, index as u32],
+            );
+
+            constituents.push(self.convert_layout(extracted, member, from, to)?);
+        }
+
+        Ok(self
+            .spirv
+            .emit_value(SPIRVOp::CompositeConstruct, to_type, &constituents))
+    }
+}
+
+/// The location of a `[[user(locnN)]]` interface variable.
+fn user_location(argument: &AIRArgumentReflection) -> Result<u32> {
+    argument
+        .qualifiers
+        .iter()
+        .find_map(|qualifier| {
+            qualifier
+                .strip_prefix("user(locn")
+                .and_then(|rest| rest.strip_suffix(')'))
+                .and_then(|location| location.parse().ok())
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "{} {:?} has no user(locnN) name, other user names not implemented.",
+                argument.kind,
+                argument.name
+            )
+        })
+}
+
+/// The bits of the `float` a `half` converts to.
+fn half_to_float(half: u16) -> u32 {
+    let sign = ((half >> 15) as u32) << 31;
+    let exponent = ((half >> 10) & 0x1F) as u32;
+    let mantissa = (half & 0x3FF) as u32;
+
+    match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, mantissa) => {
+            // Subnormal halves are normal floats.
+            let shift = mantissa.leading_zeros() - 21;
+            let mantissa = (mantissa << shift) & 0x3FF;
+
+            sign | ((113 - shift) << 23) | (mantissa << 13)
+        }
+        (0x1F, mantissa) => sign | 0x7F80_0000 | (mantissa << 13),
+        (exponent, mantissa) => sign | ((exponent + 112) << 23) | (mantissa << 13),
+    }
+}
+
+/// The bits of a `half` for a `float` it can represent exactly.
+fn float_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+
+    if exponent == 0 {
+        return sign;
+    }
+
+    sign | (((exponent - 112) as u16) << 10) | ((bits >> 13) & 0x3FF) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apple_ir::parse_apple_ir;
+    use crate::coordinates::SPIRVYFlipMode;
+    use crate::function::{AIRBasicBlock, AIRValue};
+    use crate::reflection::{AIRStructMemberReflection, AIRStructReflection};
+    use crate::spirv::SPIRVSubgroupFeatures;
+
+    fn test_module() -> AIRModule {
+        let air = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../test.air")).unwrap();
+        parse_apple_ir(&air).unwrap()
+    }
+
+    /// Builds entry points by hand, for what `test.air` doesn't cover.
+    #[derive(Default)]
+    struct AIRBuilder {
+        types: AIRTypeTable,
+        values: Vec<AIRValue>,
+        parameters: Vec<usize>,
+        arguments: Vec<AIRArgumentReflection>,
+        outputs: Vec<AIRArgumentReflection>,
+        result: Option<usize>,
+        attributes: Vec<String>,
+        blocks: Vec<AIRBasicBlock>,
+        options: SPIRVTranslationOptions,
+    }
+
+    impl AIRBuilder {
+        fn ty(&mut self, ty: AIRValueType) -> usize {
+            self.types.intern(ty)
+        }
+
+        fn value(&mut self, ty: usize, kind: AIRValueKind) -> u64 {
+            self.values.push(AIRValue { ty, kind });
+            self.values.len() as u64 - 1
+        }
+
+        fn constant(&mut self, ty: usize, constant: AIRConstant) -> u64 {
+            self.value(ty, AIRValueKind::Constant(constant))
+        }
+
+        fn function(&mut self, name: &str, result: usize, parameters: Vec<usize>) -> u64 {
+            let function_type = self.ty(AIRValueType::Function { result, parameters });
+
+            self.value(
+                function_type,
+                AIRValueKind::Function {
+                    name: name.to_string(),
+                    function_type,
+                },
+            )
+        }
+
+        /// Adds a parameter, which have to be added before any other value.
+        fn argument(&mut self, ty: usize, kind: &str, location_index: Option<u32>) -> u64 {
+            let index = self.parameters.len() as u32;
+
+            self.parameters.push(ty);
+            self.arguments.push(AIRArgumentReflection {
+                index: Some(index),
+                kind: kind.to_string(),
+                name: None,
+                type_name: None,
+                location_index,
+                address_space: None,
+                type_size: None,
+                type_alignment: None,
+                struct_type: None,
+                qualifiers: vec![],
+            });
+
+            self.value(ty, AIRValueKind::Argument(index))
+        }
+
+        /// Adds an output, in the order of the members of the result.
+        fn output(&mut self, kind: &str, qualifiers: &[&str]) {
+            self.outputs.push(AIRArgumentReflection {
+                index: None,
+                kind: kind.to_string(),
+                name: None,
+                type_name: None,
+                location_index: None,
+                address_space: None,
+                type_size: None,
+                type_alignment: None,
+                struct_type: None,
+                qualifiers: qualifiers.iter().map(|q| q.to_string()).collect(),
+            });
+        }
+
+        fn block(&mut self) -> usize {
+            self.blocks.push(AIRBasicBlock::default());
+            self.blocks.len() - 1
+        }
+
+        /// Appends an instruction, with a result if `ty` is given.
+        fn push(&mut self, block: usize, ty: Option<usize>, kind: AIRInstructionKind) -> u64 {
+            let result = ty.map(|ty| self.value(ty, AIRValueKind::Instruction));
+
+            self.blocks[block].instructions.push(AIRInstruction {
+                result,
+                kind,
+                location: None,
+            });
+
+            result.unwrap_or(u64::MAX)
+        }
+
+        fn translate(mut self, stage: AIRShaderStage) -> Result<Vec<u32>> {
+            let void = self.ty(AIRValueType::Void);
+            let function_type = self.ty(AIRValueType::Function {
+                result: self.result.unwrap_or(void),
+                parameters: self.parameters.clone(),
+            });
+
+            let reflection = AIRFunctionReflection {
+                name: "main0".to_string(),
+                stage,
+                outputs: self.outputs,
+                arguments: self.arguments,
+                attributes: self.attributes,
+            };
+
+            let function = AIRFunction {
+                name: "main0".to_string(),
+                function_type,
+                values: self.values,
+                blocks: self.blocks,
+                metadata: vec![],
+            };
+
+            translate_function(
+                &reflection,
+                self.types,
+                function,
+                AIRDebugInfo::default(),
+                &self.options,
+            )
+        }
+    }
+
+    /// The instructions of a module as (opcode, operands).
+    fn instructions(words: &[u32]) -> Vec<(u32, &[u32])> {
+        let mut instructions = vec![];
+        let mut rest = &words[5..];
+
+        while let Some(first) = rest.first() {
+            let (instruction, next) = rest.split_at((first >> 16) as usize);
+            instructions.push((first & 0xFFFF, &instruction[1..]));
+            rest = next;
+        }
+
+        instructions
+    }
+
+    #[test]
+    fn translates_vertex_function() {
+        let words = translate(&test_module(), "main0", &Default::default()).unwrap();
+        assert_eq!(words[0], 0x0723_0203);
+
+        let instructions = instructions(&words);
+        let has = |op: SPIRVOp, operands: &[u32]| {
+            instructions
+                .iter()
+                .any(|(code, rest)| *code == op as u32 && rest.ends_with(operands))
+        };
+
+        assert!(instructions.iter().any(|(code, rest)| {
+            *code == SPIRVOp::EntryPoint as u32 && rest[0] == SPIRVExecutionModel::Vertex as u32
+        }));
+        assert!(has(
+            SPIRVOp::Decorate,
+            &[
+                SPIRVDecoration::BuiltIn as u32,
+                SPIRVBuiltIn::VertexIndex as u32
+            ]
+        ));
+        assert!(has(
+            SPIRVOp::Decorate,
+            &[
+                SPIRVDecoration::BuiltIn as u32,
+                SPIRVBuiltIn::Position as u32
+            ]
+        ));
+        assert!(has(
+            SPIRVOp::Decorate,
+            &[SPIRVDecoration::Location as u32, 0]
+        ));
+        assert!(has(SPIRVOp::Return, &[]));
+    }
+
+    #[test]
+    fn flips_vertex_positions() {
+        let negations = |y_flip| {
+            let options = SPIRVTranslationOptions {
+                coordinate_fixups: SPIRVCoordinateFixups::new(y_flip),
+                ..Default::default()
+            };
+            let words = translate(&test_module(), "main0", &options).unwrap();
+
+            instructions(&words)
+                .iter()
+                .filter(|(code, _)| *code == SPIRVOp::FNegate as u32)
+                .count()
+        };
+
+        let unflipped = negations(SPIRVYFlipMode::NegativeViewport);
+        assert_eq!(negations(SPIRVYFlipMode::None), unflipped);
+        assert_eq!(negations(SPIRVYFlipMode::Position), unflipped + 1);
+    }
+
+    #[test]
+    fn translates_loops_and_buffers() {
+        let mut air = AIRBuilder::default();
+        let void = air.ty(AIRValueType::Void);
+        let int = air.ty(AIRValueType::Int(32));
+        let bool = air.ty(AIRValueType::Int(1));
+        let device_int = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Device,
+            pointee: Some(int),
+        });
+
+        let out = air.argument(device_int, "air.buffer", Some(0));
+        let id = air.argument(int, "air.thread_position_in_grid", None);
+        let zero = air.constant(int, AIRConstant::Integer(0));
+        let one = air.constant(int, AIRConstant::Integer(1));
+        let flags = air.constant(int, AIRConstant::Integer(2));
+        let barrier = air.function("air.wg.barrier", void, vec![int, int]);
+        let max = air.function("llvm.umax.i32", int, vec![int, int]);
+
+        let (entry, header, body, exit) = (air.block(), air.block(), air.block(), air.block());
+
+        air.push(entry, None, AIRInstructionKind::Branch(header));
+
+        let i = air.push(
+            header,
+            Some(int),
+            AIRInstructionKind::Phi { incoming: vec![] },
+        );
+        let sum = air.push(
+            header,
+            Some(int),
+            AIRInstructionKind::Phi { incoming: vec![] },
+        );
+        let condition = air.push(
+            header,
+            Some(bool),
+            AIRInstructionKind::Compare {
+                predicate: AIRPredicate(AIRPredicate::ICMP_ULT),
+                lhs: i,
+                rhs: id,
+            },
+        );
+        air.push(
+            header,
+            None,
+            AIRInstructionKind::ConditionalBranch {
+                condition,
+                true_target: body,
+                false_target: exit,
+            },
+        );
+
+        let next = air.push(
+            body,
+            Some(int),
+            AIRInstructionKind::Binary {
+                op: AIRBinaryOp::Add,
+                lhs: i,
+                rhs: one,
+            },
+        );
+        let total = air.push(
+            body,
+            Some(int),
+            AIRInstructionKind::Call {
+                callee: max,
+                arguments: vec![sum, i],
+            },
+        );
+        air.push(body, None, AIRInstructionKind::Branch(header));
+
+        // The phis refer to values defined after them.
+        air.blocks[header].instructions[0].kind = AIRInstructionKind::Phi {
+            incoming: vec![(zero, entry), (next, body)],
+        };
+        air.blocks[header].instructions[1].kind = AIRInstructionKind::Phi {
+            incoming: vec![(zero, entry), (total, body)],
+        };
+
+        air.push(
+            exit,
+            None,
+            AIRInstructionKind::Call {
+                callee: barrier,
+                arguments: vec![flags, one],
+            },
+        );
+        let element = air.push(
+            exit,
+            Some(device_int),
+            AIRInstructionKind::GetElementPtr {
+                source: int,
+                base: out,
+                indices: vec![id],
+            },
+        );
+        air.push(
+            exit,
+            None,
+            AIRInstructionKind::Store {
+                pointer: element,
+                value: sum,
+                ordering: None,
+            },
+        );
+        air.push(exit, None, AIRInstructionKind::Return(None));
+
+        let words = air.translate(AIRShaderStage::Kernel).unwrap();
+        let instructions = instructions(&words);
+        let has = |op: SPIRVOp| instructions.iter().any(|(code, _)| *code == op as u32);
+
+        for op in [
+            SPIRVOp::LoopMerge,
+            SPIRVOp::Phi,
+            SPIRVOp::ControlBarrier,
+            SPIRVOp::ExtInst,
+            SPIRVOp::AccessChain,
+            SPIRVOp::Store,
+        ] {
+            assert!(has(op), "{:?} wasn't emitted.", op);
+        }
+
+        assert!(instructions.iter().any(|(code, rest)| {
+            *code == SPIRVOp::Decorate as u32
+                && rest[1..]
+                    == [
+                        SPIRVDecoration::BuiltIn as u32,
+                        SPIRVBuiltIn::WorkgroupSize as u32,
+                    ]
+        }));
+    }
+
+    #[test]
+    fn translates_atomics() {
+        let mut air = AIRBuilder::default();
+        let int = air.ty(AIRValueType::Int(32));
+        let bool = air.ty(AIRValueType::Int(1));
+        let device_int = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Device,
+            pointee: Some(int),
+        });
+        let threadgroup_int = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Threadgroup,
+            pointee: Some(int),
+        });
+        let thread_int = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Thread,
+            pointee: Some(int),
+        });
+
+        let counter = air.argument(device_int, "air.buffer", Some(0));
+        let shared = air.value(
+            threadgroup_int,
+            AIRValueKind::GlobalVariable {
+                name: "shared".to_string(),
+                address_space: AIRAddressSpace::Threadgroup,
+                value_type: int,
+                initializer: None,
+            },
+        );
+        let relaxed = air.constant(int, AIRConstant::Integer(0));
+        let device_scope = air.constant(int, AIRConstant::Integer(2));
+        let one = air.constant(int, AIRConstant::Integer(1));
+        let volatile = air.constant(bool, AIRConstant::Integer(0));
+        let add = air.function(
+            "air.atomic.global.add.u.i32",
+            int,
+            vec![device_int, int, int, int, bool],
+        );
+        let cmpxchg = air.function(
+            "air.atomic.global.cmpxchg.weak.i32",
+            bool,
+            vec![device_int, thread_int, int, int, int, int, bool],
+        );
+
+        let entry = air.block();
+        let expected = air.push(
+            entry,
+            Some(thread_int),
+            AIRInstructionKind::Alloca { allocated: int },
+        );
+        let previous = air.push(
+            entry,
+            Some(int),
+            AIRInstructionKind::Call {
+                callee: add,
+                arguments: vec![counter, one, relaxed, device_scope, volatile],
+            },
+        );
+        air.push(
+            entry,
+            None,
+            AIRInstructionKind::Store {
+                pointer: expected,
+                value: previous,
+                ordering: None,
+            },
+        );
+        air.push(
+            entry,
+            Some(bool),
+            AIRInstructionKind::Call {
+                callee: cmpxchg,
+                arguments: vec![
+                    counter,
+                    expected,
+                    one,
+                    relaxed,
+                    relaxed,
+                    device_scope,
+                    volatile,
+                ],
+            },
+        );
+        // Sequentially consistent `atomicrmw umax` and an acq_rel fence.
+        air.push(
+            entry,
+            Some(int),
+            AIRInstructionKind::AtomicRMW {
+                operation: 9,
+                pointer: shared,
+                value: previous,
+                ordering: 6,
+            },
+        );
+        air.push(entry, None, AIRInstructionKind::Fence { ordering: 5 });
+        air.push(entry, None, AIRInstructionKind::Return(None));
+
+        let words = air.translate(AIRShaderStage::Kernel).unwrap();
+        let instructions = instructions(&words);
+        let has = |op: SPIRVOp| instructions.iter().any(|(code, _)| *code == op as u32);
+
+        for op in [
+            SPIRVOp::AtomicIAdd,
+            SPIRVOp::AtomicCompareExchange,
+            SPIRVOp::AtomicUMax,
+            SPIRVOp::MemoryBarrier,
+        ] {
+            assert!(has(op), "{:?} wasn't emitted.", op);
+        }
+    }
+
+    #[test]
+    fn translates_simd_functions() {
+        let mut air = AIRBuilder::default();
+        let int = air.ty(AIRValueType::Int(32));
+        let ushort = air.ty(AIRValueType::Int(16));
+        let long = air.ty(AIRValueType::Int(64));
+        let bool = air.ty(AIRValueType::Int(1));
+        let device_int = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Device,
+            pointee: Some(int),
+        });
+
+        let output = air.argument(device_int, "air.buffer", Some(0));
+        let position = air.argument(int, "air.thread_position_in_grid", None);
+        let lane = air.argument(ushort, "air.thread_index_in_simdgroup", None);
+        let first_lane = air.constant(ushort, AIRConstant::Integer(0));
+        let max = air.function("air.simd_max.s.i32", int, vec![int]);
+        let shuffle = air.function("air.simd_shuffle.i32", int, vec![int, ushort]);
+        let broadcast = air.function("air.simd_broadcast.i32", int, vec![int, ushort]);
+        let is_first = air.function("air.simd_is_first", bool, vec![]);
+        let ballot = air.function("air.simd_ballot.i64", long, vec![bool]);
+
+        let entry = air.block();
+        let mut call = |callee, ty, arguments| {
+            air.push(
+                entry,
+                Some(ty),
+                AIRInstructionKind::Call { callee, arguments },
+            )
+        };
+        let max = call(max, int, vec![position]);
+        let shuffled = call(shuffle, int, vec![max, lane]);
+        let broadcast = call(broadcast, int, vec![shuffled, first_lane]);
+        let is_first = call(is_first, bool, vec![]);
+        call(ballot, long, vec![is_first]);
+        air.push(
+            entry,
+            None,
+            AIRInstructionKind::Store {
+                pointer: output,
+                value: broadcast,
+                ordering: None,
+            },
+        );
+        air.push(entry, None, AIRInstructionKind::Return(None));
+
+        air.options.features.subgroup = SPIRVSubgroupFeatures {
+            size: 32,
+            basic: true,
+            vote: true,
+            arithmetic: true,
+            ballot: true,
+            shuffle: true,
+            shuffle_relative: true,
+            clustered: true,
+            quad: true,
+            in_vertex: false,
+            in_fragment: true,
+            in_kernel: true,
+        };
+
+        let words = air.translate(AIRShaderStage::Kernel).unwrap();
+        let instructions = instructions(&words);
+        let has = |op: SPIRVOp| instructions.iter().any(|(code, _)| *code == op as u32);
+
+        for op in [
+            SPIRVOp::GroupNonUniformSMax,
+            SPIRVOp::GroupNonUniformShuffle,
+            SPIRVOp::GroupNonUniformBroadcast,
+            SPIRVOp::GroupNonUniformElect,
+            SPIRVOp::GroupNonUniformBallot,
+        ] {
+            assert!(has(op), "{:?} wasn't emitted.", op);
+        }
+    }
+
+    #[test]
+    fn translates_argument_buffers() {
+        let mut air = AIRBuilder::default();
+        let int = air.ty(AIRValueType::Int(32));
+        let float = air.ty(AIRValueType::Float);
+        let device_float = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Device,
+            pointee: Some(float),
+        });
+        let arguments = air.ty(AIRValueType::Struct {
+            name: Some("struct.Arguments".to_string()),
+            members: vec![device_float, float],
+            packed: false,
+        });
+        let constant_arguments = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Constant,
+            pointee: Some(arguments),
+        });
+        let constant_device_float = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Constant,
+            pointee: Some(device_float),
+        });
+        let constant_float = air.ty(AIRValueType::Pointer {
+            address_space: AIRAddressSpace::Constant,
+            pointee: Some(float),
+        });
+
+        let argument_buffer = air.argument(constant_arguments, "air.indirect_buffer", Some(1));
+        let mut output = air.arguments[0].clone();
+        output.kind = "air.buffer".to_string();
+        output.location_index = Some(0);
+        output.address_space = Some(AIRAddressSpace::Device);
+
+        let member = |name: &str, type_name: &str, offset, argument| AIRStructMemberReflection {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            offset,
+            size: 8,
+            array_length: 0,
+            struct_type: None,
+            argument,
+        };
+        air.arguments[0].struct_type = Some(AIRStructReflection {
+            name: "Arguments".to_string(),
+            size: Some(16),
+            members: vec![
+                member("output", "float*", 0, Some(output)),
+                member("scale", "float", 8, None),
+            ],
+        });
+
+        let zero = air.constant(int, AIRConstant::Integer(0));
+        let one = air.constant(int, AIRConstant::Integer(1));
+
+        let entry = air.block();
+        let output = air.push(
+            entry,
+            Some(constant_device_float),
+            AIRInstructionKind::GetElementPtr {
+                source: arguments,
+                base: argument_buffer,
+                indices: vec![zero, zero],
+            },
+        );
+        let output = air.push(
+            entry,
+            Some(device_float),
+            AIRInstructionKind::Load {
+                pointer: output,
+                ordering: None,
+            },
+        );
+        let scale = air.push(
+            entry,
+            Some(constant_float),
+            AIRInstructionKind::GetElementPtr {
+                source: arguments,
+                base: argument_buffer,
+                indices: vec![zero, one],
+            },
+        );
+        let scale = air.push(
+            entry,
+            Some(float),
+            AIRInstructionKind::Load {
+                pointer: scale,
+                ordering: None,
+            },
+        );
+        air.push(
+            entry,
+            None,
+            AIRInstructionKind::Store {
+                pointer: output,
+                value: scale,
+                ordering: None,
+            },
+        );
+        air.push(entry, None, AIRInstructionKind::Return(None));
+
+        let words = air.translate(AIRShaderStage::Kernel).unwrap();
+        let instructions = instructions(&words);
+
+        // The output buffer is binding 1 of set 1, after the argument block
+        // holding the scale.
+        let decorations = |variable: u32| {
+            instructions
+                .iter()
+                .filter(|(op, operands)| *op == SPIRVOp::Decorate as u32 && operands[0] == variable)
+                .map(|(_, operands)| (operands[1], operands[2]))
+                .collect::<Vec<_>>()
+        };
+        let mut bindings = instructions
+            .iter()
+            .filter(|(op, operands)| {
+                *op == SPIRVOp::Variable as u32
+                    && operands[2] == SPIRVStorageClass::StorageBuffer as u32
+            })
+            .map(|(_, operands)| {
+                let decorations = decorations(operands[1]);
+                let find = |decoration: SPIRVDecoration| {
+                    decorations
+                        .iter()
+                        .find(|(d, _)| *d == decoration as u32)
+                        .map(|(_, value)| *value)
+                };
+
+                (
+                    find(SPIRVDecoration::DescriptorSet),
+                    find(SPIRVDecoration::Binding),
+                )
+            })
+            .collect::<Vec<_>>();
+        bindings.sort();
+
+        assert_eq!(bindings, vec![(Some(1), Some(0)), (Some(1), Some(1))]);
+    }
+
+    #[test]
+    fn translates_fragment_builtins() {
+        let mut air = AIRBuilder::default();
+        let int = air.ty(AIRValueType::Int(32));
+        let bool = air.ty(AIRValueType::Int(1));
+        let float = air.ty(AIRValueType::Float);
+        let void = air.ty(AIRValueType::Void);
+        let outputs = air.ty(AIRValueType::Struct {
+            name: None,
+            members: vec![float, int, int],
+            packed: false,
+        });
+
+        let front_facing = air.argument(bool, "air.front_facing", None);
+        let sample_id = air.argument(int, "air.sample_id", None);
+        let sample_mask = air.argument(int, "air.sample_mask_in", None);
+        air.output("air.depth", &["air.depth_greater"]);
+        air.output("air.stencil", &[]);
+        air.output("air.sample_mask", &[]);
+        air.result = Some(outputs);
+        air.attributes = vec!["air.early_fragment_tests".to_string()];
+
+        let undef = air.constant(outputs, AIRConstant::Undef);
+        let discard = air.function("air.discard_fragment", void, vec![]);
+
+        let (entry, back, front) = (air.block(), air.block(), air.block());
+        air.push(
+            entry,
+            None,
+            AIRInstructionKind::ConditionalBranch {
+                condition: front_facing,
+                true_target: front,
+                false_target: back,
+            },
+        );
+        air.push(
+            back,
+            Some(void),
+            AIRInstructionKind::Call {
+                callee: discard,
+                arguments: vec![],
+            },
+        );
+        air.push(back, None, AIRInstructionKind::Branch(front));
+
+        let depth = air.push(
+            front,
+            Some(float),
+            AIRInstructionKind::Cast {
+                op: AIRCastOp::UIToFP,
+                operand: sample_id,
+            },
+        );
+        let mut result = undef;
+        for (index, value) in [depth, sample_id, sample_mask].into_iter().enumerate() {
+            result = air.push(
+                front,
+                Some(outputs),
+                AIRInstructionKind::InsertValue {
+                    aggregate: result,
+                    value,
+                    indices: vec![index as u32],
+                },
+            );
+        }
+        air.push(front, None, AIRInstructionKind::Return(Some(result)));
+
+        air.options.features.stencil_export = true;
+        air.options.features.demote_to_helper_invocation = true;
+
+        let words = air.translate(AIRShaderStage::Fragment).unwrap();
+        let instructions = instructions(&words);
+        let has = |op: SPIRVOp| instructions.iter().any(|(code, _)| *code == op as u32);
+        let has_mode = |mode: SPIRVExecutionMode| {
+            instructions.iter().any(|(op, operands)| {
+                *op == SPIRVOp::ExecutionMode as u32 && operands[1] == mode as u32
+            })
+        };
+
+        assert!(has(SPIRVOp::DemoteToHelperInvocationEXT));
+        assert!(!has(SPIRVOp::Kill));
+
+        for mode in [
+            SPIRVExecutionMode::DepthReplacing,
+            SPIRVExecutionMode::DepthGreater,
+            SPIRVExecutionMode::StencilRefReplacingEXT,
+            SPIRVExecutionMode::EarlyFragmentTests,
+        ] {
+            assert!(has_mode(mode), "{:?} wasn't declared.", mode);
+        }
+
+        for builtin in [
+            SPIRVBuiltIn::FrontFacing,
+            SPIRVBuiltIn::SampleId,
+            SPIRVBuiltIn::SampleMask,
+            SPIRVBuiltIn::FragDepth,
+            SPIRVBuiltIn::FragStencilRefEXT,
+        ] {
+            assert!(
+                instructions.iter().any(|(op, operands)| {
+                    *op == SPIRVOp::Decorate as u32
+                        && operands[1] == SPIRVDecoration::BuiltIn as u32
+                        && operands[2] == builtin as u32
+                }),
+                "{:?} wasn't declared.",
+                builtin
+            );
+        }
+
+        // The sample index and the input sample mask are integers.
+        let flat = instructions
+            .iter()
+            .filter(|(op, operands)| {
+                *op == SPIRVOp::Decorate as u32 && operands[1] == SPIRVDecoration::Flat as u32
+            })
+            .count();
+        assert_eq!(flat, 2);
+    }
+
+    #[test]
+    fn declares_recorded_sources() {
+        let mut options = SPIRVTranslationOptions {
+            debug_info: SPIRVDebugInfoLevel::Full,
+            ..Default::default()
+        };
+        assert!(translate(&test_module(), "main0", &options).is_err());
+
+        options.features.non_semantic_info = true;
+        let words = translate(&test_module(), "main0", &options).unwrap();
+        let instructions = instructions(&words);
+        let string = |operands: &[u32]| {
+            let bytes: Vec<u8> = operands.iter().flat_map(|w| w.to_le_bytes()).collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+
+        // The recorded source is embedded after the language, version and
+        // file name.
+        assert!(instructions.iter().any(|(code, rest)| {
+            *code == SPIRVOp::Source as u32 && string(&rest[3..]).contains("vertex main0_out")
+        }));
+        assert!(instructions.iter().any(|(code, rest)| {
+            *code == SPIRVOp::String as u32 && string(&rest[1..]).contains("test.metal")
+        }));
+        assert!(instructions.iter().any(|(code, rest)| {
+            *code == SPIRVOp::ExtInstImport as u32
+                && string(&rest[1..]).starts_with("NonSemantic.Shader.DebugInfo.100")
+        }));
+    }
+
+    #[test]
+    fn rejects_unknown_entry_points() {
+        assert!(translate(&test_module(), "main1", &Default::default()).is_err());
+    }
+
+    #[test]
+    fn converts_halves() {
+        assert_eq!(half_to_float(0x3C00), 1.0f32.to_bits());
+        assert_eq!(half_to_float(0xC000), (-2.0f32).to_bits());
+        assert_eq!(half_to_float(0x0001), 2.0f32.powi(-24).to_bits());
+        assert_eq!(float_to_half(0.5), 0x3800);
+        assert_eq!(float_to_half(0.0), 0);
+    }
+}
@@ -0,0 +1,913 @@
+use anyhow::{Result, anyhow};
+
+use crate::spirv::{SPIRVCapability, SPIRVDecoration, SPIRVModule, SPIRVOp, SPIRVTargetFeatures};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AIRScalarType {
+    Bool,
+    Int {
+        width: u32,
+        signed: bool,
+    },
+    Float {
+        width: u32,
+    },
+    /// `bfloat`, the upper 16 bits of a `float`.
+    BFloat,
+}
+
+/// How a scalar type is represented in the translated module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SPIRVScalarRepresentation {
+    Native,
+    /// Computed in 32 bits and narrowed back after every operation that
+    /// could over- or underflow: `char`/`short` without `Int8`/`Int16`,
+    /// `half` without `Float16`, and always `bfloat`.
+    Widened,
+    /// `long`/`ulong` without `Int64`, as a `uint2` of the low and high
+    /// words.
+    Split,
+}
+
+impl AIRScalarType {
+    /// Parses an LLVM scalar type name (`i1`, `i32`, `f16`, ...). LLVM
+    /// integers are signless, AIR carries the sign in the intrinsic name.
+    pub fn from_llvm_name(name: &str, signed: bool) -> Result<Self> {
+        let width = |digits: &str| {
+            digits
+                .parse::<u32>()
+                .map_err(|_| anyhow!("`{}` is not a scalar type.", name))
+        };
+
+        if name == "bfloat" || name == "bf16" {
+            return Ok(Self::BFloat);
+        }
+
+        match name.split_at_checked(1) {
+            Some(("i", "1")) => Ok(Self::Bool),
+            Some(("i", digits)) => Ok(Self::Int {
+                width: width(digits)?,
+                signed,
+            }),
+            Some(("f", digits)) => Ok(Self::Float {
+                width: width(digits)?,
+            }),
+            _ => Err(anyhow!("`{}` is not a scalar type.", name)),
+        }
+    }
+
+    /// Parses a Metal scalar type name (`half`, `ushort`, ...).
+    pub fn from_metal_name(name: &str) -> Result<Self> {
+        Ok(match name {
+            "bool" => Self::Bool,
+            "char" | "int8_t" => Self::Int {
+                width: 8,
+                signed: true,
+            },
+            "uchar" | "uint8_t" => Self::Int {
+                width: 8,
+                signed: false,
+            },
+            "short" | "int16_t" => Self::Int {
+                width: 16,
+                signed: true,
+            },
+            "ushort" | "uint16_t" => Self::Int {
+                width: 16,
+                signed: false,
+            },
+            "int" | "int32_t" => Self::Int {
+                width: 32,
+                signed: true,
+            },
+            "uint" | "uint32_t" => Self::Int {
+                width: 32,
+                signed: false,
+            },
+            "long" | "int64_t" => Self::Int {
+                width: 64,
+                signed: true,
+            },
+            "ulong" | "uint64_t" => Self::Int {
+                width: 64,
+                signed: false,
+            },
+            "half" => Self::Float { width: 16 },
+            "float" => Self::Float { width: 32 },
+            "bfloat" => Self::BFloat,
+            _ => return Err(anyhow!("`{}` is not a Metal scalar type.", name)),
+        })
+    }
+
+    pub fn metal_name(&self) -> &'static str {
+        match self {
+            Self::Bool => "bool",
+            Self::Int {
+                width: 8,
+                signed: true,
+            } => "char",
+            Self::Int { width: 8, .. } => "uchar",
+            Self::Int {
+                width: 16,
+                signed: true,
+            } => "short",
+            Self::Int { width: 16, .. } => "ushort",
+            Self::Int {
+                width: 64,
+                signed: true,
+            } => "long",
+            Self::Int { width: 64, .. } => "ulong",
+            Self::Int { signed: true, .. } => "int",
+            Self::Int { .. } => "uint",
+            Self::Float { width: 16 } => "half",
+            Self::Float { .. } => "float",
+            Self::BFloat => "bfloat",
+        }
+    }
+
+    /// The size in bytes, which is also the alignment.
+    pub fn size(&self) -> u32 {
+        match self {
+            Self::Bool => 1,
+            Self::Int { width, .. } | Self::Float { width } => width / 8,
+            Self::BFloat => 2,
+        }
+    }
+
+    pub fn representation(&self, features: &SPIRVTargetFeatures) -> SPIRVScalarRepresentation {
+        let native = match self {
+            Self::Bool => true,
+            Self::Int { width: 8, .. } => features.int8,
+            Self::Int { width: 16, .. } => features.int16,
+            Self::Int { width: 64, .. } => features.int64,
+            Self::Int { .. } => true,
+            Self::Float { width: 16 } => features.float16,
+            Self::Float { .. } => true,
+            Self::BFloat => false,
+        };
+
+        match (native, self) {
+            (true, _) => SPIRVScalarRepresentation::Native,
+            (false, Self::Int { width: 64, .. }) => SPIRVScalarRepresentation::Split,
+            (false, _) => SPIRVScalarRepresentation::Widened,
+        }
+    }
+
+    /// The type values of this scalar are computed in. Integer types are
+    /// unsigned, as the translator's are, the operations carry the sign.
+    pub fn to_spirv(&self, module: &mut SPIRVModule, features: &SPIRVTargetFeatures) -> u32 {
+        match (self.representation(features), self) {
+            (SPIRVScalarRepresentation::Native, Self::Bool) => module.type_bool(),
+            (SPIRVScalarRepresentation::Native, Self::Int { width, .. }) => {
+                module.type_int(*width, false)
+            }
+            (SPIRVScalarRepresentation::Native, _) => module.type_float(self.size() * 8),
+            (SPIRVScalarRepresentation::Widened, Self::Int { .. }) => module.type_int(32, false),
+            (SPIRVScalarRepresentation::Widened, _) => module.type_float(32),
+            (SPIRVScalarRepresentation::Split, _) => {
+                let uint = module.type_int(32, false);
+                module.type_vector(uint, 2)
+            }
+        }
+    }
+
+    /// The type values of this scalar are stored as in buffers. 8 and 16-bit
+    /// types need the matching storage features, even if they are computed
+    /// in 32 bits.
+    pub fn to_spirv_storage(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+    ) -> Result<u32> {
+        match self.size() {
+            1 => {
+                if !features.storage_buffer_8bit {
+                    return Err(anyhow!(
+                        "{} in device memory needs 8-bit storage, which this device lacks.",
+                        self.metal_name()
+                    ));
+                }
+
+                module.capability(SPIRVCapability::StorageBuffer8BitAccess);
+                module.extension("SPV_KHR_8bit_storage");
+
+                // Vulkan has no booleans in memory, Metal stores them as bytes.
+                Ok(module.type_int_storage(8, false))
+            }
+            2 => {
+                if !features.storage_buffer_16bit {
+                    return Err(anyhow!(
+                        "{} in device memory needs 16-bit storage, which this device lacks.",
+                        self.metal_name()
+                    ));
+                }
+
+                module.capability(SPIRVCapability::StorageBuffer16BitAccess);
+                module.extension("SPV_KHR_16bit_storage");
+
+                Ok(match self {
+                    Self::Float { .. } => module.type_float_storage(16),
+                    // Only the bits of `bfloat`, they are widened on load.
+                    _ => module.type_int_storage(16, false),
+                })
+            }
+            _ => Ok(self.to_spirv(module, features)),
+        }
+    }
+}
+
+/// Metal types as they appear in AIR. Matrices are arrays of column vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AIRType {
+    Scalar(AIRScalarType),
+    Vector(AIRScalarType, u32),
+    /// `packed_float3` and friends, aligned to their scalar.
+    PackedVector(AIRScalarType, u32),
+    Matrix {
+        scalar: AIRScalarType,
+        columns: u32,
+        rows: u32,
+    },
+}
+
+impl AIRType {
+    /// Parses the type suffix of an overloaded intrinsic, e.g. `f32` in
+    /// `air.simd_sum.f32` or `v4i16` in `air.simd_shuffle.v4i16`.
+    pub fn from_suffix(suffix: &str, signed: bool) -> Result<Self> {
+        let Some(vector) = suffix.strip_prefix('v') else {
+            return Ok(Self::Scalar(AIRScalarType::from_llvm_name(suffix, signed)?));
+        };
+
+        let split = vector
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("`{}` is not a vector type.", suffix))?;
+        let (count, scalar) = vector.split_at(split);
+
+        Ok(Self::Vector(
+            AIRScalarType::from_llvm_name(scalar, signed)?,
+            count.parse()?,
+        ))
+    }
+
+    /// Parses a Metal type name like `half4`, `packed_float3` or
+    /// `float4x3`.
+    pub fn from_metal_name(name: &str) -> Result<Self> {
+        let (packed, rest) = match name.strip_prefix("packed_") {
+            Some(rest) => (true, rest),
+            None => (false, name),
+        };
+
+        let split = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (scalar, dimensions) = rest.split_at(split);
+
+        // `int8_t` and friends end in digits without being vectors.
+        if let Ok(scalar) = AIRScalarType::from_metal_name(rest) {
+            return Ok(Self::Scalar(scalar));
+        }
+
+        let scalar = AIRScalarType::from_metal_name(scalar)?;
+
+        let dimension = |digits: &str| -> Result<u32> {
+            match digits.parse::<u32>() {
+                Ok(n @ 2..=4) => Ok(n),
+                _ => Err(anyhow!("`{}` is not a Metal type.", name)),
+            }
+        };
+
+        match (packed, dimensions.split_once('x')) {
+            (false, Some((columns, rows))) => Ok(Self::Matrix {
+                scalar,
+                columns: dimension(columns)?,
+                rows: dimension(rows)?,
+            }),
+            (true, None) => Ok(Self::PackedVector(scalar, dimension(dimensions)?)),
+            (false, None) => Ok(Self::Vector(scalar, dimension(dimensions)?)),
+            (true, Some(_)) => Err(anyhow!("`{}` is not a Metal type.", name)),
+        }
+    }
+
+    pub fn metal_name(&self) -> String {
+        match self {
+            Self::Scalar(s) => s.metal_name().to_string(),
+            Self::Vector(s, count) => format!("{}{}", s.metal_name(), count),
+            Self::PackedVector(s, count) => format!("packed_{}{}", s.metal_name(), count),
+            Self::Matrix {
+                scalar,
+                columns,
+                rows,
+            } => format!("{}{}x{}", scalar.metal_name(), columns, rows),
+        }
+    }
+
+    pub fn scalar(&self) -> AIRScalarType {
+        match self {
+            Self::Scalar(s) | Self::Vector(s, _) | Self::PackedVector(s, _) => *s,
+            Self::Matrix { scalar, .. } => *scalar,
+        }
+    }
+
+    /// The size in bytes, following Metal's rules: three-component vectors
+    /// take up as much space as four-component ones unless packed.
+    pub fn size(&self) -> u32 {
+        match self {
+            Self::Scalar(s) => s.size(),
+            Self::Vector(s, 3) => s.size() * 4,
+            Self::Vector(s, count) | Self::PackedVector(s, count) => s.size() * count,
+            Self::Matrix {
+                scalar,
+                columns,
+                rows,
+            } => Self::Vector(*scalar, *rows).size() * columns,
+        }
+    }
+
+    pub fn alignment(&self) -> u32 {
+        match self {
+            Self::Scalar(s) | Self::PackedVector(s, _) => s.size(),
+            Self::Vector(..) => self.size(),
+            Self::Matrix { scalar, rows, .. } => Self::Vector(*scalar, *rows).alignment(),
+        }
+    }
+
+    /// The type values of this type are computed in. Packed vectors become
+    /// regular vectors once loaded, matrices stay arrays of columns.
+    pub fn to_spirv(&self, module: &mut SPIRVModule, features: &SPIRVTargetFeatures) -> u32 {
+        match self {
+            Self::Scalar(s) => s.to_spirv(module, features),
+            Self::Vector(s, count) | Self::PackedVector(s, count) => {
+                let component = s.to_spirv(module, features);
+                vector_or_split(module, *s, features, component, *count)
+            }
+            Self::Matrix {
+                scalar,
+                columns,
+                rows,
+            } => {
+                let column = Self::Vector(*scalar, *rows).to_spirv(module, features);
+                module.type_array(column, *columns)
+            }
+        }
+    }
+
+    /// The type values of this type are stored as in buffers, laid out with
+    /// Metal's sizes and alignments.
+    ///
+    /// Packed vectors are only aligned to their scalar, which Vulkan only
+    /// accepts with `scalarBlockLayout`. Without it they are stored as arrays
+    /// of scalars.
+    pub fn to_spirv_storage(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+    ) -> Result<u32> {
+        match self {
+            Self::Scalar(s) => s.to_spirv_storage(module, features),
+            Self::Vector(s, count) => {
+                let component = s.to_spirv_storage(module, features)?;
+                Ok(vector_or_split(module, *s, features, component, *count))
+            }
+            Self::PackedVector(s, count) if features.scalar_block_layout => {
+                let component = s.to_spirv_storage(module, features)?;
+                Ok(vector_or_split(module, *s, features, component, *count))
+            }
+            Self::PackedVector(s, count) => {
+                let component = s.to_spirv_storage(module, features)?;
+                let ty = module.type_array(component, *count);
+                module.decorate_unique(ty, SPIRVDecoration::ArrayStride, &[s.size()]);
+
+                Ok(ty)
+            }
+            Self::Matrix {
+                scalar,
+                columns,
+                rows,
+            } => {
+                let column = Self::Vector(*scalar, *rows);
+                let column_type = column.to_spirv_storage(module, features)?;
+
+                let ty = module.type_array(column_type, *columns);
+                module.decorate_unique(ty, SPIRVDecoration::ArrayStride, &[column.size()]);
+
+                Ok(ty)
+            }
+        }
+    }
+
+    /// Brings a value computed in a wider type back into this type's range,
+    /// which is what Metal's arithmetic would have produced.
+    pub fn narrow_emulated(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+        value: u32,
+    ) -> u32 {
+        let scalar = self.scalar();
+
+        if scalar.representation(features) != SPIRVScalarRepresentation::Widened {
+            return value;
+        }
+
+        let ty = self.to_spirv(module, features);
+        let count = self.component_count();
+
+        match scalar {
+            AIRScalarType::Float { .. } => module.emit_value(SPIRVOp::QuantizeToF16, ty, &[value]),
+            AIRScalarType::BFloat => {
+                let uint = module.type_int(32, false);
+                let uint_type = vector_of(module, uint, count);
+
+                let bits = module.emit_value(SPIRVOp::Bitcast, uint_type, &[value]);
+                let rounded = round_to_bfloat(module, bits, count);
+                let shift = splat_u32(module, 16, count);
+                let widened =
+                    module.emit_value(SPIRVOp::ShiftLeftLogical, uint_type, &[rounded, shift]);
+
+                module.emit_value(SPIRVOp::Bitcast, ty, &[widened])
+            }
+            AIRScalarType::Int { width, signed } => {
+                let shift = splat_u32(module, 32 - width, count);
+                let shifted = module.emit_value(SPIRVOp::ShiftLeftLogical, ty, &[value, shift]);
+
+                let op = match signed {
+                    true => SPIRVOp::ShiftRightArithmetic,
+                    false => SPIRVOp::ShiftRightLogical,
+                };
+
+                module.emit_value(op, ty, &[shifted, shift])
+            }
+            AIRScalarType::Bool => value,
+        }
+    }
+
+    /// Converts a value loaded from a `to_spirv_storage` type into its
+    /// `to_spirv` type.
+    pub fn load_storage(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+        value: u32,
+    ) -> Result<u32> {
+        if let Self::Matrix { .. } = self {
+            return self.convert_columns(module, features, value, true);
+        }
+
+        let scalar = self.scalar();
+        let ty = self.to_spirv(module, features);
+        let count = self.component_count();
+
+        let value = self.gather_packed(module, features, value)?;
+
+        Ok(match (scalar, scalar.representation(features)) {
+            (AIRScalarType::Bool, _) => {
+                // 8-bit storage only allows conversions, compare in 32 bits.
+                let uint = module.type_int(32, false);
+                let uint_type = vector_of(module, uint, count);
+
+                let byte = module.emit_value(SPIRVOp::UConvert, uint_type, &[value]);
+                let zero = splat_u32(module, 0, count);
+
+                module.emit_value(SPIRVOp::INotEqual, ty, &[byte, zero])
+            }
+            (AIRScalarType::BFloat, _) => {
+                let uint = module.type_int(32, false);
+                let uint_type = vector_of(module, uint, count);
+
+                let bits = module.emit_value(SPIRVOp::UConvert, uint_type, &[value]);
+                let shift = splat_u32(module, 16, count);
+                let widened =
+                    module.emit_value(SPIRVOp::ShiftLeftLogical, uint_type, &[bits, shift]);
+
+                module.emit_value(SPIRVOp::Bitcast, ty, &[widened])
+            }
+            (AIRScalarType::Float { .. }, SPIRVScalarRepresentation::Widened) => {
+                module.emit_value(SPIRVOp::FConvert, ty, &[value])
+            }
+            (AIRScalarType::Int { signed, .. }, SPIRVScalarRepresentation::Widened) => {
+                let op = match signed {
+                    true => SPIRVOp::SConvert,
+                    false => SPIRVOp::UConvert,
+                };
+
+                module.emit_value(op, ty, &[value])
+            }
+            _ => value,
+        })
+    }
+
+    /// Converts a `to_spirv` value into its `to_spirv_storage` type before
+    /// it is stored.
+    pub fn store_storage(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+        value: u32,
+    ) -> Result<u32> {
+        if let Self::Matrix { .. } = self {
+            return self.convert_columns(module, features, value, false);
+        }
+
+        let scalar = self.scalar();
+        let count = self.component_count();
+
+        let storage_scalar = scalar.to_spirv_storage(module, features)?;
+        let storage_type = match scalar.representation(features) {
+            SPIRVScalarRepresentation::Split => self.to_spirv(module, features),
+            _ => vector_of(module, storage_scalar, count),
+        };
+
+        let value = match (scalar, scalar.representation(features)) {
+            (AIRScalarType::Bool, _) => {
+                let uint = module.type_int(32, false);
+                let uint_type = vector_of(module, uint, count);
+
+                let one = splat_u32(module, 1, count);
+                let zero = splat_u32(module, 0, count);
+                let byte = module.emit_value(SPIRVOp::Select, uint_type, &[value, one, zero]);
+
+                module.emit_value(SPIRVOp::UConvert, storage_type, &[byte])
+            }
+            (AIRScalarType::BFloat, _) => {
+                let uint = module.type_int(32, false);
+                let uint_type = vector_of(module, uint, count);
+
+                let bits = module.emit_value(SPIRVOp::Bitcast, uint_type, &[value]);
+                let rounded = round_to_bfloat(module, bits, count);
+
+                module.emit_value(SPIRVOp::UConvert, storage_type, &[rounded])
+            }
+            (AIRScalarType::Float { .. }, SPIRVScalarRepresentation::Widened) => {
+                module.emit_value(SPIRVOp::FConvert, storage_type, &[value])
+            }
+            (AIRScalarType::Int { signed, .. }, SPIRVScalarRepresentation::Widened) => {
+                let op = match signed {
+                    true => SPIRVOp::SConvert,
+                    false => SPIRVOp::UConvert,
+                };
+
+                module.emit_value(op, storage_type, &[value])
+            }
+            _ => value,
+        };
+
+        self.scatter_packed(module, features, value)
+    }
+
+    fn component_count(&self) -> u32 {
+        match self {
+            Self::Scalar(_) => 1,
+            Self::Vector(_, count) | Self::PackedVector(_, count) => *count,
+            Self::Matrix { rows, .. } => *rows,
+        }
+    }
+
+    /// Turns a packed vector stored as an array back into a vector.
+    fn gather_packed(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+        value: u32,
+    ) -> Result<u32> {
+        let Self::PackedVector(scalar, count) = self else {
+            return Ok(value);
+        };
+
+        if features.scalar_block_layout {
+            return Ok(value);
+        }
+
+        let component = scalar.to_spirv_storage(module, features)?;
+        let vector = vector_or_split(module, *scalar, features, component, *count);
+
+        let components = (0..*count)
+            .map(|i| module.emit_value(SPIRVOp::CompositeExtract, component, &[value, i]))
+            .collect::<Vec<_>>();
+
+        Ok(module.emit_value(SPIRVOp::CompositeConstruct, vector, &components))
+    }
+
+    fn scatter_packed(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+        value: u32,
+    ) -> Result<u32> {
+        let Self::PackedVector(scalar, count) = self else {
+            return Ok(value);
+        };
+
+        if features.scalar_block_layout {
+            return Ok(value);
+        }
+
+        let component = scalar.to_spirv_storage(module, features)?;
+        let array = self.to_spirv_storage(module, features)?;
+
+        let components = (0..*count)
+            .map(|i| module.emit_value(SPIRVOp::CompositeExtract, component, &[value, i]))
+            .collect::<Vec<_>>();
+
+        Ok(module.emit_value(SPIRVOp::CompositeConstruct, array, &components))
+    }
+
+    /// Loads or stores a matrix column by column.
+    fn convert_columns(
+        &self,
+        module: &mut SPIRVModule,
+        features: &SPIRVTargetFeatures,
+        value: u32,
+        loading: bool,
+    ) -> Result<u32> {
+        let Self::Matrix {
+            scalar,
+            columns,
+            rows,
+        } = self
+        else {
+            return Ok(value);
+        };
+
+        let column = Self::Vector(*scalar, *rows);
+
+        let (source_column, result_type) = match loading {
+            true => (
+                column.to_spirv_storage(module, features)?,
+                self.to_spirv(module, features),
+            ),
+            false => (
+                column.to_spirv(module, features),
+                self.to_spirv_storage(module, features)?,
+            ),
+        };
+
+        let mut converted = vec![];
+
+        for i in 0..*columns {
+            let extracted =
+                module.emit_value(SPIRVOp::CompositeExtract, source_column, &[value, i]);
+            converted.push(match loading {
+                true => column.load_storage(module, features, extracted)?,
+                false => column.store_storage(module, features, extracted)?,
+            });
+        }
+
+        Ok(module.emit_value(SPIRVOp::CompositeConstruct, result_type, &converted))
+    }
+}
+
+/// Lays out a struct with Metal's rules and decorates its member offsets.
+/// Returns the struct type and its size.
+pub fn layout_struct(
+    module: &mut SPIRVModule,
+    features: &SPIRVTargetFeatures,
+    members: &[AIRType],
+) -> Result<(u32, u32)> {
+    let mut offsets = vec![];
+    let mut types = vec![];
+    let mut size = 0_u32;
+    let mut alignment = 1;
+
+    for member in members {
+        let offset = size.next_multiple_of(member.alignment());
+
+        offsets.push(offset);
+        types.push(member.to_spirv_storage(module, features)?);
+
+        size = offset + member.size();
+        alignment = alignment.max(member.alignment());
+    }
+
+    let ty = module.type_struct(&types);
+
+    for (index, offset) in offsets.into_iter().enumerate() {
+        module.member_decorate(ty, index as u32, SPIRVDecoration::Offset, &[offset]);
+    }
+
+    Ok((ty, size.next_multiple_of(alignment)))
+}
+
+fn vector_or_split(
+    module: &mut SPIRVModule,
+    scalar: AIRScalarType,
+    features: &SPIRVTargetFeatures,
+    component: u32,
+    count: u32,
+) -> u32 {
+    match scalar.representation(features) {
+        // Split components are `uint2` already, vectors of them become
+        // arrays.
+        SPIRVScalarRepresentation::Split => module.type_array(component, count),
+        _ => module.type_vector(component, count),
+    }
+}
+
+fn vector_of(module: &mut SPIRVModule, component: u32, count: u32) -> u32 {
+    match count {
+        1 => component,
+        _ => module.type_vector(component, count),
+    }
+}
+
+fn splat(module: &mut SPIRVModule, ty: u32, constant: u32, count: u32) -> u32 {
+    match count {
+        1 => constant,
+        _ => module.constant_composite(ty, &vec![constant; count as usize]),
+    }
+}
+
+fn splat_u32(module: &mut SPIRVModule, value: u32, count: u32) -> u32 {
+    let uint = module.type_int(32, false);
+    let ty = vector_of(module, uint, count);
+    let constant = module.constant_u32(value);
+
+    splat(module, ty, constant, count)
+}
+
+/// Rounds the bits of a `float` to the nearest even `bfloat`, leaving them
+/// in the low 16 bits.
+fn round_to_bfloat(module: &mut SPIRVModule, bits: u32, count: u32) -> u32 {
+    let uint = module.type_int(32, false);
+    let ty = vector_of(module, uint, count);
+
+    let shift = splat_u32(module, 16, count);
+    let one = splat_u32(module, 1, count);
+    let bias = splat_u32(module, 0x7FFF, count);
+
+    let upper = module.emit_value(SPIRVOp::ShiftRightLogical, ty, &[bits, shift]);
+    let odd = module.emit_value(SPIRVOp::BitwiseAnd, ty, &[upper, one]);
+    let bias = module.emit_value(SPIRVOp::IAdd, ty, &[bias, odd]);
+    let rounded = module.emit_value(SPIRVOp::IAdd, ty, &[bits, bias]);
+
+    module.emit_value(SPIRVOp::ShiftRightLogical, ty, &[rounded, shift])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The operands of the `op` instructions of a module.
+    fn operands(module: &SPIRVModule, op: SPIRVOp) -> Vec<Vec<u32>> {
+        let words = module.words();
+        let mut found = vec![];
+        let mut rest = &words[5..];
+
+        while let Some(first) = rest.first() {
+            let (instruction, next) = rest.split_at((first >> 16) as usize);
+            if first & 0xFFFF == op as u32 {
+                found.push(instruction[1..].to_vec());
+            }
+            rest = next;
+        }
+
+        found
+    }
+
+    #[test]
+    fn parses_metal_names() {
+        let float = AIRScalarType::Float { width: 32 };
+        let half = AIRScalarType::Float { width: 16 };
+
+        assert_eq!(
+            AIRType::from_metal_name("packed_float3").unwrap(),
+            AIRType::PackedVector(float, 3)
+        );
+        assert_eq!(
+            AIRType::from_metal_name("half4").unwrap(),
+            AIRType::Vector(half, 4)
+        );
+        assert_eq!(
+            AIRType::from_metal_name("float4x3").unwrap(),
+            AIRType::Matrix {
+                scalar: float,
+                columns: 4,
+                rows: 3
+            }
+        );
+        assert_eq!(
+            AIRType::from_metal_name("uint16_t").unwrap(),
+            AIRType::Scalar(AIRScalarType::Int {
+                width: 16,
+                signed: false
+            })
+        );
+        assert_eq!(
+            AIRType::from_suffix("v2i64", true).unwrap(),
+            AIRType::Vector(
+                AIRScalarType::Int {
+                    width: 64,
+                    signed: true
+                },
+                2
+            )
+        );
+
+        for name in ["packed_float3", "short2", "half3x2", "bfloat4"] {
+            assert_eq!(AIRType::from_metal_name(name).unwrap().metal_name(), name);
+        }
+
+        assert!(AIRType::from_metal_name("float5").is_err());
+        assert!(AIRType::from_metal_name("packed_float4x4").is_err());
+    }
+
+    #[test]
+    fn lays_out_like_metal() {
+        let float = AIRScalarType::Float { width: 32 };
+        let half = AIRScalarType::Float { width: 16 };
+
+        let packed_float3 = AIRType::PackedVector(float, 3);
+        assert_eq!((packed_float3.size(), packed_float3.alignment()), (12, 4));
+
+        let float3 = AIRType::Vector(float, 3);
+        assert_eq!((float3.size(), float3.alignment()), (16, 16));
+
+        let half3 = AIRType::Vector(half, 3);
+        assert_eq!((half3.size(), half3.alignment()), (8, 8));
+
+        let float4x3 = AIRType::Matrix {
+            scalar: float,
+            columns: 4,
+            rows: 3,
+        };
+        assert_eq!((float4x3.size(), float4x3.alignment()), (64, 16));
+
+        // Without scalar layouts, packed vectors are arrays of their scalar.
+        let features = SPIRVTargetFeatures::default();
+        let mut module = SPIRVModule::new();
+        let (_, size) = layout_struct(
+            &mut module,
+            &features,
+            &[packed_float3, AIRType::Scalar(float)],
+        )
+        .unwrap();
+
+        assert_eq!(size, 16);
+        assert!(
+            operands(&module, SPIRVOp::Decorate)
+                .iter()
+                .any(|d| d[1..] == [SPIRVDecoration::ArrayStride as u32, 4])
+        );
+        assert_eq!(
+            operands(&module, SPIRVOp::MemberDecorate)
+                .iter()
+                .map(|d| d[3])
+                .collect::<Vec<_>>(),
+            [0, 12]
+        );
+
+        let mut module = SPIRVModule::new();
+        let (_, size) =
+            layout_struct(&mut module, &features, &[float3, AIRType::Scalar(float)]).unwrap();
+        assert_eq!(size, 32);
+    }
+
+    #[test]
+    fn represents_missing_widths() {
+        let mut features = SPIRVTargetFeatures::default();
+        let half = AIRScalarType::Float { width: 16 };
+        let short = AIRScalarType::Int {
+            width: 16,
+            signed: true,
+        };
+        let long = AIRScalarType::Int {
+            width: 64,
+            signed: true,
+        };
+
+        assert_eq!(
+            half.representation(&features),
+            SPIRVScalarRepresentation::Widened
+        );
+        assert_eq!(
+            short.representation(&features),
+            SPIRVScalarRepresentation::Widened
+        );
+        assert_eq!(
+            long.representation(&features),
+            SPIRVScalarRepresentation::Split
+        );
+
+        features.float16 = true;
+        features.int64 = true;
+
+        assert_eq!(
+            half.representation(&features),
+            SPIRVScalarRepresentation::Native
+        );
+        assert_eq!(
+            long.representation(&features),
+            SPIRVScalarRepresentation::Native
+        );
+        assert_eq!(
+            AIRScalarType::BFloat.representation(&features),
+            SPIRVScalarRepresentation::Widened
+        );
+
+        // 16-bit types can't be stored without 16-bit storage.
+        let mut module = SPIRVModule::new();
+        assert!(half.to_spirv_storage(&mut module, &features).is_err());
+        features.storage_buffer_16bit = true;
+        assert!(half.to_spirv_storage(&mut module, &features).is_ok());
+        assert!(module.has_capability(SPIRVCapability::StorageBuffer16BitAccess));
+    }
+}
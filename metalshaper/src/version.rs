@@ -0,0 +1,345 @@
+use std::fmt;
+
+use anyhow::{Result, anyhow};
+
+use crate::AIRAddressSpace;
+use crate::apple_ir::{AIRBlock, AIRItem, AIRRecord, BlockType, module_code};
+use crate::function::type_code;
+use crate::metadata::AIRNamedMetadata;
+
+/// Record codes of `IDENTIFICATION_BLOCK`.
+mod identification_code {
+    pub const STRING: u32 = 1;
+    pub const EPOCH: u32 = 2;
+}
+
+/// The only bitcode epoch there is so far.
+const BITCODE_EPOCH: u64 = 0;
+
+/// The oldest and newest Metal versions the translator handles.
+pub const MIN_METAL_VERSION: MetalLanguageVersion = MetalLanguageVersion::new(2, 0);
+pub const MAX_METAL_VERSION: MetalLanguageVersion = MetalLanguageVersion::new(3, 2);
+
+/// The contents of `IDENTIFICATION_BLOCK`, which comes before the module so
+/// readers can reject bitcode they don't understand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRIdentification {
+    /// `Apple metal 32023.26 (metalfe-32023.26)` for example. Some
+    /// compilers leave it empty, so versions are read from the module's
+    /// metadata instead.
+    pub producer: String,
+    pub epoch: u64,
+}
+
+impl AIRIdentification {
+    pub fn from_block(block: &AIRBlock) -> Result<Self> {
+        let mut producer = None;
+        let mut epoch = None;
+
+        for record in block.records() {
+            match record.code {
+                identification_code::STRING => producer = Some(record.string()),
+                identification_code::EPOCH => epoch = record.operands.first().copied(),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            producer: producer.ok_or_else(|| anyhow!("Identification block has no producer."))?,
+            epoch: epoch.ok_or_else(|| anyhow!("Identification block has no epoch."))?,
+        })
+    }
+
+    pub fn check_supported(&self) -> Result<()> {
+        if self.epoch != BITCODE_EPOCH {
+            return Err(anyhow!(
+                "Bitcode epoch {} from \"{}\" is not supported, only epoch {} is.",
+                self.epoch,
+                self.producer,
+                BITCODE_EPOCH
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// `!air.version`, the version of the AIR format itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AIRVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl fmt::Display for AIRVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AIR {}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// `!air.language_version`, the `-std=metalX.Y` a module was compiled with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MetalLanguageVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl MetalLanguageVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    pub fn at_least(&self, major: u32, minor: u32) -> bool {
+        *self >= Self::new(major, minor)
+    }
+
+    /// The Metal version that introduced `air`, for modules without
+    /// `!air.language_version`. AIR 2.x tracked Metal 2.x until Metal 3.0
+    /// shipped with AIR 2.5.
+    pub fn from_air_version(air: AIRVersion) -> Self {
+        match (air.major, air.minor) {
+            (2, minor @ 0..=4) => Self::new(2, minor),
+            (2, minor) => Self::new(3, minor - 5),
+            (major, minor) => Self::new(major + 1, minor),
+        }
+    }
+}
+
+impl fmt::Display for MetalLanguageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Metal {}.{}", self.major, self.minor)
+    }
+}
+
+/// How pointer types are encoded. Typed pointers carry their pointee, which
+/// newer compilers dropped along with upstream LLVM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AIRPointerEncoding {
+    Typed,
+    Opaque,
+}
+
+/// Which compiler produced a module and for which Metal version, decoding
+/// and lowering branch on this where AIR changed between versions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AIRModuleVersion {
+    pub identification: AIRIdentification,
+    pub triple: String,
+    pub air: Option<AIRVersion>,
+    pub language: MetalLanguageVersion,
+    pub pointers: AIRPointerEncoding,
+}
+
+impl AIRModuleVersion {
+    pub fn from_items(items: &[AIRItem]) -> Result<Self> {
+        let block = |ty| {
+            items.iter().find_map(|item| match item {
+                AIRItem::Block(block) if block.ty == ty => Some(block),
+                _ => None,
+            })
+        };
+
+        let identification = block(BlockType::Identification)
+            .ok_or_else(|| anyhow!("AIR file has no identification block."))
+            .and_then(AIRIdentification::from_block)?;
+        let module =
+            block(BlockType::Module).ok_or_else(|| anyhow!("AIR file has no module block."))?;
+
+        Self::from_module(identification, module)
+    }
+
+    pub fn from_module(identification: AIRIdentification, module: &AIRBlock) -> Result<Self> {
+        let triple = module
+            .records()
+            .find(|record| record.code == module_code::TRIPLE)
+            .map(AIRRecord::string)
+            .unwrap_or_default();
+
+        let metadata = AIRNamedMetadata::new(module)?;
+
+        let air = match metadata.tuple("air.version")?.as_deref() {
+            None => None,
+            Some([major, minor, patch, ..]) => Some(AIRVersion {
+                major: major.integer()?,
+                minor: minor.integer()?,
+                patch: patch.integer()?,
+            }),
+            Some(_) => return Err(anyhow!("Malformed !air.version.")),
+        };
+
+        let language = match metadata.tuple("air.language_version")?.as_deref() {
+            Some([_, major, minor, ..]) => {
+                MetalLanguageVersion::new(major.integer()?, minor.integer()?)
+            }
+            Some(_) => return Err(anyhow!("Malformed !air.language_version.")),
+            None => MetalLanguageVersion::from_air_version(air.ok_or_else(|| {
+                anyhow!("AIR module has neither !air.version nor !air.language_version.")
+            })?),
+        };
+
+        let opaque = module
+            .blocks(BlockType::Type)
+            .flat_map(AIRBlock::records)
+            .any(|record| record.code == type_code::OPAQUE_POINTER);
+
+        Ok(Self {
+            identification,
+            triple,
+            air,
+            language,
+            pointers: match opaque {
+                true => AIRPointerEncoding::Opaque,
+                false => AIRPointerEncoding::Typed,
+            },
+        })
+    }
+
+    /// Fails with an error naming the version if the module is older or
+    /// newer than what the translator handles.
+    pub fn check_supported(&self) -> Result<()> {
+        self.identification.check_supported()?;
+
+        let air = self
+            .air
+            .map(|air| format!(" ({})", air))
+            .unwrap_or_default();
+
+        if self.language < MIN_METAL_VERSION {
+            return Err(anyhow!(
+                "{}{} is not supported, the oldest supported version is {}.",
+                self.language,
+                air,
+                MIN_METAL_VERSION
+            ));
+        }
+
+        if self.language > MAX_METAL_VERSION {
+            return Err(anyhow!(
+                "{}{} from \"{}\" is not supported, the newest supported version is {}.",
+                self.language,
+                air,
+                self.identification.producer,
+                MAX_METAL_VERSION
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The address space of a `TYPE_CODE_POINTER` or
+    /// `TYPE_CODE_OPAQUE_POINTER` record, and the pointee type id if the
+    /// module still has typed pointers.
+    pub fn pointer_type(&self, record: &AIRRecord) -> Result<(AIRAddressSpace, Option<u64>)> {
+        let operand = |index: usize| {
+            record
+                .operands
+                .get(index)
+                .copied()
+                .ok_or_else(|| anyhow!("Pointer type record is missing operand {}.", index))
+        };
+
+        match (self.pointers, record.code) {
+            (AIRPointerEncoding::Typed, type_code::POINTER) => Ok((
+                AIRAddressSpace::from_u32(record.operands.get(1).copied().unwrap_or(0) as u32)?,
+                Some(operand(0)?),
+            )),
+            (AIRPointerEncoding::Opaque, type_code::OPAQUE_POINTER) => {
+                Ok((AIRAddressSpace::from_u32(operand(0)? as u32)?, None))
+            }
+            (pointers, code) => Err(anyhow!(
+                "Type record {} is not a pointer in a module with {:?} pointers ({}).",
+                code,
+                pointers,
+                self.language
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apple_ir::parse_apple_ir;
+
+    fn test_version() -> AIRModuleVersion {
+        let air = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../test.air")).unwrap();
+        parse_apple_ir(&air).unwrap().version
+    }
+
+    #[test]
+    fn reads_test_air() {
+        let version = test_version();
+
+        assert_eq!(version.triple, "air64-apple-macosx15.0.0");
+        assert_eq!(
+            version.air,
+            Some(AIRVersion {
+                major: 2,
+                minor: 7,
+                patch: 0
+            })
+        );
+        assert_eq!(version.language, MetalLanguageVersion::new(3, 2));
+        assert_eq!(version.pointers, AIRPointerEncoding::Typed);
+        assert!(version.check_supported().is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let newer = AIRModuleVersion {
+            identification: AIRIdentification {
+                producer: "Apple metal 32024.100 (metalfe-32024.100)".to_string(),
+                epoch: 0,
+            },
+            air: Some(AIRVersion {
+                major: 2,
+                minor: 8,
+                patch: 0,
+            }),
+            language: MetalLanguageVersion::new(3, 3),
+            ..test_version()
+        };
+
+        let message = newer.check_supported().unwrap_err().to_string();
+        assert!(message.contains("Metal 3.3 (AIR 2.8.0)"), "{}", message);
+        assert!(message.contains("Apple metal 32024.100"), "{}", message);
+        assert!(message.contains("Metal 3.2"), "{}", message);
+
+        let older = AIRModuleVersion {
+            air: None,
+            language: MetalLanguageVersion::new(1, 2),
+            ..test_version()
+        };
+        let message = older.check_supported().unwrap_err().to_string();
+        assert!(
+            message.contains("Metal 1.2 is not supported"),
+            "{}",
+            message
+        );
+
+        let epoch = AIRIdentification {
+            producer: String::new(),
+            epoch: 1,
+        };
+        let message = epoch.check_supported().unwrap_err().to_string();
+        assert!(message.contains("epoch 1"), "{}", message);
+    }
+
+    #[test]
+    fn maps_air_to_metal_versions() {
+        let metal = |major, minor| {
+            MetalLanguageVersion::from_air_version(AIRVersion {
+                major,
+                minor,
+                patch: 0,
+            })
+        };
+
+        assert_eq!(metal(2, 0), MetalLanguageVersion::new(2, 0));
+        assert_eq!(metal(2, 4), MetalLanguageVersion::new(2, 4));
+        assert_eq!(metal(2, 5), MetalLanguageVersion::new(3, 0));
+        assert_eq!(metal(2, 7), MetalLanguageVersion::new(3, 2));
+        assert!(metal(2, 8).at_least(3, 3));
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+
+/// An installed LunarG Vulkan SDK, the `macOS` directory of one version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VulkanSDK {
+    pub root: PathBuf,
+    /// `1.4.313.1`, `None` if `VULKAN_SDK` points outside a versioned
+    /// install.
+    pub version: Option<Vec<u32>>,
+    /// Holds `libvulkan.dylib` and `libMoltenVK.dylib`.
+    pub library_path: PathBuf,
+    pub icd_filenames: PathBuf,
+    pub layer_path: PathBuf,
+}
+
+impl VulkanSDK {
+    /// Finds the SDK named by `VULKAN_SDK`, or the newest one installed in
+    /// `$HOME/VulkanSDK`.
+    pub fn discover() -> Result<Self> {
+        Self::discover_from(
+            env::var_os("VULKAN_SDK").map(PathBuf::from),
+            env::var_os("HOME").map(PathBuf::from),
+        )
+    }
+
+    /// `discover`, with the environment passed in.
+    ///
+    /// `vulkan_sdk` may be the `macOS` directory `setup-env.sh` exports, a
+    /// version directory, or an SDK root holding several versions.
+    pub fn discover_from(vulkan_sdk: Option<PathBuf>, home: Option<PathBuf>) -> Result<Self> {
+        match (vulkan_sdk, home) {
+            (Some(path), _) => Self::from_path(&path)
+                .with_context(|| format!("Using VULKAN_SDK={}", path.display())),
+            (None, Some(home)) => {
+                let root = home.join("VulkanSDK");
+
+                Self::newest(&root).with_context(|| {
+                    format!(
+                        "Looking for the Vulkan SDK in {}, set VULKAN_SDK to use another one",
+                        root.display()
+                    )
+                })
+            }
+            (None, None) => Err(anyhow!(
+                "Neither VULKAN_SDK nor HOME is set, can't find the Vulkan SDK."
+            )),
+        }
+    }
+
+    fn from_path(path: &Path) -> Result<Self> {
+        if path.join("share/vulkan").is_dir() {
+            let version = version(path).or_else(|| version(path.parent()?));
+            return Self::validate(path, version);
+        }
+
+        if path.join("macOS").is_dir() {
+            return Self::validate(&path.join("macOS"), version(path));
+        }
+
+        Self::newest(path)
+    }
+
+    /// The newest version under an SDK root, by version number rather than
+    /// name so `1.4.10.0` beats `1.4.9.0`.
+    fn newest(root: &Path) -> Result<Self> {
+        let entries = fs::read_dir(root).with_context(|| format!("Reading {}", root.display()))?;
+
+        let newest = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Some((version(&entry.path())?, entry.path())))
+            .filter(|(_, path)| path.join("macOS").is_dir())
+            .max_by(|(a, _), (b, _)| a.cmp(b));
+
+        match newest {
+            Some((version, path)) => Self::validate(&path.join("macOS"), Some(version)),
+            None => Err(anyhow!(
+                "No Vulkan SDK versions are installed in {}.",
+                root.display()
+            )),
+        }
+    }
+
+    fn validate(root: &Path, version: Option<Vec<u32>>) -> Result<Self> {
+        let sdk = Self {
+            root: root.to_path_buf(),
+            version,
+            library_path: root.join("lib"),
+            icd_filenames: root.join("share/vulkan/icd.d/MoltenVK_icd.json"),
+            layer_path: root.join("share/vulkan/explicit_layer.d"),
+        };
+
+        if !sdk.library_path.is_dir() {
+            return Err(anyhow!("{} has no lib directory.", sdk.root.display()));
+        }
+
+        let icd = fs::read_to_string(&sdk.icd_filenames).with_context(|| {
+            format!(
+                "Reading the MoltenVK ICD {}, is MoltenVK installed?",
+                sdk.icd_filenames.display()
+            )
+        })?;
+
+        if !icd.contains("\"ICD\"") || !icd.contains("\"library_path\"") {
+            return Err(anyhow!(
+                "{} is not an ICD manifest.",
+                sdk.icd_filenames.display()
+            ));
+        }
+
+        if !sdk.layer_path.is_dir() {
+            return Err(anyhow!(
+                "{} is missing, is the SDK installed with its layers?",
+                sdk.layer_path.display()
+            ));
+        }
+
+        Ok(sdk)
+    }
+}
+
+fn version(path: &Path) -> Option<Vec<u32>> {
+    path.file_name()?
+        .to_str()?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Points the loader at MoltenVK from a build script, so `cargo run` and
+/// `cargo test` find it without sourcing `setup-env.sh`.
+///
+/// Does nothing on hosts other than macOS and iOS, where the system loader
+/// is used.
+pub fn setup() {
+    if !cfg!(any(target_os = "macos", target_os = "ios")) {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=VULKAN_SDK");
+    println!("cargo:rerun-if-env-changed=HOME");
+
+    let sdk = match VulkanSDK::discover() {
+        Ok(sdk) => sdk,
+        Err(e) => panic!(
+            "MoltenVK setup failed: {:#}\n\n\
+             Install the Vulkan SDK from https://vulkan.lunarg.com/sdk/home \
+             or set VULKAN_SDK to its location.",
+            e
+        ),
+    };
+
+    println!(
+        "cargo:rustc-env=DYLD_FALLBACK_LIBRARY_PATH={}",
+        sdk.library_path.display()
+    );
+    println!(
+        "cargo::rustc-env=VK_ICD_FILENAMES={}",
+        sdk.icd_filenames.display()
+    );
+    println!(
+        "cargo::rustc-env=VK_LAYER_PATH={}",
+        sdk.layer_path.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory under the temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rosemetal-build-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Lays out SDK versions under `root` the way the installer does.
+    fn install(root: &Path, versions: &[&str]) {
+        for version in versions {
+            let sdk = root.join(version).join("macOS");
+
+            fs::create_dir_all(sdk.join("lib")).unwrap();
+            fs::create_dir_all(sdk.join("share/vulkan/icd.d")).unwrap();
+            fs::create_dir_all(sdk.join("share/vulkan/explicit_layer.d")).unwrap();
+            fs::write(
+                sdk.join("share/vulkan/icd.d/MoltenVK_icd.json"),
+                r#"{"file_format_version": "1.0.0", "ICD": {"library_path": "../../../lib/libMoltenVK.dylib"}}"#,
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn discovers_newest_version() -> Result<()> {
+        let home = temp_dir("newest");
+        let root = home.join("VulkanSDK");
+        install(&root, &["1.4.9.0", "1.4.313.1", "1.3.290.0"]);
+        fs::create_dir_all(root.join("Uninstaller"))?;
+
+        let sdk = VulkanSDK::discover_from(None, Some(home.clone()))?;
+        assert_eq!(sdk.version, Some(vec![1, 4, 313, 1]));
+        assert_eq!(sdk.root, root.join("1.4.313.1/macOS"));
+
+        // VULKAN_SDK wins over HOME, as a version or its macOS directory.
+        let sdk = VulkanSDK::discover_from(Some(root.join("1.4.9.0")), Some(home.clone()))?;
+        assert_eq!(sdk.version, Some(vec![1, 4, 9, 0]));
+
+        let sdk = VulkanSDK::discover_from(Some(root.join("1.3.290.0/macOS")), None)?;
+        assert_eq!(sdk.version, Some(vec![1, 3, 290, 0]));
+        assert_eq!(
+            sdk.layer_path,
+            root.join("1.3.290.0/macOS/share/vulkan/explicit_layer.d")
+        );
+
+        fs::remove_dir_all(&home)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_incomplete_sdks() -> Result<()> {
+        let root = temp_dir("incomplete");
+        install(&root, &["1.4.313.1"]);
+        let sdk = root.join("1.4.313.1/macOS");
+
+        fs::remove_dir_all(sdk.join("share/vulkan/explicit_layer.d"))?;
+        let e = VulkanSDK::discover_from(Some(root.clone()), None).unwrap_err();
+        assert!(format!("{:#}", e).contains("explicit_layer.d"));
+
+        fs::write(sdk.join("share/vulkan/icd.d/MoltenVK_icd.json"), "{}")?;
+        let e = VulkanSDK::discover_from(Some(root.clone()), None).unwrap_err();
+        assert!(format!("{:#}", e).contains("not an ICD manifest"));
+
+        fs::remove_dir_all(&root)?;
+        assert!(VulkanSDK::discover_from(Some(root), None).is_err());
+        assert!(VulkanSDK::discover_from(None, None).is_err());
+
+        Ok(())
+    }
+}
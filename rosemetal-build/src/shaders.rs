@@ -0,0 +1,208 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use metalshaper::{
+    AIRShaderStage,
+    metallib::parse_modules,
+    reflection::{AIRFunctionReflection, reflection_json},
+    translate::translate as translate_module,
+};
+
+use crate::structs::rust_structs;
+
+pub use metalshaper::translate::SPIRVTranslationOptions;
+
+/// A shader written by `translate`.
+#[derive(Debug, Clone)]
+pub struct TranslatedShader {
+    pub input: PathBuf,
+    pub name: String,
+    pub stage: AIRShaderStage,
+    /// `$OUT_DIR/shaders/<input stem>.<name>.spv`
+    pub spirv: PathBuf,
+}
+
+/// Translates `.air` and `.metallib` files to SPIR-V from a build script,
+/// so shipping builds never have to translate at runtime.
+///
+/// Every entry point ends up in `$OUT_DIR/shaders/<input stem>.<name>.spv`
+/// and the reflection of each input in `$OUT_DIR/shaders/<input stem>.json`,
+/// ready for `include_bytes!(concat!(env!("OUT_DIR"), "/shaders/..."))`.
+///
+/// ```no_run
+/// // build.rs
+/// use rosemetal_build::shaders::{SPIRVTranslationOptions, translate};
+///
+/// fn main() {
+///     translate(&["shaders/triangle.air"], &SPIRVTranslationOptions::default()).unwrap();
+/// }
+/// ```
+pub fn translate<P: AsRef<Path>>(
+    inputs: &[P],
+    options: &SPIRVTranslationOptions,
+) -> Result<Vec<TranslatedShader>> {
+    let out_dir = shaders_dir(inputs)?;
+    let mut shaders = vec![];
+
+    for input in inputs {
+        let input = input.as_ref();
+
+        shaders.extend(
+            translate_file(input, &out_dir, options)
+                .with_context(|| format!("Translating {}", input.display()))?,
+        );
+    }
+
+    Ok(shaders)
+}
+
+/// Generates `#[repr(C)]` Rust structs for the struct types behind the
+/// buffer arguments of `.air` and `.metallib` files, from a build script.
+///
+/// The structs of each input end up in `$OUT_DIR/shaders/<input stem>.rs`,
+/// padded to match the Metal layout and checked against it at compile time,
+/// so a `float3` member is followed by 4 bytes of padding while a
+/// `packed_float3` isn't. `half` and `bfloat` members are `u16` bits.
+///
+/// ```no_run
+/// // build.rs
+/// use rosemetal_build::shaders::generate_structs;
+///
+/// fn main() {
+///     generate_structs(&["shaders/triangle.air"]).unwrap();
+/// }
+///
+/// // src/shaders.rs
+/// // include!(concat!(env!("OUT_DIR"), "/shaders/triangle.rs"));
+/// ```
+pub fn generate_structs<P: AsRef<Path>>(inputs: &[P]) -> Result<Vec<PathBuf>> {
+    let out_dir = shaders_dir(inputs)?;
+    let mut outputs = vec![];
+
+    for input in inputs {
+        let input = input.as_ref();
+
+        outputs.push(
+            generate_structs_file(input, &out_dir)
+                .with_context(|| format!("Generating structs for {}", input.display()))?,
+        );
+    }
+
+    Ok(outputs)
+}
+
+/// `$OUT_DIR/shaders`, created if needed.
+fn shaders_dir<P: AsRef<Path>>(inputs: &[P]) -> Result<PathBuf> {
+    let out_dir = PathBuf::from(
+        env::var_os("OUT_DIR")
+            .ok_or_else(|| anyhow!("OUT_DIR is not set, call this from a build script."))?,
+    )
+    .join("shaders");
+
+    fs::create_dir_all(&out_dir).with_context(|| format!("Creating {}", out_dir.display()))?;
+
+    // Ask for a rerun before anything can fail, so fixing an input is
+    // picked up.
+    for input in inputs {
+        println!("cargo:rerun-if-changed={}", input.as_ref().display());
+    }
+
+    Ok(out_dir)
+}
+
+fn file_stem(input: &Path) -> Result<String> {
+    Ok(input
+        .file_stem()
+        .ok_or_else(|| anyhow!("{} has no file name.", input.display()))?
+        .to_string_lossy()
+        .into_owned())
+}
+
+fn generate_structs_file(input: &Path, out_dir: &Path) -> Result<PathBuf> {
+    let content = fs::read(input)?;
+    let mut reflection = vec![];
+
+    for module in parse_modules(&content)? {
+        reflection.extend(AIRFunctionReflection::from_module(&module)?);
+    }
+
+    let output = out_dir.join(format!("{}.rs", file_stem(input)?));
+
+    fs::write(&output, rust_structs(&reflection)?)
+        .with_context(|| format!("Writing {}", output.display()))?;
+
+    Ok(output)
+}
+
+fn translate_file(
+    input: &Path,
+    out_dir: &Path,
+    options: &SPIRVTranslationOptions,
+) -> Result<Vec<TranslatedShader>> {
+    let stem = file_stem(input)?;
+
+    let content = fs::read(input)?;
+
+    let mut reflection = vec![];
+    let mut shaders = vec![];
+
+    for module in parse_modules(&content)? {
+        for function in AIRFunctionReflection::from_module(&module)? {
+            let words = translate_module(&module, &function.name, options)?;
+            let spirv = out_dir.join(format!("{}.{}.spv", stem, function.name));
+
+            fs::write(
+                &spirv,
+                words
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            )
+            .with_context(|| format!("Writing {}", spirv.display()))?;
+
+            shaders.push(TranslatedShader {
+                input: input.to_path_buf(),
+                name: function.name.clone(),
+                stage: function.stage,
+                spirv,
+            });
+            reflection.push(function);
+        }
+    }
+
+    let json = out_dir.join(format!("{}.json", stem));
+
+    fs::write(&json, reflection_json(&reflection))
+        .with_context(|| format!("Writing {}", json.display()))?;
+
+    Ok(shaders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_fixture() {
+        let input = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../test.air"));
+        let out_dir = env::temp_dir().join(format!("rosemetal-build-{}", std::process::id()));
+        fs::create_dir_all(&out_dir).unwrap();
+
+        let shaders = translate_file(input, &out_dir, &SPIRVTranslationOptions::default()).unwrap();
+
+        assert_eq!(shaders.len(), 1);
+        assert_eq!(shaders[0].name, "main0");
+        assert_eq!(shaders[0].stage, AIRShaderStage::Vertex);
+
+        let spirv = fs::read(&shaders[0].spirv).unwrap();
+        assert_eq!(spirv[..4], 0x07230203u32.to_le_bytes());
+
+        let json = fs::read_to_string(out_dir.join("test.json")).unwrap();
+        assert!(json.contains("\"main0\""));
+
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
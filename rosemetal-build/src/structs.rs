@@ -0,0 +1,487 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use anyhow::{Result, anyhow};
+use metalshaper::{
+    reflection::{AIRFunctionReflection, AIRStructMemberReflection, AIRStructReflection},
+    types::{AIRScalarType, AIRType},
+};
+
+/// Rust keywords and reserved words that Metal allows as names.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "dyn", "final", "fn", "gen", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "trait", "try", "type", "typeof", "unsafe", "unsized", "use", "where", "yield",
+];
+
+/// Keywords that can't be raw identifiers either.
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "super"];
+
+/// Rust source with a `#[repr(C)]` struct for every struct type behind the
+/// buffer arguments of `functions`, nested ones included.
+///
+/// Members are placed at the offsets the Metal compiler chose, with the
+/// gaps filled by explicit padding, and checked with `const` assertions.
+pub(crate) fn rust_structs(functions: &[AIRFunctionReflection]) -> Result<String> {
+    let mut structs = BTreeMap::new();
+
+    for argument in functions.iter().flat_map(|f| &f.arguments) {
+        if let Some(struct_type) = &argument.struct_type {
+            collect(
+                struct_type,
+                argument.type_size,
+                argument.type_alignment,
+                &mut structs,
+            )?;
+        }
+    }
+
+    let mut source =
+        String::from("// Generated by rosemetal-build from Metal struct types, do not edit.\n");
+
+    for (name, (struct_type, size, alignment)) in &structs {
+        write_struct(&mut source, name, struct_type, *size, *alignment)?;
+    }
+
+    Ok(source)
+}
+
+type Structs = BTreeMap<String, (AIRStructReflection, u32, u32)>;
+
+fn collect(
+    struct_type: &AIRStructReflection,
+    size: Option<u32>,
+    alignment: Option<u32>,
+    structs: &mut Structs,
+) -> Result<()> {
+    for member in &struct_type.members {
+        if let Some(nested) = &member.struct_type {
+            collect(
+                nested,
+                Some(member.size / member.array_length.max(1)),
+                None,
+                structs,
+            )?;
+        }
+    }
+
+    let alignment = match alignment {
+        Some(alignment) => alignment,
+        None => msl_alignment(struct_type)?,
+    };
+
+    let size = match size {
+        Some(size) => size,
+        None => struct_type
+            .members
+            .iter()
+            .map(|member| member.offset + member.size)
+            .max()
+            .unwrap_or(0)
+            .next_multiple_of(alignment),
+    };
+
+    let name = type_ident(&struct_type.name);
+
+    match structs.get(&name) {
+        Some((existing, ..)) if existing.members != struct_type.members => Err(anyhow!(
+            "Struct {} is laid out differently by different shaders.",
+            struct_type.name
+        )),
+        Some(_) => Ok(()),
+        None => {
+            structs.insert(name, (struct_type.clone(), size, alignment));
+            Ok(())
+        }
+    }
+}
+
+/// The alignment Metal gives a struct, that of its most aligned member.
+fn msl_alignment(struct_type: &AIRStructReflection) -> Result<u32> {
+    struct_type
+        .members
+        .iter()
+        .map(|member| match &member.struct_type {
+            Some(nested) => msl_alignment(nested),
+            None => Ok(AIRType::from_metal_name(&member.type_name).map_or(1, |ty| ty.alignment())),
+        })
+        .try_fold(1, |alignment, member| Ok(member?.max(alignment)))
+}
+
+fn write_struct(
+    source: &mut String,
+    name: &str,
+    struct_type: &AIRStructReflection,
+    size: u32,
+    alignment: u32,
+) -> Result<()> {
+    writeln!(source)?;
+    writeln!(source, "/// `{}`, {} bytes.", struct_type.name, size)?;
+    writeln!(source, "#[repr(C, align({}))]", alignment)?;
+    writeln!(source, "#[derive(Debug, Clone, Copy)]")?;
+    writeln!(source, "pub struct {} {{", name)?;
+
+    let mut members = struct_type.members.iter().collect::<Vec<_>>();
+    members.sort_by_key(|member| member.offset);
+
+    let mut offset = 0;
+    let mut padding = 0;
+    let mut fields = vec![];
+
+    let mut pad = |source: &mut String, offset: u32, to: u32| -> Result<()> {
+        if to > offset {
+            writeln!(source, "    pub _pad{}: [u8; {}],", padding, to - offset)?;
+            padding += 1;
+        }
+
+        Ok(())
+    };
+
+    for member in members {
+        if member.offset < offset {
+            return Err(anyhow!(
+                "Member {} of {} overlaps the one before it.",
+                member.name,
+                struct_type.name
+            ));
+        }
+
+        pad(source, offset, member.offset)?;
+
+        let (ty, field_size) = field_type(member);
+        let field = value_ident(&member.name);
+
+        writeln!(source, "    /// `{}`", member_type_name(member))?;
+        writeln!(source, "    pub {}: {},", field, ty)?;
+
+        offset = member.offset + field_size;
+        fields.push((field, member.offset));
+    }
+
+    if offset > size {
+        return Err(anyhow!(
+            "Members of {} end at {}, past its size of {}.",
+            struct_type.name,
+            offset,
+            size
+        ));
+    }
+
+    pad(source, offset, size)?;
+
+    writeln!(source, "}}")?;
+    writeln!(source)?;
+    writeln!(
+        source,
+        "const _: () = assert!(::core::mem::size_of::<{}>() == {});",
+        name, size
+    )?;
+    writeln!(
+        source,
+        "const _: () = assert!(::core::mem::align_of::<{}>() == {});",
+        name, alignment
+    )?;
+
+    for (field, offset) in fields {
+        writeln!(
+            source,
+            "const _: () = assert!(::core::mem::offset_of!({}, {}) == {});",
+            name, field, offset
+        )?;
+    }
+
+    Ok(())
+}
+
+fn member_type_name(member: &AIRStructMemberReflection) -> String {
+    match member.array_length {
+        0 => member.type_name.clone(),
+        length => format!("{}[{}]", member.type_name, length),
+    }
+}
+
+/// The Rust type of a member and its size. Types without a Rust equivalent
+/// become bytes.
+fn field_type(member: &AIRStructMemberReflection) -> (String, u32) {
+    let count = member.array_length;
+    let stride = member.size / count.max(1);
+
+    let element = match &member.struct_type {
+        Some(nested) => Some((type_ident(&nested.name), stride)),
+        None => AIRType::from_metal_name(&member.type_name)
+            .ok()
+            .map(|ty| rust_type(&ty, count > 0)),
+    };
+
+    match element {
+        Some((ty, size)) if count == 0 && size <= member.size => (ty, size),
+        Some((ty, size)) if count > 0 && size == stride => {
+            (format!("[{}; {}]", ty, count), member.size)
+        }
+        _ => (format!("[u8; {}]", member.size), member.size),
+    }
+}
+
+/// A Metal type in Rust. A lone `float3` is 12 bytes followed by padding,
+/// but arrays of them have a stride of 16, so their elements keep the
+/// fourth component.
+fn rust_type(ty: &AIRType, in_array: bool) -> (String, u32) {
+    let scalar = rust_scalar(ty.scalar());
+    let scalar_size = ty.scalar().size();
+
+    match *ty {
+        AIRType::Scalar(_) => (scalar.to_string(), scalar_size),
+        AIRType::Vector(_, 3) if in_array => (format!("[{}; 4]", scalar), scalar_size * 4),
+        AIRType::Vector(_, count) | AIRType::PackedVector(_, count) => {
+            (format!("[{}; {}]", scalar, count), scalar_size * count)
+        }
+        // Columns are padded like vectors.
+        AIRType::Matrix { columns, rows, .. } => {
+            let rows = if rows == 3 { 4 } else { rows };
+            (
+                format!("[[{}; {}]; {}]", scalar, rows, columns),
+                scalar_size * rows * columns,
+            )
+        }
+    }
+}
+
+/// Rust has no stable `f16` or `bf16`, those are passed as their bits.
+fn rust_scalar(scalar: AIRScalarType) -> &'static str {
+    match scalar {
+        AIRScalarType::Bool => "bool",
+        AIRScalarType::Int {
+            width: 8,
+            signed: true,
+        } => "i8",
+        AIRScalarType::Int { width: 8, .. } => "u8",
+        AIRScalarType::Int {
+            width: 16,
+            signed: true,
+        } => "i16",
+        AIRScalarType::Int { width: 16, .. } => "u16",
+        AIRScalarType::Int {
+            width: 64,
+            signed: true,
+        } => "i64",
+        AIRScalarType::Int { width: 64, .. } => "u64",
+        AIRScalarType::Int { signed: true, .. } => "i32",
+        AIRScalarType::Int { .. } => "u32",
+        AIRScalarType::Float { width: 16 } | AIRScalarType::BFloat => "u16",
+        AIRScalarType::Float { width: 64 } => "f64",
+        AIRScalarType::Float { .. } => "f32",
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    let ident = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    match ident.chars().next() {
+        Some(c) if !c.is_ascii_digit() => ident,
+        _ => format!("_{}", ident),
+    }
+}
+
+/// C++ names like `ns::Light` or `Buffer<float>` turned into a Rust type
+/// name.
+fn type_ident(name: &str) -> String {
+    match sanitize(name.trim_start_matches("struct ")) {
+        ident if ident == "Self" => String::from("Self_"),
+        ident => ident,
+    }
+}
+
+fn value_ident(name: &str) -> String {
+    match sanitize(name) {
+        ident if KEYWORDS.contains(&ident.as_str()) => format!("r#{}", ident),
+        ident if PATH_KEYWORDS.contains(&ident.as_str()) => format!("{}_", ident),
+        ident if ident == "_" => String::from("_unnamed"),
+        ident => ident,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use metalshaper::{AIRAddressSpace, AIRShaderStage, reflection::AIRArgumentReflection};
+
+    use super::*;
+
+    fn member(name: &str, type_name: &str, offset: u32, size: u32) -> AIRStructMemberReflection {
+        AIRStructMemberReflection {
+            name: String::from(name),
+            type_name: String::from(type_name),
+            offset,
+            size,
+            array_length: 0,
+            struct_type: None,
+            argument: None,
+        }
+    }
+
+    fn array(member: AIRStructMemberReflection, length: u32) -> AIRStructMemberReflection {
+        AIRStructMemberReflection {
+            array_length: length,
+            ..member
+        }
+    }
+
+    fn nested(
+        member: AIRStructMemberReflection,
+        struct_type: AIRStructReflection,
+    ) -> AIRStructMemberReflection {
+        AIRStructMemberReflection {
+            struct_type: Some(struct_type),
+            ..member
+        }
+    }
+
+    fn struct_type(name: &str, members: Vec<AIRStructMemberReflection>) -> AIRStructReflection {
+        AIRStructReflection {
+            name: String::from(name),
+            size: None,
+            members,
+        }
+    }
+
+    /// A vertex function taking a pointer to `struct_type` at buffer 0.
+    fn function(
+        struct_type: AIRStructReflection,
+        size: u32,
+        alignment: u32,
+    ) -> AIRFunctionReflection {
+        AIRFunctionReflection {
+            name: String::from("main0"),
+            stage: AIRShaderStage::Vertex,
+            outputs: vec![],
+            arguments: vec![AIRArgumentReflection {
+                index: Some(0),
+                kind: String::from("air.buffer"),
+                name: Some(String::from("uniforms")),
+                type_name: Some(struct_type.name.clone()),
+                location_index: Some(0),
+                address_space: Some(AIRAddressSpace::Constant),
+                type_size: Some(size),
+                type_alignment: Some(alignment),
+                struct_type: Some(struct_type),
+                qualifiers: vec![],
+            }],
+            attributes: vec![],
+        }
+    }
+
+    #[test]
+    fn float3_is_padded_but_packed_float3_is_not() {
+        let uniforms = struct_type(
+            "Uniforms",
+            vec![
+                member("position", "float3", 0, 16),
+                member("normal", "packed_float3", 16, 12),
+                member("scale", "float", 28, 4),
+            ],
+        );
+
+        let source = rust_structs(&[function(uniforms, 32, 16)]).unwrap();
+
+        assert!(source.contains("#[repr(C, align(16))]"));
+        assert!(source.contains(
+            "    pub position: [f32; 3],\n    pub _pad0: [u8; 4],\n    /// `packed_float3`\n    pub normal: [f32; 3],\n"
+        ));
+        assert!(source.contains("size_of::<Uniforms>() == 32"));
+        assert!(source.contains("offset_of!(Uniforms, normal) == 16"));
+        assert!(source.contains("offset_of!(Uniforms, scale) == 28"));
+    }
+
+    #[test]
+    fn nested_structs_are_generated_once() {
+        let light = struct_type(
+            "Light",
+            vec![
+                member("direction", "float2", 0, 8),
+                member("intensity", "float", 8, 4),
+            ],
+        );
+        let scene = struct_type(
+            "Scene",
+            vec![
+                nested(member("sun", "Light", 0, 16), light.clone()),
+                nested(member("moon", "Light", 16, 16), light),
+                member("time", "float", 32, 4),
+            ],
+        );
+
+        let source = rust_structs(&[function(scene, 48, 8)]).unwrap();
+
+        assert_eq!(source.matches("pub struct Light {").count(), 1);
+        assert!(source.contains("size_of::<Light>() == 16"));
+        assert!(source.contains("align_of::<Light>() == 8"));
+        assert!(source.contains("    pub sun: Light,\n"));
+        assert!(source.contains("    pub moon: Light,\n"));
+        assert!(source.contains("    pub _pad0: [u8; 12],\n}"));
+        assert!(source.contains("size_of::<Scene>() == 48"));
+    }
+
+    #[test]
+    fn arrays_keep_their_stride() {
+        let point = struct_type(
+            "Point",
+            vec![member("x", "float", 0, 4), member("y", "float", 4, 4)],
+        );
+        let data = struct_type(
+            "Data",
+            vec![
+                array(member("corners", "float3", 0, 32), 2),
+                array(member("weights", "float", 32, 12), 3),
+                nested(array(member("points", "Point", 44, 16), 2), point),
+                array(member("colors", "packed_float3", 60, 24), 2),
+            ],
+        );
+
+        let source = rust_structs(&[function(data, 96, 16)]).unwrap();
+
+        assert!(source.contains("    /// `float3[2]`\n    pub corners: [[f32; 4]; 2],\n"));
+        assert!(source.contains("    pub weights: [f32; 3],\n"));
+        assert!(source.contains("    pub points: [Point; 2],\n"));
+        assert!(source.contains("    pub colors: [[f32; 3]; 2],\n"));
+        assert!(source.contains("    pub _pad0: [u8; 12],\n}"));
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        let inner = struct_type("Self", vec![member("self", "int", 0, 4)]);
+        let outer = struct_type(
+            "struct ns::Buffer<float>",
+            vec![
+                member("type", "uint", 0, 4),
+                member("2d", "float", 4, 4),
+                member("_", "float", 8, 4),
+                nested(member("inner", "Self", 12, 4), inner),
+            ],
+        );
+
+        let source = rust_structs(&[function(outer, 16, 4)]).unwrap();
+
+        assert!(source.contains("pub struct ns__Buffer_float_ {"));
+        assert!(source.contains("pub struct Self_ {"));
+        assert!(source.contains("    pub r#type: u32,\n"));
+        assert!(source.contains("    pub _2d: f32,\n"));
+        assert!(source.contains("    pub _unnamed: f32,\n"));
+        assert!(source.contains("    pub self_: i32,\n"));
+        assert!(source.contains("    pub inner: Self_,\n"));
+        assert!(source.contains("offset_of!(ns__Buffer_float_, r#type) == 0"));
+    }
+
+    #[test]
+    fn conflicting_layouts_are_rejected() {
+        let first = struct_type("Light", vec![member("intensity", "float", 0, 4)]);
+        let second = struct_type("Light", vec![member("intensity", "half", 0, 2)]);
+
+        assert!(rust_structs(&[function(first, 4, 4), function(second, 2, 2)]).is_err());
+        assert!(
+            rust_structs(&[])
+                .unwrap()
+                .starts_with("// Generated by rosemetal-build")
+        );
+    }
+}
@@ -0,0 +1,868 @@
+//! (Vulkan) Sub-allocation of device memory. Drivers allow few
+//! `vkAllocateMemory` calls and make them slow, so resources share large
+//! blocks instead.
+
+use std::{
+    ptr::NonNull,
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::BMLError;
+
+/// Blocks on heaps larger than 1 GiB, smaller heaps use an eighth of their
+/// size.
+const BLOCK_SIZE: u64 = 256 << 20;
+
+/// How long an allocation is expected to live.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum VulkanAllocationLifetime {
+    /// Freed in any order, allocated with TLSF.
+    #[default]
+    Long,
+    /// Freed soon, like staging memory. Allocated linearly from blocks that
+    /// are reused once everything in them is freed.
+    Transient,
+}
+
+/// Linear resources and optimally tiled images can't share a page of
+/// `bufferImageGranularity` bytes, so they don't share blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VulkanResourceTiling {
+    Linear,
+    Optimal,
+}
+
+/// The resource a dedicated allocation is made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VulkanDedicatedResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+pub struct VulkanAllocationRequest {
+    pub requirements: vk::MemoryRequirements,
+    pub memory_type: u32,
+    pub tiling: VulkanResourceTiling,
+    pub lifetime: VulkanAllocationLifetime,
+    pub resource: Option<VulkanDedicatedResource>,
+    /// `VkMemoryDedicatedRequirements` prefers or requires a dedicated
+    /// allocation.
+    pub dedicated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VulkanAllocatorStats {
+    /// `VkDeviceMemory` objects, blocks and dedicated allocations.
+    pub memory_count: u32,
+    /// Bytes of all `VkDeviceMemory` objects.
+    pub allocated_size: u64,
+    /// Bytes handed out to resources.
+    pub used_size: u64,
+}
+
+/// A range of device memory, mapped if it's host visible.
+#[derive(Debug)]
+pub struct VulkanAllocation {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    offset: u64,
+    size: u64,
+    mapped: Option<NonNull<u8>>,
+    coherent: bool,
+    source: VulkanAllocationSource,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum VulkanAllocationSource {
+    Dedicated,
+    Block {
+        pool: usize,
+        block: u64,
+        node: usize,
+    },
+    Linear {
+        pool: usize,
+        block: u64,
+    },
+}
+
+// Only a handle, the pointer is valid on any thread.
+unsafe impl Send for VulkanAllocation {}
+unsafe impl Sync for VulkanAllocation {}
+
+impl VulkanAllocation {
+    pub fn memory(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+
+    pub fn memory_type(&self) -> u32 {
+        self.memory_type
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// At least the requested size.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The start of the allocation, `None` unless it's host visible.
+    pub fn mapped(&self) -> Option<NonNull<u8>> {
+        self.mapped
+    }
+
+    /// Whether writes are visible without flushing. Otherwise offset and
+    /// size are multiples of `nonCoherentAtomSize`, so flushing the
+    /// allocation never touches its neighbours.
+    pub fn coherent(&self) -> bool {
+        self.coherent
+    }
+}
+
+pub struct VulkanAllocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: u64,
+    buffer_image_granularity: u64,
+    max_memory_allocation_count: u32,
+    /// `VkMemoryDedicatedAllocateInfo` is core since Vulkan 1.1.
+    dedicated_allocation: bool,
+    /// Memory is allocated with `DEVICE_ADDRESS`, so buffers bound to it
+    /// have addresses argument buffers can point at.
+    device_address: bool,
+    /// Two per memory type, for linear and optimal resources.
+    pools: Vec<Mutex<VulkanMemoryPool>>,
+    next_block: AtomicU64,
+    memory_count: AtomicU32,
+    allocated_size: AtomicU64,
+    used_size: AtomicU64,
+}
+
+impl VulkanAllocator {
+    pub fn new(
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        limits: &vk::PhysicalDeviceLimits,
+        dedicated_allocation: bool,
+        device_address: bool,
+    ) -> Self {
+        Self {
+            memory_properties,
+            non_coherent_atom_size: limits.non_coherent_atom_size.max(1),
+            buffer_image_granularity: limits.buffer_image_granularity.max(1),
+            max_memory_allocation_count: limits.max_memory_allocation_count,
+            dedicated_allocation,
+            device_address,
+            pools: (0..memory_properties.memory_type_count * 2)
+                .map(|_| Mutex::new(VulkanMemoryPool::default()))
+                .collect(),
+            next_block: AtomicU64::new(0),
+            memory_count: AtomicU32::new(0),
+            allocated_size: AtomicU64::new(0),
+            used_size: AtomicU64::new(0),
+        }
+    }
+
+    pub fn dedicated_allocation(&self) -> bool {
+        self.dedicated_allocation
+    }
+
+    pub fn stats(&self) -> VulkanAllocatorStats {
+        VulkanAllocatorStats {
+            memory_count: self.memory_count.load(Ordering::Relaxed),
+            allocated_size: self.allocated_size.load(Ordering::Relaxed),
+            used_size: self.used_size.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn allocate(
+        &self,
+        device: &ash::Device,
+        request: &VulkanAllocationRequest,
+    ) -> Result<VulkanAllocation> {
+        let flags =
+            self.memory_properties.memory_types[request.memory_type as usize].property_flags;
+        let host_visible = flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let coherent = !host_visible || flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+
+        let mut alignment = request.requirements.alignment.max(1);
+        let mut size = request.requirements.size.max(1);
+
+        if !coherent {
+            alignment = alignment.max(self.non_coherent_atom_size);
+            size = size.next_multiple_of(self.non_coherent_atom_size);
+        }
+
+        let block_size = self.block_size(request.memory_type);
+
+        if request.dedicated || size > block_size / 2 {
+            let (memory, mapped) =
+                self.allocate_memory(device, request.memory_type, size, request.resource)?;
+
+            self.used_size.fetch_add(size, Ordering::Relaxed);
+
+            return Ok(VulkanAllocation {
+                memory,
+                memory_type: request.memory_type,
+                offset: 0,
+                size,
+                mapped,
+                coherent,
+                source: VulkanAllocationSource::Dedicated,
+            });
+        }
+
+        let optimal =
+            request.tiling == VulkanResourceTiling::Optimal && self.buffer_image_granularity > 1;
+        let pool_index = request.memory_type as usize * 2 + optimal as usize;
+        let mut pool = self.pools[pool_index]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let linear = request.lifetime == VulkanAllocationLifetime::Transient;
+
+        let found = pool
+            .blocks
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, block)| block.is_linear() == linear)
+            .find_map(|(index, block)| Some((index, block.allocate(size, alignment)?)));
+
+        let (index, (node, offset)) = match found {
+            Some(found) => found,
+            None => {
+                let (memory, mapped) =
+                    self.allocate_memory(device, request.memory_type, block_size, None)?;
+
+                let mut block = VulkanMemoryBlock {
+                    id: self.next_block.fetch_add(1, Ordering::Relaxed),
+                    memory,
+                    size: block_size,
+                    mapped,
+                    allocator: match linear {
+                        true => VulkanBlockAllocator::Linear { offset: 0, live: 0 },
+                        false => VulkanBlockAllocator::Tlsf(Box::new(VulkanTlsf::new(block_size))),
+                    },
+                };
+
+                // The block stays in the pool either way, so its memory is
+                // reused or freed with the pool.
+                let allocation = block.allocate(size, alignment);
+                pool.blocks.push(block);
+
+                let allocation = allocation.ok_or(BMLError::OutOfDeviceMemory)?;
+
+                (pool.blocks.len() - 1, allocation)
+            }
+        };
+
+        let block = &pool.blocks[index];
+
+        self.used_size.fetch_add(size, Ordering::Relaxed);
+
+        Ok(VulkanAllocation {
+            memory: block.memory,
+            memory_type: request.memory_type,
+            offset,
+            size,
+            mapped: block
+                .mapped
+                .map(|mapped| unsafe { mapped.add(offset as usize) }),
+            coherent,
+            source: match linear {
+                true => VulkanAllocationSource::Linear {
+                    pool: pool_index,
+                    block: block.id,
+                },
+                false => VulkanAllocationSource::Block {
+                    pool: pool_index,
+                    block: block.id,
+                    node,
+                },
+            },
+        })
+    }
+
+    /// Returns `allocation` to its block. Blocks are freed once empty,
+    /// except for one per pool to allocate from next.
+    ///
+    /// # Safety
+    ///
+    /// The GPU must be done with the allocation.
+    pub unsafe fn free(&self, device: &ash::Device, allocation: VulkanAllocation) {
+        self.used_size.fetch_sub(allocation.size, Ordering::Relaxed);
+
+        let (pool_index, block_id) = match allocation.source {
+            VulkanAllocationSource::Dedicated => {
+                unsafe { self.free_memory(device, allocation.memory, allocation.size) };
+                return;
+            }
+            VulkanAllocationSource::Block { pool, block, .. }
+            | VulkanAllocationSource::Linear { pool, block } => (pool, block),
+        };
+
+        let mut pool = self.pools[pool_index]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let Some(index) = pool.blocks.iter().position(|block| block.id == block_id) else {
+            return;
+        };
+
+        match (&mut pool.blocks[index].allocator, allocation.source) {
+            (VulkanBlockAllocator::Tlsf(tlsf), VulkanAllocationSource::Block { node, .. }) => {
+                tlsf.free(node)
+            }
+            (VulkanBlockAllocator::Linear { offset, live }, _) => {
+                *live -= 1;
+
+                if *live == 0 {
+                    *offset = 0;
+                }
+            }
+            _ => unreachable!("Allocations come from blocks of their kind."),
+        }
+
+        let block = &pool.blocks[index];
+        let other_empty = pool
+            .blocks
+            .iter()
+            .any(|other| other.id != block.id && other.is_empty());
+
+        if block.is_empty() && other_empty {
+            let block = pool.blocks.swap_remove(index);
+            unsafe { self.free_memory(device, block.memory, block.size) };
+        }
+    }
+
+    /// Frees every block, when the device goes away.
+    ///
+    /// # Safety
+    ///
+    /// Nothing allocated may be used anymore.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for pool in &self.pools {
+            let mut pool = pool.lock().unwrap_or_else(|e| e.into_inner());
+
+            for block in pool.blocks.drain(..) {
+                unsafe { self.free_memory(device, block.memory, block.size) };
+            }
+        }
+    }
+
+    fn block_size(&self, memory_type: u32) -> u64 {
+        let heap_index = self.memory_properties.memory_types[memory_type as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+
+        match heap_size > 1 << 30 {
+            true => BLOCK_SIZE,
+            false => (heap_size / 8).next_multiple_of(1 << 20),
+        }
+    }
+
+    fn allocate_memory(
+        &self,
+        device: &ash::Device,
+        memory_type: u32,
+        size: u64,
+        resource: Option<VulkanDedicatedResource>,
+    ) -> Result<(vk::DeviceMemory, Option<NonNull<u8>>)> {
+        if self.memory_count.load(Ordering::Relaxed) >= self.max_memory_allocation_count {
+            return Err(BMLError::OutOfDeviceMemory.into());
+        }
+
+        let mut dedicated_info = match resource {
+            Some(VulkanDedicatedResource::Buffer(buffer)) => {
+                vk::MemoryDedicatedAllocateInfo::default().buffer(buffer)
+            }
+            Some(VulkanDedicatedResource::Image(image)) => {
+                vk::MemoryDedicatedAllocateInfo::default().image(image)
+            }
+            None => vk::MemoryDedicatedAllocateInfo::default(),
+        };
+
+        let mut allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+
+        if resource.is_some() && self.dedicated_allocation {
+            allocate_info = allocate_info.push_next(&mut dedicated_info);
+        }
+
+        let mut flags_info =
+            vk::MemoryAllocateFlagsInfo::default().flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+
+        if self.device_address {
+            allocate_info = allocate_info.push_next(&mut flags_info);
+        }
+
+        let memory = unsafe { device.allocate_memory(&allocate_info, None)? };
+
+        let flags = self.memory_properties.memory_types[memory_type as usize].property_flags;
+        let mapped = match flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            true => unsafe {
+                match device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) {
+                    Ok(pointer) => NonNull::new(pointer.cast::<u8>()),
+                    Err(e) => {
+                        device.free_memory(memory, None);
+                        return Err(e.into());
+                    }
+                }
+            },
+            false => None,
+        };
+
+        self.memory_count.fetch_add(1, Ordering::Relaxed);
+        self.allocated_size.fetch_add(size, Ordering::Relaxed);
+
+        Ok((memory, mapped))
+    }
+
+    /// Unmaps the memory too.
+    unsafe fn free_memory(&self, device: &ash::Device, memory: vk::DeviceMemory, size: u64) {
+        unsafe { device.free_memory(memory, None) };
+
+        self.memory_count.fetch_sub(1, Ordering::Relaxed);
+        self.allocated_size.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct VulkanMemoryPool {
+    blocks: Vec<VulkanMemoryBlock>,
+}
+
+/// One `VkDeviceMemory`, mapped as a whole if it's host visible.
+struct VulkanMemoryBlock {
+    id: u64,
+    memory: vk::DeviceMemory,
+    size: u64,
+    mapped: Option<NonNull<u8>>,
+    allocator: VulkanBlockAllocator,
+}
+
+// Only touched with the pool locked.
+unsafe impl Send for VulkanMemoryBlock {}
+
+enum VulkanBlockAllocator {
+    Tlsf(Box<VulkanTlsf>),
+    /// Bumps `offset`, which goes back to 0 once nothing is `live`.
+    Linear {
+        offset: u64,
+        live: usize,
+    },
+}
+
+impl VulkanMemoryBlock {
+    fn is_linear(&self) -> bool {
+        matches!(self.allocator, VulkanBlockAllocator::Linear { .. })
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.allocator {
+            VulkanBlockAllocator::Tlsf(tlsf) => tlsf.used == 0,
+            VulkanBlockAllocator::Linear { live, .. } => *live == 0,
+        }
+    }
+
+    /// The TLSF node, 0 for linear blocks, and the offset.
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<(usize, u64)> {
+        match &mut self.allocator {
+            VulkanBlockAllocator::Tlsf(tlsf) => tlsf.allocate(size, alignment),
+            VulkanBlockAllocator::Linear { offset, live } => {
+                let start = offset.next_multiple_of(alignment);
+
+                if start.checked_add(size)? > self.size {
+                    return None;
+                }
+
+                *offset = start + size;
+                *live += 1;
+
+                Some((0, start))
+            }
+        }
+    }
+}
+
+/// Second level classes per power of two.
+const SL_LOG: u32 = 4;
+const SL_COUNT: usize = 1 << SL_LOG;
+const FL_COUNT: usize = 64;
+
+/// Two-level segregated fit over a range of offsets: free ranges are kept
+/// in lists by size class, found through two bitmaps in constant time, and
+/// merged with free neighbours when freed. Also places resources in
+/// automatic `MTLHeap`s.
+pub(crate) struct VulkanTlsf {
+    nodes: Vec<VulkanTlsfNode>,
+    unused_nodes: Vec<usize>,
+    fl_bitmap: u64,
+    sl_bitmaps: [u32; FL_COUNT],
+    heads: [[Option<usize>; SL_COUNT]; FL_COUNT],
+    used: u64,
+}
+
+#[derive(Clone, Copy)]
+struct VulkanTlsfNode {
+    offset: u64,
+    size: u64,
+    free: bool,
+    prev_phys: Option<usize>,
+    next_phys: Option<usize>,
+    prev_free: Option<usize>,
+    next_free: Option<usize>,
+}
+
+impl VulkanTlsf {
+    pub(crate) fn new(size: u64) -> Self {
+        let mut tlsf = Self {
+            nodes: vec![],
+            unused_nodes: vec![],
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            heads: [[None; SL_COUNT]; FL_COUNT],
+            used: 0,
+        };
+
+        let node = tlsf.new_node(0, size);
+        tlsf.insert(node);
+
+        tlsf
+    }
+
+    /// The size class of `size`.
+    fn mapping(size: u64) -> (usize, usize) {
+        if size < SL_COUNT as u64 {
+            return (0, size as usize);
+        }
+
+        let log = 63 - size.leading_zeros();
+
+        (
+            (log - SL_LOG + 1) as usize,
+            (size >> (log - SL_LOG)) as usize - SL_COUNT,
+        )
+    }
+
+    /// The first size class whose ranges all fit `size`.
+    fn search_mapping(size: u64) -> (usize, usize) {
+        if size < SL_COUNT as u64 {
+            return Self::mapping(size);
+        }
+
+        let log = 63 - size.leading_zeros();
+
+        Self::mapping(size.saturating_add((1 << (log - SL_LOG)) - 1))
+    }
+
+    fn new_node(&mut self, offset: u64, size: u64) -> usize {
+        let node = VulkanTlsfNode {
+            offset,
+            size,
+            free: true,
+            prev_phys: None,
+            next_phys: None,
+            prev_free: None,
+            next_free: None,
+        };
+
+        match self.unused_nodes.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn insert(&mut self, index: usize) {
+        let (fl, sl) = Self::mapping(self.nodes[index].size);
+        let head = self.heads[fl][sl];
+
+        self.nodes[index].free = true;
+        self.nodes[index].prev_free = None;
+        self.nodes[index].next_free = head;
+
+        if let Some(head) = head {
+            self.nodes[head].prev_free = Some(index);
+        }
+
+        self.heads[fl][sl] = Some(index);
+        self.sl_bitmaps[fl] |= 1 << sl;
+        self.fl_bitmap |= 1 << fl;
+    }
+
+    fn remove(&mut self, index: usize) {
+        let (fl, sl) = Self::mapping(self.nodes[index].size);
+        let VulkanTlsfNode {
+            prev_free,
+            next_free,
+            ..
+        } = self.nodes[index];
+
+        match prev_free {
+            Some(prev) => self.nodes[prev].next_free = next_free,
+            None => self.heads[fl][sl] = next_free,
+        }
+
+        if let Some(next) = next_free {
+            self.nodes[next].prev_free = prev_free;
+        }
+
+        if self.heads[fl][sl].is_none() {
+            self.sl_bitmaps[fl] &= !(1 << sl);
+
+            if self.sl_bitmaps[fl] == 0 {
+                self.fl_bitmap &= !(1 << fl);
+            }
+        }
+    }
+
+    fn find(&self, size: u64) -> Option<usize> {
+        let (mut fl, sl) = Self::search_mapping(size);
+        let mut sl_map = self.sl_bitmaps.get(fl)? & (u32::MAX << sl);
+
+        if sl_map == 0 {
+            let fl_map = match fl + 1 < FL_COUNT {
+                true => self.fl_bitmap & (u64::MAX << (fl + 1)),
+                false => 0,
+            };
+
+            if fl_map == 0 {
+                return None;
+            }
+
+            fl = fl_map.trailing_zeros() as usize;
+            sl_map = self.sl_bitmaps[fl];
+        }
+
+        self.heads[fl][sl_map.trailing_zeros() as usize]
+    }
+
+    /// Splits the range after `index` off at `size` bytes into it, as a
+    /// free node.
+    fn split(&mut self, index: usize, size: u64) -> usize {
+        let node = self.nodes[index];
+        let rest = self.new_node(node.offset + size, node.size - size);
+
+        self.nodes[rest].prev_phys = Some(index);
+        self.nodes[rest].next_phys = node.next_phys;
+
+        if let Some(next) = node.next_phys {
+            self.nodes[next].prev_phys = Some(rest);
+        }
+
+        self.nodes[index].size = size;
+        self.nodes[index].next_phys = Some(rest);
+
+        rest
+    }
+
+    /// Grows `index` over `next` right after it, which is in no list.
+    fn absorb(&mut self, index: usize, next: usize) {
+        let next_node = self.nodes[next];
+
+        self.nodes[index].size += next_node.size;
+        self.nodes[index].next_phys = next_node.next_phys;
+
+        if let Some(after) = next_node.next_phys {
+            self.nodes[after].prev_phys = Some(index);
+        }
+
+        self.nodes[next].free = false;
+        self.unused_nodes.push(next);
+    }
+
+    /// The most bytes aligned to `alignment` a single `allocate` can get.
+    pub(crate) fn max_available(&self, alignment: u64) -> u64 {
+        self.nodes
+            .iter()
+            .filter(|node| node.free)
+            .map(|node| {
+                let padding = node.offset.next_multiple_of(alignment) - node.offset;
+                node.size.saturating_sub(padding)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The node and offset of `size` bytes aligned to `alignment`.
+    pub(crate) fn allocate(&mut self, size: u64, alignment: u64) -> Option<(usize, u64)> {
+        // Enough for the worst case of padding.
+        let mut index = self.find(size.checked_add(alignment - 1)?)?;
+        self.remove(index);
+
+        let offset = self.nodes[index].offset;
+        let padding = offset.next_multiple_of(alignment) - offset;
+
+        // The node before a free one is never free, so the padding can't
+        // be merged.
+        if padding > 0 {
+            let aligned = self.split(index, padding);
+            self.insert(index);
+            index = aligned;
+        }
+
+        if self.nodes[index].size > size {
+            let rest = self.split(index, size);
+            self.insert(rest);
+        }
+
+        self.nodes[index].free = false;
+        self.used += size;
+
+        Some((index, self.nodes[index].offset))
+    }
+
+    pub(crate) fn free(&mut self, mut index: usize) {
+        self.used -= self.nodes[index].size;
+
+        if let Some(prev) = self.nodes[index].prev_phys
+            && self.nodes[prev].free
+        {
+            self.remove(prev);
+            self.absorb(prev, index);
+            index = prev;
+        }
+
+        if let Some(next) = self.nodes[index].next_phys
+            && self.nodes[next].free
+        {
+            self.remove(next);
+            self.absorb(index, next);
+        }
+
+        self.insert(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear_block(size: u64) -> VulkanMemoryBlock {
+        VulkanMemoryBlock {
+            id: 0,
+            memory: vk::DeviceMemory::null(),
+            size,
+            mapped: None,
+            allocator: VulkanBlockAllocator::Linear { offset: 0, live: 0 },
+        }
+    }
+
+    #[test]
+    fn tlsf_splits_and_merges() {
+        let mut tlsf = VulkanTlsf::new(1024);
+
+        let (a, a_offset) = tlsf.allocate(256, 1).unwrap();
+        let (b, b_offset) = tlsf.allocate(256, 1).unwrap();
+        let (c, c_offset) = tlsf.allocate(256, 1).unwrap();
+        assert_eq!((a_offset, b_offset, c_offset), (0, 256, 512));
+        assert_eq!(tlsf.used, 768);
+        assert_eq!(tlsf.max_available(1), 256);
+
+        // Freeing the middle leaves two holes that can't hold 512 bytes,
+        // freeing its neighbour merges them.
+        tlsf.free(b);
+        assert_eq!(tlsf.max_available(1), 256);
+        assert!(tlsf.allocate(512, 1).is_none());
+
+        tlsf.free(a);
+        assert_eq!(tlsf.max_available(1), 512);
+
+        let (d, d_offset) = tlsf.allocate(512, 1).unwrap();
+        assert_eq!(d_offset, 0);
+
+        tlsf.free(c);
+        tlsf.free(d);
+        assert_eq!(tlsf.used, 0);
+    }
+
+    #[test]
+    fn tlsf_aligns_offsets() {
+        let mut tlsf = VulkanTlsf::new(4096);
+
+        let (_, offset) = tlsf.allocate(100, 1).unwrap();
+        assert_eq!(offset, 0);
+
+        let (padded, offset) = tlsf.allocate(100, 256).unwrap();
+        assert_eq!(offset, 256);
+        assert_eq!(tlsf.max_available(1024), 3072);
+
+        // The padding before it is free again and gets reused.
+        let (_, offset) = tlsf.allocate(100, 4).unwrap();
+        assert!(offset >= 100 && offset + 100 <= 256);
+        assert_eq!(offset % 4, 0);
+
+        tlsf.free(padded);
+        assert_eq!(tlsf.used, 200);
+    }
+
+    #[test]
+    fn tlsf_frees_whole_block() {
+        let mut tlsf = VulkanTlsf::new(1 << 20);
+
+        let nodes = (0..64)
+            .map(|i| tlsf.allocate(1000 + i * 17, 64).unwrap().0)
+            .collect::<Vec<_>>();
+
+        // Free out of order, so merges happen on both sides.
+        for node in nodes
+            .iter()
+            .step_by(2)
+            .chain(nodes.iter().skip(1).step_by(2))
+        {
+            tlsf.free(*node);
+        }
+
+        assert_eq!(tlsf.used, 0);
+        assert_eq!(tlsf.max_available(1), 1 << 20);
+        assert_eq!(tlsf.allocate(1 << 20, 1).unwrap().1, 0);
+    }
+
+    #[test]
+    fn tlsf_runs_out_of_space() {
+        let mut tlsf = VulkanTlsf::new(1024);
+
+        assert!(tlsf.allocate(1025, 1).is_none());
+        assert!(tlsf.allocate(u64::MAX, 1).is_none());
+        assert!(tlsf.allocate(1024, 1).is_some());
+        assert!(tlsf.allocate(1, 1).is_none());
+        assert_eq!(tlsf.max_available(1), 0);
+    }
+
+    #[test]
+    fn linear_block_bumps_and_resets() {
+        let mut block = linear_block(1024);
+
+        assert_eq!(block.allocate(100, 1), Some((0, 0)));
+        assert_eq!(block.allocate(100, 256), Some((0, 256)));
+        assert_eq!(block.allocate(600, 1), Some((0, 356)));
+        assert!(!block.is_empty());
+
+        // Freed space isn't reused until the block is empty.
+        assert_eq!(block.allocate(100, 1), None);
+        assert_eq!(block.allocate(u64::MAX, 1), None);
+
+        let VulkanBlockAllocator::Linear { offset, live } = &mut block.allocator else {
+            unreachable!()
+        };
+        assert_eq!((*offset, *live), (956, 3));
+
+        // What `VulkanAllocator::free` does for the last live allocation.
+        *live = 0;
+        *offset = 0;
+        assert!(block.is_empty());
+        assert_eq!(block.allocate(1024, 1), Some((0, 0)));
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    io::Write as _,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use anyhow::{Context, Result, anyhow};
+use rosemetal::metalshaper::{
+    apple_ir::{
+        AIRGlobalValueKind, AIRItem, AIRModule, parse_apple_ir, parse_bitstream, split_apple_ir,
+    },
+    metallib::{AIRLibrary, parse_modules},
+    reflection::{AIRFunctionReflection, reflection_json},
+    translate::{SPIRVTranslationOptions, translate},
+};
+
+const USAGE: &str = "\
+Usage: rosemetal-shaderc <command> <input.air|input.metallib> [options]
+
+Commands:
+  dump          Print the container and bitstream structure
+  functions     List the functions of a metallib, or the entry points of a module
+  reflect       Print the interface of every entry point as JSON
+  disassemble   Write LLVM assembly, using llvm-dis from $LLVM_DIS or PATH
+  translate     Translate an entry point to SPIR-V
+
+Options:
+  -o, --output <path>       Where to write the result, stdout by default
+  -f, --function <name>     The function to disassemble or translate
+  -h, --help                Print this message";
+
+struct Options {
+    command: String,
+    input: PathBuf,
+    output: Option<PathBuf>,
+    function: Option<String>,
+}
+
+impl Options {
+    fn parse() -> Result<Option<Self>> {
+        let mut args = env::args().skip(1);
+
+        let mut positional = vec![];
+        let mut output = None;
+        let mut function = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-o" | "--output" => {
+                    output = Some(PathBuf::from(
+                        args.next()
+                            .ok_or_else(|| anyhow!("{} needs a path.", arg))?,
+                    ))
+                }
+                "-f" | "--function" => {
+                    function = Some(
+                        args.next()
+                            .ok_or_else(|| anyhow!("{} needs a name.", arg))?,
+                    )
+                }
+                flag if flag.starts_with('-') => return Err(anyhow!("Unknown option {}.", flag)),
+                _ => positional.push(arg),
+            }
+        }
+
+        match <[String; 2]>::try_from(positional) {
+            Ok([command, input]) => Ok(Some(Self {
+                command,
+                input: PathBuf::from(input),
+                output,
+                function,
+            })),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn write_output(&self, bytes: &[u8]) -> Result<()> {
+        match &self.output {
+            Some(path) => {
+                fs::write(path, bytes).with_context(|| format!("Writing {}", path.display()))
+            }
+            None => Ok(std::io::stdout().write_all(bytes)?),
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let Some(options) = Options::parse()? else {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    };
+
+    let content =
+        fs::read(&options.input).with_context(|| format!("Reading {}", options.input.display()))?;
+
+    match options.command.as_str() {
+        "dump" => options.write_output(dump(&content)?.as_bytes()),
+        "functions" => options.write_output(functions(&content)?.as_bytes()),
+        "reflect" => {
+            let reflection = parse_modules(&content)?
+                .iter()
+                .map(AIRFunctionReflection::from_module)
+                .collect::<Result<Vec<_>>>()?
+                .concat();
+
+            options.write_output(format!("{}\n", reflection_json(&reflection)).as_bytes())
+        }
+        "disassemble" => disassemble(&content, &options),
+        "translate" => {
+            let (name, module) = entry_point(&content, options.function.as_deref())?;
+            let words = translate(&module, &name, &SPIRVTranslationOptions::default())?;
+
+            options.write_output(
+                &words
+                    .iter()
+                    .flat_map(|word| word.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            )
+        }
+        command => Err(anyhow!("Unknown command {}.\n\n{}", command, USAGE)),
+    }
+}
+
+/// The module and name of the entry point to translate, which may be left
+/// out if there is only one.
+fn entry_point(content: &[u8], function: Option<&str>) -> Result<(String, AIRModule)> {
+    let modules = parse_modules(content)?;
+    let mut candidates = vec![];
+
+    for (index, module) in modules.iter().enumerate() {
+        for reflection in AIRFunctionReflection::from_module(module)? {
+            if function.is_none_or(|name| reflection.name == name) {
+                candidates.push((reflection.name, index));
+            }
+        }
+    }
+
+    let (name, index) = match (candidates.as_slice(), function) {
+        ([candidate], _) => candidate.clone(),
+        ([], Some(name)) => return Err(anyhow!("No entry point named {}.", name)),
+        ([], None) => return Err(anyhow!("Input has no entry points.")),
+        (_, _) => {
+            let names = candidates
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+
+            return Err(anyhow!(
+                "Input has several entry points, pick one with --function: {}.",
+                names.join(", ")
+            ));
+        }
+    };
+
+    let module = modules
+        .into_iter()
+        .nth(index)
+        .ok_or_else(|| anyhow!("No entry point named {}.", name))?;
+
+    Ok((name, module))
+}
+
+fn functions(content: &[u8]) -> Result<String> {
+    let mut out = String::new();
+
+    if AIRLibrary::is_metallib(content) {
+        for function in AIRLibrary::parse(content)?.functions {
+            let version = match (function.air_version, function.language_version) {
+                (Some(air), Some(language)) => format!("{}, {}", language, air),
+                _ => String::from("unknown version"),
+            };
+
+            writeln!(
+                out,
+                "{:?} {} ({}, {} bytes)",
+                function.ty,
+                function.name,
+                version,
+                function.bitcode.len()
+            )?;
+        }
+
+        return Ok(out);
+    }
+
+    let module = parse_apple_ir(content)?;
+    let entry_points = AIRFunctionReflection::from_module(&module)?;
+
+    for global in module.global_values()? {
+        if global.kind != AIRGlobalValueKind::Function || global.is_declaration {
+            continue;
+        }
+
+        match entry_points.iter().find(|f| f.name == global.name) {
+            Some(entry_point) => writeln!(out, "{:?} {}", entry_point.stage, global.name)?,
+            None => writeln!(out, "Function {}", global.name)?,
+        }
+    }
+
+    Ok(out)
+}
+
+/// Dumps the raw structure, without checking versions, so unsupported files
+/// can be looked at too.
+fn dump(content: &[u8]) -> Result<String> {
+    let mut out = String::new();
+
+    if !AIRLibrary::is_metallib(content) {
+        dump_module(content, &mut out)?;
+        return Ok(out);
+    }
+
+    let library = AIRLibrary::parse(content)?;
+
+    writeln!(
+        out,
+        "metallib {}.{}, platform {:#06x}, type {}, OS {} {}.{}",
+        library.version.0,
+        library.version.1,
+        library.platform,
+        library.library_type,
+        library.os,
+        library.os_version.0,
+        library.os_version.1
+    )?;
+
+    for function in &library.functions {
+        writeln!(
+            out,
+            "\n{:?} {} at {:#x}..{:#x}",
+            function.ty, function.name, function.bitcode.start, function.bitcode.end
+        )?;
+        dump_module(&content[function.bitcode.clone()], &mut out)?;
+    }
+
+    Ok(out)
+}
+
+fn dump_module(content: &[u8], out: &mut String) -> Result<()> {
+    let (signature, bitstream) = split_apple_ir(content)?;
+
+    if let Some(signature) = signature {
+        writeln!(
+            out,
+            "wrapper version {}, offset {}, size {}, CPU type {:#x}",
+            signature.version, signature.offset, signature.size, signature.cpu_type
+        )?;
+    }
+
+    match parse_apple_ir(content) {
+        Ok(module) => writeln!(
+            out,
+            "producer {:?}, epoch {}, {}{}, {:?} pointers, {}",
+            module.version.identification.producer,
+            module.version.identification.epoch,
+            module.version.language,
+            module
+                .version
+                .air
+                .map(|air| format!(", {}", air))
+                .unwrap_or_default(),
+            module.version.pointers,
+            module.version.triple
+        )?,
+        Err(e) => writeln!(out, "unsupported: {:#}", e)?,
+    }
+
+    dump_items(&parse_bitstream(bitstream)?, 0, out)
+}
+
+fn dump_items(items: &[AIRItem], depth: usize, out: &mut String) -> Result<()> {
+    let indent = "  ".repeat(depth);
+
+    for item in items {
+        match item {
+            AIRItem::Block(block) => {
+                writeln!(
+                    out,
+                    "{}{:?} block, abbreviation width {}, {} words",
+                    indent, block.ty, block.abbreviation_width, block.block_length
+                )?;
+                dump_items(&block.items, depth + 1, out)?;
+            }
+            AIRItem::Abbreviation(abbreviation) => {
+                writeln!(out, "{}abbreviation {:?}", indent, abbreviation.operands)?
+            }
+            AIRItem::Record(record) => {
+                write!(out, "{}record {}", indent, record.code)?;
+
+                if let Some(id) = record.abbreviation {
+                    write!(out, " (abbreviation {})", id)?;
+                }
+
+                let printable = record.operands.len() > 1
+                    && record.operands.iter().all(|c| (0x20..0x7f).contains(c));
+
+                if printable {
+                    write!(out, " {:?}", record.string())?;
+                } else if record.operands.len() > 16 {
+                    write!(
+                        out,
+                        " {:?}... ({} operands)",
+                        &record.operands[..16],
+                        record.operands.len()
+                    )?;
+                } else {
+                    write!(out, " {:?}", record.operands)?;
+                }
+
+                if let Some(blob) = &record.blob {
+                    write!(out, ", blob of {} bytes", blob.len())?;
+                }
+
+                writeln!(out)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Hands the bitcode to `llvm-dis`, which reads AIR as long as it's new
+/// enough for the module's bitcode.
+fn disassemble(content: &[u8], options: &Options) -> Result<()> {
+    let bitcode = match AIRLibrary::is_metallib(content) {
+        false => content.to_vec(),
+        true => {
+            let library = AIRLibrary::parse(content)?;
+            let function = match (&options.function, library.functions.as_slice()) {
+                (Some(name), _) => library
+                    .function(name)
+                    .ok_or_else(|| anyhow!("Metallib has no function named {}.", name))?,
+                (None, [function]) => function,
+                (None, _) => {
+                    return Err(anyhow!(
+                        "Metallib has several functions, pick one with --function."
+                    ));
+                }
+            };
+
+            content[function.bitcode.clone()].to_vec()
+        }
+    };
+
+    let llvm_dis = env::var_os("LLVM_DIS").unwrap_or_else(|| "llvm-dis".into());
+    let output = options.output.clone().unwrap_or_else(|| PathBuf::from("-"));
+
+    let mut child = Command::new(&llvm_dis)
+        .arg("-o")
+        .arg(&output)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| {
+            format!(
+                "Running {}, set LLVM_DIS to use another one",
+                llvm_dis.to_string_lossy()
+            )
+        })?;
+
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("llvm-dis has no stdin."))?
+        .write_all(&bitcode)?;
+
+    let status = child.wait()?;
+
+    if !status.success() {
+        return Err(anyhow!(
+            "{} failed with {}.",
+            llvm_dis.to_string_lossy(),
+            status
+        ));
+    }
+
+    Ok(())
+}
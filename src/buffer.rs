@@ -0,0 +1,814 @@
+use std::{
+    ops::Range,
+    ptr::NonNull,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::Result;
+
+use crate::{
+    BMLError, MTLDevice, MTLHeap, MTLHeapDescriptor, MTLHeapType, MTLTexture, MTLTextureDescriptor,
+    offset_error,
+};
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::{
+    VulkanAllocation, VulkanAllocationLifetime, VulkanGarbage, VulkanHeapPlacement, VulkanMTLDevice,
+};
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use ash::vk;
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2::{rc::Retained, runtime::ProtocolObject};
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_foundation::NSString;
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_metal::{
+    MTLBuffer as MetalMTLBuffer, MTLCPUCacheMode as MetalMTLCPUCacheMode,
+    MTLDevice as MetalMTLDevice, MTLHeap as MetalMTLHeap, MTLResource as MetalMTLResource,
+    MTLResourceOptions as MetalMTLResourceOptions, MTLStorageMode as MetalMTLStorageMode,
+};
+
+/// Where a resource lives and who can access it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MTLStorageMode {
+    /// CPU and GPU memory. Host visible and coherent memory on Vulkan.
+    #[default]
+    Shared,
+    /// A CPU and a GPU copy, `did_modify_range` copies CPU writes over.
+    /// Host visible memory on Vulkan, flushed unless it's coherent. Shared
+    /// on iOS.
+    Managed,
+    /// GPU memory only. Device local memory on Vulkan.
+    Private,
+}
+
+impl MTLStorageMode {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLStorageMode {
+        match self {
+            MTLStorageMode::Shared => MetalMTLStorageMode::Shared,
+            #[cfg(target_os = "macos")]
+            MTLStorageMode::Managed => MetalMTLStorageMode::Managed,
+            #[cfg(not(target_os = "macos"))]
+            MTLStorageMode::Managed => MetalMTLStorageMode::Shared,
+            MTLStorageMode::Private => MetalMTLStorageMode::Private,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MTLCPUCacheMode {
+    #[default]
+    DefaultCache,
+    /// Faster for memory the CPU only writes. Prefers uncached memory on
+    /// Vulkan.
+    WriteCombined,
+}
+
+impl MTLCPUCacheMode {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLCPUCacheMode {
+        match self {
+            MTLCPUCacheMode::DefaultCache => MetalMTLCPUCacheMode::DefaultCache,
+            MTLCPUCacheMode::WriteCombined => MetalMTLCPUCacheMode::WriteCombined,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MTLResourceOptions {
+    pub storage_mode: MTLStorageMode,
+    pub cpu_cache_mode: MTLCPUCacheMode,
+}
+
+impl MTLResourceOptions {
+    pub fn new(storage_mode: MTLStorageMode) -> Self {
+        Self {
+            storage_mode,
+            ..Default::default()
+        }
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLResourceOptions {
+        let storage_mode = match self.storage_mode {
+            MTLStorageMode::Shared => MetalMTLResourceOptions::StorageModeShared,
+            #[cfg(target_os = "macos")]
+            MTLStorageMode::Managed => MetalMTLResourceOptions::StorageModeManaged,
+            #[cfg(not(target_os = "macos"))]
+            MTLStorageMode::Managed => MetalMTLResourceOptions::StorageModeShared,
+            MTLStorageMode::Private => MetalMTLResourceOptions::StorageModePrivate,
+        };
+
+        let cpu_cache_mode = match self.cpu_cache_mode {
+            MTLCPUCacheMode::DefaultCache => MetalMTLResourceOptions::CPUCacheModeDefaultCache,
+            MTLCPUCacheMode::WriteCombined => MetalMTLResourceOptions::CPUCacheModeWriteCombined,
+        };
+
+        storage_mode | cpu_cache_mode
+    }
+
+    /// (Vulkan) The memory properties required and preferred for these
+    /// options.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(
+        &self,
+        has_unified_memory: bool,
+    ) -> (vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) {
+        let cached = match self.cpu_cache_mode {
+            MTLCPUCacheMode::DefaultCache => vk::MemoryPropertyFlags::HOST_CACHED,
+            MTLCPUCacheMode::WriteCombined => vk::MemoryPropertyFlags::empty(),
+        };
+
+        // On unified memory all of it is local to the device.
+        let local = match has_unified_memory {
+            true => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            false => vk::MemoryPropertyFlags::empty(),
+        };
+
+        match self.storage_mode {
+            MTLStorageMode::Shared => (
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                cached | local,
+            ),
+            MTLStorageMode::Managed => (vk::MemoryPropertyFlags::HOST_VISIBLE, cached | local),
+            MTLStorageMode::Private => (
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                vk::MemoryPropertyFlags::empty(),
+            ),
+        }
+    }
+}
+
+pub struct MTLBuffer {
+    device: Arc<MTLDevice>,
+    length: u64,
+    options: MTLResourceOptions,
+    label: RwLock<Option<String>>,
+    /// Held by `read` and `write`, so they don't race each other.
+    access: Mutex<()>,
+    /// The heap the buffer was placed in.
+    heap: Option<Arc<MTLHeap>>,
+    /// Set by `make_aliasable`, after which the heap may place other
+    /// resources over the buffer.
+    aliasable: AtomicBool,
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    metal_buffer: Retained<ProtocolObject<dyn MetalMTLBuffer>>,
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_buffer: vk::Buffer,
+    /// Only `None` while dropping, and for buffers in a heap.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_allocation: Option<VulkanAllocation>,
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_placement: Option<VulkanHeapPlacement>,
+}
+
+// Only `device` isn't, for the window handles of its instance's layer, which
+// a buffer never touches. Its mapped memory is guarded by `access`.
+unsafe impl Send for MTLBuffer {}
+unsafe impl Sync for MTLBuffer {}
+
+/// Methods that hand out `Arc<MTLDevice>` clones to what they create.
+pub trait MTLDeviceArc {
+    fn new_buffer(&self, length: u64, options: MTLResourceOptions) -> Result<Arc<MTLBuffer>>;
+    /// A buffer holding a copy of `bytes`, which private buffers can't be
+    /// created with.
+    fn new_buffer_with_bytes(
+        &self,
+        bytes: &[u8],
+        options: MTLResourceOptions,
+    ) -> Result<Arc<MTLBuffer>>;
+    fn new_heap(&self, descriptor: MTLHeapDescriptor) -> Result<Arc<MTLHeap>>;
+    fn new_texture(&self, descriptor: &MTLTextureDescriptor) -> Result<Arc<MTLTexture>>;
+}
+
+impl MTLDeviceArc for Arc<MTLDevice> {
+    fn new_buffer(&self, length: u64, options: MTLResourceOptions) -> Result<Arc<MTLBuffer>> {
+        MTLBuffer::new(self.clone(), length, options)
+    }
+
+    fn new_buffer_with_bytes(
+        &self,
+        bytes: &[u8],
+        options: MTLResourceOptions,
+    ) -> Result<Arc<MTLBuffer>> {
+        MTLBuffer::with_bytes(self.clone(), bytes, options)
+    }
+
+    fn new_heap(&self, descriptor: MTLHeapDescriptor) -> Result<Arc<MTLHeap>> {
+        MTLHeap::new(self.clone(), descriptor)
+    }
+
+    fn new_texture(&self, descriptor: &MTLTextureDescriptor) -> Result<Arc<MTLTexture>> {
+        MTLTexture::new(self.clone(), descriptor)
+    }
+}
+
+impl MTLBuffer {
+    pub fn new(
+        device: Arc<MTLDevice>,
+        length: u64,
+        options: MTLResourceOptions,
+    ) -> Result<Arc<Self>> {
+        Self::check_length(&device, length)?;
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_new(device, length, options);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_new(device, length, options);
+    }
+
+    pub fn with_bytes(
+        device: Arc<MTLDevice>,
+        bytes: &[u8],
+        options: MTLResourceOptions,
+    ) -> Result<Arc<Self>> {
+        if options.storage_mode == MTLStorageMode::Private {
+            return Err(BMLError::InvalidUsage(String::from(
+                "Private buffers can't be created with bytes, copy them over from a shared one.",
+            ))
+            .into());
+        }
+
+        let buffer = Self::new(device, bytes.len() as u64, options)?;
+        buffer.write(0, bytes)?;
+
+        Ok(buffer)
+    }
+
+    /// A buffer in `heap`, at `offset` for placement heaps. It has to have
+    /// the heap's options.
+    pub fn with_heap(
+        heap: Arc<MTLHeap>,
+        length: u64,
+        options: MTLResourceOptions,
+        offset: Option<u64>,
+    ) -> Result<Arc<Self>> {
+        Self::check_length(heap.device(), length)?;
+
+        if options != heap.descriptor().resource_options() {
+            return Err(BMLError::InvalidUsage(format!(
+                "A {:?} buffer can't be placed in a {:?} heap.",
+                options,
+                heap.descriptor().resource_options()
+            ))
+            .into());
+        }
+
+        if offset.is_some() != (heap.heap_type() == MTLHeapType::Placement) {
+            return Err(offset_error(heap.heap_type()).into());
+        }
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_with_heap(heap, length, options, offset);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_with_heap(heap, length, options, offset);
+    }
+
+    fn check_length(device: &MTLDevice, length: u64) -> Result<()> {
+        if length == 0 {
+            return Err(BMLError::InvalidUsage(String::from("Buffers can't be empty.")).into());
+        }
+
+        if length > device.max_buffer_length() {
+            return Err(BMLError::InvalidUsage(format!(
+                "{} bytes is more than the maximum buffer length of {}.",
+                length,
+                device.max_buffer_length()
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_new(
+        device: Arc<MTLDevice>,
+        length: u64,
+        options: MTLResourceOptions,
+    ) -> Result<Arc<Self>> {
+        let metal_buffer = device
+            .metal_device()
+            .newBufferWithLength_options(length as usize, options.to_metal());
+
+        let metal_buffer = match metal_buffer {
+            Some(b) => b,
+            None => return Err(BMLError::OutOfDeviceMemory.into()),
+        };
+
+        Ok(Arc::new(Self {
+            device,
+            length,
+            options,
+            label: RwLock::new(None),
+            access: Mutex::new(()),
+            heap: None,
+            aliasable: AtomicBool::new(false),
+            metal_buffer,
+        }))
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_with_heap(
+        heap: Arc<MTLHeap>,
+        length: u64,
+        options: MTLResourceOptions,
+        offset: Option<u64>,
+    ) -> Result<Arc<Self>> {
+        let metal_heap = heap.metal_heap();
+
+        let metal_buffer = match offset {
+            Some(offset) => unsafe {
+                metal_heap.newBufferWithLength_options_offset(
+                    length as usize,
+                    options.to_metal(),
+                    offset as usize,
+                )
+            },
+            None => metal_heap.newBufferWithLength_options(length as usize, options.to_metal()),
+        };
+
+        let metal_buffer = match metal_buffer {
+            Some(b) => b,
+            None => return Err(BMLError::OutOfDeviceMemory.into()),
+        };
+
+        Ok(Arc::new(Self {
+            device: heap.device().clone(),
+            length,
+            options,
+            label: RwLock::new(None),
+            access: Mutex::new(()),
+            heap: Some(heap),
+            aliasable: AtomicBool::new(false),
+            metal_buffer,
+        }))
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_new(
+        device: Arc<MTLDevice>,
+        length: u64,
+        options: MTLResourceOptions,
+    ) -> Result<Arc<Self>> {
+        let vulkan_device = device.vulkan_device();
+        let logical_device = vulkan_device.logical();
+
+        let vulkan_buffer = Self::vulkan_create_buffer(vulkan_device, length)?;
+
+        let (required, preferred) = options.to_vulkan(device.has_unified_memory());
+
+        let allocation = vulkan_device.allocate_buffer_memory(
+            vulkan_buffer,
+            required,
+            preferred,
+            VulkanAllocationLifetime::Long,
+        );
+
+        let vulkan_allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                unsafe { logical_device.destroy_buffer(vulkan_buffer, None) };
+                return Err(e.context(format!(
+                    "Allocating memory for a {:?} buffer",
+                    options.storage_mode
+                )));
+            }
+        };
+
+        Ok(Arc::new(Self {
+            device,
+            length,
+            options,
+            label: RwLock::new(None),
+            access: Mutex::new(()),
+            heap: None,
+            aliasable: AtomicBool::new(false),
+            vulkan_buffer,
+            vulkan_allocation: Some(vulkan_allocation),
+            vulkan_placement: None,
+        }))
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_with_heap(
+        heap: Arc<MTLHeap>,
+        length: u64,
+        options: MTLResourceOptions,
+        offset: Option<u64>,
+    ) -> Result<Arc<Self>> {
+        let vulkan_device = heap.device().vulkan_device();
+        let logical_device = vulkan_device.logical();
+        let vulkan_buffer = Self::vulkan_create_buffer(vulkan_device, length)?;
+
+        let requirements = unsafe { logical_device.get_buffer_memory_requirements(vulkan_buffer) };
+
+        let placement = match heap.vulkan_place(&requirements, offset) {
+            Ok(placement) => placement,
+            Err(e) => {
+                unsafe { logical_device.destroy_buffer(vulkan_buffer, None) };
+                return Err(e.context(format!("Placing a {} byte buffer in a heap", length)));
+            }
+        };
+
+        let allocation = heap.vulkan_allocation();
+
+        let bound = unsafe {
+            logical_device.bind_buffer_memory(
+                vulkan_buffer,
+                allocation.memory(),
+                allocation.offset() + placement.offset,
+            )
+        };
+
+        if let Err(e) = bound {
+            heap.vulkan_release(&placement);
+            unsafe { logical_device.destroy_buffer(vulkan_buffer, None) };
+            return Err(e.into());
+        }
+
+        Ok(Arc::new(Self {
+            device: heap.device().clone(),
+            length,
+            options,
+            label: RwLock::new(None),
+            access: Mutex::new(()),
+            heap: Some(heap),
+            aliasable: AtomicBool::new(false),
+            vulkan_buffer,
+            vulkan_allocation: None,
+            vulkan_placement: Some(placement),
+        }))
+    }
+
+    /// (Vulkan) The usage of every buffer, Metal buffers can be bound as
+    /// anything, including by address from an argument buffer.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_usage(vulkan_device: &VulkanMTLDevice) -> vk::BufferUsageFlags {
+        let usage = vk::BufferUsageFlags::VERTEX_BUFFER
+            | vk::BufferUsageFlags::INDEX_BUFFER
+            | vk::BufferUsageFlags::UNIFORM_BUFFER
+            | vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::INDIRECT_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::TRANSFER_DST;
+
+        match vulkan_device.shader_features().buffer_device_address {
+            true => usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            false => usage,
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_create_buffer(vulkan_device: &VulkanMTLDevice, length: u64) -> Result<vk::Buffer> {
+        Ok(unsafe {
+            vulkan_device.logical().create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(length)
+                    .usage(Self::vulkan_usage(vulkan_device))
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                None,
+            )?
+        })
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn options(&self) -> MTLResourceOptions {
+        self.options
+    }
+
+    pub fn storage_mode(&self) -> MTLStorageMode {
+        self.options.storage_mode
+    }
+
+    /// The GPU address of the buffer, which argument buffers store to
+    /// point at it.
+    pub fn gpu_address(&self) -> Result<u64> {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Ok(self.metal_buffer.gpuAddress());
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_gpu_address();
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_gpu_address(&self) -> Result<u64> {
+        let vulkan_device = self.device.vulkan_device();
+
+        if !vulkan_device.shader_features().buffer_device_address {
+            return Err(BMLError::UnsupportedFeature(String::from(
+                "buffer GPU addresses (bufferDeviceAddress)",
+            ))
+            .into());
+        }
+
+        Ok(unsafe {
+            vulkan_device.logical().get_buffer_device_address(
+                &vk::BufferDeviceAddressInfo::default().buffer(self.vulkan_buffer),
+            )
+        })
+    }
+
+    pub fn device(&self) -> &Arc<MTLDevice> {
+        &self.device
+    }
+
+    pub fn heap(&self) -> Option<&Arc<MTLHeap>> {
+        self.heap.as_ref()
+    }
+
+    /// Lets the heap place other resources over this one, which must not be
+    /// used after. Does nothing for buffers outside heaps.
+    pub fn make_aliasable(&self) {
+        if self.heap.is_none() || self.aliasable.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        self.metal_buffer.makeAliasable();
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        self.vulkan_release_placement();
+    }
+
+    pub fn is_aliasable(&self) -> bool {
+        self.aliasable.load(Ordering::Acquire)
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self.label.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_label(&self, label: &str) -> Result<()> {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        self.metal_buffer.setLabel(Some(&NSString::from_str(label)));
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        self.device
+            .vulkan_set_object_name(self.vulkan_buffer, label)?;
+
+        self.label
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(label.to_string());
+
+        Ok(())
+    }
+
+    /// The CPU address of the buffer, `None` for private buffers.
+    ///
+    /// Reading or writing through it races with the GPU, `read` and `write`
+    /// are the safe way in.
+    pub fn contents(&self) -> Option<NonNull<u8>> {
+        if self.options.storage_mode == MTLStorageMode::Private {
+            return None;
+        }
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Some(self.metal_buffer.contents().cast());
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let (allocation, offset, _) = self.vulkan_memory();
+            allocation
+                .mapped()
+                .map(|mapped| unsafe { mapped.add(offset as usize) })
+        }
+    }
+
+    /// Copies `bytes` into the buffer at `offset`, then makes them visible
+    /// to the GPU like `did_modify_range`.
+    pub fn write(&self, offset: u64, bytes: &[u8]) -> Result<()> {
+        let range = Self::byte_range(offset, bytes.len())?;
+        let contents = self.checked_contents(&range)?;
+        let _access = self.access.lock().unwrap_or_else(|e| e.into_inner());
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                contents.as_ptr().add(offset as usize),
+                bytes.len(),
+            )
+        };
+
+        self.did_modify_range(range)
+    }
+
+    /// Copies the buffer at `offset` into `bytes`. The GPU has to be done
+    /// writing to it.
+    pub fn read(&self, offset: u64, bytes: &mut [u8]) -> Result<()> {
+        let range = Self::byte_range(offset, bytes.len())?;
+        let contents = self.checked_contents(&range)?;
+        let _access = self.access.lock().unwrap_or_else(|e| e.into_inner());
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        self.vulkan_invalidate_range(&range)?;
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                contents.as_ptr().add(offset as usize),
+                bytes.as_mut_ptr(),
+                bytes.len(),
+            )
+        };
+
+        Ok(())
+    }
+
+    fn byte_range(offset: u64, length: usize) -> Result<Range<u64>> {
+        let end = offset.checked_add(length as u64).ok_or_else(|| {
+            BMLError::InvalidUsage(format!(
+                "{} bytes at offset {} overflow the address space.",
+                length, offset
+            ))
+        })?;
+
+        Ok(offset..end)
+    }
+
+    fn checked_contents(&self, range: &Range<u64>) -> Result<NonNull<u8>> {
+        check_range(range, self.length)?;
+
+        self.contents().ok_or_else(|| {
+            BMLError::InvalidUsage(String::from(
+                "Private buffers can't be accessed by the CPU.",
+            ))
+            .into()
+        })
+    }
+
+    /// Tells the GPU about CPU writes to `range` of a managed buffer.
+    /// Shared buffers need nothing.
+    pub fn did_modify_range(&self, range: Range<u64>) -> Result<()> {
+        check_range(&range, self.length)?;
+
+        if self.options.storage_mode != MTLStorageMode::Managed || range.is_empty() {
+            return Ok(());
+        }
+
+        #[cfg(all(target_os = "macos", not(feature = "moltenvk")))]
+        unsafe {
+            self.metal_buffer
+                .didModifyRange(objc2_foundation::NSRange::new(
+                    range.start as usize,
+                    (range.end - range.start) as usize,
+                ))
+        };
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        self.vulkan_flush_range(&range)?;
+
+        Ok(())
+    }
+
+    /// (Vulkan) `range` grown to `nonCoherentAtomSize`, which flushes and
+    /// invalidations have to be aligned to.
+    /// The allocation is aligned to it too, as are heap placements in
+    /// non-coherent memory, so the range stays inside.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_mapped_range(&self, range: &Range<u64>) -> vk::MappedMemoryRange<'_> {
+        let (allocation, offset, size) = self.vulkan_memory();
+        let atom = self
+            .device
+            .vulkan_device()
+            .properties()
+            .limits
+            .non_coherent_atom_size
+            .max(1);
+
+        let base = allocation.offset() + offset;
+        let start = (base + range.start) / atom * atom;
+        let end = (base + range.end).next_multiple_of(atom).min(base + size);
+
+        vk::MappedMemoryRange::default()
+            .memory(allocation.memory())
+            .offset(start)
+            .size(end - start)
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_flush_range(&self, range: &Range<u64>) -> Result<()> {
+        if self.vulkan_memory().0.coherent() {
+            return Ok(());
+        }
+
+        unsafe {
+            self.device
+                .vulkan_device()
+                .logical()
+                .flush_mapped_memory_ranges(&[self.vulkan_mapped_range(range)])?
+        };
+
+        Ok(())
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_invalidate_range(&self, range: &Range<u64>) -> Result<()> {
+        if self.vulkan_memory().0.coherent() || range.is_empty() {
+            return Ok(());
+        }
+
+        unsafe {
+            self.device
+                .vulkan_device()
+                .logical()
+                .invalidate_mapped_memory_ranges(&[self.vulkan_mapped_range(range)])?
+        };
+
+        Ok(())
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_buffer(&self) -> &Retained<ProtocolObject<dyn MetalMTLBuffer>> {
+        &self.metal_buffer
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_buffer(&self) -> &vk::Buffer {
+        &self.vulkan_buffer
+    }
+
+    /// (Vulkan) `None` for buffers in a heap, which use the heap's.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_allocation(&self) -> Option<&VulkanAllocation> {
+        self.vulkan_allocation.as_ref()
+    }
+
+    /// (Vulkan) The allocation the buffer is in, with the offset and size of
+    /// its part of it.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_memory(&self) -> (&VulkanAllocation, u64, u64) {
+        match (&self.heap, &self.vulkan_placement) {
+            (Some(heap), Some(placement)) => {
+                (heap.vulkan_allocation(), placement.offset, placement.size)
+            }
+            _ => {
+                let allocation = self.vulkan_allocation.as_ref().unwrap();
+                (allocation, 0, allocation.size())
+            }
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_release_placement(&self) {
+        if let (Some(heap), Some(placement)) = (&self.heap, &self.vulkan_placement) {
+            heap.vulkan_release(placement);
+        }
+    }
+}
+
+/// Checks that `range` is inside a buffer of `length` bytes.
+fn check_range(range: &Range<u64>, length: u64) -> Result<()> {
+    if range.start > range.end || range.end > length {
+        return Err(BMLError::InvalidUsage(format!(
+            "Bytes {}..{} are out of bounds of a {} byte buffer.",
+            range.start, range.end, length
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLBuffer {
+    fn drop(&mut self) {
+        let device = self.device.vulkan_device();
+
+        device.destroy_later(VulkanGarbage::Buffer(self.vulkan_buffer));
+
+        // Heap memory isn't tracked, it's free for other resources now.
+        if !self.is_aliasable() {
+            self.vulkan_release_placement();
+        }
+
+        if let Some(allocation) = self.vulkan_allocation.take() {
+            device.destroy_later(VulkanGarbage::Allocation(allocation));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_checked() {
+        assert!(check_range(&(0..16), 16).is_ok());
+        assert!(check_range(&(16..16), 16).is_ok());
+        assert!(check_range(&(8..17), 16).is_err());
+        assert!(check_range(&Range { start: 12, end: 4 }, 16).is_err());
+    }
+}
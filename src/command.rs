@@ -1,14 +1,23 @@
 use crate::{
-    MTLBeginRenderPassDescriptor, MTLDevice, MTLRenderPass, MTLRenderPassDescriptor, MTLTexture,
+    BMLError, MTLBeginRenderPassDescriptor, MTLDevice, MTLRenderPass, MTLRenderPassDescriptor,
+    MTLTexture, MTLViewport,
 };
 use anyhow::{Result, anyhow};
 use crossbeam::queue::SegQueue;
 use std::sync::Arc;
 
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::{VulkanGarbage, VulkanMTLDevice, VulkanQueue, VulkanSyncObject};
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 use ash::vk::Device;
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-use std::sync::RwLock;
+use std::{
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64},
+    },
+    time::Duration,
+};
 
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 use ash::vk;
@@ -29,50 +38,152 @@ pub struct MTLCommandQueue {
     metal_command_queue: Retained<ProtocolObject<dyn MetalMTLCommandQueue>>,
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    vulkan_command_queue: VulkanMTLCommandQueue,
+    vulkan_command_queue: Arc<VulkanMTLCommandQueue>,
+}
+
+/// What a command queue is for, which picks the queue family on Vulkan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MTLCommandQueueKind {
+    #[default]
+    Graphics,
+    /// Async compute, on a compute family without graphics if there is one.
+    Compute,
+    /// Uploads and copies, on a transfer-only family if there is one.
+    Transfer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum MTLCommandQueuePriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl MTLCommandQueuePriority {
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self) -> f32 {
+        match self {
+            MTLCommandQueuePriority::Low => 0.0,
+            MTLCommandQueuePriority::Normal => 0.5,
+            MTLCommandQueuePriority::High => 1.0,
+        }
+    }
+}
+
+/// Metal schedules queues itself, so kind and priority only matter on
+/// Vulkan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MTLCommandQueueDescriptor {
+    pub kind: MTLCommandQueueKind,
+    pub priority: MTLCommandQueuePriority,
+    /// How many command buffers may exist at once, creating more blocks
+    /// until one is dropped.
+    pub max_command_buffer_count: usize,
+}
+
+impl Default for MTLCommandQueueDescriptor {
+    fn default() -> Self {
+        Self {
+            kind: MTLCommandQueueKind::default(),
+            priority: MTLCommandQueuePriority::default(),
+            max_command_buffer_count: 64,
+        }
+    }
 }
 
 impl MTLCommandQueue {
     pub fn new(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
+        Self::with_descriptor(device, &MTLCommandQueueDescriptor::default())
+    }
+
+    pub fn with_descriptor(
+        device: Arc<MTLDevice>,
+        descriptor: &MTLCommandQueueDescriptor,
+    ) -> Result<Arc<Self>> {
         #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-        return Self::metal_new(device);
+        return Self::metal_new(device, descriptor);
 
         #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-        return Self::vulkan_new(device);
+        return Self::vulkan_new(device, descriptor);
     }
 
+    /// (Vulkan) Compute and transfer queues fall back to the closest family
+    /// the device has, down to the graphics family.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_new(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
+    pub fn vulkan_new(
+        device: Arc<MTLDevice>,
+        descriptor: &MTLCommandQueueDescriptor,
+    ) -> Result<Arc<Self>> {
         use crossbeam::queue::SegQueue;
 
         let logical_device = device.vulkan_device().logical();
         let queue_families = device.vulkan_device().queue_families();
 
-        let graphics_queue =
-            unsafe { logical_device.get_device_queue(queue_families.graphics_queue, 0) };
-        let present_queue =
-            unsafe { logical_device.get_device_queue(queue_families.present_queue, 0) };
+        let family = match descriptor.kind {
+            MTLCommandQueueKind::Graphics => queue_families.graphics_queue,
+            MTLCommandQueueKind::Compute => queue_families
+                .compute_queue
+                .unwrap_or(queue_families.graphics_queue),
+            MTLCommandQueueKind::Transfer => queue_families
+                .transfer_queue
+                .or(queue_families.compute_queue)
+                .unwrap_or(queue_families.graphics_queue),
+        };
+
+        let queue = device
+            .vulkan_device()
+            .claim_queue(family, descriptor.priority)?;
+
+        // Any queue of a family that can present will do, ours included.
+        let present_queue = match queue_families.present_queue {
+            Some(present) if present == family => Some(queue.clone()),
+            Some(present) => device.vulkan_device().queue(present, 0).cloned(),
+            None => None,
+        };
 
         let command_pool_info = vk::CommandPoolCreateInfo::default()
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
-            .queue_family_index(queue_families.graphics_queue);
-
-        let command_pool = unsafe { logical_device.create_command_pool(&command_pool_info, None)? };
+            .queue_family_index(family);
+
+        let command_pool =
+            match unsafe { logical_device.create_command_pool(&command_pool_info, None) } {
+                Ok(command_pool) => command_pool,
+                Err(e) => {
+                    device.vulkan_device().release_queue(&queue);
+                    return Err(e.into());
+                }
+            };
 
         Ok(Arc::new(Self {
             device,
-            vulkan_command_queue: VulkanMTLCommandQueue {
-                graphics_queue,
+            vulkan_command_queue: Arc::new(VulkanMTLCommandQueue {
+                kind: descriptor.kind,
+                queue,
                 present_queue,
                 command_pool,
                 command_buffers: SegQueue::new(),
-            },
+                fences: SegQueue::new(),
+                max_command_buffer_count: descriptor.max_command_buffer_count.max(1),
+                command_buffer_count: Mutex::new(0),
+                command_buffer_released: Condvar::new(),
+            }),
         }))
     }
 
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_command_queue(&self) -> &VulkanMTLCommandQueue {
+        &self.vulkan_command_queue
+    }
+
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-    pub fn metal_new(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
-        let metal_command_queue = device.metal_device().newCommandQueue();
+    pub fn metal_new(
+        device: Arc<MTLDevice>,
+        descriptor: &MTLCommandQueueDescriptor,
+    ) -> Result<Arc<Self>> {
+        let metal_command_queue = device
+            .metal_device()
+            .newCommandQueueWithMaxCommandBufferCount(descriptor.max_command_buffer_count);
 
         let metal_command_queue = match metal_command_queue {
             Some(c) => c,
@@ -95,6 +206,17 @@ pub struct MTLCommandBuffer {
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_command_buffer: vk::CommandBuffer,
+    /// Signaled once the queue finished everything up to this command
+    /// buffer.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_fence: vk::Fence,
+    /// The queue's serial of the submission, 0 until committed.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_serial: AtomicU64,
+    /// Whether an encoder began recording, empty command buffers are
+    /// committed without one.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_recorded: AtomicBool,
 }
 
 impl MTLCommandBuffer {
@@ -108,32 +230,54 @@ impl MTLCommandBuffer {
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub fn vulkan_new(queue: Arc<MTLCommandQueue>) -> Result<Arc<Self>> {
+        let command_queue = &queue.vulkan_command_queue;
+        let logical_device = queue.device.vulkan_device().logical();
+
+        command_queue.acquire_command_buffer(queue.device.vulkan_device());
+
+        let vulkan_command_buffer = match command_queue.command_buffers().pop() {
+            Some(buffer) => buffer,
+            None => {
+                let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
+                    .command_pool(command_queue.command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1);
+
+                let buffers = unsafe {
+                    logical_device.allocate_command_buffers(&command_buffer_allocate_info)
+                };
+
+                match buffers {
+                    Ok(buffers) => buffers[0],
+                    Err(e) => {
+                        command_queue.release_command_buffer();
+                        return Err(e.into());
+                    }
+                }
+            }
+        };
+
+        let fence = match command_queue.fences.pop() {
+            Some(fence) => Ok(fence),
+            None => unsafe { logical_device.create_fence(&vk::FenceCreateInfo::default(), None) },
+        };
+
+        let vulkan_fence = match fence {
+            Ok(fence) => fence,
+            Err(e) => {
+                command_queue.command_buffers().push(vulkan_command_buffer);
+                command_queue.release_command_buffer();
+                return Err(e.into());
+            }
+        };
+
         Ok(Arc::new(Self {
             queue: queue.clone(),
             schedule_handler_queue: SegQueue::new(),
-            vulkan_command_buffer: {
-                if queue.vulkan_command_queue.command_buffers().is_empty() {
-                    let command_buffer_allocate_info = vk::CommandBufferAllocateInfo::default()
-                        .command_pool(queue.vulkan_command_queue.command_pool)
-                        .level(vk::CommandBufferLevel::PRIMARY)
-                        .command_buffer_count(1);
-
-                    let buffers = unsafe {
-                        queue
-                            .device
-                            .vulkan_device()
-                            .logical()
-                            .allocate_command_buffers(&command_buffer_allocate_info)?
-                    };
-
-                    queue
-                        .vulkan_command_queue
-                        .command_buffers()
-                        .push(buffers[0]);
-                }
-
-                queue.vulkan_command_queue.command_buffers().pop().unwrap()
-            },
+            vulkan_command_buffer,
+            vulkan_fence,
+            vulkan_serial: AtomicU64::new(0),
+            vulkan_recorded: AtomicBool::new(false),
         }))
     }
 
@@ -171,15 +315,19 @@ impl MTLCommandBuffer {
 
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
     pub fn metal_commit(&self) -> Result<()> {
-        while !self.schedule_handler_queue.is_empty() {
-            use objc2_metal::MTLDrawable;
-
-            let handle = self.schedule_handler_queue.pop().unwrap();
+        use objc2_metal::MTLDrawable;
 
+        while let Some(handle) = self.schedule_handler_queue.pop() {
             match handle {
-                MTLCommandBufferHandler::Present(d) => {
-                    d.ca_metal_drawable().as_ref().unwrap().present()
-                }
+                MTLCommandBufferHandler::Present(d) => d
+                    .ca_metal_drawable()
+                    .as_ref()
+                    .ok_or_else(|| {
+                        BMLError::InvalidUsage(String::from(
+                            "Only drawables from `next_drawable` can be presented.",
+                        ))
+                    })?
+                    .present(),
             }
         }
 
@@ -188,97 +336,213 @@ impl MTLCommandBuffer {
         Ok(())
     }
 
+    /// (Vulkan) Submits the command buffer, waiting for the drawables it
+    /// presents to be acquired, then presents them.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub fn vulkan_commit(&self) -> Result<()> {
+        use std::sync::atomic::Ordering;
+
+        if self.vulkan_serial.load(Ordering::Acquire) != 0 {
+            return Err(BMLError::InvalidUsage(String::from(
+                "Command buffers can only be committed once.",
+            ))
+            .into());
+        }
+
         let device = self.queue.device.vulkan_device().logical();
+        let queue = &self.queue.vulkan_command_queue.queue;
 
-        while !self.schedule_handler_queue.is_empty() {
-            let handle = self.schedule_handler_queue.pop().unwrap();
+        let mut drawables = vec![];
 
-            match handle {
+        while let Some(handler) = self.schedule_handler_queue.pop() {
+            match handler {
                 MTLCommandBufferHandler::Present(d) => {
-                    use std::sync::atomic::Ordering;
-
-                    let sync_object = d.vulkan_sync_object().read().unwrap();
-                    let sync_object = sync_object.as_ref().unwrap();
-
-                    let wait_semaphores = [*sync_object.image_available_event().vulkan_semaphore()];
-                    let signal_semaphores =
-                        [*sync_object.render_finished_event().vulkan_semaphore()];
-
-                    let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-                    let command_buffers = [self.vulkan_command_buffer];
-
-                    let submit_info = vk::SubmitInfo::default()
-                        .wait_semaphores(&wait_semaphores)
-                        .wait_dst_stage_mask(&wait_stages)
-                        .command_buffers(&command_buffers)
-                        .signal_semaphores(&signal_semaphores);
-
-                    unsafe {
-                        device.queue_submit(
-                            self.queue.vulkan_command_queue.graphics_queue,
-                            &[submit_info],
-                            *sync_object.fence().vulkan_fence(),
-                        )?
-                    };
-
-                    let (swapchain_instance, swapchain_khr) =
-                        d.vulkan_swapchain().as_ref().unwrap();
-
-                    let swapchains = [*swapchain_khr.as_ref()];
-                    let image_indices = [d.vulkan_image_index().load(Ordering::Relaxed)];
-
-                    let present_info = vk::PresentInfoKHR::default()
-                        .wait_semaphores(&signal_semaphores)
-                        .swapchains(&swapchains)
-                        .image_indices(&image_indices);
-
-                    let result = unsafe {
-                        swapchain_instance.queue_present(
-                            self.queue.vulkan_command_queue.present_queue,
-                            &present_info,
-                        )
-                    };
-
-                    match result {
-                        Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
-                            todo!("Handle Out of Date Swapchains.");
-                        }
-                        Err(error) => {
-                            return Err(anyhow!("Failed to present queue. Cause: {}", error));
-                        }
-                        _ => {}
-                    }
-
-                    unsafe {
-                        device.queue_wait_idle(self.queue.vulkan_command_queue.graphics_queue)?;
-                    }
+                    let sync_object = d
+                        .vulkan_sync_object()
+                        .read()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .clone()
+                        .ok_or_else(|| {
+                            BMLError::InvalidUsage(String::from(
+                                "Only drawables from `next_drawable` can be presented.",
+                            ))
+                        })?;
+
+                    drawables.push((d, sync_object));
                 }
             }
         }
 
+        let wait_semaphores = drawables
+            .iter()
+            .map(|(_, sync_object)| *sync_object.image_available_event().vulkan_semaphore())
+            .collect::<Vec<_>>();
+        let signal_semaphores = drawables
+            .iter()
+            .map(|(_, sync_object)| *sync_object.render_finished_event().vulkan_semaphore())
+            .collect::<Vec<_>>();
+        let wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; drawables.len()];
+
+        let command_buffers = match self.vulkan_recorded.load(Ordering::Acquire) {
+            true => vec![self.vulkan_command_buffer],
+            false => vec![],
+        };
+
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+
+        {
+            let vk_queue = queue.lock();
+
+            unsafe { device.queue_submit(*vk_queue, &[submit_info], vk::Fence::null())? };
+
+            // The GPU may use the command buffer from here on, even if
+            // submitting the fences below fails.
+            self.vulkan_serial
+                .store(queue.submitted(), Ordering::Release);
+
+            // Fences of empty submissions are signaled once everything
+            // submitted before is done, which is what the serial stands
+            // for. The drawables' frames are done then too.
+            for (_, sync_object) in &drawables {
+                unsafe {
+                    device.queue_submit(*vk_queue, &[], *sync_object.fence().vulkan_fence())?
+                };
+            }
+
+            unsafe { device.queue_submit(*vk_queue, &[], self.vulkan_fence)? };
+        }
+
+        for (d, sync_object) in &drawables {
+            self.vulkan_present(d, sync_object)?;
+        }
+
+        self.queue.device.vulkan_device().collect_garbage();
+
         Ok(())
     }
-}
 
-impl Drop for MTLCommandBuffer {
+    /// (Vulkan) Presents a drawable once its render finished semaphore is
+    /// signaled.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    fn drop(&mut self) {
-        let buffer = self.vulkan_command_buffer;
+    fn vulkan_present(&self, drawable: &MTLTexture, sync_object: &VulkanSyncObject) -> Result<()> {
+        use std::sync::atomic::Ordering;
+
+        let swapchain = drawable
+            .vulkan_swapchain()
+            .as_ref()
+            .ok_or_else(|| anyhow!("Only swapchain textures can be presented."))?;
+
+        let wait_semaphores = [*sync_object.render_finished_event().vulkan_semaphore()];
+        let swapchains = [swapchain.khr()];
+        let image_indices = [drawable.vulkan_image_index().load(Ordering::Relaxed)];
+
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(&wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+
+        let present_queue = self
+            .queue
+            .vulkan_command_queue
+            .present_queue
+            .as_ref()
+            .ok_or_else(|| anyhow!("This queue can't present."))?;
+
+        // Not counted as a submission of the present queue, as nothing
+        // signals when a present is done and garbage would wait forever.
+        let result = unsafe {
+            swapchain
+                .instance()
+                .queue_present(*present_queue.lock(), &present_info)
+        };
+
+        // Suboptimal still presented, the view is recreated once it's out
+        // of date.
+        if let Err(error) = result {
+            return Err(
+                anyhow::Error::new(BMLError::from(error)).context("Failed to present queue.")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until the GPU finished the command buffer, which has to be
+    /// committed.
+    pub fn wait_until_completed(&self) -> Result<()> {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_wait_until_completed();
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_wait_until_completed();
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_wait_until_completed(&self) -> Result<()> {
+        self.metal_command_buffer.waitUntilCompleted();
+
+        match self.metal_command_buffer.error() {
+            Some(error) => Err(BMLError::from_metal(&error).into()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_wait_until_completed(&self) -> Result<()> {
+        use std::sync::atomic::Ordering;
+
+        let serial = self.vulkan_serial.load(Ordering::Acquire);
+
+        if serial == 0 {
+            return Err(BMLError::InvalidUsage(String::from(
+                "Command buffers have to be committed before waiting for them.",
+            ))
+            .into());
+        }
+
+        let vulkan_device = self.queue.device.vulkan_device();
 
         unsafe {
-            self.queue
-                .device
-                .vulkan_device()
+            vulkan_device
                 .logical()
-                .reset_command_buffer(buffer, vk::CommandBufferResetFlags::empty())
-                .unwrap();
-        }
+                .wait_for_fences(&[self.vulkan_fence], true, u64::MAX)?
+        };
 
-        let buffer_queue = self.queue.vulkan_command_queue.command_buffers();
+        self.queue.vulkan_command_queue.queue.finished(serial);
+        vulkan_device.collect_garbage();
 
-        buffer_queue.push(buffer);
+        Ok(())
+    }
+}
+
+impl Drop for MTLCommandBuffer {
+    /// Hands the command buffer and its fence back to the queue once the GPU
+    /// finished them, without waiting for that.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn drop(&mut self) {
+        let vulkan_device = self.queue.device.vulkan_device();
+        let command_queue = &self.queue.vulkan_command_queue;
+
+        match *self.vulkan_serial.get_mut() {
+            // Never submitted, so reusable right away.
+            0 => unsafe {
+                command_queue.recycle(
+                    vulkan_device.logical(),
+                    self.vulkan_command_buffer,
+                    self.vulkan_fence,
+                )
+            },
+            serial => vulkan_device.destroy_later(VulkanGarbage::CommandBuffer {
+                command_queue: command_queue.clone(),
+                command_buffer: self.vulkan_command_buffer,
+                fence: self.vulkan_fence,
+                serial,
+            }),
+        }
     }
 
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
@@ -330,19 +594,33 @@ impl MTLRenderCommandEncoder {
             )?;
         }
 
+        command_buffer
+            .vulkan_recorded
+            .store(true, std::sync::atomic::Ordering::Release);
+
+        begin_descriptor.vulkan_transition_loaded(
+            render_pass.descriptor(),
+            device,
+            command_buffer.vulkan_command_buffer,
+        );
+
         let clear_color_values = begin_descriptor.vulkan_clear_color_values();
 
         let texture = begin_descriptor.color_attachments[0].texture.clone();
+        let (framebuffer, extent) = texture
+            .vulkan_framebuffer()
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|f| (f.framebuffer, f.extent))
+            .ok_or_else(|| anyhow!("The render pass has no framebuffer."))?;
 
         let begin_render_pass_info = vk::RenderPassBeginInfo::default()
             .render_pass(vk_render_pass)
-            .framebuffer(texture.vulkan_framebuffer().read().unwrap().unwrap())
+            .framebuffer(framebuffer)
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
-                extent: vk::Extent2D {
-                    width: texture.width(),
-                    height: texture.height(),
-                },
+                extent,
             })
             .clear_values(&clear_color_values);
 
@@ -354,6 +632,8 @@ impl MTLRenderCommandEncoder {
             );
         }
 
+        begin_descriptor.vulkan_track_final_layouts();
+
         Ok(Self { command_buffer })
     }
 
@@ -383,6 +663,35 @@ impl MTLRenderCommandEncoder {
         })
     }
 
+    pub fn set_viewport(&self, viewport: MTLViewport) {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_set_viewport(viewport);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_set_viewport(viewport);
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_set_viewport(&self, viewport: MTLViewport) {
+        let device = self.command_buffer.queue.device.vulkan_device();
+
+        let viewport = viewport.to_vulkan(device.coordinate_fixups());
+
+        unsafe {
+            device.logical().cmd_set_viewport(
+                self.command_buffer.vulkan_command_buffer,
+                0,
+                &[viewport],
+            );
+        }
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_set_viewport(&self, viewport: MTLViewport) {
+        self.metal_render_command_encoder
+            .setViewport(viewport.to_metal());
+    }
+
     pub fn end_encoding(&self) -> Result<()> {
         #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
         return self.metal_end_encoding();
@@ -413,10 +722,17 @@ impl MTLRenderCommandEncoder {
 
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 pub struct VulkanMTLCommandQueue {
-    graphics_queue: vk::Queue,
-    present_queue: vk::Queue,
+    kind: MTLCommandQueueKind,
+    queue: Arc<VulkanQueue>,
+    /// `None` on headless devices, or when no present family exists.
+    present_queue: Option<Arc<VulkanQueue>>,
     command_pool: vk::CommandPool,
     command_buffers: SegQueue<vk::CommandBuffer>,
+    /// Unsignaled fences of recycled command buffers.
+    fences: SegQueue<vk::Fence>,
+    max_command_buffer_count: usize,
+    command_buffer_count: Mutex<usize>,
+    command_buffer_released: Condvar,
 }
 
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
@@ -424,6 +740,111 @@ impl VulkanMTLCommandQueue {
     pub fn command_buffers(&self) -> &SegQueue<vk::CommandBuffer> {
         &self.command_buffers
     }
+
+    pub fn kind(&self) -> MTLCommandQueueKind {
+        self.kind
+    }
+
+    pub fn queue(&self) -> &Arc<VulkanQueue> {
+        &self.queue
+    }
+
+    /// Blocks while `max_command_buffer_count` command buffers exist.
+    /// Dropped ones only come back once the device collects its garbage,
+    /// so that's checked for in between.
+    fn acquire_command_buffer(&self, device: &VulkanMTLDevice) {
+        loop {
+            let count = self
+                .command_buffer_count
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+
+            let (mut count, _) = self
+                .command_buffer_released
+                .wait_timeout_while(count, Duration::from_millis(1), |count| {
+                    *count >= self.max_command_buffer_count
+                })
+                .unwrap_or_else(|e| e.into_inner());
+
+            if *count < self.max_command_buffer_count {
+                *count += 1;
+                return;
+            }
+
+            drop(count);
+            device.collect_garbage();
+        }
+    }
+
+    fn release_command_buffer(&self) {
+        let mut count = self
+            .command_buffer_count
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        *count = count.saturating_sub(1);
+        self.command_buffer_released.notify_one();
+    }
+
+    /// Resets a command buffer and its fence for the next command buffer.
+    ///
+    /// # Safety
+    ///
+    /// Both must belong to this queue and no longer be in use.
+    pub unsafe fn recycle(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+    ) {
+        let reset = unsafe {
+            device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+        };
+
+        match reset {
+            Ok(()) => self.command_buffers.push(command_buffer),
+            // Freed with the pool instead.
+            Err(e) => log::error!("Resetting a command buffer failed: {}", e),
+        }
+
+        match unsafe { device.reset_fences(&[fence]) } {
+            Ok(()) => self.fences.push(fence),
+            Err(e) => {
+                log::error!("Resetting a fence failed: {}", e);
+                unsafe { device.destroy_fence(fence, None) };
+            }
+        }
+
+        self.release_command_buffer();
+    }
+
+    /// Destroys the pool, which frees its command buffers, and the fences
+    /// kept for reuse.
+    ///
+    /// # Safety
+    ///
+    /// No command buffer of the queue may be in use anymore.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        while let Some(fence) = self.fences.pop() {
+            unsafe { device.destroy_fence(fence, None) };
+        }
+
+        unsafe { device.destroy_command_pool(self.command_pool, None) };
+    }
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLCommandQueue {
+    /// Command buffers hold on to their queue, so the pool is unused by now
+    /// besides work still on the GPU.
+    fn drop(&mut self) {
+        let device = self.device.vulkan_device();
+
+        device.release_queue(&self.vulkan_command_queue.queue);
+        device.destroy_later(VulkanGarbage::CommandPool(
+            self.vulkan_command_queue.clone(),
+        ));
+    }
 }
 
 pub enum MTLCommandBufferHandler {
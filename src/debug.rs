@@ -0,0 +1,278 @@
+use std::sync::Arc;
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use anyhow::Result;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use ash::{ext::debug_utils, vk};
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use std::{
+    ffi::{CStr, c_void},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::vulkan_entry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BMLMessageSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BMLMessageType {
+    General,
+    /// Breaks the spec, what tests want none of.
+    Validation,
+    /// Legal, but likely slow.
+    Performance,
+    DeviceAddressBinding,
+}
+
+/// An object a message is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BMLDebugObject {
+    /// `BUFFER`, `COMMAND_BUFFER` and so on.
+    pub ty: String,
+    pub handle: u64,
+    /// The name given with `vkSetDebugUtilsObjectNameEXT`.
+    pub name: Option<String>,
+}
+
+/// A message from the validation layers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BMLDebugMessage {
+    pub severity: BMLMessageSeverity,
+    pub ty: BMLMessageType,
+    /// `VUID-vkCmdDraw-None-08600` and the like.
+    pub id_name: Option<String>,
+    pub id_number: i32,
+    pub message: String,
+    pub objects: Vec<BMLDebugObject>,
+}
+
+/// Receives validation messages instead of `log`. Called from whatever
+/// thread made the offending call.
+pub type BMLDebugCallback = Arc<dyn Fn(&BMLDebugMessage) + Send + Sync>;
+
+impl BMLDebugMessage {
+    /// Forwards to `log` with the matching level, under the
+    /// `rosemetal::validation` target.
+    pub fn log(&self) {
+        let level = match self.severity {
+            BMLMessageSeverity::Verbose => log::Level::Trace,
+            BMLMessageSeverity::Info => log::Level::Debug,
+            BMLMessageSeverity::Warning => log::Level::Warn,
+            BMLMessageSeverity::Error => log::Level::Error,
+        };
+
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| match &object.name {
+                Some(name) => format!("{} {:#x} {:?}", object.ty, object.handle, name),
+                None => format!("{} {:#x}", object.ty, object.handle),
+            })
+            .collect::<Vec<_>>();
+
+        log::log!(
+            target: "rosemetal::validation",
+            level,
+            "[{:?}] {} ({}): {}{}",
+            self.ty,
+            self.id_name.as_deref().unwrap_or("-"),
+            self.id_number,
+            self.message,
+            match objects.is_empty() {
+                true => String::new(),
+                false => format!(" [{}]", objects.join(", ")),
+            }
+        );
+    }
+}
+
+impl BMLMessageSeverity {
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        match self {
+            BMLMessageSeverity::Verbose => vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            BMLMessageSeverity::Info => vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            BMLMessageSeverity::Warning => vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            BMLMessageSeverity::Error => vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        }
+    }
+
+    /// (Vulkan) Flags for this severity and everything more severe.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_at_least(&self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        [Self::Verbose, Self::Info, Self::Warning, Self::Error]
+            .iter()
+            .filter(|severity| *severity >= self)
+            .fold(
+                vk::DebugUtilsMessageSeverityFlagsEXT::empty(),
+                |flags, severity| flags | severity.to_vulkan(),
+            )
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn from_vulkan(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+            BMLMessageSeverity::Error
+        } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+            BMLMessageSeverity::Warning
+        } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+            BMLMessageSeverity::Info
+        } else {
+            BMLMessageSeverity::Verbose
+        }
+    }
+}
+
+impl BMLMessageType {
+    /// The most specific type of the flags.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn from_vulkan(types: vk::DebugUtilsMessageTypeFlagsEXT) -> Self {
+        if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+            BMLMessageType::Validation
+        } else if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+            BMLMessageType::Performance
+        } else if types.contains(vk::DebugUtilsMessageTypeFlagsEXT::DEVICE_ADDRESS_BINDING) {
+            BMLMessageType::DeviceAddressBinding
+        } else {
+            BMLMessageType::General
+        }
+    }
+}
+
+/// (Vulkan) What the messenger callback gets as user data. Boxed, so it
+/// stays put while the instance holds a pointer to it.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub struct VulkanDebugState {
+    callback: Option<BMLDebugCallback>,
+    severity: BMLMessageSeverity,
+    validation_errors: AtomicUsize,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanDebugState {
+    pub fn new(callback: Option<BMLDebugCallback>, severity: BMLMessageSeverity) -> Box<Self> {
+        Box::new(Self {
+            callback,
+            severity,
+            validation_errors: AtomicUsize::new(0),
+        })
+    }
+
+    /// Chained to `VkInstanceCreateInfo` as well, to catch messages about
+    /// instance creation and destruction.
+    pub fn create_info(&self) -> vk::DebugUtilsMessengerCreateInfoEXT<'_> {
+        vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(self.severity.vulkan_at_least())
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+            )
+            .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(self as *const Self as *mut c_void)
+    }
+
+    fn send(&self, message: &BMLDebugMessage) {
+        if message.severity == BMLMessageSeverity::Error && message.ty == BMLMessageType::Validation
+        {
+            self.validation_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        match &self.callback {
+            Some(callback) => callback(message),
+            None => message.log(),
+        }
+    }
+}
+
+/// (Vulkan) A `VK_EXT_debug_utils` messenger, alive as long as the
+/// instance.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub struct VulkanDebugMessenger {
+    instance: debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+    state: Box<VulkanDebugState>,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanDebugMessenger {
+    pub fn new(instance: &ash::Instance, state: Box<VulkanDebugState>) -> Result<Self> {
+        let debug_instance = debug_utils::Instance::new(vulkan_entry()?, instance);
+        let messenger =
+            unsafe { debug_instance.create_debug_utils_messenger(&state.create_info(), None)? };
+
+        Ok(Self {
+            instance: debug_instance,
+            messenger,
+            state,
+        })
+    }
+
+    /// Validation errors reported so far.
+    pub fn validation_errors(&self) -> usize {
+        self.state.validation_errors.load(Ordering::Relaxed)
+    }
+
+    /// Destroys the messenger, before the instance it belongs to.
+    ///
+    /// # Safety
+    ///
+    /// Must be called once, and the messenger not used afterwards.
+    pub unsafe fn destroy(&mut self) {
+        unsafe {
+            self.instance
+                .destroy_debug_utils_messenger(self.messenger, None)
+        };
+        self.messenger = vk::DebugUtilsMessengerEXT::null();
+    }
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+unsafe extern "system" fn vulkan_debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    user_data: *mut c_void,
+) -> vk::Bool32 {
+    let (Some(data), Some(state)) = (unsafe { data.as_ref() }, unsafe {
+        (user_data as *const VulkanDebugState).as_ref()
+    }) else {
+        return vk::FALSE;
+    };
+
+    let string = |c_str: Option<&CStr>| c_str.map(|s| s.to_string_lossy().into_owned());
+
+    let objects = match data.p_objects.is_null() {
+        true => &[][..],
+        false => unsafe { std::slice::from_raw_parts(data.p_objects, data.object_count as usize) },
+    };
+
+    let message = BMLDebugMessage {
+        severity: BMLMessageSeverity::from_vulkan(severity),
+        ty: BMLMessageType::from_vulkan(types),
+        id_name: string(unsafe { data.message_id_name_as_c_str() }),
+        id_number: data.message_id_number,
+        message: string(unsafe { data.message_as_c_str() }).unwrap_or_default(),
+        objects: objects
+            .iter()
+            .map(|object| BMLDebugObject {
+                ty: format!("{:?}", object.object_type),
+                handle: object.object_handle,
+                name: string(unsafe { object.object_name_as_c_str() }),
+            })
+            .collect(),
+    };
+
+    // Unwinding into the driver is undefined behavior.
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| state.send(&message)));
+
+    // Only layer developers may return true.
+    vk::FALSE
+}
@@ -1,6 +1,21 @@
 use crate::BMLInstance;
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::MTLCommandQueuePriority;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 use crate::MTLRenderPassDescriptor;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::VulkanMTLCommandQueue;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::metalshaper::{
+    coordinates::{SPIRVCoordinateFixups, SPIRVYFlipMode},
+    reflection::{SPIRVArgumentBufferLayout, SPIRVArgumentLocation, SPIRVDescriptorType},
+    spirv::{SPIRVSubgroupFeatures, SPIRVTargetFeatures},
+};
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::{
+    BMLError, VulkanAllocation, VulkanAllocationLifetime, VulkanAllocationRequest, VulkanAllocator,
+    VulkanDedicatedResource, VulkanResourceTiling,
+};
 use anyhow::{Result, anyhow};
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 use ash::vk;
@@ -10,7 +25,10 @@ use std::sync::Arc;
 use std::{
     cell::RefCell,
     collections::BTreeMap,
-    sync::{RwLock, atomic::AtomicU32},
+    sync::{
+        Mutex, MutexGuard, RwLock,
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    },
 };
 
 #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
@@ -39,31 +57,131 @@ impl MTLDevice {
         return Self::vulkan_create(instance);
     }
 
+    /// Every device the instance can use, like `MTLCopyAllDevices`, in an
+    /// order that doesn't change between runs on the same machine.
+    pub fn all_devices(instance: &Arc<BMLInstance>) -> Result<Vec<MTLDeviceEntry>> {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_all_devices(instance);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_all_devices(instance);
+    }
+
+    /// The first device of `all_devices` ranked best by `preference`.
+    pub fn create_with_preference(
+        instance: Arc<BMLInstance>,
+        preference: MTLDevicePreference,
+    ) -> Result<Arc<Self>> {
+        let devices = Self::all_devices(&instance)?;
+
+        let entry = devices
+            .iter()
+            .min_by_key(|entry| preference.rank(entry.ty))
+            .ok_or_else(|| anyhow!("No suitable device found."))?;
+
+        Self::create_with_entry(instance, entry)
+    }
+
+    pub fn create_with_entry(
+        instance: Arc<BMLInstance>,
+        entry: &MTLDeviceEntry,
+    ) -> Result<Arc<Self>> {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_create_with_entry(instance, entry);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_create_with_entry(instance, entry);
+    }
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_create(instance: Arc<BMLInstance>) -> Result<Arc<Self>> {
+    pub fn vulkan_all_devices(instance: &Arc<BMLInstance>) -> Result<Vec<MTLDeviceEntry>> {
         let devices = unsafe { instance.vulkan_instance().enumerate_physical_devices()? };
 
-        let physical_device = devices
+        let mut entries = devices
             .into_iter()
-            .find(|device| Self::vulkan_device_check(&instance, device).is_ok())
-            .expect("No suitable Physical Device.");
+            .filter(|device| Self::vulkan_device_check(instance, device).is_ok())
+            .map(|device| MTLDeviceEntry::vulkan_new(instance, device))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Loaders enumerate in whatever order they find the drivers in.
+        entries.sort_by(|a, b| {
+            (a.vendor_id, a.device_id, a.registry_id, &a.name).cmp(&(
+                b.vendor_id,
+                b.device_id,
+                b.registry_id,
+                &b.name,
+            ))
+        });
+
+        Ok(entries)
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_create(instance: Arc<BMLInstance>) -> Result<Arc<Self>> {
+        Self::create_with_preference(instance, MTLDevicePreference::default())
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_create_with_entry(
+        instance: Arc<BMLInstance>,
+        entry: &MTLDeviceEntry,
+    ) -> Result<Arc<Self>> {
+        let physical_device = entry.vulkan_physical_device;
 
         let queue_families = Self::vulkan_find_queue_families(&instance, &physical_device)?;
 
-        let properties = unsafe {
-            instance
-                .vulkan_instance()
-                .get_physical_device_properties(physical_device)
-        };
+        let name = entry.name.clone();
 
-        let name = unsafe {
-            CStr::from_ptr(properties.device_name.as_ptr())
-                .to_str()?
-                .to_string()
+        let shader_features = Self::vulkan_shader_features(&instance, &physical_device)?;
+
+        let (properties, memory_properties) = unsafe {
+            (
+                instance
+                    .vulkan_instance()
+                    .get_physical_device_properties(physical_device),
+                instance
+                    .vulkan_instance()
+                    .get_physical_device_memory_properties(physical_device),
+            )
         };
+        let max_buffer_length = Self::vulkan_max_buffer_length(&instance, &physical_device);
+        let read_write_texture_tier =
+            Self::vulkan_read_write_texture_tier(&instance, &physical_device);
+        let features = Self::vulkan_device_features(&instance, &physical_device, &shader_features)?;
+        let gpu_families = Self::vulkan_gpu_families(&features, &shader_features);
+        let allocator = VulkanAllocator::new(
+            memory_properties,
+            &properties.limits,
+            Self::vulkan_device_api_version(&instance, &physical_device) >= vk::API_VERSION_1_1,
+            shader_features.buffer_device_address,
+        );
 
-        let logical_device =
-            Self::vulkan_create_logical_device(&instance, &physical_device, &queue_families)?;
+        let logical_device = Self::vulkan_create_logical_device(
+            &instance,
+            &physical_device,
+            &queue_families,
+            &features,
+            &shader_features,
+        )?;
+
+        let queues = queue_families
+            .families()
+            .into_iter()
+            .flat_map(|family| {
+                (0..queue_families.queue_count(family)).map(move |index| (family, index))
+            })
+            .map(|(family, index)| {
+                Arc::new(VulkanQueue {
+                    family,
+                    index,
+                    priority: VulkanQueueFamilies::priority(index),
+                    queue: Mutex::new(unsafe { logical_device.get_device_queue(family, index) }),
+                    users: AtomicUsize::new(0),
+                    submitted: AtomicU64::new(0),
+                    completed: AtomicU64::new(0),
+                })
+            })
+            .collect::<Vec<_>>();
 
         Ok(Arc::new(Self {
             name,
@@ -72,130 +190,206 @@ impl MTLDevice {
                 physical_device,
                 logical_device,
                 queue_families,
+                shader_features,
+                coordinate_fixups: SPIRVCoordinateFixups::new(SPIRVYFlipMode::Position),
+                properties,
+                memory_properties,
+                max_buffer_length,
+                read_write_texture_tier,
+                features,
+                gpu_families,
+                has_unified_memory: entry.has_unified_memory,
+                allocator,
+                queues,
+                garbage: Mutex::new(vec![]),
             },
         }))
     }
 
+    /// (Vulkan) The largest buffer that can be created, which Vulkan 1.3
+    /// reports directly and 1.1 as the largest allocation.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_create_logical_device(
+    pub fn vulkan_max_buffer_length(
         instance: &Arc<BMLInstance>,
         device: &vk::PhysicalDevice,
-        queue_families: &VulkanQueueFamilies,
-    ) -> Result<ash::Device> {
-        let mut indices = vec![queue_families.graphics_queue, queue_families.present_queue];
-        indices.dedup();
+    ) -> u64 {
+        let api_version = Self::vulkan_device_api_version(instance, device);
 
-        let queue_info = indices
-            .iter()
-            .map(|index| {
-                vk::DeviceQueueCreateInfo::default()
-                    .queue_family_index(*index)
-                    .queue_priorities(&[1.0_f32])
-            })
-            .collect::<Vec<_>>();
+        let properties = unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_properties(*device)
+        };
 
-        let device_extensions = Self::vulkan_required_extensions()
-            .iter()
-            .map(|ext| ext.as_ptr())
-            .collect::<Vec<_>>();
+        if api_version < vk::API_VERSION_1_1 {
+            return properties.limits.max_storage_buffer_range as u64;
+        }
 
-        let device_create_info = vk::DeviceCreateInfo::default()
-            .queue_create_infos(&queue_info)
-            .enabled_extension_names(&device_extensions);
+        let mut maintenance3 = vk::PhysicalDeviceMaintenance3Properties::default();
+        let mut maintenance4 = vk::PhysicalDeviceMaintenance4Properties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut maintenance3);
 
-        Ok(unsafe {
+        if api_version >= vk::API_VERSION_1_3 {
+            properties2 = properties2.push_next(&mut maintenance4);
+        }
+
+        unsafe {
             instance
                 .vulkan_instance()
-                .create_device(*device, &device_create_info, None)?
-        })
+                .get_physical_device_properties2(*device, &mut properties2);
+        }
+
+        match api_version >= vk::API_VERSION_1_3 {
+            true => maintenance4.max_buffer_size,
+            false => maintenance3.max_memory_allocation_size,
+        }
     }
 
+    /// (Vulkan) Tier 1 needs 32-bit single channel storage images, tier 2 the
+    /// four channel formats Metal lists for it too.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_device_check(
+    pub fn vulkan_read_write_texture_tier(
         instance: &Arc<BMLInstance>,
         device: &vk::PhysicalDevice,
-    ) -> Result<()> {
-        Self::vulkan_find_queue_families(instance, device)?;
+    ) -> MTLReadWriteTextureTier {
+        let supports_storage = |formats: &[vk::Format]| {
+            formats.iter().all(|format| {
+                let properties = unsafe {
+                    instance
+                        .vulkan_instance()
+                        .get_physical_device_format_properties(*device, *format)
+                };
 
-        Self::vulkan_check_extension_support(instance, device)?;
+                properties
+                    .optimal_tiling_features
+                    .contains(vk::FormatFeatureFlags::STORAGE_IMAGE)
+            })
+        };
 
-        Ok(())
+        let tier1 = [
+            vk::Format::R32_SFLOAT,
+            vk::Format::R32_UINT,
+            vk::Format::R32_SINT,
+        ];
+        let tier2 = [
+            vk::Format::R32G32B32A32_SFLOAT,
+            vk::Format::R32G32B32A32_UINT,
+            vk::Format::R32G32B32A32_SINT,
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::Format::R16G16B16A16_UINT,
+            vk::Format::R16G16B16A16_SINT,
+            vk::Format::R8G8B8A8_UNORM,
+            vk::Format::R8G8B8A8_UINT,
+            vk::Format::R8G8B8A8_SINT,
+            vk::Format::R16_SFLOAT,
+            vk::Format::R16_UINT,
+            vk::Format::R16_SINT,
+            vk::Format::R8_UNORM,
+            vk::Format::R8_UINT,
+            vk::Format::R8_SINT,
+        ];
+
+        match (supports_storage(&tier1), supports_storage(&tier2)) {
+            (true, true) => MTLReadWriteTextureTier::Tier2,
+            (true, false) => MTLReadWriteTextureTier::Tier1,
+            _ => MTLReadWriteTextureTier::None,
+        }
     }
 
-    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_find_queue_families(
-        instance: &Arc<BMLInstance>,
-        device: &vk::PhysicalDevice,
-    ) -> Result<VulkanQueueFamilies> {
-        let mut graphics: Option<u32> = None;
-        let mut present: Option<u32> = None;
+    /// The largest threadgroup a kernel may be dispatched with, per
+    /// dimension.
+    pub fn max_threads_per_threadgroup(&self) -> MTLSize {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        {
+            let size = self.metal_device.maxThreadsPerThreadgroup();
+            MTLSize::new(size.width as u64, size.height as u64, size.depth as u64)
+        }
 
-        let properties = unsafe {
-            instance
-                .vulkan_instance()
-                .get_physical_device_queue_family_properties(*device)
-        };
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let [width, height, depth] = self
+                .vulkan_device
+                .properties
+                .limits
+                .max_compute_work_group_size;
+            MTLSize::new(width as u64, height as u64, depth as u64)
+        }
+    }
 
-        for (index, family) in properties.iter().filter(|f| f.queue_count > 0).enumerate() {
-            if family.queue_flags.contains(vk::QueueFlags::GRAPHICS) && graphics.is_none() {
-                graphics = Some(index as u32);
-            }
+    /// The largest buffer that can be created, in bytes.
+    pub fn max_buffer_length(&self) -> u64 {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.maxBufferLength() as u64;
 
-            // TODO: Present Support so far has to be done with a surface.
-            // Find a way to get present support without it.
-            //
-            // By finding out, we could also follow the Metal way of requesting
-            // a surface without having to instantiate it in `BMLInstance`.
-            let present_support = unsafe {
-                match instance.vulkan_surface() {
-                    Some(v) => v.instance().get_physical_device_surface_support(
-                        *device,
-                        index as u32,
-                        *v.khr(),
-                    )?,
-                    // Fallback to the graphics queue.
-                    // Not ideal, but it'll work for now...
-                    None => graphics.is_some(),
-                }
-            };
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_device.max_buffer_length;
+    }
 
-            if present_support && present.is_none() {
-                present = Some(index as u32);
-            }
+    /// Threadgroup memory available to a kernel, in bytes.
+    pub fn max_threadgroup_memory_length(&self) -> u64 {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.maxThreadgroupMemoryLength() as u64;
 
-            if graphics.is_some() && present.is_some() {
-                break;
-            }
-        }
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self
+            .vulkan_device
+            .properties
+            .limits
+            .max_compute_shared_memory_size as u64;
+    }
 
-        if graphics.is_some() && present.is_some() {
-            return Ok(VulkanQueueFamilies {
-                graphics_queue: graphics.unwrap(),
-                present_queue: present.unwrap(),
-            });
+    /// How much memory the device can use without hurting performance, the
+    /// size of the device local heaps on Vulkan.
+    pub fn recommended_max_working_set_size(&self) -> u64 {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.recommendedMaxWorkingSetSize();
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let memory = &self.vulkan_device.memory_properties;
+
+            memory.memory_heaps[..memory.memory_heap_count as usize]
+                .iter()
+                .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|heap| heap.size)
+                .sum()
         }
+    }
 
-        Err(anyhow!("No suitable Graphics and Present queue found."))
+    /// Memory allocated through this device, in bytes.
+    pub fn current_allocated_size(&self) -> u64 {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.currentAllocatedSize() as u64;
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_device.allocator.stats().allocated_size;
     }
 
-    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_required_extensions() -> Vec<&'static CStr> {
-        if cfg!(any(target_os = "macos", target_os = "ios")) {
-            return vec![
-                ash::khr::swapchain::NAME,
-                ash::khr::portability_subset::NAME,
-            ];
-        }
+    /// Whether the device has everything `family` stands for. Vulkan
+    /// devices are checked against the table on `MTLGPUFamily`.
+    pub fn supports_family(&self, family: MTLGPUFamily) -> bool {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.supportsFamily(family.to_metal());
 
-        vec![ash::khr::swapchain::NAME]
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_device.gpu_families.contains(&family);
     }
 
+    /// (Vulkan) The optional features of `device` that `MTLGPUFamily`
+    /// stands for, which device creation enables.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_check_extension_support(
+    pub fn vulkan_device_features(
         instance: &Arc<BMLInstance>,
         device: &vk::PhysicalDevice,
-    ) -> Result<()> {
-        let required_extensions = Self::vulkan_required_extensions();
+        shader_features: &SPIRVTargetFeatures,
+    ) -> Result<VulkanDeviceFeatures> {
+        let api_version = Self::vulkan_device_api_version(instance, device);
+
+        let supported = unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_features(*device)
+        };
 
         let extension_properties = unsafe {
             instance
@@ -203,76 +397,1933 @@ impl MTLDevice {
                 .enumerate_device_extension_properties(*device)?
         };
 
-        for required in required_extensions.iter() {
-            let found = extension_properties.iter().any(|ext| {
-                let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
-                required == &name
-            });
+        let has = |extension| Self::vulkan_has_extension(&extension_properties, extension);
 
-            if !found {
-                return Err(anyhow!("No required extension found."));
+        // Both need SPIR-V 1.4, and acceleration structures need buffer
+        // device addresses, core since Vulkan 1.2.
+        let has_mesh_shader =
+            api_version >= vk::API_VERSION_1_2 && has(ash::ext::mesh_shader::NAME);
+        let has_ray_query = api_version >= vk::API_VERSION_1_2
+            && shader_features.buffer_device_address
+            && has(ash::khr::ray_query::NAME)
+            && has(ash::khr::acceleration_structure::NAME)
+            && has(ash::khr::deferred_host_operations::NAME);
+
+        let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+        let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+        let mut acceleration_structure =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+
+        if has_mesh_shader || has_ray_query {
+            let mut features2 = vk::PhysicalDeviceFeatures2::default();
+
+            if has_mesh_shader {
+                features2 = features2.push_next(&mut mesh_shader);
+            }
+
+            if has_ray_query {
+                features2 = features2
+                    .push_next(&mut ray_query)
+                    .push_next(&mut acceleration_structure);
+            }
+
+            unsafe {
+                instance
+                    .vulkan_instance()
+                    .get_physical_device_features2(*device, &mut features2);
             }
         }
 
-        Ok(())
+        // Only what the families need, the rest can cost performance.
+        let core = vk::PhysicalDeviceFeatures::default()
+            .multi_draw_indirect(supported.multi_draw_indirect == vk::TRUE)
+            .draw_indirect_first_instance(supported.draw_indirect_first_instance == vk::TRUE)
+            .tessellation_shader(supported.tessellation_shader == vk::TRUE)
+            .occlusion_query_precise(supported.occlusion_query_precise == vk::TRUE)
+            .fragment_stores_and_atomics(supported.fragment_stores_and_atomics == vk::TRUE)
+            .image_cube_array(supported.image_cube_array == vk::TRUE)
+            .texture_compression_bc(supported.texture_compression_bc == vk::TRUE)
+            .depth_clamp(supported.depth_clamp == vk::TRUE)
+            .sampler_anisotropy(supported.sampler_anisotropy == vk::TRUE);
+
+        Ok(VulkanDeviceFeatures {
+            core,
+            viewport_index_layer: has(ash::ext::shader_viewport_index_layer::NAME),
+            mesh_shader: mesh_shader.mesh_shader == vk::TRUE && mesh_shader.task_shader == vk::TRUE,
+            ray_query: ray_query.ray_query == vk::TRUE
+                && acceleration_structure.acceleration_structure == vk::TRUE,
+        })
     }
 
-    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-    pub fn metal_create(instance: Arc<BMLInstance>) -> Result<Arc<Self>> {
-        let metal_device = MTLCreateSystemDefaultDevice();
+    /// (Vulkan) The families of `MTLGPUFamily` whose requirements a device
+    /// with `features` meets.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_gpu_families(
+        features: &VulkanDeviceFeatures,
+        shader_features: &SPIRVTargetFeatures,
+    ) -> Vec<MTLGPUFamily> {
+        let core = &features.core;
 
-        let metal_device = match metal_device {
-            Some(m) => m,
-            None => return Err(anyhow!("No device found.")),
-        };
+        let common2 = core.multi_draw_indirect == vk::TRUE
+            && core.draw_indirect_first_instance == vk::TRUE
+            && core.tessellation_shader == vk::TRUE
+            && core.occlusion_query_precise == vk::TRUE
+            && core.fragment_stores_and_atomics == vk::TRUE;
 
-        let name = metal_device.name().to_string();
+        let common3 = common2
+            && core.image_cube_array == vk::TRUE
+            && features.viewport_index_layer
+            && shader_features.stencil_export
+            && shader_features.runtime_descriptor_arrays;
 
-        Ok(Arc::new(Self {
-            name,
-            instance,
-            metal_device,
-        }))
-    }
+        let mac2 = common3
+            && core.texture_compression_bc == vk::TRUE
+            && core.depth_clamp == vk::TRUE
+            && core.sampler_anisotropy == vk::TRUE;
 
-    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-    pub fn metal_device(&self) -> &Retained<ProtocolObject<dyn MetalMTLDevice>> {
-        &self.metal_device
-    }
+        let metal3 = common3
+            && features.mesh_shader
+            && features.ray_query
+            && shader_features.buffer_device_address
+            && shader_features.non_uniform_indexing;
 
-    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_device(&self) -> &VulkanMTLDevice {
-        &self.vulkan_device
+        let families = [
+            (MTLGPUFamily::Common1, true),
+            (MTLGPUFamily::Common2, common2),
+            (MTLGPUFamily::Common3, common3),
+            (MTLGPUFamily::Mac2, mac2),
+            (MTLGPUFamily::Metal3, metal3),
+        ];
+
+        families
+            .into_iter()
+            .filter_map(|(family, supported)| supported.then_some(family))
+            .collect()
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
+    pub fn has_unified_memory(&self) -> bool {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.hasUnifiedMemory();
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_device.has_unified_memory;
     }
-}
 
-#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-pub struct VulkanMTLDevice {
-    physical_device: vk::PhysicalDevice,
-    logical_device: ash::Device,
-    queue_families: VulkanQueueFamilies,
-}
+    /// Whether color and depth render targets can have `count` samples.
+    pub fn supports_texture_sample_count(&self, count: u32) -> bool {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_device.supportsTextureSampleCount(count as usize);
 
-#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-impl VulkanMTLDevice {
-    pub fn physical(&self) -> &vk::PhysicalDevice {
-        &self.physical_device
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let limits = &self.vulkan_device.properties.limits;
+            let flag = vk::SampleCountFlags::from_raw(count);
+
+            count.is_power_of_two()
+                && limits.framebuffer_color_sample_counts.contains(flag)
+                && limits.framebuffer_depth_sample_counts.contains(flag)
+        }
     }
 
-    pub fn logical(&self) -> &ash::Device {
-        &self.logical_device
+    /// Tier 2 on Vulkan when argument buffers can hold unbounded, non-uniformly
+    /// indexed arrays.
+    pub fn argument_buffers_support(&self) -> MTLArgumentBuffersTier {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return match self.metal_device.argumentBuffersSupport() {
+            objc2_metal::MTLArgumentBuffersTier::Tier2 => MTLArgumentBuffersTier::Tier2,
+            _ => MTLArgumentBuffersTier::Tier1,
+        };
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let features = &self.vulkan_device.shader_features;
+
+            match features.runtime_descriptor_arrays && features.non_uniform_indexing {
+                true => MTLArgumentBuffersTier::Tier2,
+                false => MTLArgumentBuffersTier::Tier1,
+            }
+        }
     }
 
-    pub fn queue_families(&self) -> &VulkanQueueFamilies {
-        &self.queue_families
+    pub fn read_write_texture_support(&self) -> MTLReadWriteTextureTier {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return match self.metal_device.readWriteTextureSupport() {
+            objc2_metal::MTLReadWriteTextureTier::Tier2 => MTLReadWriteTextureTier::Tier2,
+            objc2_metal::MTLReadWriteTextureTier::Tier1 => MTLReadWriteTextureTier::Tier1,
+            _ => MTLReadWriteTextureTier::None,
+        };
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_device.read_write_texture_tier;
     }
-}
 
-pub struct VulkanQueueFamilies {
-    pub graphics_queue: u32,
-    pub present_queue: u32,
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_create_logical_device(
+        instance: &Arc<BMLInstance>,
+        device: &vk::PhysicalDevice,
+        queue_families: &VulkanQueueFamilies,
+        features: &VulkanDeviceFeatures,
+        shader_features: &SPIRVTargetFeatures,
+    ) -> Result<ash::Device> {
+        let priorities = queue_families
+            .families()
+            .into_iter()
+            .map(|family| {
+                let priorities = (0..queue_families.queue_count(family))
+                    .map(|index| VulkanQueueFamilies::priority(index).to_vulkan())
+                    .collect::<Vec<_>>();
+
+                (family, priorities)
+            })
+            .collect::<Vec<_>>();
+
+        let queue_info = priorities
+            .iter()
+            .map(|(family, priorities)| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(*family)
+                    .queue_priorities(priorities)
+            })
+            .collect::<Vec<_>>();
+
+        let enable_atomic_int64 =
+            shader_features.buffer_int64_atomics || shader_features.shared_int64_atomics;
+        let enable_atomic_float = shader_features.buffer_float32_atomics
+            || shader_features.shared_float32_atomics
+            || shader_features.image_float32_atomics
+            || shader_features.buffer_float32_atomic_add
+            || shader_features.shared_float32_atomic_add
+            || shader_features.image_float32_atomic_add
+            || shader_features.buffer_float64_atomic_add
+            || shader_features.shared_float64_atomic_add;
+        let enable_image_atomic_int64 = shader_features.image_int64_atomics;
+        let enable_descriptor_indexing =
+            shader_features.runtime_descriptor_arrays || shader_features.non_uniform_indexing;
+        let enable_buffer_device_address = shader_features.buffer_device_address;
+        let enable_demote = shader_features.demote_to_helper_invocation;
+        let enable_float16_int8 = shader_features.float16 || shader_features.int8;
+        let enable_8bit_storage = shader_features.storage_buffer_8bit;
+        let enable_16bit_storage = shader_features.storage_buffer_16bit;
+        let enable_scalar_block_layout = shader_features.scalar_block_layout;
+
+        let mut device_extensions = Self::vulkan_required_extensions(instance);
+
+        let below_1_2 = Self::vulkan_device_api_version(instance, device) < vk::API_VERSION_1_2;
+
+        if enable_atomic_int64 && below_1_2 {
+            device_extensions.push(ash::khr::shader_atomic_int64::NAME);
+        }
+
+        if enable_descriptor_indexing && below_1_2 {
+            device_extensions.push(ash::ext::descriptor_indexing::NAME);
+        }
+
+        if enable_buffer_device_address && below_1_2 {
+            device_extensions.push(ash::khr::buffer_device_address::NAME);
+        }
+
+        if enable_float16_int8 && below_1_2 {
+            device_extensions.push(ash::khr::shader_float16_int8::NAME);
+        }
+
+        if enable_8bit_storage && below_1_2 {
+            device_extensions.push(ash::khr::_8bit_storage::NAME);
+        }
+
+        if enable_scalar_block_layout && below_1_2 {
+            device_extensions.push(ash::ext::scalar_block_layout::NAME);
+        }
+
+        if enable_demote && Self::vulkan_device_api_version(instance, device) < vk::API_VERSION_1_3
+        {
+            device_extensions.push(ash::ext::shader_demote_to_helper_invocation::NAME);
+        }
+
+        if shader_features.stencil_export {
+            device_extensions.push(ash::ext::shader_stencil_export::NAME);
+        }
+
+        if shader_features.non_semantic_info
+            && Self::vulkan_device_api_version(instance, device) < vk::API_VERSION_1_3
+        {
+            device_extensions.push(ash::khr::shader_non_semantic_info::NAME);
+        }
+
+        if enable_atomic_float {
+            device_extensions.push(ash::ext::shader_atomic_float::NAME);
+        }
+
+        if enable_image_atomic_int64 {
+            device_extensions.push(ash::ext::shader_image_atomic_int64::NAME);
+        }
+
+        if features.viewport_index_layer {
+            device_extensions.push(ash::ext::shader_viewport_index_layer::NAME);
+        }
+
+        if features.mesh_shader {
+            device_extensions.push(ash::ext::mesh_shader::NAME);
+        }
+
+        if features.ray_query {
+            device_extensions.push(ash::khr::ray_query::NAME);
+            device_extensions.push(ash::khr::acceleration_structure::NAME);
+            device_extensions.push(ash::khr::deferred_host_operations::NAME);
+        }
+
+        let device_extensions = device_extensions
+            .iter()
+            .map(|ext| ext.as_ptr())
+            .collect::<Vec<_>>();
+
+        let mut atomic_int64 = vk::PhysicalDeviceShaderAtomicInt64Features::default()
+            .shader_buffer_int64_atomics(shader_features.buffer_int64_atomics)
+            .shader_shared_int64_atomics(shader_features.shared_int64_atomics);
+
+        let mut atomic_float = vk::PhysicalDeviceShaderAtomicFloatFeaturesEXT::default()
+            .shader_buffer_float32_atomics(shader_features.buffer_float32_atomics)
+            .shader_shared_float32_atomics(shader_features.shared_float32_atomics)
+            .shader_image_float32_atomics(shader_features.image_float32_atomics)
+            .shader_buffer_float32_atomic_add(shader_features.buffer_float32_atomic_add)
+            .shader_shared_float32_atomic_add(shader_features.shared_float32_atomic_add)
+            .shader_image_float32_atomic_add(shader_features.image_float32_atomic_add)
+            .shader_buffer_float64_atomic_add(shader_features.buffer_float64_atomic_add)
+            .shader_shared_float64_atomic_add(shader_features.shared_float64_atomic_add);
+
+        let mut image_atomic_int64 = vk::PhysicalDeviceShaderImageAtomicInt64FeaturesEXT::default()
+            .shader_image_int64_atomics(shader_features.image_int64_atomics);
+
+        let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default()
+            .runtime_descriptor_array(shader_features.runtime_descriptor_arrays)
+            .descriptor_binding_partially_bound(shader_features.runtime_descriptor_arrays)
+            .descriptor_binding_variable_descriptor_count(shader_features.runtime_descriptor_arrays)
+            .shader_sampled_image_array_non_uniform_indexing(shader_features.non_uniform_indexing)
+            .shader_storage_image_array_non_uniform_indexing(shader_features.non_uniform_indexing)
+            .shader_storage_buffer_array_non_uniform_indexing(shader_features.non_uniform_indexing);
+
+        let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default()
+            .buffer_device_address(shader_features.buffer_device_address);
+
+        let mut demote = vk::PhysicalDeviceShaderDemoteToHelperInvocationFeatures::default()
+            .shader_demote_to_helper_invocation(enable_demote);
+
+        let mut float16_int8 = vk::PhysicalDeviceShaderFloat16Int8Features::default()
+            .shader_float16(shader_features.float16)
+            .shader_int8(shader_features.int8);
+
+        let mut storage_8bit = vk::PhysicalDevice8BitStorageFeatures::default()
+            .storage_buffer8_bit_access(enable_8bit_storage);
+
+        let mut storage_16bit = vk::PhysicalDevice16BitStorageFeatures::default()
+            .storage_buffer16_bit_access(enable_16bit_storage);
+
+        let mut scalar_block_layout = vk::PhysicalDeviceScalarBlockLayoutFeatures::default()
+            .scalar_block_layout(enable_scalar_block_layout);
+
+        let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default()
+            .mesh_shader(true)
+            .task_shader(true);
+
+        let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default().ray_query(true);
+
+        let mut acceleration_structure =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
+                .acceleration_structure(true);
+
+        let core_features = features
+            .core
+            .shader_int16(shader_features.int16)
+            .shader_int64(shader_features.int64);
+
+        let mut device_create_info = vk::DeviceCreateInfo::default()
+            .queue_create_infos(&queue_info)
+            .enabled_extension_names(&device_extensions)
+            .enabled_features(&core_features);
+
+        if enable_atomic_int64 {
+            device_create_info = device_create_info.push_next(&mut atomic_int64);
+        }
+
+        if enable_atomic_float {
+            device_create_info = device_create_info.push_next(&mut atomic_float);
+        }
+
+        if enable_image_atomic_int64 {
+            device_create_info = device_create_info.push_next(&mut image_atomic_int64);
+        }
+
+        if enable_descriptor_indexing {
+            device_create_info = device_create_info.push_next(&mut descriptor_indexing);
+        }
+
+        if enable_buffer_device_address {
+            device_create_info = device_create_info.push_next(&mut buffer_device_address);
+        }
+
+        if enable_demote {
+            device_create_info = device_create_info.push_next(&mut demote);
+        }
+
+        if enable_float16_int8 {
+            device_create_info = device_create_info.push_next(&mut float16_int8);
+        }
+
+        if enable_8bit_storage {
+            device_create_info = device_create_info.push_next(&mut storage_8bit);
+        }
+
+        if enable_16bit_storage {
+            device_create_info = device_create_info.push_next(&mut storage_16bit);
+        }
+
+        if enable_scalar_block_layout {
+            device_create_info = device_create_info.push_next(&mut scalar_block_layout);
+        }
+
+        if features.mesh_shader {
+            device_create_info = device_create_info.push_next(&mut mesh_shader);
+        }
+
+        if features.ray_query {
+            device_create_info = device_create_info
+                .push_next(&mut ray_query)
+                .push_next(&mut acceleration_structure);
+        }
+
+        Ok(unsafe {
+            instance
+                .vulkan_instance()
+                .create_device(*device, &device_create_info, None)?
+        })
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_device_check(
+        instance: &Arc<BMLInstance>,
+        device: &vk::PhysicalDevice,
+    ) -> Result<()> {
+        Self::vulkan_find_queue_families(instance, device)?;
+
+        Self::vulkan_check_extension_support(instance, device)?;
+
+        Ok(())
+    }
+
+    /// (Vulkan) The graphics family, and a family that can present to the
+    /// instance's surface. Headless instances don't look for the latter.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_find_queue_families(
+        instance: &Arc<BMLInstance>,
+        device: &vk::PhysicalDevice,
+    ) -> Result<VulkanQueueFamilies> {
+        let properties = unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_queue_family_properties(*device)
+        };
+
+        let families = properties
+            .iter()
+            .enumerate()
+            .filter(|(_, family)| family.queue_count > 0)
+            .map(|(index, family)| (index as u32, family))
+            .collect::<Vec<_>>();
+
+        let graphics = families
+            .iter()
+            .find(|(_, family)| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .map(|(index, _)| *index)
+            .ok_or_else(|| anyhow!("No graphics queue found."))?;
+
+        let compute = families
+            .iter()
+            .find(|(_, family)| {
+                family.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !family.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+            })
+            .map(|(index, _)| *index);
+
+        let transfer = families
+            .iter()
+            .find(|(_, family)| {
+                family.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !family
+                        .queue_flags
+                        .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|(index, _)| *index);
+
+        let queue_counts = properties
+            .iter()
+            .map(|family| family.queue_count)
+            .collect::<Vec<_>>();
+
+        let Some(surface) = instance.vulkan_surface() else {
+            return Ok(VulkanQueueFamilies {
+                graphics_queue: graphics,
+                present_queue: None,
+                compute_queue: compute,
+                transfer_queue: transfer,
+                queue_counts,
+            });
+        };
+
+        let supports_present = |index: u32| unsafe {
+            surface
+                .instance()
+                .get_physical_device_surface_support(*device, index, *surface.khr())
+        };
+
+        // Presenting from the graphics family saves an ownership transfer.
+        let present = match supports_present(graphics)? {
+            true => Some(graphics),
+            false => families
+                .iter()
+                .map(|(index, _)| *index)
+                .find(|index| supports_present(*index).unwrap_or(false)),
+        };
+
+        match present {
+            Some(present) => Ok(VulkanQueueFamilies {
+                graphics_queue: graphics,
+                present_queue: Some(present),
+                compute_queue: compute,
+                transfer_queue: transfer,
+                queue_counts,
+            }),
+            None => Err(anyhow!("No queue can present to the surface.")),
+        }
+    }
+
+    /// (Vulkan) Swapchains are only needed to present, headless instances
+    /// work with devices that have no WSI support.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_required_extensions(instance: &Arc<BMLInstance>) -> Vec<&'static CStr> {
+        let mut extensions = vec![];
+
+        if instance.vulkan_surface().is_some() {
+            extensions.push(ash::khr::swapchain::NAME);
+        }
+
+        if cfg!(any(target_os = "macos", target_os = "ios")) {
+            extensions.push(ash::khr::portability_subset::NAME);
+        }
+
+        extensions
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_check_extension_support(
+        instance: &Arc<BMLInstance>,
+        device: &vk::PhysicalDevice,
+    ) -> Result<()> {
+        let required_extensions = Self::vulkan_required_extensions(instance);
+
+        let extension_properties = unsafe {
+            instance
+                .vulkan_instance()
+                .enumerate_device_extension_properties(*device)?
+        };
+
+        for required in required_extensions.iter() {
+            if !Self::vulkan_has_extension(&extension_properties, required) {
+                return Err(anyhow!("No required extension found."));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_has_extension(
+        extension_properties: &[vk::ExtensionProperties],
+        extension: &CStr,
+    ) -> bool {
+        extension_properties.iter().any(|ext| {
+            let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+            extension == name
+        })
+    }
+
+    /// (Vulkan) The API version usable with `device`, which is capped by both
+    /// the instance and the driver.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_device_api_version(
+        instance: &Arc<BMLInstance>,
+        device: &vk::PhysicalDevice,
+    ) -> u32 {
+        let properties = unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_properties(*device)
+        };
+
+        instance.vulkan_api_version().min(properties.api_version)
+    }
+
+    /// (Vulkan) Query the optional shader features metalshaper may lower to,
+    /// such as 64-bit and floating point atomics, subgroup operations,
+    /// descriptor indexing for argument buffers or 8, 16 and 64-bit types.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_shader_features(
+        instance: &Arc<BMLInstance>,
+        device: &vk::PhysicalDevice,
+    ) -> Result<SPIRVTargetFeatures> {
+        let mut result = SPIRVTargetFeatures::default();
+
+        let api_version = Self::vulkan_device_api_version(instance, device);
+
+        let core_features = unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_features(*device)
+        };
+
+        result.int16 = core_features.shader_int16 == vk::TRUE;
+        result.int64 = core_features.shader_int64 == vk::TRUE;
+
+        // `vkGetPhysicalDeviceFeatures2` is core since Vulkan 1.1, older
+        // instances only get the baseline features.
+        if api_version < vk::API_VERSION_1_1 {
+            return Ok(result);
+        }
+
+        let extension_properties = unsafe {
+            instance
+                .vulkan_instance()
+                .enumerate_device_extension_properties(*device)?
+        };
+
+        let has_atomic_int64 = api_version >= vk::API_VERSION_1_2
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::khr::shader_atomic_int64::NAME,
+            );
+        let has_atomic_float =
+            Self::vulkan_has_extension(&extension_properties, ash::ext::shader_atomic_float::NAME);
+        let has_image_atomic_int64 = Self::vulkan_has_extension(
+            &extension_properties,
+            ash::ext::shader_image_atomic_int64::NAME,
+        );
+        let has_descriptor_indexing = api_version >= vk::API_VERSION_1_2
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::ext::descriptor_indexing::NAME,
+            );
+        let has_buffer_device_address = api_version >= vk::API_VERSION_1_2
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::khr::buffer_device_address::NAME,
+            );
+        let has_demote = api_version >= vk::API_VERSION_1_3
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::ext::shader_demote_to_helper_invocation::NAME,
+            );
+
+        let has_float16_int8 = api_version >= vk::API_VERSION_1_2
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::khr::shader_float16_int8::NAME,
+            );
+        let has_8bit_storage = api_version >= vk::API_VERSION_1_2
+            || Self::vulkan_has_extension(&extension_properties, ash::khr::_8bit_storage::NAME);
+        let has_scalar_block_layout = api_version >= vk::API_VERSION_1_2
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::ext::scalar_block_layout::NAME,
+            );
+
+        // Stencil export and non-semantic info have no feature bits, the
+        // extension is all it takes.
+        result.stencil_export = Self::vulkan_has_extension(
+            &extension_properties,
+            ash::ext::shader_stencil_export::NAME,
+        );
+        result.non_semantic_info = api_version >= vk::API_VERSION_1_3
+            || Self::vulkan_has_extension(
+                &extension_properties,
+                ash::khr::shader_non_semantic_info::NAME,
+            );
+
+        let mut atomic_int64 = vk::PhysicalDeviceShaderAtomicInt64Features::default();
+        let mut atomic_float = vk::PhysicalDeviceShaderAtomicFloatFeaturesEXT::default();
+        let mut image_atomic_int64 = vk::PhysicalDeviceShaderImageAtomicInt64FeaturesEXT::default();
+        let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut buffer_device_address = vk::PhysicalDeviceBufferDeviceAddressFeatures::default();
+        let mut demote = vk::PhysicalDeviceShaderDemoteToHelperInvocationFeatures::default();
+        let mut float16_int8 = vk::PhysicalDeviceShaderFloat16Int8Features::default();
+        let mut storage_8bit = vk::PhysicalDevice8BitStorageFeatures::default();
+        let mut storage_16bit = vk::PhysicalDevice16BitStorageFeatures::default();
+        let mut scalar_block_layout = vk::PhysicalDeviceScalarBlockLayoutFeatures::default();
+
+        // 16-bit storage is core since Vulkan 1.1.
+        let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut storage_16bit);
+
+        if has_atomic_int64 {
+            features = features.push_next(&mut atomic_int64);
+        }
+
+        if has_atomic_float {
+            features = features.push_next(&mut atomic_float);
+        }
+
+        if has_image_atomic_int64 {
+            features = features.push_next(&mut image_atomic_int64);
+        }
+
+        if has_descriptor_indexing {
+            features = features.push_next(&mut descriptor_indexing);
+        }
+
+        if has_buffer_device_address {
+            features = features.push_next(&mut buffer_device_address);
+        }
+
+        if has_demote {
+            features = features.push_next(&mut demote);
+        }
+
+        if has_float16_int8 {
+            features = features.push_next(&mut float16_int8);
+        }
+
+        if has_8bit_storage {
+            features = features.push_next(&mut storage_8bit);
+        }
+
+        if has_scalar_block_layout {
+            features = features.push_next(&mut scalar_block_layout);
+        }
+
+        unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_features2(*device, &mut features);
+        }
+
+        result.buffer_int64_atomics = atomic_int64.shader_buffer_int64_atomics == vk::TRUE;
+        result.shared_int64_atomics = atomic_int64.shader_shared_int64_atomics == vk::TRUE;
+        result.image_int64_atomics = image_atomic_int64.shader_image_int64_atomics == vk::TRUE;
+        result.buffer_float32_atomics = atomic_float.shader_buffer_float32_atomics == vk::TRUE;
+        result.shared_float32_atomics = atomic_float.shader_shared_float32_atomics == vk::TRUE;
+        result.image_float32_atomics = atomic_float.shader_image_float32_atomics == vk::TRUE;
+        result.buffer_float32_atomic_add =
+            atomic_float.shader_buffer_float32_atomic_add == vk::TRUE;
+        result.shared_float32_atomic_add =
+            atomic_float.shader_shared_float32_atomic_add == vk::TRUE;
+        result.image_float32_atomic_add = atomic_float.shader_image_float32_atomic_add == vk::TRUE;
+        result.buffer_float64_atomic_add =
+            atomic_float.shader_buffer_float64_atomic_add == vk::TRUE;
+        result.shared_float64_atomic_add =
+            atomic_float.shader_shared_float64_atomic_add == vk::TRUE;
+
+        // Unbounded arrays are bound partially and sized at allocation time.
+        result.runtime_descriptor_arrays = descriptor_indexing.runtime_descriptor_array == vk::TRUE
+            && descriptor_indexing.descriptor_binding_partially_bound == vk::TRUE
+            && descriptor_indexing.descriptor_binding_variable_descriptor_count == vk::TRUE;
+        result.non_uniform_indexing =
+            descriptor_indexing.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+                && descriptor_indexing.shader_storage_image_array_non_uniform_indexing == vk::TRUE
+                && descriptor_indexing.shader_storage_buffer_array_non_uniform_indexing == vk::TRUE;
+        result.buffer_device_address = buffer_device_address.buffer_device_address == vk::TRUE;
+        result.demote_to_helper_invocation = demote.shader_demote_to_helper_invocation == vk::TRUE;
+        result.float16 = float16_int8.shader_float16 == vk::TRUE;
+        result.int8 = float16_int8.shader_int8 == vk::TRUE;
+        result.storage_buffer_8bit = storage_8bit.storage_buffer8_bit_access == vk::TRUE;
+        result.storage_buffer_16bit = storage_16bit.storage_buffer16_bit_access == vk::TRUE;
+        result.scalar_block_layout = scalar_block_layout.scalar_block_layout == vk::TRUE;
+
+        let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut subgroup);
+
+        unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_properties2(*device, &mut properties);
+        }
+
+        let operations = subgroup.supported_operations;
+        let stages = subgroup.supported_stages;
+
+        result.subgroup = SPIRVSubgroupFeatures {
+            size: subgroup.subgroup_size,
+            basic: operations.contains(vk::SubgroupFeatureFlags::BASIC),
+            vote: operations.contains(vk::SubgroupFeatureFlags::VOTE),
+            arithmetic: operations.contains(vk::SubgroupFeatureFlags::ARITHMETIC),
+            ballot: operations.contains(vk::SubgroupFeatureFlags::BALLOT),
+            shuffle: operations.contains(vk::SubgroupFeatureFlags::SHUFFLE),
+            shuffle_relative: operations.contains(vk::SubgroupFeatureFlags::SHUFFLE_RELATIVE),
+            clustered: operations.contains(vk::SubgroupFeatureFlags::CLUSTERED),
+            quad: operations.contains(vk::SubgroupFeatureFlags::QUAD),
+            in_vertex: stages.contains(vk::ShaderStageFlags::VERTEX),
+            in_fragment: stages.contains(vk::ShaderStageFlags::FRAGMENT),
+            in_kernel: stages.contains(vk::ShaderStageFlags::COMPUTE),
+        };
+
+        Ok(result)
+    }
+
+    /// (Vulkan) Create the descriptor set layout matching an argument buffer
+    /// translated by metalshaper. An unbounded array needs descriptor
+    /// indexing and gets whatever the other members leave of the limits.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_create_argument_buffer_layout(
+        &self,
+        layout: &SPIRVArgumentBufferLayout,
+        stages: vk::ShaderStageFlags,
+    ) -> Result<vk::DescriptorSetLayout> {
+        let limits = unsafe {
+            self.instance
+                .vulkan_instance()
+                .get_physical_device_properties(self.vulkan_device.physical_device)
+                .limits
+        };
+
+        let mut bindings = vec![];
+        // The index of the unbounded array and the limit of its type.
+        let mut unbounded = None;
+
+        if let Some(block) = &layout.block {
+            bindings.push(
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(block.binding)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(stages),
+            );
+        }
+
+        for entry in &layout.entries {
+            let SPIRVArgumentLocation::Descriptor {
+                binding,
+                descriptor_type,
+                count,
+            } = entry.location
+            else {
+                continue;
+            };
+
+            let (descriptor_type, max_per_stage, max_per_set) = match descriptor_type {
+                SPIRVDescriptorType::Sampler => (
+                    vk::DescriptorType::SAMPLER,
+                    limits.max_per_stage_descriptor_samplers,
+                    limits.max_descriptor_set_samplers,
+                ),
+                SPIRVDescriptorType::SampledImage => (
+                    vk::DescriptorType::SAMPLED_IMAGE,
+                    limits.max_per_stage_descriptor_sampled_images,
+                    limits.max_descriptor_set_sampled_images,
+                ),
+                SPIRVDescriptorType::StorageImage => (
+                    vk::DescriptorType::STORAGE_IMAGE,
+                    limits.max_per_stage_descriptor_storage_images,
+                    limits.max_descriptor_set_storage_images,
+                ),
+                SPIRVDescriptorType::UniformBuffer => (
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                    limits.max_per_stage_descriptor_uniform_buffers,
+                    limits.max_descriptor_set_uniform_buffers,
+                ),
+                SPIRVDescriptorType::StorageBuffer => (
+                    vk::DescriptorType::STORAGE_BUFFER,
+                    limits.max_per_stage_descriptor_storage_buffers,
+                    limits.max_descriptor_set_storage_buffers,
+                ),
+            };
+
+            if count.is_none() {
+                if !self.vulkan_device.shader_features.runtime_descriptor_arrays {
+                    return Err(BMLError::UnsupportedFeature(String::from(
+                        "Unbounded arrays in argument buffers need descriptor indexing.",
+                    ))
+                    .into());
+                }
+
+                if unbounded.is_some() {
+                    return Err(BMLError::InvalidUsage(String::from(
+                        "An argument buffer can only have one unbounded array.",
+                    ))
+                    .into());
+                }
+
+                unbounded = Some((bindings.len(), max_per_stage.min(max_per_set)));
+            }
+
+            bindings.push(
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding)
+                    .descriptor_type(descriptor_type)
+                    .descriptor_count(count.unwrap_or(0))
+                    .stage_flags(stages),
+            );
+        }
+
+        if let Some((index, max_count)) = unbounded {
+            bindings[index].descriptor_count = Self::vulkan_variable_descriptor_count(
+                &bindings,
+                index,
+                max_count,
+                limits.max_per_stage_resources,
+            )?;
+        }
+
+        let binding_flags =
+            Self::vulkan_argument_binding_flags(&bindings, unbounded.map(|(index, _)| index));
+
+        let mut create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+
+        let mut flags_info =
+            vk::DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&binding_flags);
+
+        if self.vulkan_device.shader_features.runtime_descriptor_arrays {
+            create_info = create_info.push_next(&mut flags_info);
+        }
+
+        Ok(unsafe {
+            self.vulkan_device
+                .logical_device
+                .create_descriptor_set_layout(&create_info, None)?
+        })
+    }
+
+    /// (Vulkan) The count of the unbounded array at `index`: what the other
+    /// bindings leave of `max_count` for its type and of the stage's
+    /// resources. Only the highest binding can have a variable count.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_variable_descriptor_count(
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        index: usize,
+        max_count: u32,
+        max_per_stage_resources: u32,
+    ) -> Result<u32> {
+        let unbounded = bindings[index];
+        let others = || {
+            bindings
+                .iter()
+                .enumerate()
+                .filter(move |(other, _)| *other != index)
+                .map(|(_, binding)| binding)
+        };
+
+        if others().any(|binding| binding.binding > unbounded.binding) {
+            return Err(BMLError::InvalidUsage(String::from(
+                "Only the last member of an argument buffer can be an unbounded array.",
+            ))
+            .into());
+        }
+
+        let used: u32 = others().map(|binding| binding.descriptor_count).sum();
+        let used_of_type: u32 = others()
+            .filter(|binding| binding.descriptor_type == unbounded.descriptor_type)
+            .map(|binding| binding.descriptor_count)
+            .sum();
+
+        let count = max_count
+            .saturating_sub(used_of_type)
+            .min(max_per_stage_resources.saturating_sub(used));
+
+        if count == 0 {
+            return Err(BMLError::UnsupportedFeature(String::from(
+                "No descriptors are left for the unbounded array of the argument buffer.",
+            ))
+            .into());
+        }
+
+        Ok(count)
+    }
+
+    /// (Vulkan) Metal doesn't require every resource of an argument buffer
+    /// to be set, so neither should we. The unbounded array, if any, has a
+    /// variable count.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_argument_binding_flags(
+        bindings: &[vk::DescriptorSetLayoutBinding],
+        unbounded: Option<usize>,
+    ) -> Vec<vk::DescriptorBindingFlags> {
+        let mut flags = vec![vk::DescriptorBindingFlags::PARTIALLY_BOUND; bindings.len()];
+
+        if let Some(index) = unbounded {
+            flags[index] |= vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT;
+        }
+
+        flags
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_all_devices(_instance: &Arc<BMLInstance>) -> Result<Vec<MTLDeviceEntry>> {
+        #[cfg(target_os = "macos")]
+        let devices = objc2_metal::MTLCopyAllDevices().to_vec();
+
+        #[cfg(target_os = "ios")]
+        let devices = MTLCreateSystemDefaultDevice()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut entries = devices
+            .into_iter()
+            .map(MTLDeviceEntry::metal_new)
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|entry| entry.registry_id);
+
+        Ok(entries)
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_create_with_entry(
+        instance: Arc<BMLInstance>,
+        entry: &MTLDeviceEntry,
+    ) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            name: entry.name.clone(),
+            instance,
+            metal_device: entry.metal_device.clone(),
+        }))
+    }
+
+    /// The system default device, which `create_with_preference` may not
+    /// pick on machines with several GPUs.
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_create(instance: Arc<BMLInstance>) -> Result<Arc<Self>> {
+        let metal_device = MTLCreateSystemDefaultDevice();
+
+        let metal_device = match metal_device {
+            Some(m) => m,
+            None => return Err(anyhow!("No device found.")),
+        };
+
+        let name = metal_device.name().to_string();
+
+        Ok(Arc::new(Self {
+            name,
+            instance,
+            metal_device,
+        }))
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_device(&self) -> &Retained<ProtocolObject<dyn MetalMTLDevice>> {
+        &self.metal_device
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_device(&self) -> &VulkanMTLDevice {
+        &self.vulkan_device
+    }
+
+    /// (Vulkan) Names `handle` for validation messages and debuggers. Does
+    /// nothing without validation, which `VK_EXT_debug_utils` comes with.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_set_object_name<H: vk::Handle>(&self, handle: H, name: &str) -> Result<()> {
+        if !self.instance.vulkan_validation() {
+            return Ok(());
+        }
+
+        let name = std::ffi::CString::new(name.replace('\0', ""))?;
+        let debug_utils = ash::ext::debug_utils::Device::new(
+            self.instance.vulkan_instance(),
+            &self.vulkan_device.logical_device,
+        );
+
+        unsafe {
+            debug_utils.set_debug_utils_object_name(
+                &vk::DebugUtilsObjectNameInfoEXT::default()
+                    .object_handle(handle)
+                    .object_name(&name),
+            )?
+        };
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Everything made from the device holds on to it, so only the garbage they
+/// left behind is still around.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLDevice {
+    fn drop(&mut self) {
+        let device = &self.vulkan_device;
+
+        // Garbage is destroyed even if waiting fails, the device is about to
+        // go away regardless.
+        let _ = unsafe { device.logical_device.device_wait_idle() };
+
+        for queue in &device.queues {
+            queue.idle();
+        }
+
+        device.collect_garbage();
+
+        unsafe {
+            device.allocator.destroy(&device.logical_device);
+            device.logical_device.destroy_device(None);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MTLSize {
+    pub width: u64,
+    pub height: u64,
+    pub depth: u64,
+}
+
+impl MTLSize {
+    pub fn new(width: u64, height: u64, depth: u64) -> Self {
+        Self {
+            width,
+            height,
+            depth,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MTLArgumentBuffersTier {
+    Tier1,
+    Tier2,
+}
+
+/// Which formats kernels may both read and write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MTLReadWriteTextureTier {
+    None,
+    /// `r32Float`, `r32Uint` and `r32Sint`.
+    Tier1,
+    /// Tier 1 plus the 8, 16 and 32-bit one and four channel formats.
+    Tier2,
+}
+
+/// What `MTLDevice::supports_family` checks for.
+///
+/// Vulkan devices are given a family when they have everything it needs,
+/// so code written against Metal can keep its checks:
+///
+/// | Family    | Vulkan requirements |
+/// |-----------|---------------------|
+/// | `Common1` | Any device. |
+/// | `Common2` | `multiDrawIndirect`, `drawIndirectFirstInstance`, `tessellationShader`, `occlusionQueryPrecise` and `fragmentStoresAndAtomics`. |
+/// | `Common3` | `Common2`, `imageCubeArray`, layered rendering (`VK_EXT_shader_viewport_index_layer`), `VK_EXT_shader_stencil_export` and unbounded descriptor arrays. |
+/// | `Mac2`    | `Common3`, `textureCompressionBC`, `depthClamp` and `samplerAnisotropy`. |
+/// | `Metal3`  | `Common3`, task and mesh shaders (`VK_EXT_mesh_shader`), ray queries (`VK_KHR_ray_query` and `VK_KHR_acceleration_structure`), buffer device addresses and non-uniform descriptor indexing. |
+/// | `Apple1` to `Apple9` | Never, tile shading, imageblocks and raster order groups have no Vulkan equivalent. |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MTLGPUFamily {
+    Apple1,
+    Apple2,
+    Apple3,
+    Apple4,
+    Apple5,
+    Apple6,
+    Apple7,
+    Apple8,
+    Apple9,
+    Mac2,
+    Common1,
+    Common2,
+    Common3,
+    Metal3,
+}
+
+impl MTLGPUFamily {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> objc2_metal::MTLGPUFamily {
+        match self {
+            MTLGPUFamily::Apple1 => objc2_metal::MTLGPUFamily::Apple1,
+            MTLGPUFamily::Apple2 => objc2_metal::MTLGPUFamily::Apple2,
+            MTLGPUFamily::Apple3 => objc2_metal::MTLGPUFamily::Apple3,
+            MTLGPUFamily::Apple4 => objc2_metal::MTLGPUFamily::Apple4,
+            MTLGPUFamily::Apple5 => objc2_metal::MTLGPUFamily::Apple5,
+            MTLGPUFamily::Apple6 => objc2_metal::MTLGPUFamily::Apple6,
+            MTLGPUFamily::Apple7 => objc2_metal::MTLGPUFamily::Apple7,
+            MTLGPUFamily::Apple8 => objc2_metal::MTLGPUFamily::Apple8,
+            MTLGPUFamily::Apple9 => objc2_metal::MTLGPUFamily::Apple9,
+            MTLGPUFamily::Mac2 => objc2_metal::MTLGPUFamily::Mac2,
+            MTLGPUFamily::Common1 => objc2_metal::MTLGPUFamily::Common1,
+            MTLGPUFamily::Common2 => objc2_metal::MTLGPUFamily::Common2,
+            MTLGPUFamily::Common3 => objc2_metal::MTLGPUFamily::Common3,
+            MTLGPUFamily::Metal3 => objc2_metal::MTLGPUFamily::Metal3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MTLDeviceType {
+    Discrete,
+    Integrated,
+    Virtual,
+    /// A CPU implementation, such as lavapipe or SwiftShader.
+    Software,
+    Other,
+}
+
+/// Which device `MTLDevice::create_with_preference` picks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MTLDevicePreference {
+    /// Discrete over integrated GPUs, software renderers last.
+    #[default]
+    HighPerformance,
+    /// Integrated over discrete GPUs, software renderers last.
+    LowPower,
+}
+
+impl MTLDevicePreference {
+    /// Lower is better.
+    pub fn rank(&self, ty: MTLDeviceType) -> u32 {
+        match (self, ty) {
+            (MTLDevicePreference::HighPerformance, MTLDeviceType::Discrete) => 0,
+            (MTLDevicePreference::HighPerformance, MTLDeviceType::Integrated) => 1,
+            (MTLDevicePreference::LowPower, MTLDeviceType::Integrated) => 0,
+            (MTLDevicePreference::LowPower, MTLDeviceType::Discrete) => 1,
+            (_, MTLDeviceType::Virtual) => 2,
+            (_, MTLDeviceType::Other) => 3,
+            (_, MTLDeviceType::Software) => 4,
+        }
+    }
+}
+
+/// A device `MTLDevice::all_devices` found, to create an `MTLDevice` from.
+#[derive(Debug, Clone)]
+pub struct MTLDeviceEntry {
+    pub name: String,
+    /// The PCI vendor ID, 0 on Metal, which doesn't expose it.
+    pub vendor_id: u32,
+    /// The PCI device ID, 0 on Metal.
+    pub device_id: u32,
+    pub ty: MTLDeviceType,
+    /// Identifies the device across processes. Metal's `registryID`, the
+    /// LUID or a hash of the device UUID on Vulkan.
+    pub registry_id: u64,
+    /// Whether the CPU and GPU share memory. On Vulkan, integrated and
+    /// software devices are assumed to.
+    pub has_unified_memory: bool,
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    metal_device: Retained<ProtocolObject<dyn MetalMTLDevice>>,
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_physical_device: vk::PhysicalDevice,
+}
+
+impl MTLDeviceEntry {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_new(metal_device: Retained<ProtocolObject<dyn MetalMTLDevice>>) -> Self {
+        let has_unified_memory = metal_device.hasUnifiedMemory();
+
+        #[cfg(target_os = "macos")]
+        let ty = match (metal_device.isLowPower(), metal_device.isRemovable()) {
+            (true, _) => MTLDeviceType::Integrated,
+            (_, true) => MTLDeviceType::Discrete,
+            _ if has_unified_memory => MTLDeviceType::Integrated,
+            _ => MTLDeviceType::Discrete,
+        };
+
+        #[cfg(target_os = "ios")]
+        let ty = MTLDeviceType::Integrated;
+
+        Self {
+            name: metal_device.name().to_string(),
+            vendor_id: 0,
+            device_id: 0,
+            ty,
+            registry_id: metal_device.registryID(),
+            has_unified_memory,
+            metal_device,
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_new(instance: &Arc<BMLInstance>, device: vk::PhysicalDevice) -> Result<Self> {
+        let properties = unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_properties(device)
+        };
+
+        let name = unsafe {
+            CStr::from_ptr(properties.device_name.as_ptr())
+                .to_str()?
+                .to_string()
+        };
+
+        let ty = match properties.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => MTLDeviceType::Discrete,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => MTLDeviceType::Integrated,
+            vk::PhysicalDeviceType::VIRTUAL_GPU => MTLDeviceType::Virtual,
+            vk::PhysicalDeviceType::CPU => MTLDeviceType::Software,
+            _ => MTLDeviceType::Other,
+        };
+
+        Ok(Self {
+            name,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            ty,
+            registry_id: Self::vulkan_registry_id(instance, device, &properties),
+            has_unified_memory: matches!(ty, MTLDeviceType::Integrated | MTLDeviceType::Software),
+            vulkan_physical_device: device,
+        })
+    }
+
+    /// (Vulkan) The LUID where there is one, the two halves of the device
+    /// UUID folded together otherwise. Vulkan 1.0 devices have neither and
+    /// fall back to their vendor and device IDs.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_registry_id(
+        instance: &Arc<BMLInstance>,
+        device: vk::PhysicalDevice,
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> u64 {
+        if MTLDevice::vulkan_device_api_version(instance, &device) < vk::API_VERSION_1_1 {
+            return ((properties.vendor_id as u64) << 32) | properties.device_id as u64;
+        }
+
+        let mut id = vk::PhysicalDeviceIDProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut id);
+
+        unsafe {
+            instance
+                .vulkan_instance()
+                .get_physical_device_properties2(device, &mut properties2);
+        }
+
+        if id.device_luid_valid == vk::TRUE {
+            return u64::from_le_bytes(id.device_luid);
+        }
+
+        let (low, high) = id.device_uuid.split_at(8);
+
+        u64::from_le_bytes(low.try_into().unwrap_or_default())
+            ^ u64::from_le_bytes(high.try_into().unwrap_or_default())
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_physical_device(&self) -> vk::PhysicalDevice {
+        self.vulkan_physical_device
+    }
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub struct VulkanMTLDevice {
+    physical_device: vk::PhysicalDevice,
+    logical_device: ash::Device,
+    queue_families: VulkanQueueFamilies,
+    shader_features: SPIRVTargetFeatures,
+    coordinate_fixups: SPIRVCoordinateFixups,
+    properties: vk::PhysicalDeviceProperties,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    max_buffer_length: u64,
+    read_write_texture_tier: MTLReadWriteTextureTier,
+    features: VulkanDeviceFeatures,
+    gpu_families: Vec<MTLGPUFamily>,
+    has_unified_memory: bool,
+    allocator: VulkanAllocator,
+    queues: Vec<Arc<VulkanQueue>>,
+    /// Handles dropped while the GPU may still use them, with the
+    /// submission count of every queue at the time.
+    garbage: Mutex<Vec<(Vec<u64>, VulkanGarbage)>>,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanMTLDevice {
+    pub fn physical(&self) -> &vk::PhysicalDevice {
+        &self.physical_device
+    }
+
+    pub fn logical(&self) -> &ash::Device {
+        &self.logical_device
+    }
+
+    pub fn queue_families(&self) -> &VulkanQueueFamilies {
+        &self.queue_families
+    }
+
+    pub fn shader_features(&self) -> &SPIRVTargetFeatures {
+        &self.shader_features
+    }
+
+    /// The optional features enabled on the device.
+    pub fn features(&self) -> &VulkanDeviceFeatures {
+        &self.features
+    }
+
+    /// The coordinate fixups shaders are translated with, which viewports
+    /// and front-facing windings have to agree with.
+    pub fn coordinate_fixups(&self) -> &SPIRVCoordinateFixups {
+        &self.coordinate_fixups
+    }
+
+    pub fn properties(&self) -> &vk::PhysicalDeviceProperties {
+        &self.properties
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    /// The first memory type of `type_bits` with the `required` and
+    /// `preferred` properties, or only the `required` ones.
+    pub fn memory_type_index(
+        &self,
+        type_bits: u32,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+    ) -> Option<u32> {
+        let memory_types = &self.memory_properties.memory_types
+            [..self.memory_properties.memory_type_count as usize];
+
+        let find = |flags: vk::MemoryPropertyFlags| {
+            (0..memory_types.len() as u32).find(|&index| {
+                type_bits & (1 << index) != 0
+                    && memory_types[index as usize].property_flags.contains(flags)
+            })
+        };
+
+        find(required | preferred).or_else(|| find(required))
+    }
+
+    pub fn allocator(&self) -> &VulkanAllocator {
+        &self.allocator
+    }
+
+    /// Memory for `buffer`, bound to it.
+    pub fn allocate_buffer_memory(
+        &self,
+        buffer: vk::Buffer,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+        lifetime: VulkanAllocationLifetime,
+    ) -> Result<VulkanAllocation> {
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+
+        unsafe {
+            match self.allocator.dedicated_allocation() {
+                true => self.logical_device.get_buffer_memory_requirements2(
+                    &vk::BufferMemoryRequirementsInfo2::default().buffer(buffer),
+                    &mut requirements,
+                ),
+                false => {
+                    requirements.memory_requirements =
+                        self.logical_device.get_buffer_memory_requirements(buffer)
+                }
+            }
+        };
+
+        let allocation = self.allocate(
+            requirements.memory_requirements,
+            required,
+            preferred,
+            VulkanResourceTiling::Linear,
+            lifetime,
+            Some(VulkanDedicatedResource::Buffer(buffer)),
+            dedicated.prefers_dedicated_allocation == vk::TRUE,
+        )?;
+
+        let bound = unsafe {
+            self.logical_device
+                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+        };
+
+        if let Err(e) = bound {
+            unsafe { self.allocator.free(&self.logical_device, allocation) };
+            return Err(e.into());
+        }
+
+        Ok(allocation)
+    }
+
+    /// Memory for `image`, bound to it. Large images get their own.
+    pub fn allocate_image_memory(
+        &self,
+        image: vk::Image,
+        tiling: vk::ImageTiling,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+    ) -> Result<VulkanAllocation> {
+        let mut dedicated = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default().push_next(&mut dedicated);
+
+        unsafe {
+            match self.allocator.dedicated_allocation() {
+                true => self.logical_device.get_image_memory_requirements2(
+                    &vk::ImageMemoryRequirementsInfo2::default().image(image),
+                    &mut requirements,
+                ),
+                false => {
+                    requirements.memory_requirements =
+                        self.logical_device.get_image_memory_requirements(image)
+                }
+            }
+        };
+
+        let allocation = self.allocate(
+            requirements.memory_requirements,
+            required,
+            preferred,
+            match tiling {
+                vk::ImageTiling::LINEAR => VulkanResourceTiling::Linear,
+                _ => VulkanResourceTiling::Optimal,
+            },
+            VulkanAllocationLifetime::Long,
+            Some(VulkanDedicatedResource::Image(image)),
+            dedicated.prefers_dedicated_allocation == vk::TRUE,
+        )?;
+
+        let bound = unsafe {
+            self.logical_device
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+        };
+
+        if let Err(e) = bound {
+            unsafe { self.allocator.free(&self.logical_device, allocation) };
+            return Err(e.into());
+        }
+
+        Ok(allocation)
+    }
+
+    /// A `VkDeviceMemory` of its own for an `MTLHeap`, of one of the memory
+    /// types in `type_bits`.
+    pub fn allocate_heap_memory(
+        &self,
+        size: u64,
+        type_bits: u32,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+    ) -> Result<VulkanAllocation> {
+        self.allocate(
+            vk::MemoryRequirements {
+                size,
+                alignment: 1,
+                memory_type_bits: type_bits,
+            },
+            required,
+            preferred,
+            VulkanResourceTiling::Linear,
+            VulkanAllocationLifetime::Long,
+            None,
+            true,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        required: vk::MemoryPropertyFlags,
+        preferred: vk::MemoryPropertyFlags,
+        tiling: VulkanResourceTiling,
+        lifetime: VulkanAllocationLifetime,
+        resource: Option<VulkanDedicatedResource>,
+        dedicated: bool,
+    ) -> Result<VulkanAllocation> {
+        let memory_type = self
+            .memory_type_index(requirements.memory_type_bits, required, preferred)
+            .ok_or_else(|| {
+                BMLError::UnsupportedFeature(format!("No memory type is {:?}", required))
+            })?;
+
+        self.allocator.allocate(
+            &self.logical_device,
+            &VulkanAllocationRequest {
+                requirements,
+                memory_type,
+                tiling,
+                lifetime,
+                resource,
+                dedicated,
+            },
+        )
+    }
+
+    pub fn queue(&self, family: u32, index: u32) -> Option<&Arc<VulkanQueue>> {
+        self.queues
+            .iter()
+            .find(|queue| queue.family == family && queue.index == index)
+    }
+
+    /// A queue of `family` for a new `MTLCommandQueue`, of the closest
+    /// priority, preferring queues fewer other command queues use.
+    pub fn claim_queue(
+        &self,
+        family: u32,
+        priority: MTLCommandQueuePriority,
+    ) -> Result<Arc<VulkanQueue>> {
+        let queue = self
+            .queues
+            .iter()
+            .filter(|queue| queue.family == family)
+            .min_by_key(|queue| {
+                (
+                    (queue.priority as i32 - priority as i32).abs(),
+                    queue.users.load(Ordering::Relaxed),
+                    queue.index,
+                )
+            })
+            .ok_or_else(|| anyhow!("No queue was created in family {}.", family))?;
+
+        queue.users.fetch_add(1, Ordering::Relaxed);
+
+        Ok(queue.clone())
+    }
+
+    /// Undoes `claim_queue` once the command queue is gone.
+    pub fn release_queue(&self, queue: &VulkanQueue) {
+        queue.users.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Destroys `garbage` once every queue has finished what was submitted
+    /// to it so far, or only its own queue for command buffers. Called from
+    /// `Drop`, in the order handles have to be destroyed in.
+    pub fn destroy_later(&self, garbage: VulkanGarbage) {
+        let serials = self
+            .queues
+            .iter()
+            .map(|queue| queue.submitted.load(Ordering::Acquire))
+            .collect();
+
+        self.garbage
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((serials, garbage));
+
+        self.collect_garbage();
+    }
+
+    /// Destroys the garbage no queue can still be using.
+    pub fn collect_garbage(&self) {
+        let mut garbage = self.garbage.lock().unwrap_or_else(|e| e.into_inner());
+
+        // A command buffer only waits for its own queue. Anything else waits
+        // for every queue, so later garbage never finishes first and the
+        // order handles are destroyed in is kept.
+        let finished = garbage
+            .extract_if(.., |(serials, garbage)| match garbage {
+                VulkanGarbage::CommandBuffer {
+                    command_queue,
+                    fence,
+                    serial,
+                    ..
+                } => {
+                    let queue = command_queue.queue();
+
+                    // Nobody waits for dropped command buffers, so their
+                    // fences tell how far their queue got.
+                    if queue.completed.load(Ordering::Acquire) < *serial
+                        && unsafe { self.logical_device.get_fence_status(*fence) } == Ok(true)
+                    {
+                        queue.finished(*serial);
+                    }
+
+                    queue.completed.load(Ordering::Acquire) >= *serial
+                }
+                _ => self
+                    .queues
+                    .iter()
+                    .zip(serials.iter())
+                    .all(|(queue, serial)| queue.completed.load(Ordering::Acquire) >= *serial),
+            })
+            .collect::<Vec<_>>();
+
+        for (_, garbage) in finished {
+            unsafe { garbage.destroy(self) };
+        }
+    }
+
+    /// Waits for every queue, then destroys all garbage.
+    pub fn wait_idle(&self) -> Result<()> {
+        let result = unsafe { self.logical_device.device_wait_idle() };
+
+        // Nothing runs anymore after losing the device either.
+        if matches!(result, Ok(()) | Err(vk::Result::ERROR_DEVICE_LOST)) {
+            for queue in &self.queues {
+                queue.idle();
+            }
+        }
+
+        self.collect_garbage();
+
+        Ok(result?)
+    }
+}
+
+/// (Vulkan) The optional features a device is created with, besides the
+/// ones shaders need.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VulkanDeviceFeatures {
+    /// Enabled as they are, only ones `MTLGPUFamily` needs are set.
+    pub core: vk::PhysicalDeviceFeatures,
+    /// `VK_EXT_shader_viewport_index_layer`, for layered rendering.
+    pub viewport_index_layer: bool,
+    /// Task and mesh shaders of `VK_EXT_mesh_shader`.
+    pub mesh_shader: bool,
+    /// `VK_KHR_ray_query` and the acceleration structures it needs.
+    pub ray_query: bool,
+}
+
+/// (Vulkan) A handle waiting for `VulkanMTLDevice::destroy_later`.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub enum VulkanGarbage {
+    Framebuffer(vk::Framebuffer),
+    ImageView(vk::ImageView),
+    Image(vk::Image),
+    Buffer(vk::Buffer),
+    Allocation(VulkanAllocation),
+    RenderPass(vk::RenderPass),
+    Semaphore(vk::Semaphore),
+    Fence(vk::Fence),
+    /// A committed command buffer and its fence, recycled once its queue
+    /// finished `serial`.
+    CommandBuffer {
+        command_queue: Arc<VulkanMTLCommandQueue>,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+        serial: u64,
+    },
+    CommandPool(Arc<VulkanMTLCommandQueue>),
+    Swapchain(Arc<ash::khr::swapchain::Device>, vk::SwapchainKHR),
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanGarbage {
+    /// # Safety
+    ///
+    /// The handle must belong to `device` and no longer be in use.
+    unsafe fn destroy(self, vulkan_device: &VulkanMTLDevice) {
+        let device = &vulkan_device.logical_device;
+
+        unsafe {
+            match self {
+                VulkanGarbage::Framebuffer(framebuffer) => {
+                    device.destroy_framebuffer(framebuffer, None)
+                }
+                VulkanGarbage::ImageView(image_view) => device.destroy_image_view(image_view, None),
+                VulkanGarbage::Image(image) => device.destroy_image(image, None),
+                VulkanGarbage::Buffer(buffer) => device.destroy_buffer(buffer, None),
+                VulkanGarbage::Allocation(allocation) => {
+                    vulkan_device.allocator.free(device, allocation)
+                }
+                VulkanGarbage::RenderPass(render_pass) => {
+                    device.destroy_render_pass(render_pass, None)
+                }
+                VulkanGarbage::Semaphore(semaphore) => device.destroy_semaphore(semaphore, None),
+                VulkanGarbage::Fence(fence) => device.destroy_fence(fence, None),
+                VulkanGarbage::CommandBuffer {
+                    command_queue,
+                    command_buffer,
+                    fence,
+                    ..
+                } => command_queue.recycle(device, command_buffer, fence),
+                VulkanGarbage::CommandPool(command_queue) => command_queue.destroy(device),
+                VulkanGarbage::Swapchain(swapchain, khr) => swapchain.destroy_swapchain(khr, None),
+            }
+        }
+    }
+}
+
+pub struct VulkanQueueFamilies {
+    pub graphics_queue: u32,
+    /// `None` on headless instances.
+    pub present_queue: Option<u32>,
+    /// A compute family without graphics, for async compute.
+    pub compute_queue: Option<u32>,
+    /// A transfer family without graphics or compute, usually a copy
+    /// engine.
+    pub transfer_queue: Option<u32>,
+    /// The queues of every family, indexed by family.
+    pub queue_counts: Vec<u32>,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanQueueFamilies {
+    /// More queues than this per family wouldn't run any more in parallel.
+    pub const MAX_QUEUES_PER_FAMILY: u32 = 4;
+
+    /// The families above, without duplicates.
+    pub fn families(&self) -> Vec<u32> {
+        let mut families = vec![self.graphics_queue];
+        families.extend(self.present_queue);
+        families.extend(self.compute_queue);
+        families.extend(self.transfer_queue);
+        families.sort();
+        families.dedup();
+        families
+    }
+
+    /// How many queues are created in `family`.
+    pub fn queue_count(&self, family: u32) -> u32 {
+        self.queue_counts
+            .get(family as usize)
+            .map_or(1, |count| (*count).clamp(1, Self::MAX_QUEUES_PER_FAMILY))
+    }
+
+    /// The first queue of a family is high priority, the second normal and
+    /// the third low, so every priority gets a queue of its own where there
+    /// are enough.
+    pub fn priority(index: u32) -> MTLCommandQueuePriority {
+        match index {
+            0 => MTLCommandQueuePriority::High,
+            2 => MTLCommandQueuePriority::Low,
+            _ => MTLCommandQueuePriority::Normal,
+        }
+    }
+}
+
+/// (Vulkan) A `VkQueue`, shared by the `MTLCommandQueue`s using it.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub struct VulkanQueue {
+    family: u32,
+    index: u32,
+    priority: MTLCommandQueuePriority,
+    /// Submitting and presenting need external synchronization.
+    queue: Mutex<vk::Queue>,
+    users: AtomicUsize,
+    /// Submissions made, and how many of them are known to be finished.
+    submitted: AtomicU64,
+    completed: AtomicU64,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanQueue {
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn priority(&self) -> MTLCommandQueuePriority {
+        self.priority
+    }
+
+    /// Held while submitting to or presenting from the queue.
+    pub fn lock(&self) -> MutexGuard<'_, vk::Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Counts a submission, which garbage dropped from now on waits for.
+    /// Returns its serial.
+    pub fn submitted(&self) -> u64 {
+        self.submitted.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Marks everything up to `serial` as finished, after a fence
+    /// submitted with it was signaled.
+    pub fn finished(&self, serial: u64) {
+        self.completed.fetch_max(serial, Ordering::AcqRel);
+    }
+
+    /// Marks everything submitted so far as finished, after waiting for the
+    /// queue to go idle.
+    pub fn idle(&self) {
+        self.completed
+            .fetch_max(self.submitted.load(Ordering::Acquire), Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    #[test]
+    fn gpu_families_follow_enabled_features() {
+        let mut features = VulkanDeviceFeatures::default();
+        let mut shader_features = SPIRVTargetFeatures::default();
+
+        assert_eq!(
+            MTLDevice::vulkan_gpu_families(&features, &shader_features),
+            [MTLGPUFamily::Common1]
+        );
+
+        features.core = features
+            .core
+            .multi_draw_indirect(true)
+            .draw_indirect_first_instance(true)
+            .tessellation_shader(true)
+            .occlusion_query_precise(true)
+            .fragment_stores_and_atomics(true)
+            .image_cube_array(true)
+            .texture_compression_bc(true)
+            .depth_clamp(true)
+            .sampler_anisotropy(true);
+
+        // Without layered rendering only `Common2` is met.
+        assert_eq!(
+            MTLDevice::vulkan_gpu_families(&features, &shader_features),
+            [MTLGPUFamily::Common1, MTLGPUFamily::Common2]
+        );
+
+        features.viewport_index_layer = true;
+        shader_features.stencil_export = true;
+        shader_features.runtime_descriptor_arrays = true;
+
+        assert_eq!(
+            MTLDevice::vulkan_gpu_families(&features, &shader_features),
+            [
+                MTLGPUFamily::Common1,
+                MTLGPUFamily::Common2,
+                MTLGPUFamily::Common3,
+                MTLGPUFamily::Mac2
+            ]
+        );
+
+        features.mesh_shader = true;
+        features.ray_query = true;
+        shader_features.buffer_device_address = true;
+        shader_features.non_uniform_indexing = true;
+
+        assert!(
+            MTLDevice::vulkan_gpu_families(&features, &shader_features)
+                .contains(&MTLGPUFamily::Metal3)
+        );
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    #[test]
+    fn only_the_highest_binding_has_a_variable_count() {
+        let binding = |binding, descriptor_type, count| {
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(count)
+        };
+        let bindings = [
+            binding(0, vk::DescriptorType::STORAGE_BUFFER, 1),
+            binding(3, vk::DescriptorType::SAMPLED_IMAGE, 0),
+            binding(1, vk::DescriptorType::SAMPLED_IMAGE, 4),
+        ];
+
+        // The remaining images, then the remaining resources of the stage.
+        assert_eq!(
+            MTLDevice::vulkan_variable_descriptor_count(&bindings, 1, 16, 100).unwrap(),
+            12
+        );
+        assert_eq!(
+            MTLDevice::vulkan_variable_descriptor_count(&bindings, 1, 16, 10).unwrap(),
+            5
+        );
+        assert!(MTLDevice::vulkan_variable_descriptor_count(&bindings, 1, 4, 100).is_err());
+        assert!(MTLDevice::vulkan_variable_descriptor_count(&bindings, 2, 16, 100).is_err());
+
+        let partially_bound = vk::DescriptorBindingFlags::PARTIALLY_BOUND;
+        let variable = partially_bound | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT;
+
+        assert_eq!(
+            MTLDevice::vulkan_argument_binding_flags(&bindings, Some(1)),
+            [partially_bound, variable, partially_bound]
+        );
+        assert_eq!(
+            MTLDevice::vulkan_argument_binding_flags(&bindings, None),
+            [partially_bound; 3]
+        );
+    }
 }
@@ -1,5 +1,8 @@
-use crate::{BMLLayer, MTLDevice, device};
-use crate::{MTLEvent, MTLFence};
+use crate::{BMLError, BMLLayer, MTLDevice, device};
+use crate::{
+    MTLEvent, MTLFence, MTLHeap, MTLHeapType, MTLStorageMode, MTLTextureDescriptor, MTLTextureType,
+    MTLTextureUsage, offset_error,
+};
 use anyhow::{Result, anyhow};
 use crossbeam::queue::SegQueue;
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
@@ -12,7 +15,9 @@ use std::sync::{Arc, Mutex, RwLock};
 use std::{cell::RefCell, sync::atomic::AtomicU32};
 
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-use crate::{MTLRenderPassDescriptor, VulkanSurface};
+use crate::{
+    MTLRenderPassDescriptor, VulkanAllocation, VulkanGarbage, VulkanHeapPlacement, VulkanSurface,
+};
 
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 use ash::vk;
@@ -28,19 +33,21 @@ use raw_window_metal::Layer;
 use objc2_metal::{
     MTLClearColor as MetalMTLClearColor, MTLCommandBuffer as MetalMTLCommandBuffer,
     MTLCommandEncoder as MetalMTLCommandEncoder, MTLCommandQueue as MetalMTLCommandQueue,
-    MTLDevice as MetalMTLDevice, MTLLoadAction as MetalMTLLoadAction,
+    MTLDevice as MetalMTLDevice, MTLHeap as MetalMTLHeap, MTLLoadAction as MetalMTLLoadAction,
     MTLPixelFormat as MetalMTLPixelFormat, MTLRenderCommandEncoder as MetalMTLRenderCommandEncoder,
     MTLRenderPassColorAttachmentDescriptor as MetalMTLRenderPassColorAttachmentDescriptor,
-    MTLRenderPassDescriptor as MetalMTLRenderPassDescriptor, MTLStoreAction as MetalMTLStoreAction,
-    MTLTexture as MetalMTLTexture,
+    MTLRenderPassDescriptor as MetalMTLRenderPassDescriptor, MTLResource as MetalMTLResource,
+    MTLStoreAction as MetalMTLStoreAction, MTLTexture as MetalMTLTexture,
 };
 
 pub struct MTLTexture {
     device: Arc<MTLDevice>,
-    pixel_format: MTLPixelFormat,
-    width: u32,
-    height: u32,
-    depth: u32,
+    descriptor: MTLTextureDescriptor,
+    /// The heap the texture was placed in.
+    heap: Option<Arc<MTLHeap>>,
+    /// Set by `make_aliasable`, after which the heap may place other
+    /// resources over the texture.
+    aliasable: AtomicBool,
 
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
     metal_texture: Option<Retained<ProtocolObject<dyn MetalMTLTexture>>>,
@@ -52,73 +59,430 @@ pub struct MTLTexture {
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_image_view: vk::ImageView,
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    vulkan_framebuffer: RwLock<Option<vk::Framebuffer>>,
+    vulkan_framebuffer: RwLock<Option<VulkanFramebuffer>>,
+    /// The layout of every level and slice as of the last recorded render
+    /// pass, indexed by `level * layers + slice`.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    vulkan_swapchain: Option<(Arc<ash::khr::swapchain::Device>, Arc<vk::SwapchainKHR>)>,
+    vulkan_layouts: Mutex<Vec<vk::ImageLayout>>,
+    /// The swapchain owning the image, `None` if the texture owns it.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_swapchain: Option<Arc<VulkanSwapchainKHR>>,
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_sync_object: RwLock<Option<VulkanSyncObject>>,
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_image_index: AtomicU32,
+    /// `None` for swapchain images and textures in a heap, and while
+    /// dropping.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_allocation: Option<VulkanAllocation>,
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_placement: Option<VulkanHeapPlacement>,
 }
 
 impl MTLTexture {
+    pub fn new(device: Arc<MTLDevice>, descriptor: &MTLTextureDescriptor) -> Result<Arc<Self>> {
+        descriptor.validate(&device)?;
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_new(device, descriptor);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_new(device, descriptor);
+    }
+
+    /// A texture in `heap`, at `offset` for placement heaps. It has to have
+    /// the heap's storage and CPU cache modes.
+    pub fn with_heap(
+        heap: Arc<MTLHeap>,
+        descriptor: &MTLTextureDescriptor,
+        offset: Option<u64>,
+    ) -> Result<Arc<Self>> {
+        descriptor.validate(heap.device())?;
+
+        if descriptor.resource_options() != heap.descriptor().resource_options() {
+            return Err(BMLError::InvalidUsage(format!(
+                "A {:?} texture can't be placed in a {:?} heap.",
+                descriptor.resource_options(),
+                heap.descriptor().resource_options()
+            ))
+            .into());
+        }
+
+        if offset.is_some() != (heap.heap_type() == MTLHeapType::Placement) {
+            return Err(offset_error(heap.heap_type()).into());
+        }
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_with_heap(heap, descriptor, offset);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_with_heap(heap, descriptor, offset);
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_new(
+        device: Arc<MTLDevice>,
+        descriptor: &MTLTextureDescriptor,
+    ) -> Result<Arc<Self>> {
+        let metal_texture = device
+            .metal_device()
+            .newTextureWithDescriptor(&descriptor.to_metal());
+
+        let metal_texture = match metal_texture {
+            Some(t) => t,
+            None => return Err(BMLError::OutOfDeviceMemory.into()),
+        };
+
+        Ok(Arc::new(Self {
+            device,
+            descriptor: *descriptor,
+            heap: None,
+            aliasable: AtomicBool::new(false),
+            metal_texture: Some(metal_texture),
+            ca_metal_drawable: None,
+        }))
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_with_heap(
+        heap: Arc<MTLHeap>,
+        descriptor: &MTLTextureDescriptor,
+        offset: Option<u64>,
+    ) -> Result<Arc<Self>> {
+        let metal_heap = heap.metal_heap();
+        let metal_descriptor = descriptor.to_metal();
+
+        let metal_texture = match offset {
+            Some(offset) => unsafe {
+                metal_heap.newTextureWithDescriptor_offset(&metal_descriptor, offset as usize)
+            },
+            None => metal_heap.newTextureWithDescriptor(&metal_descriptor),
+        };
+
+        let metal_texture = match metal_texture {
+            Some(t) => t,
+            None => return Err(BMLError::OutOfDeviceMemory.into()),
+        };
+
+        Ok(Arc::new(Self {
+            device: heap.device().clone(),
+            descriptor: *descriptor,
+            heap: Some(heap),
+            aliasable: AtomicBool::new(false),
+            metal_texture: Some(metal_texture),
+            ca_metal_drawable: None,
+        }))
+    }
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn from_vulkan(
+    pub fn vulkan_new(
+        device: Arc<MTLDevice>,
+        descriptor: &MTLTextureDescriptor,
+    ) -> Result<Arc<Self>> {
+        let vulkan_device = device.vulkan_device();
+        let vulkan_image = Self::vulkan_create_image(&device, descriptor, false)?;
+
+        let (required, preferred) = descriptor
+            .resource_options()
+            .to_vulkan(device.has_unified_memory());
+
+        let allocation = vulkan_device.allocate_image_memory(
+            vulkan_image,
+            vk::ImageTiling::OPTIMAL,
+            required,
+            preferred,
+        );
+
+        let vulkan_allocation = match allocation {
+            Ok(allocation) => allocation,
+            Err(e) => {
+                unsafe { vulkan_device.logical().destroy_image(vulkan_image, None) };
+                return Err(e.context(format!(
+                    "Allocating memory for a {:?} {:?} texture",
+                    descriptor.storage_mode, descriptor.pixel_format
+                )));
+            }
+        };
+
+        Self::vulkan_finish(
+            device,
+            descriptor,
+            None,
+            vulkan_image,
+            Some(vulkan_allocation),
+            None,
+        )
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_with_heap(
+        heap: Arc<MTLHeap>,
+        descriptor: &MTLTextureDescriptor,
+        offset: Option<u64>,
+    ) -> Result<Arc<Self>> {
+        let device = heap.device().clone();
+        let logical_device = device.vulkan_device().logical();
+        let vulkan_image = Self::vulkan_create_image(&device, descriptor, true)?;
+
+        let requirements = Self::vulkan_heap_requirements(&device, vulkan_image);
+
+        let placement = match heap.vulkan_place(&requirements, offset) {
+            Ok(placement) => placement,
+            Err(e) => {
+                unsafe { logical_device.destroy_image(vulkan_image, None) };
+                return Err(e.context(format!(
+                    "Placing a {:?} texture in a heap",
+                    descriptor.pixel_format
+                )));
+            }
+        };
+
+        let allocation = heap.vulkan_allocation();
+
+        let bound = unsafe {
+            logical_device.bind_image_memory(
+                vulkan_image,
+                allocation.memory(),
+                allocation.offset() + placement.offset,
+            )
+        };
+
+        if let Err(e) = bound {
+            heap.vulkan_release(&placement);
+            unsafe { logical_device.destroy_image(vulkan_image, None) };
+            return Err(e.into());
+        }
+
+        Self::vulkan_finish(
+            device,
+            descriptor,
+            Some(heap),
+            vulkan_image,
+            None,
+            Some(placement),
+        )
+    }
+
+    /// (Vulkan) Checks the format is supported before creating the image,
+    /// so the error says which. Images in a heap may alias the resources
+    /// placed over them.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_create_image(
+        device: &MTLDevice,
+        descriptor: &MTLTextureDescriptor,
+        in_heap: bool,
+    ) -> Result<vk::Image> {
+        descriptor.vulkan_check_support(device)?;
+
+        let mut info = descriptor.to_vulkan();
+        if in_heap {
+            info.flags |= vk::ImageCreateFlags::ALIAS;
+        }
+
+        Ok(unsafe { device.vulkan_device().logical().create_image(&info, None)? })
+    }
+
+    /// (Vulkan) The memory requirements of `image` in a heap. Optimal
+    /// images take up whole pages of `bufferImageGranularity`, so buffers
+    /// next to them never share one.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_heap_requirements(
+        device: &MTLDevice,
+        image: vk::Image,
+    ) -> vk::MemoryRequirements {
+        let vulkan_device = device.vulkan_device();
+        let granularity = vulkan_device
+            .properties()
+            .limits
+            .buffer_image_granularity
+            .max(1);
+
+        let requirements = unsafe { vulkan_device.logical().get_image_memory_requirements(image) };
+        let alignment = requirements.alignment.max(granularity);
+
+        vk::MemoryRequirements {
+            size: requirements.size.next_multiple_of(alignment),
+            alignment,
+            memory_type_bits: requirements.memory_type_bits,
+        }
+    }
+
+    /// (Vulkan) Wraps an image with memory bound to it. If the view can't
+    /// be created, dropping the texture cleans up.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_finish(
         device: Arc<MTLDevice>,
+        descriptor: &MTLTextureDescriptor,
+        heap: Option<Arc<MTLHeap>>,
         vulkan_image: vk::Image,
-        pixel_format: MTLPixelFormat,
-        width: u32,
-        height: u32,
-        depth: u32,
-        vulkan_swapchain_instance: Arc<ash::khr::swapchain::Device>,
-        vulkan_swapchain_khr: Arc<vk::SwapchainKHR>,
+        vulkan_allocation: Option<VulkanAllocation>,
+        vulkan_placement: Option<VulkanHeapPlacement>,
     ) -> Result<Arc<Self>> {
-        let vulkan_image_view = unsafe {
+        let mut texture = Self {
+            device,
+            descriptor: *descriptor,
+            heap,
+            aliasable: AtomicBool::new(false),
+            vulkan_image,
+            vulkan_image_view: vk::ImageView::null(),
+            vulkan_swapchain: None,
+            vulkan_image_index: AtomicU32::new(0),
+            vulkan_framebuffer: RwLock::new(None),
+            vulkan_layouts: Mutex::new(Self::vulkan_undefined_layouts(descriptor)),
+            vulkan_sync_object: RwLock::new(None),
+            vulkan_allocation,
+            vulkan_placement,
+        };
+
+        texture.vulkan_image_view =
+            Self::vulkan_create_image_view(&texture.device, vulkan_image, descriptor)?;
+
+        Ok(Arc::new(texture))
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_undefined_layouts(descriptor: &MTLTextureDescriptor) -> Vec<vk::ImageLayout> {
+        let count = descriptor.mipmap_level_count * descriptor.vulkan_array_layers();
+
+        vec![vk::ImageLayout::UNDEFINED; count.max(1) as usize]
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_create_image_view(
+        device: &MTLDevice,
+        image: vk::Image,
+        descriptor: &MTLTextureDescriptor,
+    ) -> Result<vk::ImageView> {
+        Ok(unsafe {
             device.vulkan_device().logical().create_image_view(
                 &vk::ImageViewCreateInfo::default()
-                    .image(vulkan_image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(pixel_format.to_vulkan())
+                    .image(image)
+                    .view_type(descriptor.texture_type.to_vulkan())
+                    .format(descriptor.pixel_format.to_vulkan())
                     .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        aspect_mask: descriptor.pixel_format.vulkan_aspect(),
                         base_mip_level: 0,
-                        level_count: 1,
+                        level_count: descriptor.mipmap_level_count,
                         base_array_layer: 0,
-                        layer_count: 1,
+                        layer_count: descriptor.vulkan_array_layers(),
                     }),
                 None,
             )?
-        };
+        })
+    }
 
-        Ok(Arc::new(Self {
-            device,
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn from_vulkan(
+        device: Arc<MTLDevice>,
+        vulkan_image: vk::Image,
+        pixel_format: MTLPixelFormat,
+        width: u32,
+        height: u32,
+        depth: u32,
+        vulkan_swapchain: Arc<VulkanSwapchainKHR>,
+    ) -> Result<Arc<Self>> {
+        let descriptor = MTLTextureDescriptor {
             pixel_format,
             width,
             height,
             depth,
+            usage: MTLTextureUsage {
+                shader_read: false,
+                render_target: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let vulkan_image_view = Self::vulkan_create_image_view(&device, vulkan_image, &descriptor)?;
+
+        Ok(Arc::new(Self {
+            device,
+            descriptor,
+            heap: None,
+            aliasable: AtomicBool::new(false),
             vulkan_image,
             vulkan_image_view,
-            vulkan_swapchain: Some((vulkan_swapchain_instance, vulkan_swapchain_khr)),
+            vulkan_swapchain: Some(vulkan_swapchain),
             vulkan_image_index: AtomicU32::new(0),
             vulkan_framebuffer: RwLock::new(None),
+            vulkan_layouts: Mutex::new(Self::vulkan_undefined_layouts(&descriptor)),
             vulkan_sync_object: RwLock::new(None),
+            vulkan_allocation: None,
+            vulkan_placement: None,
         }))
     }
 
+    pub fn descriptor(&self) -> &MTLTextureDescriptor {
+        &self.descriptor
+    }
+
+    pub fn texture_type(&self) -> MTLTextureType {
+        self.descriptor.texture_type
+    }
+
     pub fn width(&self) -> u32 {
-        self.width
+        self.descriptor.width
     }
 
     pub fn height(&self) -> u32 {
-        self.height
+        self.descriptor.height
     }
 
     pub fn depth(&self) -> u32 {
-        self.depth
+        self.descriptor.depth
     }
 
     pub fn pixel_format(&self) -> &MTLPixelFormat {
-        &self.pixel_format
+        &self.descriptor.pixel_format
+    }
+
+    pub fn mipmap_level_count(&self) -> u32 {
+        self.descriptor.mipmap_level_count
+    }
+
+    pub fn array_length(&self) -> u32 {
+        self.descriptor.array_length
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.descriptor.sample_count
+    }
+
+    pub fn usage(&self) -> MTLTextureUsage {
+        self.descriptor.usage
+    }
+
+    pub fn storage_mode(&self) -> MTLStorageMode {
+        self.descriptor.storage_mode
+    }
+
+    pub fn device(&self) -> &Arc<MTLDevice> {
+        &self.device
+    }
+
+    pub fn heap(&self) -> Option<&Arc<MTLHeap>> {
+        self.heap.as_ref()
+    }
+
+    /// Lets the heap place other resources over this one, which must not be
+    /// used after. Does nothing for textures outside heaps.
+    pub fn make_aliasable(&self) {
+        if self.heap.is_none() || self.aliasable.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        if let Some(t) = &self.metal_texture {
+            t.makeAliasable();
+        }
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        self.vulkan_release_placement();
+    }
+
+    pub fn is_aliasable(&self) -> bool {
+        self.aliasable.load(Ordering::Acquire)
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
@@ -132,41 +496,170 @@ impl MTLTexture {
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_framebuffer(&self) -> &RwLock<Option<vk::Framebuffer>> {
+    pub fn vulkan_allocation(&self) -> Option<&VulkanAllocation> {
+        self.vulkan_allocation.as_ref()
+    }
+
+    /// (Vulkan) The layout render passes leave the texture in, ready to be
+    /// presented or sampled.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_final_layout(&self) -> vk::ImageLayout {
+        let pixel_format = self.descriptor.pixel_format;
+
+        match (&self.vulkan_swapchain, self.descriptor.usage.shader_read) {
+            (Some(_), _) => vk::ImageLayout::PRESENT_SRC_KHR,
+            (None, true) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            (None, false) if pixel_format.is_depth() || pixel_format.is_stencil() => {
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            }
+            (None, false) => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_release_placement(&self) {
+        if let (Some(heap), Some(placement)) = (&self.heap, &self.vulkan_placement) {
+            heap.vulkan_release(placement);
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_framebuffer(&self) -> &RwLock<Option<VulkanFramebuffer>> {
         &self.vulkan_framebuffer
     }
 
+    /// (Vulkan) Whether the framebuffer attaches `level` and `slice`.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_is_framebuffer(&self) -> bool {
-        self.vulkan_framebuffer.read().unwrap().is_some()
+    pub fn vulkan_is_framebuffer(&self, level: u32, slice: u32) -> bool {
+        self.vulkan_framebuffer
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_some_and(|f| f.level == level && f.slice == slice)
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_swapchain(
-        &self,
-    ) -> &Option<(Arc<ash::khr::swapchain::Device>, Arc<vk::SwapchainKHR>)> {
+    pub fn vulkan_swapchain(&self) -> &Option<Arc<VulkanSwapchainKHR>> {
         &self.vulkan_swapchain
     }
 
+    /// (Vulkan) The layout `level` and `slice` were left in by the last
+    /// recorded render pass, `UNDEFINED` before the first.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_layout(&self, level: u32, slice: u32) -> vk::ImageLayout {
+        let layouts = self
+            .vulkan_layouts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        layouts
+            .get(self.vulkan_subresource_index(level, slice))
+            .copied()
+            .unwrap_or(vk::ImageLayout::UNDEFINED)
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_set_layout(&self, level: u32, slice: u32, layout: vk::ImageLayout) {
+        let index = self.vulkan_subresource_index(level, slice);
+        let mut layouts = self
+            .vulkan_layouts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if let Some(l) = layouts.get_mut(index) {
+            *l = layout;
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    fn vulkan_subresource_index(&self, level: u32, slice: u32) -> usize {
+        (level * self.descriptor.vulkan_array_layers() + slice) as usize
+    }
+
+    /// (Vulkan) Creates a framebuffer over a 2D view of one mipmap level
+    /// and slice, replacing the one for any other level or slice.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub unsafe fn vulkan_create_framebuffer(
         &self,
         render_pass: &vk::RenderPass,
+        level: u32,
+        slice: u32,
         device: Arc<MTLDevice>,
     ) -> Result<()> {
-        let mut framebuffer = self.vulkan_framebuffer().write().unwrap();
+        if self.descriptor.texture_type == MTLTextureType::Type3D {
+            return Err(
+                BMLError::UnsupportedFeature(String::from("rendering to 3D textures")).into(),
+            );
+        }
+        if level >= self.mipmap_level_count() || slice >= self.descriptor.vulkan_array_layers() {
+            return Err(BMLError::InvalidUsage(format!(
+                "Level {level}, slice {slice} is outside the texture, which has {} levels and {} slices.",
+                self.mipmap_level_count(),
+                self.descriptor.vulkan_array_layers(),
+            ))
+            .into());
+        }
+
+        let vulkan_device = device.vulkan_device();
+        let logical = vulkan_device.logical();
+
+        let image_view = unsafe {
+            logical.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .image(self.vulkan_image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(self.descriptor.pixel_format.to_vulkan())
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: self.descriptor.pixel_format.vulkan_aspect(),
+                        base_mip_level: level,
+                        level_count: 1,
+                        base_array_layer: slice,
+                        layer_count: 1,
+                    }),
+                None,
+            )?
+        };
+
+        let extent = vk::Extent2D {
+            width: (self.width() >> level).max(1),
+            height: (self.height() >> level).max(1),
+        };
 
-        framebuffer.replace(unsafe {
-            device.vulkan_device().logical().create_framebuffer(
+        let framebuffer = unsafe {
+            logical.create_framebuffer(
                 &vk::FramebufferCreateInfo::default()
                     .render_pass(*render_pass)
-                    .attachments(&[*self.vulkan_image_view()])
-                    .width(self.width)
-                    .height(self.height)
+                    .attachments(&[image_view])
+                    .width(extent.width)
+                    .height(extent.height)
                     .layers(1),
                 None,
-            )?
-        });
+            )
+        };
+
+        let framebuffer = match framebuffer {
+            Ok(f) => f,
+            Err(e) => {
+                unsafe { logical.destroy_image_view(image_view, None) };
+                return Err(e.into());
+            }
+        };
+
+        let previous = self
+            .vulkan_framebuffer()
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(VulkanFramebuffer {
+                level,
+                slice,
+                extent,
+                image_view,
+                framebuffer,
+            });
+
+        if let Some(previous) = previous {
+            previous.destroy_later(&device);
+        }
 
         Ok(())
     }
@@ -186,43 +679,37 @@ impl MTLTexture {
         device: Arc<MTLDevice>,
         ca_metal_drawable: Option<Retained<ProtocolObject<dyn CAMetalDrawable>>>,
         metal_texture: Option<Retained<ProtocolObject<dyn MetalMTLTexture>>>,
-    ) -> Arc<Self> {
-        let mut width: u32 = 0;
-        let mut height: u32 = 0;
-        let mut depth: u32 = 0;
-        let mut pixel_format: MTLPixelFormat = MTLPixelFormat::Bgra8Unorm;
-
-        match &ca_metal_drawable {
-            Some(d) => unsafe {
-                let texture = d.texture();
-
-                width = texture.width() as u32;
-                height = texture.height() as u32;
-                depth = texture.depth() as u32;
-                pixel_format = MTLPixelFormat::from_metal(texture.pixelFormat());
-            },
-            None => {}
-        }
+    ) -> Result<Arc<Self>> {
+        let texture = match (&ca_metal_drawable, &metal_texture) {
+            (Some(d), _) => unsafe { d.texture() },
+            (None, Some(t)) => t.clone(),
+            (None, None) => return Err(anyhow!("No Metal Texture Found.")),
+        };
 
-        match &metal_texture {
-            Some(t) => {
-                width = t.width() as u32;
-                height = t.height() as u32;
-                depth = t.depth() as u32;
-                pixel_format = MTLPixelFormat::from_metal(t.pixelFormat());
-            }
-            None => {}
-        }
+        let descriptor = MTLTextureDescriptor {
+            pixel_format: MTLPixelFormat::from_metal(texture.pixelFormat())?,
+            width: texture.width() as u32,
+            height: texture.height() as u32,
+            depth: texture.depth() as u32,
+            mipmap_level_count: texture.mipmapLevelCount() as u32,
+            array_length: texture.arrayLength() as u32,
+            sample_count: texture.sampleCount() as u32,
+            usage: MTLTextureUsage {
+                shader_read: false,
+                render_target: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
 
-        Arc::new(Self {
+        Ok(Arc::new(Self {
             device,
+            descriptor,
+            heap: None,
+            aliasable: AtomicBool::new(false),
             ca_metal_drawable,
             metal_texture,
-            width,
-            height,
-            depth,
-            pixel_format,
-        })
+        }))
     }
 
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
@@ -244,6 +731,42 @@ impl MTLTexture {
     }
 }
 
+/// The swapchain, if any, goes after the view and framebuffer made from its
+/// image. Otherwise the image goes, then its memory.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLTexture {
+    fn drop(&mut self) {
+        let device = self.device.vulkan_device();
+
+        let framebuffer = self
+            .vulkan_framebuffer
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+
+        if let Some(framebuffer) = framebuffer {
+            framebuffer.destroy_later(&self.device);
+        }
+
+        device.destroy_later(VulkanGarbage::ImageView(self.vulkan_image_view));
+
+        if self.vulkan_swapchain.is_some() {
+            return;
+        }
+
+        device.destroy_later(VulkanGarbage::Image(self.vulkan_image));
+
+        if let Some(allocation) = self.vulkan_allocation.take() {
+            device.destroy_later(VulkanGarbage::Allocation(allocation));
+        }
+
+        // Heap memory isn't tracked, it's free for other resources now.
+        if !self.is_aliasable() {
+            self.vulkan_release_placement();
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct MTLViewSettings {
     pub vsync: AtomicBool,
@@ -314,7 +837,7 @@ impl MTLView {
         Ok(Arc::new(Self {
             device: device.clone(),
             ca_metal_layer,
-            pixel_format: MTLPixelFormat::from_metal(pixel_format),
+            pixel_format: MTLPixelFormat::from_metal(pixel_format)?,
         }))
     }
 
@@ -406,12 +929,14 @@ impl MTLView {
         // Metal types for finer granular control.
         // =======================================================
         //
-        let surface = device.instance.vulkan_surface().as_ref().unwrap();
+        let surface = device.instance.vulkan_surface().as_ref().ok_or_else(|| {
+            BMLError::InvalidUsage(String::from("Views need an instance created with a layer."))
+        })?;
 
         let surface_details =
             Self::vulkan_get_surface_details(surface, device, bml_layer, &settings)?;
 
-        let pixel_format = MTLPixelFormat::from_vulkan(surface_details.format.format);
+        let pixel_format = MTLPixelFormat::from_vulkan(surface_details.format.format)?;
 
         let image_count = {
             let max = surface_details.capabilities.max_image_count;
@@ -422,9 +947,12 @@ impl MTLView {
             preferred
         };
 
+        let queue_families = device.vulkan_device().queue_families();
         let queue_family_indices = [
-            device.vulkan_device().queue_families().graphics_queue,
-            device.vulkan_device().queue_families().present_queue,
+            queue_families.graphics_queue,
+            queue_families
+                .present_queue
+                .ok_or_else(|| anyhow!("Headless devices can't present."))?,
         ];
 
         let swapchain_create_info = {
@@ -457,11 +985,14 @@ impl MTLView {
             device.vulkan_device().logical(),
         ));
 
-        let swapchain_khr =
-            Arc::new(unsafe { swapchain_instance.create_swapchain(&swapchain_create_info, None)? });
+        let swapchain_khr = Arc::new(VulkanSwapchainKHR {
+            device: device.clone(),
+            instance: swapchain_instance.clone(),
+            khr: unsafe { swapchain_instance.create_swapchain(&swapchain_create_info, None)? },
+        });
 
         let swapchain_images =
-            unsafe { swapchain_instance.get_swapchain_images(*swapchain_khr.as_ref())? };
+            unsafe { swapchain_instance.get_swapchain_images(swapchain_khr.khr)? };
         let mut textures: Vec<Arc<MTLTexture>> = vec![];
         for i in swapchain_images {
             textures.push(MTLTexture::from_vulkan(
@@ -471,7 +1002,6 @@ impl MTLView {
                 surface_details.extent.width,
                 surface_details.extent.height,
                 0,
-                swapchain_instance.clone(),
                 swapchain_khr.clone(),
             )?);
         }
@@ -559,7 +1089,7 @@ impl MTLViewArc for Arc<MTLView> {
             .swapchain()
             .khr()
             .read()
-            .unwrap()
+            .unwrap_or_else(|e| e.into_inner())
             .to_owned();
         let sync_object = self.vulkan_view().swapchain().in_flight_frames().next();
 
@@ -578,7 +1108,7 @@ impl MTLViewArc for Arc<MTLView> {
                 .swapchain()
                 .instance()
                 .acquire_next_image(
-                    *swapchain_khr.as_ref(),
+                    swapchain_khr.khr(),
                     u64::MAX,
                     *image_available_event.vulkan_semaphore(),
                     vk::Fence::null(),
@@ -587,12 +1117,9 @@ impl MTLViewArc for Arc<MTLView> {
 
         let image_index = match result {
             Ok((image_index, _)) => image_index,
-            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => todo!("Handle Out of Date Swapchain"),
             Err(error) => {
-                return Err(anyhow!(
-                    "Vulkan Error while acquiring next drawable: {}",
-                    error
-                ));
+                return Err(anyhow::Error::new(BMLError::from(error))
+                    .context("Failed to acquire the next drawable."));
             }
         };
 
@@ -603,13 +1130,18 @@ impl MTLViewArc for Arc<MTLView> {
                 .reset_fences(&wait_fences)?
         }
 
-        let texture =
-            self.vulkan_view().swapchain().textures.read().unwrap()[image_index as usize].clone();
+        let texture = self
+            .vulkan_view()
+            .swapchain()
+            .textures
+            .read()
+            .unwrap_or_else(|e| e.into_inner())[image_index as usize]
+            .clone();
 
         texture
             .vulkan_sync_object
             .write()
-            .unwrap()
+            .unwrap_or_else(|e| e.into_inner())
             .replace(sync_object.clone());
 
         texture
@@ -632,11 +1164,7 @@ impl MTLViewArc for Arc<MTLView> {
             }
         };
 
-        Ok(MTLTexture::from_metal(
-            device,
-            Some(ca_metal_drawable),
-            None,
-        ))
+        MTLTexture::from_metal(device, Some(ca_metal_drawable), None)
     }
 }
 
@@ -661,7 +1189,7 @@ impl VulkanMTLView {
 pub struct VulkanSwapchain {
     surface_details: VulkanSurfaceDetails,
     instance: Arc<ash::khr::swapchain::Device>,
-    khr: RwLock<Arc<vk::SwapchainKHR>>,
+    khr: RwLock<Arc<VulkanSwapchainKHR>>,
     textures: RwLock<Vec<Arc<MTLTexture>>>,
     in_flight_frames: VulkanInFlightFrames,
     image_count: u32,
@@ -681,7 +1209,7 @@ impl VulkanSwapchain {
         &self.image_count
     }
 
-    pub fn khr(&self) -> &RwLock<Arc<vk::SwapchainKHR>> {
+    pub fn khr(&self) -> &RwLock<Arc<VulkanSwapchainKHR>> {
         &self.khr
     }
 
@@ -694,6 +1222,56 @@ impl VulkanSwapchain {
     }
 }
 
+/// (Vulkan) A `VkSwapchainKHR`, destroyed once the view and all of its
+/// textures are gone.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub struct VulkanSwapchainKHR {
+    device: Arc<MTLDevice>,
+    instance: Arc<ash::khr::swapchain::Device>,
+    khr: vk::SwapchainKHR,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanSwapchainKHR {
+    pub fn instance(&self) -> &ash::khr::swapchain::Device {
+        &self.instance
+    }
+
+    pub fn khr(&self) -> vk::SwapchainKHR {
+        self.khr
+    }
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for VulkanSwapchainKHR {
+    fn drop(&mut self) {
+        self.device
+            .vulkan_device()
+            .destroy_later(VulkanGarbage::Swapchain(self.instance.clone(), self.khr));
+    }
+}
+
+/// (Vulkan) A framebuffer over one level and slice of a texture, with the
+/// view it attaches.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub struct VulkanFramebuffer {
+    pub level: u32,
+    pub slice: u32,
+    pub extent: vk::Extent2D,
+    pub image_view: vk::ImageView,
+    pub framebuffer: vk::Framebuffer,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl VulkanFramebuffer {
+    fn destroy_later(self, device: &MTLDevice) {
+        let device = device.vulkan_device();
+
+        device.destroy_later(VulkanGarbage::Framebuffer(self.framebuffer));
+        device.destroy_later(VulkanGarbage::ImageView(self.image_view));
+    }
+}
+
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 #[derive(Clone)]
 pub struct VulkanSyncObject {
@@ -752,33 +1330,230 @@ impl VulkanInFlightFrames {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MTLPixelFormat {
+    R8Unorm,
+    R8Snorm,
+    R8Uint,
+    R8Sint,
+    Rg8Unorm,
+    Rgba8Unorm,
+    Rgba8UnormSrgb,
+    Rgba8Snorm,
+    Rgba8Uint,
+    Rgba8Sint,
     Bgra8Unorm,
+    Bgra8UnormSrgb,
+    Rgb10a2Unorm,
+    Rg11b10Float,
+    R16Unorm,
+    R16Float,
+    R16Uint,
+    Rg16Float,
+    Rgba16Unorm,
+    Rgba16Float,
+    Rgba16Uint,
+    R32Float,
+    R32Uint,
+    R32Sint,
+    Rg32Float,
+    Rgba32Float,
+    Rgba32Uint,
+    Depth16Unorm,
+    Depth32Float,
+    Stencil8,
+    /// Not on Apple silicon.
+    Depth24UnormStencil8,
+    Depth32FloatStencil8,
 }
 
 impl MTLPixelFormat {
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-    pub fn from_metal(metal_format: MetalMTLPixelFormat) -> Self {
-        match metal_format {
-            MetalMTLPixelFormat::BGRA8Unorm => MTLPixelFormat::Bgra8Unorm,
-            _ => todo!("Format not yet handled."),
+    pub fn from_metal(metal_format: MetalMTLPixelFormat) -> Result<Self> {
+        Ok(match metal_format {
+            MetalMTLPixelFormat::R8Unorm => Self::R8Unorm,
+            MetalMTLPixelFormat::R8Snorm => Self::R8Snorm,
+            MetalMTLPixelFormat::R8Uint => Self::R8Uint,
+            MetalMTLPixelFormat::R8Sint => Self::R8Sint,
+            MetalMTLPixelFormat::RG8Unorm => Self::Rg8Unorm,
+            MetalMTLPixelFormat::RGBA8Unorm => Self::Rgba8Unorm,
+            MetalMTLPixelFormat::RGBA8Unorm_sRGB => Self::Rgba8UnormSrgb,
+            MetalMTLPixelFormat::RGBA8Snorm => Self::Rgba8Snorm,
+            MetalMTLPixelFormat::RGBA8Uint => Self::Rgba8Uint,
+            MetalMTLPixelFormat::RGBA8Sint => Self::Rgba8Sint,
+            MetalMTLPixelFormat::BGRA8Unorm => Self::Bgra8Unorm,
+            MetalMTLPixelFormat::BGRA8Unorm_sRGB => Self::Bgra8UnormSrgb,
+            MetalMTLPixelFormat::RGB10A2Unorm => Self::Rgb10a2Unorm,
+            MetalMTLPixelFormat::RG11B10Float => Self::Rg11b10Float,
+            MetalMTLPixelFormat::R16Unorm => Self::R16Unorm,
+            MetalMTLPixelFormat::R16Float => Self::R16Float,
+            MetalMTLPixelFormat::R16Uint => Self::R16Uint,
+            MetalMTLPixelFormat::RG16Float => Self::Rg16Float,
+            MetalMTLPixelFormat::RGBA16Unorm => Self::Rgba16Unorm,
+            MetalMTLPixelFormat::RGBA16Float => Self::Rgba16Float,
+            MetalMTLPixelFormat::RGBA16Uint => Self::Rgba16Uint,
+            MetalMTLPixelFormat::R32Float => Self::R32Float,
+            MetalMTLPixelFormat::R32Uint => Self::R32Uint,
+            MetalMTLPixelFormat::R32Sint => Self::R32Sint,
+            MetalMTLPixelFormat::RG32Float => Self::Rg32Float,
+            MetalMTLPixelFormat::RGBA32Float => Self::Rgba32Float,
+            MetalMTLPixelFormat::RGBA32Uint => Self::Rgba32Uint,
+            MetalMTLPixelFormat::Depth16Unorm => Self::Depth16Unorm,
+            MetalMTLPixelFormat::Depth32Float => Self::Depth32Float,
+            MetalMTLPixelFormat::Stencil8 => Self::Stencil8,
+            MetalMTLPixelFormat::Depth24Unorm_Stencil8 => Self::Depth24UnormStencil8,
+            MetalMTLPixelFormat::Depth32Float_Stencil8 => Self::Depth32FloatStencil8,
+            _ => return Err(BMLError::UnsupportedFormat(format!("{:?}", metal_format)).into()),
+        })
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLPixelFormat {
+        match self {
+            Self::R8Unorm => MetalMTLPixelFormat::R8Unorm,
+            Self::R8Snorm => MetalMTLPixelFormat::R8Snorm,
+            Self::R8Uint => MetalMTLPixelFormat::R8Uint,
+            Self::R8Sint => MetalMTLPixelFormat::R8Sint,
+            Self::Rg8Unorm => MetalMTLPixelFormat::RG8Unorm,
+            Self::Rgba8Unorm => MetalMTLPixelFormat::RGBA8Unorm,
+            Self::Rgba8UnormSrgb => MetalMTLPixelFormat::RGBA8Unorm_sRGB,
+            Self::Rgba8Snorm => MetalMTLPixelFormat::RGBA8Snorm,
+            Self::Rgba8Uint => MetalMTLPixelFormat::RGBA8Uint,
+            Self::Rgba8Sint => MetalMTLPixelFormat::RGBA8Sint,
+            Self::Bgra8Unorm => MetalMTLPixelFormat::BGRA8Unorm,
+            Self::Bgra8UnormSrgb => MetalMTLPixelFormat::BGRA8Unorm_sRGB,
+            Self::Rgb10a2Unorm => MetalMTLPixelFormat::RGB10A2Unorm,
+            Self::Rg11b10Float => MetalMTLPixelFormat::RG11B10Float,
+            Self::R16Unorm => MetalMTLPixelFormat::R16Unorm,
+            Self::R16Float => MetalMTLPixelFormat::R16Float,
+            Self::R16Uint => MetalMTLPixelFormat::R16Uint,
+            Self::Rg16Float => MetalMTLPixelFormat::RG16Float,
+            Self::Rgba16Unorm => MetalMTLPixelFormat::RGBA16Unorm,
+            Self::Rgba16Float => MetalMTLPixelFormat::RGBA16Float,
+            Self::Rgba16Uint => MetalMTLPixelFormat::RGBA16Uint,
+            Self::R32Float => MetalMTLPixelFormat::R32Float,
+            Self::R32Uint => MetalMTLPixelFormat::R32Uint,
+            Self::R32Sint => MetalMTLPixelFormat::R32Sint,
+            Self::Rg32Float => MetalMTLPixelFormat::RG32Float,
+            Self::Rgba32Float => MetalMTLPixelFormat::RGBA32Float,
+            Self::Rgba32Uint => MetalMTLPixelFormat::RGBA32Uint,
+            Self::Depth16Unorm => MetalMTLPixelFormat::Depth16Unorm,
+            Self::Depth32Float => MetalMTLPixelFormat::Depth32Float,
+            Self::Stencil8 => MetalMTLPixelFormat::Stencil8,
+            Self::Depth24UnormStencil8 => MetalMTLPixelFormat::Depth24Unorm_Stencil8,
+            Self::Depth32FloatStencil8 => MetalMTLPixelFormat::Depth32Float_Stencil8,
         }
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn from_vulkan(vulkan_format: vk::Format) -> Self {
-        match vulkan_format {
+    pub fn from_vulkan(vulkan_format: vk::Format) -> Result<Self> {
+        Ok(match vulkan_format {
+            vk::Format::R8_UNORM => Self::R8Unorm,
+            vk::Format::R8_SNORM => Self::R8Snorm,
+            vk::Format::R8_UINT => Self::R8Uint,
+            vk::Format::R8_SINT => Self::R8Sint,
+            vk::Format::R8G8_UNORM => Self::Rg8Unorm,
+            vk::Format::R8G8B8A8_UNORM => Self::Rgba8Unorm,
+            vk::Format::R8G8B8A8_SRGB => Self::Rgba8UnormSrgb,
+            vk::Format::R8G8B8A8_SNORM => Self::Rgba8Snorm,
+            vk::Format::R8G8B8A8_UINT => Self::Rgba8Uint,
+            vk::Format::R8G8B8A8_SINT => Self::Rgba8Sint,
             vk::Format::B8G8R8A8_UNORM => Self::Bgra8Unorm,
-            _ => todo!("Format not yet handled."),
-        }
+            vk::Format::B8G8R8A8_SRGB => Self::Bgra8UnormSrgb,
+            vk::Format::A2B10G10R10_UNORM_PACK32 => Self::Rgb10a2Unorm,
+            vk::Format::B10G11R11_UFLOAT_PACK32 => Self::Rg11b10Float,
+            vk::Format::R16_UNORM => Self::R16Unorm,
+            vk::Format::R16_SFLOAT => Self::R16Float,
+            vk::Format::R16_UINT => Self::R16Uint,
+            vk::Format::R16G16_SFLOAT => Self::Rg16Float,
+            vk::Format::R16G16B16A16_UNORM => Self::Rgba16Unorm,
+            vk::Format::R16G16B16A16_SFLOAT => Self::Rgba16Float,
+            vk::Format::R16G16B16A16_UINT => Self::Rgba16Uint,
+            vk::Format::R32_SFLOAT => Self::R32Float,
+            vk::Format::R32_UINT => Self::R32Uint,
+            vk::Format::R32_SINT => Self::R32Sint,
+            vk::Format::R32G32_SFLOAT => Self::Rg32Float,
+            vk::Format::R32G32B32A32_SFLOAT => Self::Rgba32Float,
+            vk::Format::R32G32B32A32_UINT => Self::Rgba32Uint,
+            vk::Format::D16_UNORM => Self::Depth16Unorm,
+            vk::Format::D32_SFLOAT => Self::Depth32Float,
+            vk::Format::S8_UINT => Self::Stencil8,
+            vk::Format::D24_UNORM_S8_UINT => Self::Depth24UnormStencil8,
+            vk::Format::D32_SFLOAT_S8_UINT => Self::Depth32FloatStencil8,
+            _ => return Err(BMLError::UnsupportedFormat(format!("{:?}", vulkan_format)).into()),
+        })
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub fn to_vulkan(&self) -> vk::Format {
         match self {
+            Self::R8Unorm => vk::Format::R8_UNORM,
+            Self::R8Snorm => vk::Format::R8_SNORM,
+            Self::R8Uint => vk::Format::R8_UINT,
+            Self::R8Sint => vk::Format::R8_SINT,
+            Self::Rg8Unorm => vk::Format::R8G8_UNORM,
+            Self::Rgba8Unorm => vk::Format::R8G8B8A8_UNORM,
+            Self::Rgba8UnormSrgb => vk::Format::R8G8B8A8_SRGB,
+            Self::Rgba8Snorm => vk::Format::R8G8B8A8_SNORM,
+            Self::Rgba8Uint => vk::Format::R8G8B8A8_UINT,
+            Self::Rgba8Sint => vk::Format::R8G8B8A8_SINT,
             Self::Bgra8Unorm => vk::Format::B8G8R8A8_UNORM,
-            _ => todo!("Format not yet handled."),
+            Self::Bgra8UnormSrgb => vk::Format::B8G8R8A8_SRGB,
+            Self::Rgb10a2Unorm => vk::Format::A2B10G10R10_UNORM_PACK32,
+            Self::Rg11b10Float => vk::Format::B10G11R11_UFLOAT_PACK32,
+            Self::R16Unorm => vk::Format::R16_UNORM,
+            Self::R16Float => vk::Format::R16_SFLOAT,
+            Self::R16Uint => vk::Format::R16_UINT,
+            Self::Rg16Float => vk::Format::R16G16_SFLOAT,
+            Self::Rgba16Unorm => vk::Format::R16G16B16A16_UNORM,
+            Self::Rgba16Float => vk::Format::R16G16B16A16_SFLOAT,
+            Self::Rgba16Uint => vk::Format::R16G16B16A16_UINT,
+            Self::R32Float => vk::Format::R32_SFLOAT,
+            Self::R32Uint => vk::Format::R32_UINT,
+            Self::R32Sint => vk::Format::R32_SINT,
+            Self::Rg32Float => vk::Format::R32G32_SFLOAT,
+            Self::Rgba32Float => vk::Format::R32G32B32A32_SFLOAT,
+            Self::Rgba32Uint => vk::Format::R32G32B32A32_UINT,
+            Self::Depth16Unorm => vk::Format::D16_UNORM,
+            Self::Depth32Float => vk::Format::D32_SFLOAT,
+            Self::Stencil8 => vk::Format::S8_UINT,
+            Self::Depth24UnormStencil8 => vk::Format::D24_UNORM_S8_UINT,
+            Self::Depth32FloatStencil8 => vk::Format::D32_SFLOAT_S8_UINT,
+        }
+    }
+
+    pub fn is_depth(&self) -> bool {
+        matches!(
+            self,
+            Self::Depth16Unorm
+                | Self::Depth32Float
+                | Self::Depth24UnormStencil8
+                | Self::Depth32FloatStencil8
+        )
+    }
+
+    pub fn is_stencil(&self) -> bool {
+        matches!(
+            self,
+            Self::Stencil8 | Self::Depth24UnormStencil8 | Self::Depth32FloatStencil8
+        )
+    }
+
+    /// (Vulkan) The aspects of images in this format.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_aspect(&self) -> vk::ImageAspectFlags {
+        let mut aspect = vk::ImageAspectFlags::empty();
+
+        if self.is_depth() {
+            aspect |= vk::ImageAspectFlags::DEPTH;
+        }
+        if self.is_stencil() {
+            aspect |= vk::ImageAspectFlags::STENCIL;
+        }
+
+        match aspect.is_empty() {
+            true => vk::ImageAspectFlags::COLOR,
+            false => aspect,
         }
     }
 }
@@ -0,0 +1,146 @@
+use std::fmt;
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use ash::vk;
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_foundation::NSError;
+
+/// Errors callers can recover from, or at least tell apart.
+///
+/// Functions still return `anyhow::Result`, use `BMLError::find` to get
+/// these out, e.g. to recreate the device after `DeviceLost`:
+///
+/// ```no_run
+/// # use rosemetal::{BMLError, MTLCommandBuffer};
+/// # fn frame(command_buffer: &MTLCommandBuffer) -> anyhow::Result<()> {
+/// match command_buffer.commit() {
+///     Err(e) if BMLError::find(&e) == Some(BMLError::DeviceLost) => todo!("Recreate the device."),
+///     result => result,
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BMLError {
+    /// The driver reset or the GPU was removed, every object made from the
+    /// device has to be recreated.
+    DeviceLost,
+    OutOfDeviceMemory,
+    OutOfHostMemory,
+    /// The window went away, the view has to be recreated.
+    SurfaceLost,
+    /// The window changed, the swapchain has to be recreated.
+    OutOfDate,
+    UnsupportedFormat(String),
+    UnsupportedFeature(String),
+    /// The API was used wrong, which validation would have caught.
+    InvalidUsage(String),
+    ShaderTranslation(String),
+    /// (Vulkan) A result with no better match.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    Vulkan(vk::Result),
+    /// (Metal) An `NSError` with no better match.
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    Metal {
+        domain: String,
+        code: isize,
+        description: String,
+    },
+}
+
+impl BMLError {
+    /// The `BMLError` somewhere in the chain of `error`, or the one a Vulkan
+    /// result in there maps to.
+    pub fn find(error: &anyhow::Error) -> Option<BMLError> {
+        error.chain().find_map(|cause| {
+            if let Some(error) = cause.downcast_ref::<BMLError>() {
+                return Some(error.clone());
+            }
+
+            #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+            if let Some(result) = cause.downcast_ref::<vk::Result>() {
+                return Some(BMLError::from(*result));
+            }
+
+            None
+        })
+    }
+
+    /// (Metal) Command buffer errors by their code, library errors as
+    /// translation failures.
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn from_metal(error: &NSError) -> Self {
+        let domain = error.domain().to_string();
+        let code = error.code();
+        let description = error.localizedDescription().to_string();
+
+        match (domain.as_str(), code) {
+            // MTLCommandBufferErrorOutOfMemory
+            ("MTLCommandBufferErrorDomain", 8) => BMLError::OutOfDeviceMemory,
+            // MTLCommandBufferErrorNotPermitted, AccessRevoked and
+            // DeviceRemoved.
+            ("MTLCommandBufferErrorDomain", 4 | 7 | 11) => BMLError::DeviceLost,
+            ("MTLCommandBufferErrorDomain", 9) => BMLError::InvalidUsage(description),
+            ("MTLLibraryErrorDomain", _) => BMLError::ShaderTranslation(description),
+            _ => BMLError::Metal {
+                domain,
+                code,
+                description,
+            },
+        }
+    }
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl From<vk::Result> for BMLError {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => BMLError::DeviceLost,
+            vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => BMLError::OutOfDeviceMemory,
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY => BMLError::OutOfHostMemory,
+            vk::Result::ERROR_SURFACE_LOST_KHR => BMLError::SurfaceLost,
+            vk::Result::ERROR_OUT_OF_DATE_KHR => BMLError::OutOfDate,
+            vk::Result::ERROR_FORMAT_NOT_SUPPORTED => {
+                BMLError::UnsupportedFormat(result.to_string())
+            }
+            vk::Result::ERROR_FEATURE_NOT_PRESENT
+            | vk::Result::ERROR_EXTENSION_NOT_PRESENT
+            | vk::Result::ERROR_LAYER_NOT_PRESENT
+            | vk::Result::ERROR_INCOMPATIBLE_DRIVER => {
+                BMLError::UnsupportedFeature(result.to_string())
+            }
+            vk::Result::ERROR_INVALID_SHADER_NV => BMLError::ShaderTranslation(result.to_string()),
+            _ => BMLError::Vulkan(result),
+        }
+    }
+}
+
+impl fmt::Display for BMLError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BMLError::DeviceLost => write!(f, "Device lost."),
+            BMLError::OutOfDeviceMemory => write!(f, "Out of device memory."),
+            BMLError::OutOfHostMemory => write!(f, "Out of host memory."),
+            BMLError::SurfaceLost => write!(f, "Surface lost."),
+            BMLError::OutOfDate => write!(f, "Swapchain is out of date."),
+            BMLError::UnsupportedFormat(format) => write!(f, "Unsupported format: {}.", format),
+            BMLError::UnsupportedFeature(feature) => {
+                write!(f, "Unsupported feature: {}.", feature)
+            }
+            BMLError::InvalidUsage(message) => write!(f, "Invalid usage: {}", message),
+            BMLError::ShaderTranslation(message) => {
+                write!(f, "Shader translation failed: {}", message)
+            }
+            #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+            BMLError::Vulkan(result) => write!(f, "Vulkan error: {}.", result),
+            #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+            BMLError::Metal {
+                domain,
+                code,
+                description,
+            } => write!(f, "Metal error {} {}: {}", domain, code, description),
+        }
+    }
+}
+
+impl std::error::Error for BMLError {}
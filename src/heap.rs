@@ -0,0 +1,692 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+
+use crate::{
+    BMLError, MTLBuffer, MTLCPUCacheMode, MTLDevice, MTLResourceOptions, MTLStorageMode,
+    MTLTexture, MTLTextureDescriptor,
+};
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::{MTLTextureUsage, VulkanAllocation, VulkanGarbage, VulkanTlsf};
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use ash::vk;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2::{rc::Retained, runtime::ProtocolObject};
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_foundation::NSString;
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_metal::{
+    MTLDevice as MetalMTLDevice, MTLHeap as MetalMTLHeap,
+    MTLHeapDescriptor as MetalMTLHeapDescriptor, MTLHeapType as MetalMTLHeapType,
+};
+
+/// How resources are placed in a heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MTLHeapType {
+    /// The heap finds room for each resource.
+    #[default]
+    Automatic,
+    /// Resources go at the offsets they're created with, and may overlap.
+    Placement,
+}
+
+impl MTLHeapType {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLHeapType {
+        match self {
+            MTLHeapType::Automatic => MetalMTLHeapType::Automatic,
+            MTLHeapType::Placement => MetalMTLHeapType::Placement,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MTLHeapDescriptor {
+    /// In bytes.
+    pub size: u64,
+    /// Shared or private, the heap's resources have to use the same.
+    pub storage_mode: MTLStorageMode,
+    pub cpu_cache_mode: MTLCPUCacheMode,
+    pub heap_type: MTLHeapType,
+}
+
+impl Default for MTLHeapDescriptor {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            storage_mode: MTLStorageMode::Private,
+            cpu_cache_mode: MTLCPUCacheMode::DefaultCache,
+            heap_type: MTLHeapType::Automatic,
+        }
+    }
+}
+
+impl MTLHeapDescriptor {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> Retained<MetalMTLHeapDescriptor> {
+        let descriptor = MetalMTLHeapDescriptor::new();
+
+        unsafe { descriptor.setSize(self.size as usize) };
+        descriptor.setStorageMode(self.storage_mode.to_metal());
+        descriptor.setCpuCacheMode(self.cpu_cache_mode.to_metal());
+        descriptor.setType(self.heap_type.to_metal());
+
+        descriptor
+    }
+
+    /// The options resources in the heap are created with.
+    pub fn resource_options(&self) -> MTLResourceOptions {
+        MTLResourceOptions {
+            storage_mode: self.storage_mode,
+            cpu_cache_mode: self.cpu_cache_mode,
+        }
+    }
+}
+
+/// The bytes and alignment a resource takes up in a heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MTLSizeAndAlign {
+    pub size: u64,
+    pub align: u64,
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl MTLSizeAndAlign {
+    /// Heaps are never managed, so their memory needs no alignment to
+    /// `nonCoherentAtomSize`. The size is rounded up so resources placed
+    /// one after another stay aligned.
+    pub fn from_vulkan(requirements: &vk::MemoryRequirements) -> Self {
+        Self {
+            size: requirements
+                .size
+                .next_multiple_of(requirements.alignment.max(1)),
+            align: requirements.alignment,
+        }
+    }
+}
+
+/// One allocation resources are placed in, which saves allocating memory
+/// for each and lets resources that are never used at the same time share
+/// it.
+///
+/// Heap resources aren't tracked: their memory is reused as soon as they
+/// are dropped or made aliasable, fences have to keep the GPU from using
+/// it twice.
+pub struct MTLHeap {
+    device: Arc<MTLDevice>,
+    descriptor: MTLHeapDescriptor,
+    label: RwLock<Option<String>>,
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    metal_heap: Retained<ProtocolObject<dyn MetalMTLHeap>>,
+
+    /// Only `None` while dropping.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_allocation: Option<VulkanAllocation>,
+    /// Finds room for resources in automatic heaps.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_tlsf: Option<Mutex<VulkanTlsf>>,
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_used_size: AtomicU64,
+}
+
+// Only `device` isn't, for the window handles of its instance's layer, which
+// a heap never touches. Placements are guarded by `vulkan_tlsf`.
+unsafe impl Send for MTLHeap {}
+unsafe impl Sync for MTLHeap {}
+
+/// (Vulkan) Where a resource is in its heap.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+#[derive(Debug, Clone, Copy)]
+pub struct VulkanHeapPlacement {
+    /// From the start of the heap.
+    pub offset: u64,
+    pub size: u64,
+    /// The heap's range for it, in automatic heaps.
+    node: Option<usize>,
+}
+
+/// Methods that hand out `Arc<MTLHeap>` clones to what they create.
+pub trait MTLHeapArc {
+    /// A buffer wherever there's room in an automatic heap.
+    fn new_buffer(&self, length: u64, options: MTLResourceOptions) -> Result<Arc<MTLBuffer>>;
+    /// A buffer at `offset` in a placement heap, aligned to
+    /// `heap_buffer_size_and_align`.
+    fn new_buffer_with_offset(
+        &self,
+        length: u64,
+        options: MTLResourceOptions,
+        offset: u64,
+    ) -> Result<Arc<MTLBuffer>>;
+    /// A texture wherever there's room in an automatic heap.
+    fn new_texture(&self, descriptor: &MTLTextureDescriptor) -> Result<Arc<MTLTexture>>;
+    /// A texture at `offset` in a placement heap, aligned to
+    /// `heap_texture_size_and_align`.
+    fn new_texture_with_offset(
+        &self,
+        descriptor: &MTLTextureDescriptor,
+        offset: u64,
+    ) -> Result<Arc<MTLTexture>>;
+}
+
+impl MTLHeapArc for Arc<MTLHeap> {
+    fn new_buffer(&self, length: u64, options: MTLResourceOptions) -> Result<Arc<MTLBuffer>> {
+        MTLBuffer::with_heap(self.clone(), length, options, None)
+    }
+
+    fn new_buffer_with_offset(
+        &self,
+        length: u64,
+        options: MTLResourceOptions,
+        offset: u64,
+    ) -> Result<Arc<MTLBuffer>> {
+        MTLBuffer::with_heap(self.clone(), length, options, Some(offset))
+    }
+
+    fn new_texture(&self, descriptor: &MTLTextureDescriptor) -> Result<Arc<MTLTexture>> {
+        MTLTexture::with_heap(self.clone(), descriptor, None)
+    }
+
+    fn new_texture_with_offset(
+        &self,
+        descriptor: &MTLTextureDescriptor,
+        offset: u64,
+    ) -> Result<Arc<MTLTexture>> {
+        MTLTexture::with_heap(self.clone(), descriptor, Some(offset))
+    }
+}
+
+impl MTLHeap {
+    pub fn new(device: Arc<MTLDevice>, descriptor: MTLHeapDescriptor) -> Result<Arc<Self>> {
+        if descriptor.size == 0 {
+            return Err(BMLError::InvalidUsage(String::from("Heaps can't be empty.")).into());
+        }
+
+        check_storage_mode(descriptor.storage_mode)?;
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return Self::metal_new(device, descriptor);
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return Self::vulkan_new(device, descriptor);
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_new(device: Arc<MTLDevice>, descriptor: MTLHeapDescriptor) -> Result<Arc<Self>> {
+        let metal_heap = device
+            .metal_device()
+            .newHeapWithDescriptor(&descriptor.to_metal());
+
+        let metal_heap = match metal_heap {
+            Some(h) => h,
+            None => return Err(BMLError::OutOfDeviceMemory.into()),
+        };
+
+        Ok(Arc::new(Self {
+            device,
+            descriptor,
+            label: RwLock::new(None),
+            metal_heap,
+        }))
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_new(device: Arc<MTLDevice>, descriptor: MTLHeapDescriptor) -> Result<Arc<Self>> {
+        let vulkan_device = device.vulkan_device();
+        let logical_device = vulkan_device.logical();
+
+        // The memory types buffers can be bound to.
+        let buffer_type_bits = unsafe {
+            let probe = logical_device.create_buffer(
+                &vk::BufferCreateInfo::default()
+                    .size(1)
+                    .usage(MTLBuffer::vulkan_usage(vulkan_device))
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                None,
+            )?;
+            let requirements = logical_device.get_buffer_memory_requirements(probe);
+            logical_device.destroy_buffer(probe, None);
+
+            requirements.memory_type_bits
+        };
+
+        // And the ones textures of the heap's storage mode can.
+        let texture_type_bits = {
+            let probe = MTLTexture::vulkan_create_image(
+                &device,
+                &MTLTextureDescriptor {
+                    usage: MTLTextureUsage {
+                        shader_read: true,
+                        shader_write: true,
+                        render_target: true,
+                        pixel_format_view: false,
+                    },
+                    storage_mode: descriptor.storage_mode,
+                    cpu_cache_mode: descriptor.cpu_cache_mode,
+                    ..Default::default()
+                },
+                true,
+            )?;
+            let requirements = MTLTexture::vulkan_heap_requirements(&device, probe);
+            unsafe { logical_device.destroy_image(probe, None) };
+
+            requirements.memory_type_bits
+        };
+
+        let type_bits = buffer_type_bits & texture_type_bits;
+
+        if type_bits == 0 {
+            return Err(BMLError::UnsupportedFeature(format!(
+                "{:?} heaps, no memory type holds both buffers and textures",
+                descriptor.storage_mode
+            ))
+            .into());
+        }
+
+        let (required, preferred) = descriptor
+            .resource_options()
+            .to_vulkan(device.has_unified_memory());
+
+        let vulkan_allocation = vulkan_device
+            .allocate_heap_memory(descriptor.size, type_bits, required, preferred)
+            .map_err(|e| {
+                e.context(format!(
+                    "Allocating a {} byte {:?} heap",
+                    descriptor.size, descriptor.storage_mode
+                ))
+            })?;
+
+        let vulkan_tlsf = match descriptor.heap_type {
+            MTLHeapType::Automatic => Some(Mutex::new(VulkanTlsf::new(descriptor.size))),
+            MTLHeapType::Placement => None,
+        };
+
+        Ok(Arc::new(Self {
+            device,
+            descriptor,
+            label: RwLock::new(None),
+            vulkan_allocation: Some(vulkan_allocation),
+            vulkan_tlsf,
+            vulkan_used_size: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn device(&self) -> &Arc<MTLDevice> {
+        &self.device
+    }
+
+    pub fn descriptor(&self) -> &MTLHeapDescriptor {
+        &self.descriptor
+    }
+
+    pub fn size(&self) -> u64 {
+        self.descriptor.size
+    }
+
+    pub fn storage_mode(&self) -> MTLStorageMode {
+        self.descriptor.storage_mode
+    }
+
+    pub fn cpu_cache_mode(&self) -> MTLCPUCacheMode {
+        self.descriptor.cpu_cache_mode
+    }
+
+    pub fn heap_type(&self) -> MTLHeapType {
+        self.descriptor.heap_type
+    }
+
+    /// Bytes taken by resources that aren't aliasable.
+    pub fn used_size(&self) -> u64 {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self.metal_heap.usedSize() as u64;
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return self.vulkan_used_size.load(Ordering::Relaxed);
+    }
+
+    /// The largest resource aligned to `alignment` that still fits. The
+    /// free bytes of placement heaps, which don't keep track of where.
+    pub fn max_available_size(&self, alignment: u64) -> u64 {
+        let alignment = alignment.max(1);
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        return self
+            .metal_heap
+            .maxAvailableSizeWithAlignment(alignment as usize) as u64;
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        return match &self.vulkan_tlsf {
+            Some(tlsf) => tlsf
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .max_available(alignment),
+            None => self.size().saturating_sub(self.used_size()),
+        };
+    }
+
+    pub fn label(&self) -> Option<String> {
+        self.label.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_label(&self, label: &str) -> Result<()> {
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        self.metal_heap.setLabel(Some(&NSString::from_str(label)));
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        self.device
+            .vulkan_set_object_name(self.vulkan_allocation().memory(), label)?;
+
+        self.label
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .replace(label.to_string());
+
+        Ok(())
+    }
+
+    /// (Vulkan) Finds room for a resource, or checks that it fits at
+    /// `offset`. In non-coherent memory resources take up whole
+    /// `nonCoherentAtomSize` atoms, so flushing one never reaches another.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_place(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        offset: Option<u64>,
+    ) -> Result<VulkanHeapPlacement> {
+        let allocation = self.vulkan_allocation();
+
+        if requirements.memory_type_bits & (1 << allocation.memory_type()) == 0 {
+            return Err(BMLError::UnsupportedFeature(String::from(
+                "The resource can't be placed in the heap's memory type.",
+            ))
+            .into());
+        }
+
+        let vk::MemoryRequirements {
+            mut size,
+            alignment: mut align,
+            ..
+        } = *requirements;
+
+        if !allocation.coherent() {
+            let atom = self
+                .device
+                .vulkan_device()
+                .properties()
+                .limits
+                .non_coherent_atom_size
+                .max(1);
+
+            size = size.next_multiple_of(atom);
+            align = align.max(atom);
+        }
+
+        let placement = match (&self.vulkan_tlsf, offset) {
+            (Some(tlsf), None) => {
+                let (node, offset) = tlsf
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .allocate(size, align)
+                    .ok_or(BMLError::OutOfDeviceMemory)?;
+
+                VulkanHeapPlacement {
+                    offset,
+                    size,
+                    node: Some(node),
+                }
+            }
+            (None, Some(offset)) => {
+                check_placement(offset, size, align, self.size())?;
+
+                VulkanHeapPlacement {
+                    offset,
+                    size,
+                    node: None,
+                }
+            }
+            _ => return Err(offset_error(self.heap_type()).into()),
+        };
+
+        self.vulkan_used_size.fetch_add(size, Ordering::Relaxed);
+
+        Ok(placement)
+    }
+
+    /// (Vulkan) Gives the room of a dropped or aliasable resource back.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_release(&self, placement: &VulkanHeapPlacement) {
+        if let (Some(tlsf), Some(node)) = (&self.vulkan_tlsf, placement.node) {
+            tlsf.lock().unwrap_or_else(|e| e.into_inner()).free(node);
+        }
+
+        self.vulkan_used_size
+            .fetch_sub(placement.size, Ordering::Relaxed);
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_heap(&self) -> &Retained<ProtocolObject<dyn MetalMTLHeap>> {
+        &self.metal_heap
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_allocation(&self) -> &VulkanAllocation {
+        self.vulkan_allocation.as_ref().unwrap()
+    }
+}
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLHeap {
+    fn drop(&mut self) {
+        if let Some(allocation) = self.vulkan_allocation.take() {
+            self.device
+                .vulkan_device()
+                .destroy_later(VulkanGarbage::Allocation(allocation));
+        }
+    }
+}
+
+/// Heaps have no CPU copy to keep in sync.
+fn check_storage_mode(storage_mode: MTLStorageMode) -> Result<()> {
+    if storage_mode == MTLStorageMode::Managed {
+        return Err(
+            BMLError::InvalidUsage(String::from("Heaps can only be shared or private.")).into(),
+        );
+    }
+
+    Ok(())
+}
+
+/// (Vulkan) Checks that `size` bytes at `offset` of a placement heap are
+/// inside it and aligned.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+fn check_placement(offset: u64, size: u64, align: u64, heap_size: u64) -> Result<()> {
+    let inside = offset.checked_add(size).is_some_and(|end| end <= heap_size);
+
+    if !offset.is_multiple_of(align.max(1)) || !inside {
+        return Err(BMLError::InvalidUsage(format!(
+            "{} bytes at {} aren't inside the heap, or aren't aligned to {}.",
+            size, offset, align
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Why an offset was or wasn't expected by a heap of `heap_type`.
+pub(crate) fn offset_error(heap_type: MTLHeapType) -> BMLError {
+    BMLError::InvalidUsage(String::from(match heap_type {
+        MTLHeapType::Automatic => "Automatic heaps place resources themselves.",
+        MTLHeapType::Placement => "Placement heaps need the offset of each resource.",
+    }))
+}
+
+impl MTLDevice {
+    /// The bytes and alignment a buffer of `length` takes up in a heap, for
+    /// sizing heaps and placing buffers in placement heaps.
+    pub fn heap_buffer_size_and_align(
+        &self,
+        length: u64,
+        options: MTLResourceOptions,
+    ) -> Result<MTLSizeAndAlign> {
+        check_storage_mode(options.storage_mode)?;
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        {
+            let size_and_align = self
+                .metal_device()
+                .heapBufferSizeAndAlignWithLength_options(length as usize, options.to_metal());
+
+            Ok(MTLSizeAndAlign {
+                size: size_and_align.size as u64,
+                align: size_and_align.align as u64,
+            })
+        }
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let logical_device = self.vulkan_device().logical();
+
+            let requirements = unsafe {
+                let probe = logical_device.create_buffer(
+                    &vk::BufferCreateInfo::default()
+                        .size(length.max(1))
+                        .usage(MTLBuffer::vulkan_usage(self.vulkan_device()))
+                        .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                    None,
+                )?;
+                let requirements = logical_device.get_buffer_memory_requirements(probe);
+                logical_device.destroy_buffer(probe, None);
+
+                requirements
+            };
+
+            let mut size_and_align = MTLSizeAndAlign::from_vulkan(&requirements);
+
+            // CPU-visible heaps may be non-coherent, where placements take up
+            // whole atoms.
+            if options.storage_mode != MTLStorageMode::Private {
+                let atom = self
+                    .vulkan_device()
+                    .properties()
+                    .limits
+                    .non_coherent_atom_size
+                    .max(1);
+
+                size_and_align.size = size_and_align.size.next_multiple_of(atom);
+                size_and_align.align = size_and_align.align.max(atom);
+            }
+
+            Ok(size_and_align)
+        }
+    }
+
+    /// The bytes and alignment a texture takes up in a heap.
+    pub fn heap_texture_size_and_align(
+        &self,
+        descriptor: &MTLTextureDescriptor,
+    ) -> Result<MTLSizeAndAlign> {
+        descriptor.validate(self)?;
+
+        #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+        {
+            let size_and_align = self
+                .metal_device()
+                .heapTextureSizeAndAlignWithDescriptor(&descriptor.to_metal());
+
+            Ok(MTLSizeAndAlign {
+                size: size_and_align.size as u64,
+                align: size_and_align.align as u64,
+            })
+        }
+
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        {
+            let probe = MTLTexture::vulkan_create_image(self, descriptor, true)?;
+            let requirements = MTLTexture::vulkan_heap_requirements(self, probe);
+            unsafe { self.vulkan_device().logical().destroy_image(probe, None) };
+
+            Ok(MTLSizeAndAlign::from_vulkan(&requirements))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heaps_reject_managed_storage() {
+        assert!(check_storage_mode(MTLStorageMode::Shared).is_ok());
+        assert!(check_storage_mode(MTLStorageMode::Private).is_ok());
+
+        let e = check_storage_mode(MTLStorageMode::Managed).unwrap_err();
+        assert!(matches!(
+            BMLError::find(&e),
+            Some(BMLError::InvalidUsage(_))
+        ));
+    }
+
+    #[test]
+    fn offset_error_names_the_heap_type() {
+        let BMLError::InvalidUsage(automatic) = offset_error(MTLHeapType::Automatic) else {
+            panic!("Offset errors are usage errors.");
+        };
+        let BMLError::InvalidUsage(placement) = offset_error(MTLHeapType::Placement) else {
+            panic!("Offset errors are usage errors.");
+        };
+
+        assert!(automatic.contains("Automatic"));
+        assert!(placement.contains("offset"));
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    #[test]
+    fn buffer_size_and_align_from_vulkan() {
+        let size_and_align = MTLSizeAndAlign::from_vulkan(&vk::MemoryRequirements {
+            size: 1000,
+            alignment: 256,
+            memory_type_bits: 1,
+        });
+        assert_eq!(
+            size_and_align,
+            MTLSizeAndAlign {
+                size: 1024,
+                align: 256
+            }
+        );
+
+        let size_and_align = MTLSizeAndAlign::from_vulkan(&vk::MemoryRequirements {
+            size: 512,
+            alignment: 256,
+            memory_type_bits: 1,
+        });
+        assert_eq!(
+            size_and_align,
+            MTLSizeAndAlign {
+                size: 512,
+                align: 256
+            }
+        );
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    #[test]
+    fn placement_offsets_are_validated() {
+        assert!(check_placement(0, 1024, 256, 1024).is_ok());
+        assert!(check_placement(768, 256, 256, 1024).is_ok());
+
+        // Misaligned, past the end, and overflowing.
+        assert!(check_placement(100, 256, 256, 1024).is_err());
+        assert!(check_placement(1024, 1, 1, 1024).is_err());
+        assert!(check_placement(768, 512, 256, 1024).is_err());
+        assert!(check_placement(u64::MAX - 255, 512, 256, u64::MAX).is_err());
+    }
+}
@@ -1,12 +1,69 @@
 use anyhow::Result;
 
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use anyhow::anyhow;
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
 use ash::{Entry, Instance, khr::surface, vk::SurfaceKHR};
 
 use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::BMLError;
+use crate::{BMLDebugCallback, BMLMessageSeverity};
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::{VulkanDebugMessenger, VulkanDebugState};
 use std::sync::Arc;
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-use std::{ffi::CString, os::raw::c_char, sync::LazyLock};
+use std::{ffi::CString, sync::LazyLock};
+
+/// How the instance is created. `BMLInstance::new` uses the defaults.
+pub struct BMLInstanceDescriptor {
+    /// The window to present to, `None` for headless instances.
+    pub layer: Option<BMLLayer>,
+    pub application_name: String,
+    /// (major, minor, patch)
+    pub application_version: (u32, u32, u32),
+    /// (Vulkan) The (major, minor) API version to request, lowered to what
+    /// the loader supports.
+    pub api_version: (u32, u32),
+    pub validation: BMLValidation,
+    /// (Vulkan) Instance extensions to enable on top of the ones needed to
+    /// present to `layer`.
+    pub extensions: Vec<String>,
+    /// (Vulkan) Layers to enable on top of validation.
+    pub layers: Vec<String>,
+    /// (Vulkan) Receives validation messages, which go to `log` otherwise.
+    pub debug_callback: Option<BMLDebugCallback>,
+    /// (Vulkan) The least severe validation message to receive.
+    pub debug_severity: BMLMessageSeverity,
+}
+
+impl Default for BMLInstanceDescriptor {
+    fn default() -> Self {
+        Self {
+            layer: None,
+            application_name: String::from("BlackMetal"),
+            application_version: (0, 1, 0),
+            api_version: (1, 3),
+            validation: BMLValidation::default(),
+            extensions: vec![],
+            layers: vec![],
+            debug_callback: None,
+            debug_severity: BMLMessageSeverity::Warning,
+        }
+    }
+}
+
+/// (Vulkan) Whether to enable `VK_LAYER_KHRONOS_validation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BMLValidation {
+    Disabled,
+    /// Fails if the layer isn't installed.
+    Enabled,
+    /// Enabled in debug builds if the layer is installed.
+    #[default]
+    Auto,
+}
 
 pub struct BMLInstance {
     layer: Option<BMLLayer>,
@@ -16,76 +73,146 @@ pub struct BMLInstance {
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_surface: Option<VulkanSurface>,
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_api_version: u32,
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_validation: bool,
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    vulkan_debug_messenger: Option<VulkanDebugMessenger>,
 }
 
+/// The loaded Vulkan loader, or why it couldn't be loaded.
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-pub static VULKAN_ENTRY: LazyLock<Entry> = LazyLock::new(|| unsafe { Entry::load().unwrap() });
+static VULKAN_ENTRY: LazyLock<Result<Entry, String>> =
+    LazyLock::new(|| unsafe { Entry::load() }.map_err(|e| e.to_string()));
+
+/// (Vulkan) The loader's entry points. A missing loader is reported as
+/// `BMLError::UnsupportedFeature`, like a missing driver.
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub fn vulkan_entry() -> Result<&'static Entry> {
+    VULKAN_ENTRY.as_ref().map_err(|e| {
+        BMLError::UnsupportedFeature(format!("the Vulkan loader couldn't be loaded ({})", e)).into()
+    })
+}
 
 impl BMLInstance {
     pub fn new(layer: Option<BMLLayer>) -> Result<Arc<Self>> {
+        Self::with_descriptor(BMLInstanceDescriptor {
+            layer,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_descriptor(descriptor: BMLInstanceDescriptor) -> Result<Arc<Self>> {
         #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-        return Self::metal_new(layer);
+        return Self::metal_new(descriptor);
 
         #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-        return Self::vulkan_new(layer);
+        return Self::vulkan_new(descriptor);
     }
 
+    /// Metal has no instance, only the layer is kept.
     #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-    pub fn metal_new(layer: Option<BMLLayer>) -> Result<Arc<Self>> {
-        Ok(Arc::new(Self { layer }))
+    pub fn metal_new(descriptor: BMLInstanceDescriptor) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            layer: descriptor.layer,
+        }))
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_new(layer: Option<BMLLayer>) -> Result<Arc<Self>> {
-        use std::ops::Deref;
+    pub fn vulkan_new(descriptor: BMLInstanceDescriptor) -> Result<Arc<Self>> {
+        let vulkan_api_version = Self::vulkan_requested_api_version(&descriptor)?;
+        let (layer_names, vulkan_validation) = Self::vulkan_layer_names(&descriptor)?;
+        let debug_state = vulkan_validation.then(|| {
+            VulkanDebugState::new(descriptor.debug_callback.clone(), descriptor.debug_severity)
+        });
+        let vulkan_instance = Self::vulkan_create_instance(
+            &descriptor,
+            vulkan_api_version,
+            &layer_names,
+            debug_state.as_deref(),
+        )?;
+
+        // Built right away so dropping it on an error below destroys the
+        // instance and whatever was made from it.
+        let mut instance = Self {
+            layer: descriptor.layer,
+            vulkan_instance,
+            vulkan_surface: None,
+            vulkan_api_version,
+            vulkan_validation,
+            vulkan_debug_messenger: None,
+        };
+
+        instance.vulkan_debug_messenger = debug_state
+            .map(|state| VulkanDebugMessenger::new(&instance.vulkan_instance, state))
+            .transpose()?;
 
-        let vulkan_instance = Self::vulkan_create_instance(&layer)?;
+        if let Some(l) = &instance.layer {
+            let vulkan_instance = &instance.vulkan_instance;
 
-        let vulkan_surface = unsafe {
-            match &layer {
-                Some(l) => Some(VulkanSurface {
-                    instance: surface::Instance::new(&VULKAN_ENTRY, &vulkan_instance),
-                    khr: ash_window::create_surface(
-                        VULKAN_ENTRY.deref(),
-                        &vulkan_instance,
+            instance.vulkan_surface = Some(VulkanSurface {
+                instance: surface::Instance::new(vulkan_entry()?, vulkan_instance),
+                khr: unsafe {
+                    ash_window::create_surface(
+                        vulkan_entry()?,
+                        vulkan_instance,
                         l.window_display,
                         l.window_handle,
                         None,
-                    )?,
-                }),
-                None => None,
-            }
-        };
+                    )?
+                },
+            });
+        }
 
-        Ok(Arc::new(Self {
-            layer,
-            vulkan_instance,
-            vulkan_surface,
-        }))
+        Ok(Arc::new(instance))
     }
 
+    /// (Vulkan) The API version of the descriptor, or the loader's if it's
+    /// older.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_create_instance(layer: &Option<BMLLayer>) -> Result<Instance> {
+    pub fn vulkan_requested_api_version(descriptor: &BMLInstanceDescriptor) -> Result<u32> {
         use ash::vk;
-        use std::ffi::CString;
 
-        let api_version = vk::make_api_version(
-            env!("CARGO_PKG_VERSION_MAJOR").parse::<u32>()?,
-            env!("CARGO_PKG_VERSION_MINOR").parse::<u32>()?,
-            env!("CARGO_PKG_VERSION_PATCH").parse::<u32>()?,
-            0,
-        );
+        let (major, minor) = descriptor.api_version;
+        let requested = vk::make_api_version(0, major, minor, 0);
+
+        // Vulkan 1.0 loaders don't have vkEnumerateInstanceVersion.
+        let supported = unsafe { vulkan_entry()?.try_enumerate_instance_version()? }
+            .unwrap_or(vk::API_VERSION_1_0);
 
-        let app_name = CString::new("BlackMetal")?;
+        Ok(requested.min(supported))
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_create_instance(
+        descriptor: &BMLInstanceDescriptor,
+        api_version: u32,
+        layer_names: &[CString],
+        debug_state: Option<&VulkanDebugState>,
+    ) -> Result<Instance> {
+        use ash::vk;
+
+        let (major, minor, patch) = descriptor.application_version;
+
+        let app_name = CString::new(descriptor.application_name.as_str())?;
         let engine_name = CString::new("BlackMetal")?;
         let app_info = vk::ApplicationInfo::default()
             .application_name(app_name.as_c_str())
-            .application_version(api_version)
+            .application_version(vk::make_api_version(0, major, minor, patch))
             .engine_name(engine_name.as_c_str())
-            .engine_version(api_version)
+            .engine_version(vk::make_api_version(
+                0,
+                env!("CARGO_PKG_VERSION_MAJOR").parse::<u32>()?,
+                env!("CARGO_PKG_VERSION_MINOR").parse::<u32>()?,
+                env!("CARGO_PKG_VERSION_PATCH").parse::<u32>()?,
+            ))
             .api_version(api_version);
 
-        let mut extension_names = match layer {
+        let mut extension_names = match &descriptor.layer {
             Some(l) => ash_window::enumerate_required_extensions(l.window_display)?.to_vec(),
             None => vec![],
         };
@@ -96,7 +223,17 @@ impl BMLInstance {
             extension_names.push(ash::khr::get_physical_device_properties2::NAME.as_ptr());
         }
 
-        let (_layer_names, layer_names_ptrs) = Self::vulkan_get_layer_names_and_pointers();
+        if debug_state.is_some() {
+            extension_names.push(ash::ext::debug_utils::NAME.as_ptr());
+        }
+
+        let extra_extensions = Self::vulkan_extension_names(descriptor)?;
+        extension_names.extend(extra_extensions.iter().map(|name| name.as_ptr()));
+
+        let layer_names_ptrs = layer_names
+            .iter()
+            .map(|name| name.as_ptr())
+            .collect::<Vec<_>>();
 
         let create_flags = if cfg!(any(target_os = "macos", target_os = "ios")) {
             vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
@@ -104,33 +241,94 @@ impl BMLInstance {
             vk::InstanceCreateFlags::default()
         };
 
-        // TODO: Add debug info.
-
-        let instance_create_info = vk::InstanceCreateInfo::default()
+        let mut instance_create_info = vk::InstanceCreateInfo::default()
             .application_info(&app_info)
             .enabled_extension_names(&extension_names)
             .enabled_layer_names(&layer_names_ptrs)
             .flags(create_flags);
 
-        Ok(unsafe { VULKAN_ENTRY.create_instance(&instance_create_info, None)? })
+        let mut debug_create_info = debug_state.map(VulkanDebugState::create_info);
+
+        if let Some(debug_create_info) = &mut debug_create_info {
+            instance_create_info = instance_create_info.push_next(debug_create_info);
+        }
+
+        Ok(unsafe { vulkan_entry()?.create_instance(&instance_create_info, None)? })
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub const REQUIRED_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
+    pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
 
-    /// (Vulkan) Get the pointers to the validation layers names.
-    /// Also return the corresponding `CString` to avoid dangling pointers.
+    /// (Vulkan) The layers to enable, and whether validation is one of them.
+    /// Layers the descriptor asks for have to be installed, validation only
+    /// with `BMLValidation::Enabled`.
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
-    pub fn vulkan_get_layer_names_and_pointers() -> (Vec<CString>, Vec<*const c_char>) {
-        let layer_names = Self::REQUIRED_LAYERS
+    pub fn vulkan_layer_names(descriptor: &BMLInstanceDescriptor) -> Result<(Vec<CString>, bool)> {
+        let available = unsafe { vulkan_entry()?.enumerate_instance_layer_properties()? }
             .iter()
-            .map(|name| CString::new(*name).unwrap())
+            .filter_map(|properties| properties.layer_name_as_c_str().ok())
+            .map(|name| name.to_string_lossy().into_owned())
             .collect::<Vec<_>>();
-        let layer_names_ptrs = layer_names
+
+        let validation = match descriptor.validation {
+            BMLValidation::Disabled => false,
+            BMLValidation::Enabled => true,
+            BMLValidation::Auto => {
+                cfg!(debug_assertions)
+                    && available.iter().any(|name| name == Self::VALIDATION_LAYER)
+            }
+        };
+
+        let mut names = vec![];
+
+        if validation {
+            names.push(Self::VALIDATION_LAYER);
+        }
+
+        names.extend(descriptor.layers.iter().map(String::as_str));
+
+        let names = names
+            .into_iter()
+            .map(
+                |name| match available.iter().any(|available| available == name) {
+                    true => Ok(CString::new(name)?),
+                    false => Err(anyhow!("Vulkan layer {} is not installed.", name)),
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok((names, validation))
+    }
+
+    /// (Vulkan) The extensions the descriptor asks for, which have to be
+    /// supported.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_extension_names(descriptor: &BMLInstanceDescriptor) -> Result<Vec<CString>> {
+        if descriptor.extensions.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let available = unsafe { vulkan_entry()?.enumerate_instance_extension_properties(None)? };
+
+        descriptor
+            .extensions
             .iter()
-            .map(|name| name.as_ptr())
-            .collect::<Vec<_>>();
-        (layer_names, layer_names_ptrs)
+            .map(|name| {
+                let supported = available.iter().any(|properties| {
+                    properties
+                        .extension_name_as_c_str()
+                        .is_ok_and(|available| available.to_bytes() == name.as_bytes())
+                });
+
+                match supported {
+                    true => Ok(CString::new(name.as_str())?),
+                    false => Err(anyhow!(
+                        "Vulkan instance extension {} is not supported.",
+                        name
+                    )),
+                }
+            })
+            .collect()
     }
 
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
@@ -143,11 +341,50 @@ impl BMLInstance {
         &self.vulkan_surface
     }
 
+    /// (Vulkan) The API version the instance was created with.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_api_version(&self) -> u32 {
+        self.vulkan_api_version
+    }
+
+    /// (Vulkan) Whether `VK_LAYER_KHRONOS_validation` is enabled.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_validation(&self) -> bool {
+        self.vulkan_validation
+    }
+
+    /// (Vulkan) Validation errors reported so far, for tests to check after
+    /// running a frame. Always 0 without validation.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_validation_errors(&self) -> usize {
+        self.vulkan_debug_messenger
+            .as_ref()
+            .map_or(0, VulkanDebugMessenger::validation_errors)
+    }
+
     pub fn layer(&self) -> &Option<BMLLayer> {
         &self.layer
     }
 }
 
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for BMLInstance {
+    /// Devices hold on to their instance, so they and their swapchains are
+    /// gone by now.
+    fn drop(&mut self) {
+        if let Some(surface) = &self.vulkan_surface {
+            unsafe { surface.instance.destroy_surface(surface.khr, None) };
+        }
+
+        // Last, to still report anything wrong with the instance's objects.
+        if let Some(messenger) = &mut self.vulkan_debug_messenger {
+            unsafe { messenger.destroy() };
+        }
+
+        unsafe { self.vulkan_instance.destroy_instance(None) };
+    }
+}
+
 pub struct BMLLayer {
     pub window_display: RawDisplayHandle,
     pub window_handle: RawWindowHandle,
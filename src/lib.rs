@@ -1,17 +1,32 @@
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub mod allocator;
+pub mod buffer;
 pub mod command;
+pub mod debug;
 pub mod device;
 pub mod drawable;
+pub mod error;
+pub mod heap;
 pub mod instance;
-pub mod metalshaper;
 pub mod render;
 pub mod sync;
+pub mod texture;
 
+pub use metalshaper;
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+pub use allocator::*;
+pub use buffer::*;
 pub use command::*;
+pub use debug::*;
 pub use device::*;
 pub use drawable::*;
+pub use error::*;
+pub use heap::*;
 pub use instance::*;
 pub use render::*;
 pub use sync::*;
+pub use texture::*;
 
 #[cfg(test)]
 mod tests {
@@ -20,10 +35,16 @@ mod tests {
     use super::*;
 
     #[test]
+    #[ignore = "needs a Vulkan loader and driver"]
     fn headless_environment() -> Result<()> {
         let instance = BMLInstance::new(None)?;
 
+        assert!(instance.layer().is_none());
+        #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+        assert!(instance.vulkan_surface().is_none());
+
         let device = MTLDevice::create(instance)?;
+        assert!(!device.name().is_empty());
 
         Ok(())
     }
@@ -1 +0,0 @@
-pub mod apple_ir;
@@ -5,6 +5,11 @@ use std::sync::Arc;
 
 use crate::{MTLDevice, MTLTexture};
 
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::VulkanGarbage;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::metalshaper::coordinates::SPIRVCoordinateFixups;
+
 #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
 use anyhow::Result;
 #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
@@ -18,6 +23,7 @@ use objc2_metal::{
     MTLClearColor as MetalMTLClearColor, MTLLoadAction as MetalMTLLoadAction,
     MTLRenderPassColorAttachmentDescriptor as MetalMTLRenderPassColorAttachmentDescriptor,
     MTLRenderPassDescriptor as MetalMTLRenderPassDescriptor, MTLStoreAction as MetalMTLStoreAction,
+    MTLViewport as MetalMTLViewport, MTLWinding as MetalMTLWinding,
 };
 
 pub struct MTLRenderPass {
@@ -80,6 +86,17 @@ impl MTLRenderPass {
     }
 }
 
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLRenderPass {
+    fn drop(&mut self) {
+        if let Some(render_pass) = self.vulkan_render_pass.take() {
+            self.device
+                .vulkan_device()
+                .destroy_later(VulkanGarbage::RenderPass(render_pass));
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct MTLRenderPassDescriptor {
     pub color_attachments: Vec<MTLRenderPassColorAttachment>,
@@ -194,10 +211,14 @@ impl MTLBeginRenderPassDescriptor {
         device: Arc<MTLDevice>,
     ) -> Result<()> {
         for i in &self.color_attachments {
-            if !i.texture.vulkan_is_framebuffer() {
+            if !i.texture.vulkan_is_framebuffer(i.level, i.slice) {
                 unsafe {
-                    i.texture
-                        .vulkan_create_framebuffer(render_pass, device.clone())?;
+                    i.texture.vulkan_create_framebuffer(
+                        render_pass,
+                        i.level,
+                        i.slice,
+                        device.clone(),
+                    )?;
                 }
             }
         }
@@ -205,6 +226,70 @@ impl MTLBeginRenderPassDescriptor {
         Ok(())
     }
 
+    /// (Vulkan) Moves attachments that are loaded but were never rendered to
+    /// into the layout the render pass expects them in. Their contents are
+    /// undefined either way.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_transition_loaded(
+        &self,
+        descriptor: &MTLRenderPassDescriptor,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let barriers: Vec<vk::ImageMemoryBarrier> = self
+            .color_attachments
+            .iter()
+            .zip(&descriptor.color_attachments)
+            .filter(|(begin, attachment)| {
+                matches!(attachment.load_action, MTLLoadAction::Load)
+                    && begin.texture.vulkan_layout(begin.level, begin.slice)
+                        != begin.texture.vulkan_final_layout()
+            })
+            .map(|(begin, _)| {
+                vk::ImageMemoryBarrier::default()
+                    .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(begin.texture.vulkan_final_layout())
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(*begin.texture.vulkan_image())
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: begin.texture.pixel_format().vulkan_aspect(),
+                        base_mip_level: begin.level,
+                        level_count: 1,
+                        base_array_layer: begin.slice,
+                        layer_count: 1,
+                    })
+            })
+            .collect();
+
+        if barriers.is_empty() {
+            return;
+        }
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+    }
+
+    /// (Vulkan) Records the layouts the render pass leaves its attachments
+    /// in, so later passes can load them.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_track_final_layouts(&self) {
+        for i in &self.color_attachments {
+            i.texture
+                .vulkan_set_layout(i.level, i.slice, i.texture.vulkan_final_layout());
+        }
+    }
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub fn vulkan_clear_color_values(&self) -> Vec<vk::ClearValue> {
         let mut result: Vec<vk::ClearValue> = vec![];
@@ -220,6 +305,10 @@ impl MTLBeginRenderPassDescriptor {
 pub struct MTLBeginRenderPassColorAttachment {
     pub clear_color: MTLClearColor,
     pub texture: Arc<MTLTexture>,
+    /// The mipmap level rendered to.
+    pub level: u32,
+    /// The array slice or cube face rendered to.
+    pub slice: u32,
 }
 
 pub struct MTLRenderPassColorAttachment {
@@ -246,6 +335,9 @@ impl MTLRenderPassColorAttachment {
         color_result.setStoreAction(self.store_action.to_metal());
 
         unsafe {
+            color_result.setLevel(begin_descriptor.color_attachments[count].level as usize);
+            color_result.setSlice(begin_descriptor.color_attachments[count].slice as usize);
+
             // TODO: Add Cross-platform options in the future.
             color_result.setTexture(Some(
                 begin_descriptor.color_attachments[count]
@@ -268,19 +360,22 @@ impl MTLRenderPassColorAttachment {
         begin: &MTLBeginRenderPassDescriptor,
         count: usize,
     ) -> vk::AttachmentDescription {
-        let format = begin.color_attachments[count]
-            .texture
-            .pixel_format()
-            .to_vulkan();
+        let texture = &begin.color_attachments[count].texture;
+
+        // Loaded contents only survive if the pass starts in the layout the
+        // last one left them in, `vulkan_transition_loaded` makes sure of it.
+        let initial_layout = match self.load_action {
+            MTLLoadAction::Load => texture.vulkan_final_layout(),
+            MTLLoadAction::Clear | MTLLoadAction::DontCare => vk::ImageLayout::UNDEFINED,
+        };
 
         vk::AttachmentDescription::default()
-            .format(format)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .format(texture.pixel_format().to_vulkan())
+            .samples(vk::SampleCountFlags::from_raw(texture.sample_count()))
             .load_op(self.load_action.to_vulkan())
             .store_op(self.store_action.to_vulkan())
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-        //.final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .initial_layout(initial_layout)
+            .final_layout(texture.vulkan_final_layout())
     }
 }
 
@@ -368,3 +463,70 @@ impl MTLStoreAction {
         }
     }
 }
+
+pub struct MTLViewport {
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub znear: f64,
+    pub zfar: f64,
+}
+
+impl MTLViewport {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLViewport {
+        MetalMTLViewport {
+            originX: self.origin_x,
+            originY: self.origin_y,
+            width: self.width,
+            height: self.height,
+            znear: self.znear,
+            zfar: self.zfar,
+        }
+    }
+
+    /// Translated shaders that don't flip `position.y` themselves expect the
+    /// viewport to do it, by starting at the bottom with a negative height.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self, fixups: &SPIRVCoordinateFixups) -> vk::Viewport {
+        let (y, height) = match fixups.negative_viewport() {
+            true => (self.origin_y + self.height, -self.height),
+            false => (self.origin_y, self.height),
+        };
+
+        vk::Viewport {
+            x: self.origin_x as f32,
+            y: y as f32,
+            width: self.width as f32,
+            height: height as f32,
+            min_depth: self.znear as f32,
+            max_depth: self.zfar as f32,
+        }
+    }
+}
+
+pub enum MTLWinding {
+    Clockwise,
+    CounterClockwise,
+}
+
+impl MTLWinding {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLWinding {
+        match self {
+            MTLWinding::Clockwise => MetalMTLWinding::Clockwise,
+            MTLWinding::CounterClockwise => MetalMTLWinding::CounterClockwise,
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self, fixups: &SPIRVCoordinateFixups) -> vk::FrontFace {
+        match (self, fixups.inverts_winding()) {
+            (Self::Clockwise, false) | (Self::CounterClockwise, true) => vk::FrontFace::CLOCKWISE,
+            (Self::CounterClockwise, false) | (Self::Clockwise, true) => {
+                vk::FrontFace::COUNTER_CLOCKWISE
+            }
+        }
+    }
+}
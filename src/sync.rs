@@ -5,10 +5,24 @@ use anyhow::Result;
 use ash::vk;
 
 use crate::MTLDevice;
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use crate::VulkanGarbage;
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use crate::BMLError;
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2::{rc::Retained, runtime::ProtocolObject};
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_metal::{
+    MTLDevice as MetalMTLDevice, MTLEvent as MetalMTLEvent, MTLFence as MetalMTLFence,
+};
 
 pub struct MTLEvent {
     device: Arc<MTLDevice>,
 
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    metal_event: Retained<ProtocolObject<dyn MetalMTLEvent>>,
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_semaphore: vk::Semaphore,
 }
@@ -16,12 +30,25 @@ pub struct MTLEvent {
 impl MTLEvent {
     pub fn make(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
         #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-        todo!("Metal Support");
+        return Self::metal_make(device);
 
         #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
         return Self::vulkan_make(device);
     }
 
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_make(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
+        let metal_event = device
+            .metal_device()
+            .newEvent()
+            .ok_or(BMLError::OutOfDeviceMemory)?;
+
+        Ok(Arc::new(Self {
+            device,
+            metal_event,
+        }))
+    }
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub fn vulkan_make(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
         let vulkan_semaphore = unsafe {
@@ -42,14 +69,31 @@ impl MTLEvent {
         &self.vulkan_semaphore
     }
 
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_event(&self) -> &Retained<ProtocolObject<dyn MetalMTLEvent>> {
+        &self.metal_event
+    }
+
     pub fn device(&self) -> &Arc<MTLDevice> {
         &self.device
     }
 }
 
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLEvent {
+    fn drop(&mut self) {
+        self.device
+            .vulkan_device()
+            .destroy_later(VulkanGarbage::Semaphore(self.vulkan_semaphore));
+    }
+}
+
 pub struct MTLFence {
     device: Arc<MTLDevice>,
 
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    metal_fence: Retained<ProtocolObject<dyn MetalMTLFence>>,
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     vulkan_fence: vk::Fence,
 }
@@ -57,12 +101,25 @@ pub struct MTLFence {
 impl MTLFence {
     pub fn make(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
         #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
-        todo!("Metal Support");
+        return Self::metal_make(device);
 
         #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
         return Self::vulkan_make(device);
     }
 
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_make(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
+        let metal_fence = device
+            .metal_device()
+            .newFence()
+            .ok_or(BMLError::OutOfDeviceMemory)?;
+
+        Ok(Arc::new(Self {
+            device,
+            metal_fence,
+        }))
+    }
+
     #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
     pub fn vulkan_make(device: Arc<MTLDevice>) -> Result<Arc<Self>> {
         let vulkan_fence = unsafe {
@@ -83,7 +140,21 @@ impl MTLFence {
         &self.vulkan_fence
     }
 
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn metal_fence(&self) -> &Retained<ProtocolObject<dyn MetalMTLFence>> {
+        &self.metal_fence
+    }
+
     pub fn device(&self) -> &Arc<MTLDevice> {
         &self.device
     }
 }
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+impl Drop for MTLFence {
+    fn drop(&mut self) {
+        self.device
+            .vulkan_device()
+            .destroy_later(VulkanGarbage::Fence(self.vulkan_fence));
+    }
+}
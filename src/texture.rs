@@ -0,0 +1,633 @@
+use anyhow::Result;
+
+use crate::{
+    BMLError, MTLCPUCacheMode, MTLDevice, MTLPixelFormat, MTLResourceOptions, MTLStorageMode,
+};
+
+#[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+use ash::vk;
+
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2::rc::Retained;
+#[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+use objc2_metal::{
+    MTLTextureDescriptor as MetalMTLTextureDescriptor, MTLTextureType as MetalMTLTextureType,
+    MTLTextureUsage as MetalMTLTextureUsage,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MTLTextureType {
+    Type1D,
+    #[default]
+    Type2D,
+    Type2DArray,
+    Type2DMultisample,
+    /// Six square 2D faces.
+    Cube,
+    CubeArray,
+    Type3D,
+}
+
+impl MTLTextureType {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLTextureType {
+        match self {
+            MTLTextureType::Type1D => MetalMTLTextureType::Type1D,
+            MTLTextureType::Type2D => MetalMTLTextureType::Type2D,
+            MTLTextureType::Type2DArray => MetalMTLTextureType::Type2DArray,
+            MTLTextureType::Type2DMultisample => MetalMTLTextureType::Type2DMultisample,
+            MTLTextureType::Cube => MetalMTLTextureType::TypeCube,
+            MTLTextureType::CubeArray => MetalMTLTextureType::TypeCubeArray,
+            MTLTextureType::Type3D => MetalMTLTextureType::Type3D,
+        }
+    }
+
+    /// (Vulkan) The type of the image, cubes are 2D images with six layers
+    /// each.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_image_type(&self) -> vk::ImageType {
+        match self {
+            MTLTextureType::Type1D => vk::ImageType::TYPE_1D,
+            MTLTextureType::Type3D => vk::ImageType::TYPE_3D,
+            _ => vk::ImageType::TYPE_2D,
+        }
+    }
+
+    /// (Vulkan) The type of the view the texture is used through.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self) -> vk::ImageViewType {
+        match self {
+            MTLTextureType::Type1D => vk::ImageViewType::TYPE_1D,
+            MTLTextureType::Type2D | MTLTextureType::Type2DMultisample => {
+                vk::ImageViewType::TYPE_2D
+            }
+            MTLTextureType::Type2DArray => vk::ImageViewType::TYPE_2D_ARRAY,
+            MTLTextureType::Cube => vk::ImageViewType::CUBE,
+            MTLTextureType::CubeArray => vk::ImageViewType::CUBE_ARRAY,
+            MTLTextureType::Type3D => vk::ImageViewType::TYPE_3D,
+        }
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(
+            self,
+            MTLTextureType::Type2DArray | MTLTextureType::CubeArray
+        )
+    }
+
+    pub fn is_cube(&self) -> bool {
+        matches!(self, MTLTextureType::Cube | MTLTextureType::CubeArray)
+    }
+}
+
+/// How a texture will be used, textures can't be used any other way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MTLTextureUsage {
+    /// Sampled or read in shaders.
+    pub shader_read: bool,
+    /// Written in shaders.
+    pub shader_write: bool,
+    /// A color, depth or stencil attachment.
+    pub render_target: bool,
+    /// Viewed with other pixel formats.
+    pub pixel_format_view: bool,
+}
+
+impl Default for MTLTextureUsage {
+    fn default() -> Self {
+        Self {
+            shader_read: true,
+            shader_write: false,
+            render_target: false,
+            pixel_format_view: false,
+        }
+    }
+}
+
+impl MTLTextureUsage {
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> MetalMTLTextureUsage {
+        let mut usage = MetalMTLTextureUsage::Unknown;
+
+        if self.shader_read {
+            usage |= MetalMTLTextureUsage::ShaderRead;
+        }
+        if self.shader_write {
+            usage |= MetalMTLTextureUsage::ShaderWrite;
+        }
+        if self.render_target {
+            usage |= MetalMTLTextureUsage::RenderTarget;
+        }
+        if self.pixel_format_view {
+            usage |= MetalMTLTextureUsage::PixelFormatView;
+        }
+
+        usage
+    }
+
+    /// (Vulkan) Render targets are color or depth stencil attachments by
+    /// `pixel_format`. Every texture can be copied from and to.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self, pixel_format: MTLPixelFormat) -> vk::ImageUsageFlags {
+        let mut usage = vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST;
+
+        if self.shader_read {
+            usage |= vk::ImageUsageFlags::SAMPLED;
+        }
+        if self.shader_write {
+            usage |= vk::ImageUsageFlags::STORAGE;
+        }
+        if self.render_target {
+            usage |= match pixel_format.is_depth() || pixel_format.is_stencil() {
+                true => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                false => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            };
+        }
+
+        usage
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MTLTextureDescriptor {
+    pub texture_type: MTLTextureType,
+    pub pixel_format: MTLPixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub mipmap_level_count: u32,
+    /// Of the array types, cubes each count once.
+    pub array_length: u32,
+    /// More than one only for `Type2DMultisample`.
+    pub sample_count: u32,
+    pub usage: MTLTextureUsage,
+    /// Private by default, the CPU can't reach textures yet.
+    pub storage_mode: MTLStorageMode,
+    pub cpu_cache_mode: MTLCPUCacheMode,
+}
+
+impl Default for MTLTextureDescriptor {
+    fn default() -> Self {
+        Self {
+            texture_type: MTLTextureType::Type2D,
+            pixel_format: MTLPixelFormat::Rgba8Unorm,
+            width: 1,
+            height: 1,
+            depth: 1,
+            mipmap_level_count: 1,
+            array_length: 1,
+            sample_count: 1,
+            usage: MTLTextureUsage::default(),
+            storage_mode: MTLStorageMode::Private,
+            cpu_cache_mode: MTLCPUCacheMode::DefaultCache,
+        }
+    }
+}
+
+impl MTLTextureDescriptor {
+    /// A 2D texture, with every mipmap level down to 1x1 if `mipmapped`.
+    pub fn texture_2d(
+        pixel_format: MTLPixelFormat,
+        width: u32,
+        height: u32,
+        mipmapped: bool,
+    ) -> Self {
+        let mut descriptor = Self {
+            pixel_format,
+            width,
+            height,
+            ..Default::default()
+        };
+
+        if mipmapped {
+            descriptor.mipmap_level_count = descriptor.max_mipmap_level_count();
+        }
+
+        descriptor
+    }
+
+    /// A cube texture with `size` by `size` faces, with every mipmap level
+    /// down to 1x1 if `mipmapped`.
+    pub fn texture_cube(pixel_format: MTLPixelFormat, size: u32, mipmapped: bool) -> Self {
+        Self {
+            texture_type: MTLTextureType::Cube,
+            ..Self::texture_2d(pixel_format, size, size, mipmapped)
+        }
+    }
+
+    /// The options the texture is allocated with.
+    pub fn resource_options(&self) -> MTLResourceOptions {
+        MTLResourceOptions {
+            storage_mode: self.storage_mode,
+            cpu_cache_mode: self.cpu_cache_mode,
+        }
+    }
+
+    /// Levels until the largest dimension is 1.
+    pub fn max_mipmap_level_count(&self) -> u32 {
+        let size = match self.texture_type {
+            MTLTextureType::Type1D => self.width,
+            MTLTextureType::Type3D => self.width.max(self.height).max(self.depth),
+            _ => self.width.max(self.height),
+        };
+
+        32 - size.max(1).leading_zeros()
+    }
+
+    /// Checks the descriptor against what Metal allows, which Vulkan would
+    /// otherwise only catch with validation on.
+    pub fn validate(&self, device: &MTLDevice) -> Result<()> {
+        self.validate_shape()?;
+
+        if self.sample_count > 1 && !device.supports_texture_sample_count(self.sample_count) {
+            return Err(BMLError::UnsupportedFeature(format!(
+                "{} samples per pixel",
+                self.sample_count
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// The checks of `validate` that don't depend on the device.
+    fn validate_shape(&self) -> Result<()> {
+        let invalid = |message: String| Err(BMLError::InvalidUsage(message).into());
+
+        if [self.width, self.height, self.depth].contains(&0) {
+            return invalid(format!(
+                "A {}x{}x{} texture is empty.",
+                self.width, self.height, self.depth
+            ));
+        }
+
+        let dimensions_valid = match self.texture_type {
+            MTLTextureType::Type1D => self.height == 1 && self.depth == 1,
+            MTLTextureType::Cube | MTLTextureType::CubeArray => {
+                self.width == self.height && self.depth == 1
+            }
+            MTLTextureType::Type3D => true,
+            _ => self.depth == 1,
+        };
+
+        if !dimensions_valid {
+            return invalid(format!(
+                "{:?} textures can't be {}x{}x{}.",
+                self.texture_type, self.width, self.height, self.depth
+            ));
+        }
+
+        if self.array_length == 0 || (self.array_length > 1 && !self.texture_type.is_array()) {
+            return invalid(format!(
+                "{:?} textures can't have an array length of {}.",
+                self.texture_type, self.array_length
+            ));
+        }
+
+        if self.mipmap_level_count == 0 || self.mipmap_level_count > self.max_mipmap_level_count() {
+            return invalid(format!(
+                "A {}x{}x{} texture can't have {} mipmap levels.",
+                self.width, self.height, self.depth, self.mipmap_level_count
+            ));
+        }
+
+        let multisample = self.texture_type == MTLTextureType::Type2DMultisample;
+
+        if self.sample_count == 0
+            || (self.sample_count > 1) != multisample
+            || (multisample && self.mipmap_level_count > 1)
+        {
+            return invalid(format!(
+                "{:?} textures with {} mipmap levels can't have {} samples.",
+                self.texture_type, self.mipmap_level_count, self.sample_count
+            ));
+        }
+
+        if (self.pixel_format.is_depth() || self.pixel_format.is_stencil())
+            && self.storage_mode != MTLStorageMode::Private
+        {
+            return invalid(format!(
+                "{:?} textures have to be private.",
+                self.pixel_format
+            ));
+        }
+
+        let usage = self.usage;
+        if !(usage.shader_read || usage.shader_write || usage.render_target) {
+            return invalid(String::from(
+                "A texture has to be read, written or rendered to.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(all(any(target_os = "macos", target_os = "ios"), not(feature = "moltenvk")))]
+    pub fn to_metal(&self) -> Retained<MetalMTLTextureDescriptor> {
+        let descriptor = MetalMTLTextureDescriptor::new();
+
+        descriptor.setTextureType(self.texture_type.to_metal());
+        descriptor.setPixelFormat(self.pixel_format.to_metal());
+        unsafe {
+            descriptor.setWidth(self.width as usize);
+            descriptor.setHeight(self.height as usize);
+            descriptor.setDepth(self.depth as usize);
+            descriptor.setMipmapLevelCount(self.mipmap_level_count as usize);
+            descriptor.setArrayLength(self.array_length as usize);
+            descriptor.setSampleCount(self.sample_count as usize);
+        }
+        descriptor.setUsage(self.usage.to_metal());
+        descriptor.setStorageMode(self.storage_mode.to_metal());
+        descriptor.setCpuCacheMode(self.cpu_cache_mode.to_metal());
+
+        descriptor
+    }
+
+    /// (Vulkan) The layers of the image, six for each cube.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_array_layers(&self) -> u32 {
+        match self.texture_type.is_cube() {
+            true => self.array_length * 6,
+            false => self.array_length,
+        }
+    }
+
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_flags(&self) -> vk::ImageCreateFlags {
+        let mut flags = vk::ImageCreateFlags::empty();
+
+        if self.texture_type.is_cube() {
+            flags |= vk::ImageCreateFlags::CUBE_COMPATIBLE;
+        }
+        if self.usage.pixel_format_view {
+            flags |= vk::ImageCreateFlags::MUTABLE_FORMAT;
+        }
+
+        flags
+    }
+
+    /// (Vulkan) An optimally tiled image, which starts out undefined.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn to_vulkan(&self) -> vk::ImageCreateInfo<'static> {
+        vk::ImageCreateInfo::default()
+            .flags(self.vulkan_flags())
+            .image_type(self.texture_type.vulkan_image_type())
+            .format(self.pixel_format.to_vulkan())
+            .extent(vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: self.depth,
+            })
+            .mip_levels(self.mipmap_level_count)
+            .array_layers(self.vulkan_array_layers())
+            .samples(vk::SampleCountFlags::from_raw(self.sample_count))
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(self.usage.to_vulkan(self.pixel_format))
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+    }
+
+    /// (Vulkan) Checks the device supports the format with this usage and
+    /// size.
+    #[cfg(any(not(any(target_os = "macos", target_os = "ios")), feature = "moltenvk"))]
+    pub fn vulkan_check_support(&self, device: &MTLDevice) -> Result<()> {
+        let features = &device.vulkan_device().features().core;
+
+        if self.texture_type == MTLTextureType::CubeArray && features.image_cube_array != vk::TRUE {
+            return Err(BMLError::UnsupportedFeature(String::from(
+                "cube array textures (imageCubeArray)",
+            ))
+            .into());
+        }
+
+        let info = self.to_vulkan();
+
+        let properties = unsafe {
+            device
+                .instance
+                .vulkan_instance()
+                .get_physical_device_image_format_properties(
+                    *device.vulkan_device().physical(),
+                    info.format,
+                    info.image_type,
+                    info.tiling,
+                    info.usage,
+                    info.flags,
+                )
+        };
+
+        let properties = match properties {
+            Ok(properties) => properties,
+            Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED) => {
+                return Err(BMLError::UnsupportedFormat(format!(
+                    "{:?} for {:?} with {:?}",
+                    self.pixel_format, self.texture_type, self.usage
+                ))
+                .into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let extent = properties.max_extent;
+
+        if self.width > extent.width
+            || self.height > extent.height
+            || self.depth > extent.depth
+            || self.vulkan_array_layers() > properties.max_array_layers
+            || self.mipmap_level_count > properties.max_mip_levels
+            || !properties.sample_counts.contains(info.samples)
+        {
+            return Err(BMLError::UnsupportedFeature(format!(
+                "{:?} textures of {}x{}x{} with {} layers, {} levels and {} samples",
+                self.pixel_format,
+                self.width,
+                self.height,
+                self.depth,
+                self.vulkan_array_layers(),
+                self.mipmap_level_count,
+                self.sample_count
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid(descriptor: MTLTextureDescriptor) -> bool {
+        descriptor
+            .validate_shape()
+            .is_err_and(|e| matches!(BMLError::find(&e), Some(BMLError::InvalidUsage(_))))
+    }
+
+    #[test]
+    fn max_mipmap_level_count() {
+        let descriptor = |texture_type, width, height, depth| MTLTextureDescriptor {
+            texture_type,
+            width,
+            height,
+            depth,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            descriptor(MTLTextureType::Type2D, 1, 1, 1).max_mipmap_level_count(),
+            1
+        );
+        assert_eq!(
+            descriptor(MTLTextureType::Type2D, 256, 256, 1).max_mipmap_level_count(),
+            9
+        );
+        assert_eq!(
+            descriptor(MTLTextureType::Type2D, 300, 17, 1).max_mipmap_level_count(),
+            9
+        );
+        assert_eq!(
+            descriptor(MTLTextureType::Type1D, 16, 1, 1).max_mipmap_level_count(),
+            5
+        );
+        assert_eq!(
+            descriptor(MTLTextureType::Type3D, 4, 4, 64).max_mipmap_level_count(),
+            7
+        );
+        // Only 3D textures count their depth.
+        assert_eq!(
+            descriptor(MTLTextureType::Type2DArray, 4, 4, 64).max_mipmap_level_count(),
+            3
+        );
+
+        let mipmapped =
+            MTLTextureDescriptor::texture_2d(MTLPixelFormat::Rgba8Unorm, 1024, 512, true);
+        assert_eq!(mipmapped.mipmap_level_count, 11);
+        assert!(mipmapped.validate_shape().is_ok());
+    }
+
+    #[test]
+    fn validates_dimensions() {
+        let valid = MTLTextureDescriptor::texture_2d(MTLPixelFormat::Rgba8Unorm, 64, 32, false);
+        assert!(valid.validate_shape().is_ok());
+
+        assert!(is_invalid(MTLTextureDescriptor { width: 0, ..valid }));
+        assert!(is_invalid(MTLTextureDescriptor { depth: 2, ..valid }));
+        assert!(is_invalid(MTLTextureDescriptor {
+            texture_type: MTLTextureType::Type1D,
+            ..valid
+        }));
+
+        // Cube faces are square.
+        let cube = MTLTextureDescriptor::texture_cube(MTLPixelFormat::Rgba8Unorm, 64, true);
+        assert!(cube.validate_shape().is_ok());
+        assert!(is_invalid(MTLTextureDescriptor { height: 32, ..cube }));
+
+        let volume = MTLTextureDescriptor {
+            texture_type: MTLTextureType::Type3D,
+            depth: 16,
+            ..valid
+        };
+        assert!(volume.validate_shape().is_ok());
+    }
+
+    #[test]
+    fn validates_array_length() {
+        let array = MTLTextureDescriptor {
+            texture_type: MTLTextureType::Type2DArray,
+            array_length: 4,
+            ..Default::default()
+        };
+        assert!(array.validate_shape().is_ok());
+        assert!(is_invalid(MTLTextureDescriptor {
+            array_length: 0,
+            ..array
+        }));
+
+        let cube_array = MTLTextureDescriptor {
+            texture_type: MTLTextureType::CubeArray,
+            ..array
+        };
+        assert!(cube_array.validate_shape().is_ok());
+
+        assert!(is_invalid(MTLTextureDescriptor {
+            texture_type: MTLTextureType::Type2D,
+            ..array
+        }));
+        assert!(is_invalid(MTLTextureDescriptor {
+            texture_type: MTLTextureType::Cube,
+            ..array
+        }));
+    }
+
+    #[test]
+    fn validates_mipmaps_and_samples() {
+        let texture = MTLTextureDescriptor::texture_2d(MTLPixelFormat::Rgba8Unorm, 16, 16, false);
+
+        assert!(
+            MTLTextureDescriptor {
+                mipmap_level_count: 5,
+                ..texture
+            }
+            .validate_shape()
+            .is_ok()
+        );
+        assert!(is_invalid(MTLTextureDescriptor {
+            mipmap_level_count: 6,
+            ..texture
+        }));
+        assert!(is_invalid(MTLTextureDescriptor {
+            mipmap_level_count: 0,
+            ..texture
+        }));
+
+        let multisample = MTLTextureDescriptor {
+            texture_type: MTLTextureType::Type2DMultisample,
+            sample_count: 4,
+            ..texture
+        };
+        assert!(multisample.validate_shape().is_ok());
+        assert!(is_invalid(MTLTextureDescriptor {
+            sample_count: 1,
+            ..multisample
+        }));
+        assert!(is_invalid(MTLTextureDescriptor {
+            mipmap_level_count: 2,
+            ..multisample
+        }));
+        assert!(is_invalid(MTLTextureDescriptor {
+            sample_count: 4,
+            ..texture
+        }));
+        assert!(is_invalid(MTLTextureDescriptor {
+            sample_count: 0,
+            ..texture
+        }));
+    }
+
+    #[test]
+    fn depth_textures_are_private() {
+        let depth = MTLTextureDescriptor::texture_2d(MTLPixelFormat::Depth32Float, 16, 16, false);
+        assert!(depth.validate_shape().is_ok());
+
+        assert!(is_invalid(MTLTextureDescriptor {
+            storage_mode: MTLStorageMode::Shared,
+            ..depth
+        }));
+    }
+
+    #[test]
+    fn usage_is_not_empty() {
+        let texture = MTLTextureDescriptor::texture_2d(MTLPixelFormat::Rgba8Unorm, 16, 16, false);
+
+        assert!(is_invalid(MTLTextureDescriptor {
+            usage: MTLTextureUsage {
+                shader_read: false,
+                shader_write: false,
+                render_target: false,
+                pixel_format_view: true,
+            },
+            ..texture
+        }));
+    }
+}